@@ -1,25 +1,39 @@
 extern crate nedbase;
 extern crate rand;
 
-use nedbase::{BTree, LockSet, TransactionMode};
+use nedbase::{BTree, Error, Transaction, TransactionMode};
+use rand::{distributions::Alphanumeric, prelude::*};
+use std::collections::HashMap;
+use std::iter;
 use std::sync::Arc;
 use std::thread;
 
-const MAX_KEYS_PER_NODE: usize = 32;
-const NUM_KEYS: usize =
-  MAX_KEYS_PER_NODE * MAX_KEYS_PER_NODE * MAX_KEYS_PER_NODE;
+// An entry is two 8 character strings, so about 24 bytes.
+const MAX_NODE_SIZE: usize = 768;
+const NUM_KEYS: usize = 32 * 32 * 32;
 const NUM_THREADS: u32 = 32;
+const KEY_LENGTH: usize = 8;
+
+fn random_key() -> String {
+  let mut rng = thread_rng();
+  iter::repeat(())
+    .map(|()| rng.sample(Alphanumeric))
+    .take(KEY_LENGTH)
+    .collect()
+}
 
 fn main() {
   // Make the BTree.
-  let btree = Arc::new(BTree::new(MAX_KEYS_PER_NODE));
+  let btree = Arc::new(
+    BTree::new(MAX_NODE_SIZE).expect("MAX_NODE_SIZE is big enough"),
+  );
 
   // Make the work.
   let keyset = {
     let mut keyset = vec![];
     for _ in 0..NUM_KEYS {
-      let key1 = btree.get_new_identifier();
-      let key2 = btree.get_new_identifier();
+      let key1 = random_key();
+      let key2 = random_key();
 
       let pair = if key1 < key2 {
         (key1, key2)
@@ -50,24 +64,73 @@ fn main() {
     handle.join().expect("no threads should panic");
   }
 
-  // Check no keys were lost.
+  // Nothing else is running now, so there can be no deadlocks.
+  let msg = "no deadlock possible with a single thread";
+
+  // Keys are inserted and deleted in pairs, so no pair should be left
+  // half present.
   for (key1, key2) in (*keyset).iter() {
-    let mut lock_set = LockSet::new(&btree, TransactionMode::ReadOnly);
-    let key1_present = BTree::contains_key(&mut lock_set, &key1);
-    let key2_present = BTree::contains_key(&mut lock_set, &key2);
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let key1_present = txn.contains_key(key1).expect(msg);
+    let key2_present = txn.contains_key(key2).expect(msg);
+    txn.commit().expect(msg);
 
-    if !key1_present || !key2_present {
+    if key1_present != key2_present {
       println!("We lost a key?!");
     }
   }
 
+  // A full scan should also find every present key paired up.
+  let mut txn = btree.begin(TransactionMode::ReadOnly);
+  let pairs: HashMap<_, _> = txn
+    .range(..)
+    .expect(msg)
+    .collect::<Result<_, _>>()
+    .expect(msg);
+  txn.commit().expect(msg);
+  for (key, value) in pairs.iter() {
+    if pairs.get(value) != Some(key) {
+      println!("Scan found a key without its pair?!");
+    }
+  }
+
   // Finally, validate the structure of the tree.
-  let mut lock_set = LockSet::new(&btree, TransactionMode::ReadOnly);
-  btree.validate(&mut lock_set)
+  btree.validate().expect(msg)
+}
+
+// Two transactions that lock the same pair of keys in opposite orders
+// can deadlock. The victim has already been rolled back, so we can just
+// try it again.
+fn retry_on_deadlock<F>(mut transaction_fn: F)
+where
+  F: FnMut() -> Result<(), Error>,
+{
+  loop {
+    match transaction_fn() {
+      Ok(()) => return,
+      Err(Error::Deadlock) => continue,
+      Err(error) => panic!("Unexpected error: {}", error),
+    }
+  }
+}
+
+// The first few keys at or after `key`.
+fn first_keys_from(
+  txn: &mut Transaction<String, String>,
+  key: &String,
+) -> Result<Vec<String>, Error> {
+  txn
+    .range(key..)?
+    .take(3)
+    .map(|entry| Ok(entry?.0))
+    .collect()
 }
 
 // A thread's work.
-fn run_thread(btree: &Arc<BTree>, keyset: Arc<Vec<(String, String)>>) {
+fn run_thread(
+  btree: &Arc<BTree<String, String>>,
+  keyset: Arc<Vec<(String, String)>>,
+) {
   // First, shuffle the keys.
   let keyset = {
     let mut rng = thread_rng();
@@ -81,51 +144,84 @@ fn run_thread(btree: &Arc<BTree>, keyset: Arc<Vec<(String, String)>>) {
   let third_of_keyset = keyset.len() / 3;
 
   for idx in 0..keyset.len() {
-    {
-      // Do a transaction of two insertions.
+    // Do a transaction of two insertions.
+    retry_on_deadlock(|| {
       let (key1, key2) = keyset[idx].clone();
-      let mut lock_set =
-        LockSet::new(btree, TransactionMode::ReadWrite);
+      let mut txn = btree.begin(TransactionMode::ReadWrite);
 
-      BTree::insert(btree, &mut lock_set, &key1);
-      let key1_present = BTree::contains_key(&mut lock_set, &key1);
-      if !key1_present {
+      // Each key's value is the other key of its pair.
+      txn.insert(&key1, key2.clone())?;
+      let key1_value = txn.get(&key1)?;
+      if key1_value.as_ref() != Some(&key2) {
         println!("Where did key1 go? {}", key1);
       }
 
-      BTree::insert(btree, &mut lock_set, &key2);
-      let key2_present = BTree::contains_key(&mut lock_set, &key2);
-      if !key2_present {
+      txn.insert(&key2, key1.clone())?;
+      let key2_value = txn.get(&key2)?;
+      if key2_value.as_ref() != Some(&key1) {
         println!("Where did key2 go? {}", key2);
       }
-    }
 
-    {
-      // Do a transaction of two reads.
-      let idx = (idx + third_of_keyset) % keyset.len();
+      txn.commit()
+    });
+
+    // Do a transaction of two deletions, but then abort it. The pair
+    // should be left just as it was.
+    retry_on_deadlock(|| {
       let (key1, key2) = keyset[idx].clone();
-      let mut lock_set = LockSet::new(btree, TransactionMode::ReadOnly);
-      let key1_present = BTree::contains_key(&mut lock_set, &key1);
-      let key2_present = BTree::contains_key(&mut lock_set, &key2);
+      let mut txn = btree.begin(TransactionMode::ReadWrite);
+      txn.delete(&key1)?;
+      txn.delete(&key2)?;
+      txn.abort()?;
+
+      Ok(())
+    });
 
-      if key1_present != key2_present {
-        println!("Read transaction isolation violated!");
+    // Do a transaction of two reads.
+    retry_on_deadlock(|| {
+      let idx = (idx + third_of_keyset) % keyset.len();
+      let (key1, key2) = keyset[idx].clone();
+      let mut txn = btree.begin(TransactionMode::ReadOnly);
+      let keys_from_key1 = first_keys_from(&mut txn, &key1)?;
+      let key1_value = txn.get(&key1)?;
+      let key2_value = txn.get(&key2)?;
+
+      // Other threads keep inserting keys near key1, but none of them
+      // should show up in between.
+      if first_keys_from(&mut txn, &key1)? != keys_from_key1 {
+        println!("Phantom read!");
+      }
+      txn.commit()?;
+
+      match (key1_value, key2_value) {
+        (None, None) => (),
+        (Some(key1_value), Some(key2_value)) => {
+          if key1_value != key2 || key2_value != key1 {
+            println!("Values were mixed up!");
+          }
+        }
+        _ => println!("Read transaction isolation violated!"),
       }
-    }
 
-    {
-      // let idx = (idx + 2*third_of_keyset) % keyset.len();
-      // let (key1, key2) = keyset[idx].clone();
+      Ok(())
+    });
+
+    // Do a transaction of two deletions.
+    retry_on_deadlock(|| {
+      let idx = (idx + 2 * third_of_keyset) % keyset.len();
+      let (key1, key2) = keyset[idx].clone();
 
-      // let mut lock_set = LockSet::new(btree, TransactionMode::ReadWrite);
-      // BTree::delete(btree, &mut lock_set, &key1);
-      // BTree::delete(btree, &mut lock_set, &key2);
+      let mut txn = btree.begin(TransactionMode::ReadWrite);
+      txn.delete(&key1)?;
+      txn.delete(&key2)?;
 
-      // let key1_present = BTree::contains_key(&mut lock_set, &key1);
-      // let key2_present = BTree::contains_key(&mut lock_set, &key2);
-      // if key1_present || key2_present {
-      //   println!("A key wasn't deleted?");
-      // }
-    }
+      let key1_present = txn.contains_key(&key1)?;
+      let key2_present = txn.contains_key(&key2)?;
+      if key1_present || key2_present {
+        println!("A key wasn't deleted?");
+      }
+
+      txn.commit()
+    });
   }
 }
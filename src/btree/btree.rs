@@ -1,36 +1,228 @@
-use node::{LeafNode, Node};
-use parking_lot::RwLock;
+use buffer_pool::{BufferPool, NodePin};
+use constants::{
+  ASYNC_COMMIT_INTERVAL, DEFAULT_BUFFER_POOL_CAPACITY,
+  DEFAULT_CHECKPOINT_LOG_SIZE, MIN_ENTRIES_PER_NODE, MIN_NODE_SIZE,
+};
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::{KeyLockTable, LockManager};
+use mvcc::VersionStore;
+use node::{LeafNode, SplitPolicy};
+use parking_lot::{Mutex, RwLock};
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::Arc;
+use storage::{
+  MemoryNodeStore, NodeIdentifier, NodeStore, PageFileNodeStore,
+};
+use transaction::{Transaction, TransactionId, TransactionMode};
+use wal::{Lsn, WriteAheadLog};
 
-// A BTree holds the map from identifiers to `Arc<RwLock<Node>>`s.
+// A BTree holds the BufferPool, which maps identifiers to
+// `Arc<NodeLatch>`s. A NodeLatch is the `RwLock` on a node.
 //
 // Many threads can share the `RwLock<Node>`, but of course only one can
-// lock it for reading at any given time.
+// lock it for reading at any given time. To descend the tree, threads
+// needn't lock interior nodes at all; see `NodeLatch`.
 //
 // The BTree also knows the entry point into the nodes: the root
 // identifier.
+//
+// The BTree owns the LockManager that every transaction's LockSet
+// reports to, and the KeyLockTable that grants locks on keys. Reads and
+// writes take the LockSet to lock with, so users go through a
+// Transaction (see `begin`), which owns one.
+//
+// Last, the BufferPool owns the NodeStore. A node is read from the
+// NodeStore when it isn't in the BufferPool, and written back when it is
+// evicted or flushed. See `storage.rs`.
+//
+// A BTree may also keep a WriteAheadLog, so that it can recover from a
+// crash. See `logging.rs` and `recovery.rs`. To keep the log from
+// growing forever, we checkpoint every so often. See `storage.rs`.
+//
+// Old versions of keys are kept in the VersionStore for Snapshot
+// transactions. See `versioning.rs`.
 
-type IdentifierToNodeArcLockMap = HashMap<String, Arc<RwLock<Node>>>;
-
-pub struct BTree {
+pub struct BTree<K: Key, V: Value> {
   // Keeps track of which node is the root node.
-  pub root_identifier_lock: RwLock<String>,
+  pub root_identifier_lock: RwLock<NodeIdentifier>,
   // Associates node identifiers with the node.
-  pub identifier_to_node_arc_lock_map:
-    RwLock<IdentifierToNodeArcLockMap>,
-  // Used when creating new nodes.
-  pub max_key_capacity: usize,
+  pub buffer_pool: Arc<BufferPool<K, V>>,
+  // Used when creating new nodes. Nodes split before they take up more
+  // bytes than this.
+  pub max_node_size: usize,
+  // Decides where overfull nodes split.
+  pub split_policy: SplitPolicy,
+  // Tracks who holds and waits for locks, to detect deadlock.
+  pub lock_manager: Arc<LockManager>,
+  // Grants the locks on ranges of keys, and on the tree as a whole.
+  pub key_lock_table: KeyLockTable<K>,
+  // Set if the root identifier may have changed since it was last
+  // written back.
+  pub root_identifier_is_dirty: AtomicBool,
+  // Every change is logged here first, if we have a log at all.
+  pub wal: Option<Arc<WriteAheadLog>>,
+  // The first record of each running transaction that logged a change.
+  // Recovery may have to undo it, so a checkpoint keeps it.
+  pub running_transaction_lsns: Mutex<HashMap<TransactionId, Lsn>>,
+  // The record that started each split whose parent hasn't learned of
+  // it yet, by the split's new right sibbling. Recovery may have to
+  // complete it, so a checkpoint keeps it.
+  pub unfinished_split_lsns: Mutex<HashMap<NodeIdentifier, Lsn>>,
+  // We checkpoint once the log grows by this many bytes.
+  pub checkpoint_log_size: u64,
+  // How big the log was after the last checkpoint.
+  pub log_size_at_checkpoint: AtomicU64,
+  // Set while someone checkpoints, so that no one else starts to.
+  pub is_checkpointing: AtomicBool,
+  // Nodes removed from the tree, each with the first transaction that
+  // can't have reached it, and the record that retired it. See
+  // `free_retired_nodes.rs`.
+  pub retired_nodes:
+    Mutex<HashMap<NodeIdentifier, (TransactionId, Lsn)>>,
+  // The newest of those transactions, or zero if there are no retired
+  // nodes.
+  pub retired_nodes_first_unaware: AtomicU64,
+  // The versions that running snapshots may still need.
+  pub version_store: VersionStore<K, V>,
+  // Set once `close` has checkpointed, so that dropping doesn't again.
+  pub(super) is_closed: bool,
 }
 
-impl BTree {
-  pub fn new(max_key_capacity: usize) -> BTree {
+impl<K: Key, V: Value> BTree<K, V> {
+  // Makes a BTree that lives only in memory. There's no point evicting
+  // anything, so the BufferPool is unbounded.
+  //
+  // Nodes are split before they take up more than `max_node_size`
+  // bytes. That bounds how big an entry may be (see `max_entry_size`).
+  // If it is less than `MIN_NODE_SIZE`, we fail with
+  // `Error::NodeSizeTooSmall`.
+  pub fn new(max_node_size: usize) -> Result<BTree<K, V>> {
+    check_max_node_size(max_node_size)?;
+
+    Ok(BTree::with_empty_root(
+      BufferPool::new(
+        Box::new(MemoryNodeStore::new()),
+        None,
+        usize::MAX,
+      ),
+      None,
+      max_node_size,
+    ))
+  }
+
+  // Opens the BTree stored in the data file at `path`, creating it if
+  // need be. The log lives next to it, with `.wal` on the end.
+  //
+  // A new data file is made with pages of `page_size` bytes, and each
+  // node fits in a page. An existing file keeps its page size. Pages
+  // too small for a node of `MIN_NODE_SIZE` bytes are refused with
+  // `Error::NodeSizeTooSmall`, before the file is made.
+  pub fn open<P: AsRef<Path>>(
+    path: P,
+    page_size: usize,
+  ) -> Result<BTree<K, V>> {
+    let mut wal_path = path.as_ref().as_os_str().to_owned();
+    wal_path.push(".wal");
+
+    let node_store =
+      PageFileNodeStore::open_with_page_size(path, page_size)?;
+    let wal = WriteAheadLog::open(wal_path)?;
+    // Nodes are as big as the file's pages allow.
+    BTree::with_node_store(
+      Box::new(node_store),
+      Some(wal),
+      usize::MAX,
+      DEFAULT_BUFFER_POOL_CAPACITY,
+    )
+  }
+
+  // If the NodeStore is brand new, we write an empty tree to it. The
+  // BufferPool keeps at most `buffer_pool_capacity` nodes in memory.
+  // Nodes split before they get bigger than `max_node_size`, or than
+  // the NodeStore can write. If that is less than `MIN_NODE_SIZE`, we
+  // fail with `Error::NodeSizeTooSmall`.
+  //
+  // If there is a WriteAheadLog, we recover from it before returning.
+  pub fn with_node_store(
+    node_store: Box<dyn NodeStore>,
+    wal: Option<WriteAheadLog>,
+    max_node_size: usize,
+    buffer_pool_capacity: usize,
+  ) -> Result<BTree<K, V>> {
+    let max_node_size = match node_store.max_node_size() {
+      Some(limit) => max_node_size.min(limit),
+      None => max_node_size,
+    };
+    check_max_node_size(max_node_size)?;
+
+    let root_identifier = node_store.read_root_identifier()?;
+    let wal = wal.map(Arc::new);
+    if let Some(wal) = &wal {
+      wal.start_background_flushing(ASYNC_COMMIT_INTERVAL);
+    }
+    let buffer_pool =
+      BufferPool::new(node_store, wal.clone(), buffer_pool_capacity);
+
+    let btree = match root_identifier {
+      Some(root_identifier) => BTree {
+        root_identifier_lock: RwLock::new(root_identifier),
+        buffer_pool: Arc::new(buffer_pool),
+        max_node_size,
+        split_policy: SplitPolicy::default(),
+        lock_manager: Arc::new(LockManager::new()),
+        key_lock_table: KeyLockTable::new(),
+        root_identifier_is_dirty: AtomicBool::new(false),
+        wal,
+        running_transaction_lsns: Mutex::new(HashMap::new()),
+        unfinished_split_lsns: Mutex::new(HashMap::new()),
+        checkpoint_log_size: DEFAULT_CHECKPOINT_LOG_SIZE,
+        log_size_at_checkpoint: AtomicU64::new(0),
+        is_checkpointing: AtomicBool::new(false),
+        retired_nodes: Mutex::new(HashMap::new()),
+        retired_nodes_first_unaware: AtomicU64::new(0),
+        version_store: VersionStore::new(),
+        is_closed: false,
+      },
+
+      None => {
+        let btree =
+          BTree::with_empty_root(buffer_pool, wal, max_node_size);
+        btree.flush()?;
+        btree
+      }
+    };
+
+    btree.recover()
+  }
+
+  fn with_empty_root(
+    buffer_pool: BufferPool<K, V>,
+    wal: Option<Arc<WriteAheadLog>>,
+    max_node_size: usize,
+  ) -> BTree<K, V> {
     // First we make a BTree with a bogus root.
     let btree = BTree {
-      // Default root identifier is "" which is bogus.
-      root_identifier_lock: RwLock::default(),
-      identifier_to_node_arc_lock_map: RwLock::default(),
-      max_key_capacity,
+      // No node has identifier zero, so this is bogus.
+      root_identifier_lock: RwLock::new(0),
+      buffer_pool: Arc::new(buffer_pool),
+      max_node_size,
+      split_policy: SplitPolicy::default(),
+      lock_manager: Arc::new(LockManager::new()),
+      key_lock_table: KeyLockTable::new(),
+      root_identifier_is_dirty: AtomicBool::new(true),
+      wal,
+      running_transaction_lsns: Mutex::new(HashMap::new()),
+      unfinished_split_lsns: Mutex::new(HashMap::new()),
+      checkpoint_log_size: DEFAULT_CHECKPOINT_LOG_SIZE,
+      log_size_at_checkpoint: AtomicU64::new(0),
+      is_checkpointing: AtomicBool::new(false),
+      retired_nodes: Mutex::new(HashMap::new()),
+      retired_nodes_first_unaware: AtomicU64::new(0),
+      version_store: VersionStore::new(),
+      is_closed: false,
     };
 
     // Then we do create an empty leaf node for the root.
@@ -42,28 +234,86 @@ impl BTree {
     btree
   }
 
-  pub fn get_node_arc_lock(
+  pub fn begin(
+    self: &Arc<Self>,
+    tx_mode: TransactionMode,
+  ) -> Transaction<K, V> {
+    Transaction::new(self, tx_mode)
+  }
+
+  // The node stays in the BufferPool for as long as you hold the pin.
+  pub fn pin_node(
     &self,
-    identifier: &str,
-  ) -> Arc<RwLock<Node>> {
-    let identifier_to_nodes_map =
-      self.identifier_to_node_arc_lock_map.read();
-
-    let node_lock_option = identifier_to_nodes_map.get(identifier);
-
-    match node_lock_option {
-      Some(node_lock) => Arc::clone(node_lock),
-      // The theory is that the Arc<RwLock<Node>> might not be here
-      // because it is paged onto the disk.
-      None => panic!("Eventually should fetch from disk."),
-    }
+    identifier: NodeIdentifier,
+  ) -> Result<NodePin<K, V>> {
+    self.buffer_pool.pin(identifier)
   }
 
-  pub fn max_key_capacity(&self) -> usize {
-    self.max_key_capacity
+  pub fn lock_manager(&self) -> &Arc<LockManager> {
+    &self.lock_manager
   }
 
-  pub fn root_identifier_lock(&self) -> &RwLock<String> {
+  pub fn key_lock_table(&self) -> &KeyLockTable<K> {
+    &self.key_lock_table
+  }
+
+  pub fn version_store(&self) -> &VersionStore<K, V> {
+    &self.version_store
+  }
+
+  pub fn max_node_size(&self) -> usize {
+    self.max_node_size
+  }
+
+  // The most bytes a key and its value may take up together. Inserting
+  // a bigger entry fails with `Error::EntryTooLarge`.
+  pub fn max_entry_size(&self) -> usize {
+    self.max_node_size / MIN_ENTRIES_PER_NODE
+  }
+
+  pub fn split_policy(&self) -> SplitPolicy {
+    self.split_policy
+  }
+
+  // The policy isn't stored in the NodeStore, so set it again each time
+  // you open the BTree.
+  pub fn set_split_policy(&mut self, split_policy: SplitPolicy) {
+    self.split_policy = split_policy;
+  }
+
+  // A transaction that commits after the log has grown by this many
+  // bytes since the last checkpoint checkpoints again. (See
+  // `checkpoint`.) Like the split policy, this isn't stored.
+  pub fn set_checkpoint_log_size(&mut self, checkpoint_log_size: u64) {
+    self.checkpoint_log_size = checkpoint_log_size;
+  }
+
+  pub fn root_identifier_lock(&self) -> &RwLock<NodeIdentifier> {
     &self.root_identifier_lock
   }
 }
+
+fn check_max_node_size(max_node_size: usize) -> Result<()> {
+  if max_node_size < MIN_NODE_SIZE {
+    return Err(Error::NodeSizeTooSmall(max_node_size));
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use constants::MIN_NODE_SIZE;
+  use error::Error;
+
+  #[test]
+  fn new_refuses_tiny_nodes() {
+    let result = BTree::<String, String>::new(MIN_NODE_SIZE - 1);
+    assert_eq!(
+      result.err(),
+      Some(Error::NodeSizeTooSmall(MIN_NODE_SIZE - 1)),
+    );
+    assert!(BTree::<String, String>::new(MIN_NODE_SIZE).is_ok());
+  }
+}
@@ -0,0 +1,493 @@
+use btree::BTree;
+use error::{Error, Result};
+use key::{Key, Value};
+use node::{
+  entry_capacity, ComparisonValue, InteriorNode, LeafNode, Node,
+};
+use std::cmp::Ordering;
+use std::mem;
+use storage::NodeIdentifier;
+
+// Each level is built left to right, as a list of the nodes' identifiers
+// and max values. The rightmost node of a level has max value infinity,
+// and every other node links to the node after it.
+type Level<K> = Vec<(NodeIdentifier, ComparisonValue<K>)>;
+
+// Bulk loading builds the tree bottom-up: first the leaves, straight
+// from the entries, then each level of interior nodes from the level
+// below, until a level has just one node. That node is the new root.
+//
+// Nothing is logged. Instead we checkpoint before we start (so that no
+// old log record can be redone onto the new nodes), and write the new
+// nodes back before the new root identifier. A crash before
+// `bulk_load` returns leaves the tree empty.
+impl<K: Key, V: Value> BTree<K, V> {
+  // The entries must be sorted by key, with no duplicates, and the tree
+  // must be empty. Nodes are filled to `fill_factor` of the bytes they
+  // can hold, which must be in (0, 1]. A fill factor below one leaves
+  // room for later inserts without immediate splits.
+  pub fn bulk_load<I>(
+    &mut self,
+    entries: I,
+    fill_factor: f64,
+  ) -> Result<()>
+  where
+    I: IntoIterator<Item = (K, V)>,
+  {
+    if !(fill_factor > 0.0 && fill_factor <= 1.0) {
+      return Err(Error::InvalidBulkLoad(
+        "fill factor must be in (0, 1]",
+      ));
+    }
+
+    let old_root_identifier = *self.root_identifier_lock.read();
+    {
+      let pin = self.pin_node(old_root_identifier)?;
+      let node = pin.node().read();
+      match &*node {
+        Node::LeafNode(leaf_node) if leaf_node.is_empty() => {}
+        _ => return Err(Error::InvalidBulkLoad("tree is not empty")),
+      }
+    }
+
+    self.checkpoint()?;
+
+    let mut level = self.load_leaves(entries, fill_factor)?;
+    if level.is_empty() {
+      return Ok(());
+    }
+    while level.len() > 1 {
+      level = self.load_interior_level(level, fill_factor)?;
+    }
+
+    // The new nodes must be in the NodeStore before anything points to
+    // them.
+    self.buffer_pool.flush()?;
+    self.buffer_pool.node_store().sync()?;
+
+    *self.root_identifier_lock.write() = level[0].0;
+    self.mark_root_identifier_dirty();
+    self.free_node(old_root_identifier);
+
+    self.checkpoint()
+  }
+
+  // We can't store a leaf until we know the identifier of the leaf after
+  // it, so we only store a leaf once the entry that doesn't fit in it
+  // arrives.
+  //
+  // A leaf is filled by the bytes it will take up in the NodeStore,
+  // with the prefix its keys share written only once. Its max value is
+  // the separator between its last key and the next leaf's first, so we
+  // only know that size once we store it. If the separator makes the
+  // leaf too big after all, its last entries go to the next leaf.
+  fn load_leaves<I>(
+    &self,
+    entries: I,
+    fill_factor: f64,
+  ) -> Result<Level<K>>
+  where
+    I: IntoIterator<Item = (K, V)>,
+  {
+    let target_size =
+      (self.max_node_size() as f64 * fill_factor) as usize;
+
+    let mut leaves = vec![];
+    let mut identifier = self.get_new_identifier();
+    let mut keys = vec![];
+    let mut values = vec![];
+
+    for (key, value) in entries {
+      if let Err(error) =
+        self.check_loaded_entry(&key, &value, keys.last())
+      {
+        self.free_node(identifier);
+        for (identifier, _) in leaves {
+          self.free_node(identifier);
+        }
+        return Err(error);
+      }
+
+      keys.push(key);
+      values.push(value);
+      let leaf_size = LeafNode::encoded_size_of(
+        &keys,
+        &values,
+        ComparisonValue::Infinity,
+      );
+      if keys.len() == 1 || leaf_size <= target_size {
+        continue;
+      }
+
+      // The new entry starts the next leaf.
+      let split_idx = self.fitting_leaf_len(&keys, &values)?;
+      let next_keys = keys.split_off(split_idx);
+      let next_values = values.split_off(split_idx);
+      let next_identifier = self.get_new_identifier();
+      let max_value = ComparisonValue::DefiniteValue(K::separator(
+        &keys[split_idx - 1],
+        &next_keys[0],
+      ));
+      LeafNode::store_as(
+        self,
+        identifier,
+        mem::replace(&mut keys, next_keys),
+        mem::replace(&mut values, next_values),
+        max_value.clone(),
+        Some(next_identifier),
+      );
+      leaves.push((identifier, max_value));
+      identifier = next_identifier;
+    }
+
+    if keys.is_empty() {
+      self.free_node(identifier);
+      return Ok(leaves);
+    }
+
+    LeafNode::store_as(
+      self,
+      identifier,
+      keys,
+      values,
+      ComparisonValue::Infinity,
+      None,
+    );
+    leaves.push((identifier, ComparisonValue::Infinity));
+
+    Ok(leaves)
+  }
+
+  // All but the last of the entries are to make up a leaf. Returns how
+  // many of them fit, with the separator from the next entry as the
+  // max value. One always does.
+  fn fitting_leaf_len(
+    &self,
+    keys: &[K],
+    values: &[V],
+  ) -> Result<usize> {
+    for leaf_len in (1..keys.len()).rev() {
+      let separator =
+        K::separator(&keys[leaf_len - 1], &keys[leaf_len]);
+      let leaf_size = LeafNode::encoded_size_of(
+        &keys[..leaf_len],
+        &values[..leaf_len],
+        ComparisonValue::DefiniteValue(&separator),
+      );
+      if leaf_size <= self.max_node_size() {
+        return Ok(leaf_len);
+      }
+    }
+
+    Err(Error::InvariantViolation("a leaf fits one entry"))
+  }
+
+  fn check_loaded_entry(
+    &self,
+    key: &K,
+    value: &V,
+    previous_key: Option<&K>,
+  ) -> Result<()> {
+    let entry_size = LeafNode::entry_size(key, value);
+    if entry_size > self.max_entry_size() {
+      return Err(Error::EntryTooLarge(entry_size));
+    }
+
+    match previous_key {
+      Some(previous_key)
+        if key.compare(previous_key) != Ordering::Greater =>
+      {
+        Err(Error::InvalidBulkLoad(
+          "entries are not sorted by key, or repeat a key",
+        ))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  // The children are spread as evenly as we can over the fewest nodes
+  // that hold them. Each node gets at least two children, so that every
+  // level is smaller than the one below. Should the last child be left
+  // on its own, the node before takes it too. That fits, since
+  // `entry_capacity` leaves room for one more entry.
+  fn load_interior_level(
+    &self,
+    children: Level<K>,
+    fill_factor: f64,
+  ) -> Result<Level<K>> {
+    // Each child but the first comes with the max value of the child
+    // before it, as a split key.
+    let mut total_size = 0;
+    for (_, max_value) in &children[..children.len() - 1] {
+      total_size += InteriorNode::entry_size(split_key(max_value)?);
+    }
+    let num_nodes = total_size
+      .div_ceil(self.loaded_entries_size(fill_factor))
+      .max(1);
+    let target_size = total_size.div_ceil(num_nodes);
+
+    let mut nodes = vec![];
+    let mut identifier = self.get_new_identifier();
+    let mut splits = vec![];
+    let mut child_identifiers = vec![];
+    let mut entries_size = 0;
+    let mut max_value = None;
+    let num_children = children.len();
+
+    for (idx, (child_identifier, child_max_value)) in
+      children.into_iter().enumerate()
+    {
+      if let Some(previous_max_value) =
+        max_value.replace(child_max_value)
+      {
+        let split = split_key(&previous_max_value)?.clone();
+        let entry_size = InteriorNode::entry_size(&split);
+        let is_last_child = idx + 1 == num_children;
+
+        if child_identifiers.len() >= 2
+          && !is_last_child
+          && entries_size + entry_size > target_size
+        {
+          // The previous child is this node's last, so its max value is
+          // ours.
+          let next_identifier = self.get_new_identifier();
+          InteriorNode::store_as(
+            self,
+            identifier,
+            mem::take(&mut splits),
+            mem::take(&mut child_identifiers),
+            previous_max_value.clone(),
+            Some(next_identifier),
+          );
+          nodes.push((identifier, previous_max_value));
+          identifier = next_identifier;
+          entries_size = 0;
+        } else {
+          splits.push(split);
+          entries_size += entry_size;
+        }
+      }
+      child_identifiers.push(child_identifier);
+    }
+
+    let max_value =
+      max_value.ok_or(Error::InvariantViolation("a level has nodes"))?;
+    InteriorNode::store_as(
+      self,
+      identifier,
+      splits,
+      child_identifiers,
+      max_value.clone(),
+      None,
+    );
+    nodes.push((identifier, max_value));
+
+    Ok(nodes)
+  }
+
+  // How many bytes of split keys a loaded interior node gets. Unlike
+  // leaves, these go by sizes before prefix compression, which leaves a
+  // little room spare. However small the fill factor, a node gets at
+  // least two children.
+  fn loaded_entries_size(&self, fill_factor: f64) -> usize {
+    let entries_size =
+      entry_capacity(self.max_node_size()) as f64 * fill_factor;
+    (entries_size as usize).max(1)
+  }
+
+  fn free_node(&self, identifier: NodeIdentifier) {
+    self.buffer_pool.discard(identifier);
+    self.buffer_pool.node_store().free_identifier(identifier);
+  }
+}
+
+// Only the rightmost node of a level has an indefinite max value, and it
+// is never followed by another child.
+fn split_key<K: Key>(max_value: &ComparisonValue<K>) -> Result<&K> {
+  match max_value {
+    ComparisonValue::DefiniteValue(split) => Ok(split),
+    _ => Err(Error::InvariantViolation(
+      "only the rightmost node is unbounded",
+    )),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use error::Error;
+  use node::Node;
+  use std::fs;
+  use std::path::Path;
+  use std::sync::Arc;
+  use test_util::{data_path, key, remove_files};
+  use transaction::TransactionMode;
+
+  const PAGE_SIZE: usize = 512;
+
+  // Returns how many pages the data file takes up, once closed.
+  fn num_pages(btree: BTree<String, String>, path: &Path) -> u64 {
+    btree.close().unwrap();
+    fs::metadata(path).unwrap().len() / PAGE_SIZE as u64
+  }
+
+  fn entries(num_entries: usize) -> Vec<(String, String)> {
+    (0..num_entries)
+      .map(|idx| (key(idx), format!("value {}", idx)))
+      .collect()
+  }
+
+  fn contents(
+    btree: &Arc<BTree<String, String>>,
+  ) -> Vec<(String, String)> {
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let range = txn.range(..).unwrap();
+    range.map(|entry| entry.unwrap()).collect()
+  }
+
+  // The fewest children any interior node has.
+  fn min_num_children(btree: &BTree<String, String>) -> Option<usize> {
+    let mut min_num_children = None;
+    let mut level_start = *btree.root_identifier_lock().read();
+    loop {
+      let mut identifier = Some(level_start);
+      while let Some(current_identifier) = identifier {
+        let pin = btree.pin_node(current_identifier).unwrap();
+        let node = pin.node().read();
+        let interior_node = match &*node {
+          Node::LeafNode(_) => return min_num_children,
+          Node::InteriorNode(interior_node) => interior_node,
+        };
+        let num_children = interior_node.num_children();
+        min_num_children = Some(
+          min_num_children.map_or(num_children, |min: usize| {
+            min.min(num_children)
+          }),
+        );
+        if current_identifier == level_start {
+          level_start = interior_node.child_identifier_by_idx(0);
+        }
+        identifier = interior_node.next_node_identifier();
+      }
+    }
+  }
+
+  // Loads the entries, and checks that the tree holds just those, and
+  // still takes inserts.
+  fn check_bulk_load(num_entries: usize, fill_factor: f64) {
+    let mut btree = BTree::new(PAGE_SIZE).unwrap();
+    btree.bulk_load(entries(num_entries), fill_factor).unwrap();
+    let btree = Arc::new(btree);
+    btree.validate().unwrap();
+    assert_eq!(contents(&btree), entries(num_entries));
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in num_entries..(num_entries + 100) {
+      txn.insert(&key(idx), format!("value {}", idx)).unwrap();
+    }
+    txn.commit().unwrap();
+    btree.validate().unwrap();
+    assert_eq!(contents(&btree), entries(num_entries + 100));
+  }
+
+  #[test]
+  fn loads_a_single_entry() {
+    check_bulk_load(1, 1.0);
+  }
+
+  #[test]
+  fn loads_full_nodes() {
+    check_bulk_load(2000, 1.0);
+  }
+
+  // Every leaf gets one entry, and every interior node two children.
+  // With an odd number of children, the last node of the level takes
+  // three.
+  #[test]
+  fn loads_with_a_tiny_fill_factor() {
+    for num_entries in &[100, 101] {
+      let mut btree = BTree::new(PAGE_SIZE).unwrap();
+      btree.bulk_load(entries(*num_entries), 1e-6).unwrap();
+      assert_eq!(min_num_children(&btree), Some(2));
+
+      check_bulk_load(*num_entries, 1e-6);
+    }
+  }
+
+  #[test]
+  fn refuses_fill_factors_out_of_range() {
+    for fill_factor in &[0.0, -0.5, 1.5, f64::NAN] {
+      let mut btree = BTree::<String, String>::new(PAGE_SIZE).unwrap();
+      assert!(matches!(
+        btree.bulk_load(entries(10), *fill_factor),
+        Err(Error::InvalidBulkLoad(_)),
+      ));
+    }
+  }
+
+  // A failed load leaves the tree empty, so it can be loaded again.
+  #[test]
+  fn refuses_unsorted_and_repeated_keys() {
+    let mut unsorted = entries(500);
+    unsorted.swap(200, 300);
+    let mut repeated = entries(500);
+    repeated[300].0 = key(299);
+
+    for bad_entries in [unsorted, repeated] {
+      let mut btree = BTree::new(PAGE_SIZE).unwrap();
+      assert!(matches!(
+        btree.bulk_load(bad_entries, 1.0),
+        Err(Error::InvalidBulkLoad(_)),
+      ));
+
+      btree.bulk_load(entries(500), 1.0).unwrap();
+      let btree = Arc::new(btree);
+      btree.validate().unwrap();
+      assert_eq!(contents(&btree), entries(500));
+    }
+  }
+
+  #[test]
+  fn refuses_a_tree_that_is_not_empty() {
+    let mut btree = BTree::new(PAGE_SIZE).unwrap();
+    btree.bulk_load(entries(10), 1.0).unwrap();
+    assert_eq!(
+      btree.bulk_load(entries(10), 1.0).err(),
+      Some(Error::InvalidBulkLoad("tree is not empty")),
+    );
+  }
+
+  // Leaves are sized as they are written, with their keys' prefix
+  // written once. So a full bulk load takes no more pages than
+  // inserting the same keys one by one.
+  #[test]
+  fn loads_leaves_as_tightly_as_inserts_do() {
+    let entries: Vec<(String, String)> = (0..20_000)
+      .map(|idx| (format!("key{:08}", idx), String::from("v")))
+      .collect();
+
+    let path = data_path("bulk-load-pages");
+    let mut btree = BTree::open(&path, PAGE_SIZE).unwrap();
+    btree.bulk_load(entries.clone(), 1.0).unwrap();
+    let bulk_loaded_pages = num_pages(btree, &path);
+
+    let btree = BTree::open(&path, PAGE_SIZE).unwrap();
+    let btree = Arc::new(btree);
+    btree.validate().unwrap();
+    assert_eq!(contents(&btree), entries);
+    drop(btree);
+    remove_files(&path);
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for (key, value) in &entries {
+      txn.insert(key, value.clone()).unwrap();
+    }
+    txn.commit().unwrap();
+    let btree = Arc::try_unwrap(btree).ok().unwrap();
+    let inserted_pages = num_pages(btree, &path);
+    remove_files(&path);
+
+    assert!(bulk_loaded_pages <= inserted_pages);
+  }
+}
@@ -0,0 +1,98 @@
+use btree::insertion::{descend_toward_key, DescentDecision};
+use btree::BTree;
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::LockSet;
+use node::DeletionResult;
+use transaction::TransactionMode;
+
+// `on_leaf_change` is called with the deleted value as soon as the leaf
+// has changed.
+pub fn delete<K: Key, V: Value, F>(
+  btree: &BTree<K, V>,
+  lock_set: &mut LockSet<K, V>,
+  key_to_delete: &K,
+  on_leaf_change: F,
+) -> Result<Option<V>>
+where
+  F: FnOnce(&V),
+{
+  // We may find the key missing without ever asking for a write guard.
+  // That must fail all the same.
+  if lock_set.tx_mode() != TransactionMode::ReadWrite {
+    return Err(Error::ReadOnlyTransaction);
+  }
+
+  let leaf_guard = loop {
+    // Build a path to the leaf where the key should live.
+    let mut delete_path =
+      descend_toward_key(lock_set, key_to_delete, |_| {
+        DescentDecision::ContinueDescending
+      })?;
+
+    // Got to the leaf. The key may not be there, so we only read the
+    // leaf to begin with. Just like when inserting, the leaf may have
+    // split in the meantime.
+    let leaf_entry = delete_path.pop().ok_or(
+      Error::InvariantViolation("delete_path must never be empty"),
+    )?;
+    let leaf_guard = BTree::scan_right_for_read_guard(
+      lock_set,
+      leaf_entry.current_node_identifier(),
+      key_to_delete,
+    )?;
+
+    // As with an insert, we keep the guard only for this delete, and
+    // hold the lock on the key instead.
+    let (lock_wait, is_present) = {
+      let leaf_node = leaf_guard
+        .unwrap_leaf_node_ref("final node is always LeafNode")?;
+      let lock_wait = BTree::lock_key_for_delete(
+        lock_set,
+        &leaf_node,
+        key_to_delete,
+      )?;
+      (lock_wait, leaf_node.contains_key(key_to_delete))
+    };
+    match lock_wait {
+      // Deleting a missing key changes nothing, so we never write to
+      // the leaf.
+      None if !is_present => return Ok(None),
+      // Otherwise we upgrade our guard on the leaf. That can't fail to
+      // find the leaf as we read it, since no one else can change it
+      // while we hold the guard.
+      None => {
+        let leaf_identifier =
+          leaf_guard.unwrap_node_ref()?.identifier();
+        let leaf_write_guard =
+          lock_set.node_write_guard(leaf_identifier)?;
+        drop(leaf_guard);
+        break leaf_write_guard;
+      }
+      Some(lock_wait) => {
+        drop(leaf_guard);
+        lock_wait.wait(lock_set)?;
+      }
+    }
+  };
+
+  // Perform the deletion. Even if the leaf is left empty, we don't
+  // unlink it now. Its right sibbling would take over its key range
+  // while our delete is uncommitted. The Transaction reclaims it once
+  // it commits.
+  let mut leaf_node = leaf_guard
+    .unwrap_leaf_node_mut_ref("final node is always LeafNode")?;
+  btree.log_leaf_delete(lock_set, &mut leaf_node, key_to_delete)?;
+
+  let deleted_value = match leaf_node.delete(key_to_delete) {
+    DeletionResult::KeyWasNotPresent => return Ok(None),
+    DeletionResult::DidDelete(deleted_value) => deleted_value,
+  };
+  on_leaf_change(&deleted_value);
+  btree.version_leaf_change(
+    lock_set,
+    key_to_delete,
+    Some(&deleted_value),
+  );
+  Ok(Some(deleted_value))
+}
@@ -0,0 +1,132 @@
+use btree::BTree;
+use key::{Key, Value};
+use std::sync::atomic::Ordering;
+use storage::NodeIdentifier;
+use transaction::TransactionId;
+use wal::Lsn;
+
+// A retired node can only be freed once nothing refers to it. Its
+// parent let go of it and its left sibbling linked past it when it was
+// retired. But a transaction that was running then may have read its
+// identifier before, and may still arrive at it.
+//
+// So each retired node remembers the first transaction id handed out
+// after it was retired. Once every transaction with a smaller id has
+// ended, nobody can arrive any more. Each LockSet checks when it ends.
+//
+// The log may still hold changes to a freed node. Recovery copes with
+// that (see `recovery.rs`).
+impl<K: Key, V: Value> BTree<K, V> {
+  // Transactions end all the time, and almost never free anything. So
+  // we first check, without taking any lock, whether the transaction
+  // was old enough to have kept some retired node from being freed.
+  pub fn free_retired_nodes_after(
+    &self,
+    transaction_id: TransactionId,
+  ) {
+    let first_unaware_transaction =
+      self.retired_nodes_first_unaware.load(Ordering::SeqCst);
+    if transaction_id < first_unaware_transaction {
+      self.free_retired_nodes();
+    }
+  }
+
+  // Hands the identifiers of the retired nodes nobody can reach back to
+  // the NodeStore.
+  pub fn free_retired_nodes(&self) {
+    let mut retired_nodes = self.retired_nodes.lock();
+    if retired_nodes.is_empty() {
+      return;
+    }
+
+    let oldest_running_transaction =
+      self.lock_manager().oldest_running_transaction();
+    let node_store = self.buffer_pool.node_store();
+    retired_nodes.retain(|identifier, (first_unaware_transaction, _)| {
+      let is_unreachable = match oldest_running_transaction {
+        None => true,
+        Some(oldest) => *first_unaware_transaction <= oldest,
+      };
+      if is_unreachable {
+        self.buffer_pool.discard(*identifier);
+        node_store.free_identifier(*identifier);
+      }
+      !is_unreachable
+    });
+
+    let first_unaware_transaction = retired_nodes
+      .values()
+      .map(|(first_unaware_transaction, _)| *first_unaware_transaction)
+      .max()
+      .unwrap_or(0);
+    self
+      .retired_nodes_first_unaware
+      .store(first_unaware_transaction, Ordering::SeqCst);
+  }
+
+  // Transactions that begin from now on can't reach the node. Until it
+  // is freed, a checkpoint keeps the record at `lsn` that retired it.
+  // (Otherwise, if we crashed, nothing would tell recovery to free it.)
+  pub fn add_retired_node(&self, identifier: NodeIdentifier, lsn: Lsn) {
+    let first_unaware_transaction =
+      self.lock_manager().next_transaction_id();
+    let mut retired_nodes = self.retired_nodes.lock();
+    retired_nodes.insert(identifier, (first_unaware_transaction, lsn));
+    self
+      .retired_nodes_first_unaware
+      .fetch_max(first_unaware_transaction, Ordering::SeqCst);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use std::fs;
+  use std::sync::Arc;
+  use test_util::{data_path, key, remove_files};
+  use transaction::{Commit, TransactionMode};
+
+  const PAGE_SIZE: usize = 512;
+
+  // A window of live keys slides along: each new key pushes out the
+  // oldest. The pages of the leaves left behind must be reused, so the
+  // data file stops growing.
+  #[test]
+  fn freed_pages_are_reused() {
+    const NUM_LIVE_KEYS: usize = 500;
+    const NUM_ROUNDS: usize = 10;
+    const KEYS_PER_ROUND: usize = 2000;
+    let path = data_path("freed-pages-are-reused");
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let mut file_sizes = vec![];
+    let mut next_idx = 0;
+    for _ in 0..NUM_ROUNDS {
+      for _ in 0..KEYS_PER_ROUND / 100 {
+        let mut txn = btree.begin(TransactionMode::ReadWrite);
+        txn.set_commit(Commit::None);
+        for _ in 0..100 {
+          let value = format!("value {}", next_idx);
+          txn.insert(&key(next_idx), value).unwrap();
+          if next_idx >= NUM_LIVE_KEYS {
+            txn.delete(&key(next_idx - NUM_LIVE_KEYS)).unwrap();
+          }
+          next_idx += 1;
+        }
+        txn.commit().unwrap();
+      }
+      btree.flush().unwrap();
+      file_sizes.push(fs::metadata(&path).unwrap().len());
+    }
+
+    btree.validate().unwrap();
+    // The first round fills the window; after that, the file only grows
+    // by the odd page.
+    let first_size = file_sizes[0];
+    let last_size = file_sizes[NUM_ROUNDS - 1];
+    assert!(last_size <= first_size + first_size / 4);
+
+    drop(btree);
+    remove_files(&path);
+  }
+}
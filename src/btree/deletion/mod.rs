@@ -0,0 +1,47 @@
+mod delete;
+mod free_retired_nodes;
+mod reclaim_leaf_if_empty;
+
+pub(self) use self::reclaim_leaf_if_empty::*;
+
+use btree::BTree;
+use error::Result;
+use key::{Key, Value};
+use locking::LockSet;
+use std::sync::Arc;
+
+impl<K: Key, V: Value> BTree<K, V> {
+  // Deletion never has to store new nodes, but it does log its changes.
+  // Returns the deleted value, if the key was present.
+  pub(crate) fn delete(
+    btree: &Arc<BTree<K, V>>,
+    lock_set: &mut LockSet<K, V>,
+    key_to_delete: &K,
+  ) -> Result<Option<V>> {
+    delete::delete(btree, lock_set, key_to_delete, |_| ())
+  }
+
+  // Like `delete`, but calls `on_leaf_change` with the deleted value as
+  // soon as the leaf has changed.
+  pub(crate) fn delete_reporting_change<F>(
+    btree: &Arc<BTree<K, V>>,
+    lock_set: &mut LockSet<K, V>,
+    key_to_delete: &K,
+    on_leaf_change: F,
+  ) -> Result<Option<V>>
+  where
+    F: FnOnce(&V),
+  {
+    delete::delete(btree, lock_set, key_to_delete, on_leaf_change)
+  }
+
+  // Called after a Transaction that deleted `key` commits. If that left
+  // the key's leaf empty, we unlink the leaf from its parent.
+  pub(crate) fn reclaim_leaf_if_empty(
+    btree: &Arc<BTree<K, V>>,
+    lock_set: &mut LockSet<K, V>,
+    key: &K,
+  ) -> Result<()> {
+    reclaim_leaf_if_empty(btree, lock_set, key)
+  }
+}
@@ -0,0 +1,425 @@
+use btree::insertion::{
+  descend_toward_key, scan_right_for_write_guard, DescentDecision,
+};
+use btree::scanning::find_leaf_with_lower_bound;
+use btree::BTree;
+use error::Result;
+use key::{Key, Value};
+use locking::{LockSet, LockSetNodeWriteGuard};
+use node::{ComparisonValue, Node};
+use storage::NodeIdentifier;
+
+// We use a "free-at-empty" strategy for deletion. A leaf is left alone
+// until it becomes entirely empty. At that point, we remove it from its
+// parent, so that its right sibbling takes over its key range. In the
+// same breath, its left sibbling links straight past it.
+//
+// If the leaf is its parent's only child, the parent can't give it up.
+// But then the parent is as empty as the leaf: we remove the parent
+// from the grandparent instead, along with the leaf. That goes on up
+// the tree for as long as each node is its parent's only child. So an
+// interior node never outlives its last child.
+//
+// The removed nodes are *retired* rather than destroyed. Other
+// transactions may have read their identifiers before we unlinked
+// them. When they arrive, a retired node sends them right to the
+// sibbling that took over. Transactions that begin from now on can't
+// reach it, so it is freed once every transaction running now has
+// ended (see `free_retired_nodes.rs`).
+//
+// We only reclaim a leaf once the transaction that emptied it has
+// committed. Until then, the leaf must keep its key range: if the
+// transaction rolls back, the keys go back where they were, and until
+// it commits, no one else may insert into the range it emptied.
+//
+// This is lazy on purpose: we give up (and leave an empty leaf) rather
+// than wait on anything other than the latches we need. That leaves
+// these limits:
+//
+// * A leaf that someone filled again in the meantime is left alone.
+// * A node that is its parent's rightmost child can only be removed if
+//   the split for its parent is in the grandparent. (It isn't if the
+//   parent is the grandparent's rightmost child too.)
+// * The rightmost node of each level has no right sibbling to take
+//   over. So the tree keeps one node per level, however empty.
+// * If the lower bound we learn for the leaf is stale, we can't find
+//   its left sibbling and give up.
+//
+// Latches are taken bottom-up, and left to right within a level, like
+// everywhere else.
+pub fn reclaim_leaf_if_empty<K: Key, V: Value>(
+  btree: &BTree<K, V>,
+  lock_set: &mut LockSet<K, V>,
+  key: &K,
+) -> Result<()> {
+  // Find the leaf where the key lived, and the key just below its
+  // range. We only read it for now; the latches we need must be taken
+  // left to right.
+  let (leaf_identifier, lower_bound) = {
+    let (leaf_guard, lower_bound) = find_leaf_with_lower_bound(
+      lock_set,
+      ComparisonValue::DefiniteValue(key),
+    )?;
+    let leaf_node = leaf_guard
+      .unwrap_leaf_node_ref("final node is always LeafNode")?;
+    if !leaf_node.is_empty() {
+      return Ok(());
+    }
+    (leaf_node.identifier(), lower_bound)
+  };
+
+  // The path gives us the ancestors of the leaf, from the leaf up. If
+  // the leaf was (allegedly) the root, there is no parent to unlink it
+  // from. A tree always keeps its root.
+  let ancestor_identifiers: Vec<NodeIdentifier> =
+    descend_toward_key(lock_set, key, |_| {
+      DescentDecision::ContinueDescending
+    })?
+    .iter()
+    .rev()
+    .map(|entry| entry.current_node_identifier())
+    .collect();
+
+  // The nodes we are removing, from the leaf up, each with its left
+  // sibbling. Every one of them has the leaf's key range.
+  let left_guard = match left_sibbling_write_guard(
+    lock_set,
+    &lower_bound,
+    0,
+    leaf_identifier,
+    key,
+  )? {
+    LeftSibbling::NotFound => return Ok(()),
+    LeftSibbling::None => None,
+    LeftSibbling::Found(left_guard) => Some(left_guard),
+  };
+  let leaf_guard = lock_set.node_write_guard(leaf_identifier)?;
+  {
+    let leaf_node = leaf_guard
+      .unwrap_leaf_node_mut_ref("leaves only link to leaves")?;
+    if !leaf_node.is_empty() || leaf_node.is_retired() {
+      return Ok(());
+    }
+  }
+  let mut removed_guards = vec![(left_guard, leaf_guard)];
+
+  let parent_guards = loop {
+    let level = removed_guards.len();
+    let parent_identifier = match ancestor_identifiers.get(level) {
+      None => return Ok(()),
+      Some(parent_identifier) => *parent_identifier,
+    };
+
+    // The parent may have split, so we scan right toward the key, which
+    // every node we're removing is responsible for.
+    let parent_guard =
+      scan_right_for_write_guard(lock_set, parent_identifier, key)?;
+    let child_guard = &removed_guards[level - 1].1;
+    let removal = remove_from_parent(
+      lock_set,
+      &parent_guard,
+      child_guard,
+      ancestor_identifiers.get(level + 1).cloned(),
+      key,
+    )?;
+
+    match removal {
+      Removal::GaveUp => return Ok(()),
+      Removal::Removed(grandparent_guard) => {
+        let mut parent_guards = vec![parent_guard];
+        parent_guards.extend(grandparent_guard);
+        break parent_guards;
+      }
+      Removal::OnlyChild => {}
+    }
+
+    // The parent goes too. Its left sibbling must be latched before it,
+    // so we let go of the parent and start over on this level.
+    let parent_identifier =
+      parent_guard.unwrap_node_ref()?.identifier();
+    drop(parent_guard);
+    let left_guard = match left_sibbling_write_guard(
+      lock_set,
+      &lower_bound,
+      level,
+      parent_identifier,
+      key,
+    )? {
+      LeftSibbling::NotFound => return Ok(()),
+      LeftSibbling::None => None,
+      LeftSibbling::Found(left_guard) => Some(left_guard),
+    };
+    let parent_guard = lock_set.node_write_guard(parent_identifier)?;
+    {
+      let parent_node = parent_guard.unwrap_interior_node_ref(
+        "only interior nodes can be parents",
+      )?;
+      let child_node = child_guard.unwrap_node_ref()?;
+      if !parent_node.has_only_child(&child_node) {
+        return Ok(());
+      }
+    }
+    removed_guards.push((left_guard, parent_guard));
+  };
+
+  for (left_guard, guard) in &removed_guards {
+    let mut node = guard.unwrap_node_mut_ref()?;
+    node.retire()?;
+    if let Some(left_guard) = left_guard {
+      left_guard.unwrap_node_mut_ref()?.unlink_retired_sibbling(&node)?;
+    }
+  }
+
+  let mut node_refs = vec![];
+  for guard in removed_guards
+    .iter()
+    .flat_map(|(left_guard, guard)| {
+      left_guard.iter().chain(Some(guard))
+    })
+    .chain(parent_guards.iter())
+  {
+    node_refs.push(guard.unwrap_node_mut_ref()?);
+  }
+  let mut nodes: Vec<&mut Node<K, V>> =
+    node_refs.iter_mut().map(|node| &mut **node).collect();
+  btree.log_structure_change(&mut nodes, None, None, None)?;
+  drop(nodes);
+  drop(node_refs);
+
+  // We still hold the retired nodes, so none has been written back.
+  for (_, guard) in &removed_guards {
+    let node = guard.unwrap_node_ref()?;
+    btree.add_retired_node(node.identifier(), node.lsn());
+  }
+
+  Ok(())
+}
+
+enum LeftSibbling<K: Key, V: Value> {
+  // The node is the leftmost of its level.
+  None,
+  Found(LockSetNodeWriteGuard<K, V>),
+  NotFound,
+}
+
+// Descends toward the lower bound, and then walks right along the given
+// level (counted up from the leaves) to the node that links to ours. We
+// hold on to each node until we have the next, so that the one we end
+// up with can't have been retired meanwhile.
+//
+// We don't find it before the node that holds the key if our node was
+// reclaimed by someone else, or if the lower bound was stale (see
+// `find_leaf_with_lower_bound`).
+fn left_sibbling_write_guard<K: Key, V: Value>(
+  lock_set: &mut LockSet<K, V>,
+  lower_bound: &ComparisonValue<K>,
+  level: usize,
+  identifier: NodeIdentifier,
+  key: &K,
+) -> Result<LeftSibbling<K, V>> {
+  let lower_bound = match lower_bound {
+    ComparisonValue::DefiniteValue(lower_bound) => lower_bound,
+    _ => return Ok(LeftSibbling::None),
+  };
+
+  let path = descend_toward_key(lock_set, lower_bound, |_| {
+    DescentDecision::ContinueDescending
+  })?;
+  let start_identifier = match path.iter().rev().nth(level) {
+    None => return Ok(LeftSibbling::NotFound),
+    Some(entry) => entry.current_node_identifier(),
+  };
+
+  let mut current_guard = lock_set.node_write_guard(start_identifier)?;
+  loop {
+    let next_identifier = {
+      let current_node = current_guard.unwrap_node_ref()?;
+      if current_node.is_retired() {
+        return Ok(LeftSibbling::NotFound);
+      }
+      if current_node.next_node_identifier() == Some(identifier) {
+        break;
+      }
+      if current_node.max_value().is_ge_to(key) {
+        return Ok(LeftSibbling::NotFound);
+      }
+      match current_node.next_node_identifier() {
+        None => return Ok(LeftSibbling::NotFound),
+        Some(next_identifier) => next_identifier,
+      }
+    };
+
+    current_guard = lock_set.node_write_guard(next_identifier)?;
+  }
+
+  Ok(LeftSibbling::Found(current_guard))
+}
+
+enum Removal<K: Key, V: Value> {
+  // If the child was its parent's rightmost child, the grandparent's
+  // split for the parent came down too, and here is its guard.
+  Removed(Option<LockSetNodeWriteGuard<K, V>>),
+  // The child is all the parent has, so the parent must go instead.
+  OnlyChild,
+  GaveUp,
+}
+
+// The parent decides whether it is safe to unlink the child.
+fn remove_from_parent<K: Key, V: Value>(
+  lock_set: &mut LockSet<K, V>,
+  parent_guard: &LockSetNodeWriteGuard<K, V>,
+  child_guard: &LockSetNodeWriteGuard<K, V>,
+  grandparent_identifier: Option<NodeIdentifier>,
+  key: &K,
+) -> Result<Removal<K, V>> {
+  let new_max_value = {
+    let mut parent_node = parent_guard.unwrap_interior_node_mut_ref(
+      "only interior nodes can be parents",
+    )?;
+    let child_node = child_guard.unwrap_node_ref()?;
+
+    if parent_node.remove_empty_child(&child_node) {
+      return Ok(Removal::Removed(None));
+    }
+    if parent_node.has_only_child(&child_node) {
+      return Ok(Removal::OnlyChild);
+    }
+    match parent_node.max_value_without_last_child(&child_node) {
+      None => return Ok(Removal::GaveUp),
+      Some(new_max_value) => new_max_value.clone(),
+    }
+  };
+
+  // The parent was (allegedly) the root, so its max_value is
+  // indefinite. We can't get here then, but it costs nothing to check.
+  let grandparent_identifier = match grandparent_identifier {
+    None => return Ok(Removal::GaveUp),
+    Some(grandparent_identifier) => grandparent_identifier,
+  };
+  let grandparent_guard =
+    scan_right_for_write_guard(lock_set, grandparent_identifier, key)?;
+
+  {
+    let mut grandparent_node = grandparent_guard
+      .unwrap_interior_node_mut_ref(
+        "only interior nodes can be parents",
+      )?;
+    let mut parent_node = parent_guard
+      .unwrap_interior_node_mut_ref(
+        "only interior nodes can be parents",
+      )?;
+    if !grandparent_node
+      .lower_split_for_child(&parent_node, &new_max_value)
+    {
+      return Ok(Removal::GaveUp);
+    }
+    parent_node.remove_empty_last_child()?;
+  }
+
+  Ok(Removal::Removed(Some(grandparent_guard)))
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use std::sync::Arc;
+  use test_util::{count_nodes, key};
+  use transaction::TransactionMode;
+
+  fn insert_keys(btree: &Arc<BTree<String, String>>, num_keys: usize) {
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..num_keys {
+      txn.insert(&key(idx), format!("value {}", idx)).unwrap();
+    }
+    txn.commit().unwrap();
+  }
+
+  #[test]
+  fn deleting_every_key_leaves_one_node_per_level() {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    insert_keys(&btree, 2000);
+    let (_, num_levels) = count_nodes(&btree);
+    assert!(num_levels >= 3);
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..2000 {
+      assert!(txn.delete(&key(idx)).unwrap().is_some());
+    }
+    txn.commit().unwrap();
+
+    btree.validate().unwrap();
+    assert_eq!(count_nodes(&btree), (num_levels, num_levels));
+    // Nobody else was running, so nothing retired is kept.
+    assert!(btree.retired_nodes.lock().is_empty());
+  }
+
+  // Rolling back the inserts empties the leaves they split off, and
+  // those are reclaimed just the same.
+  #[test]
+  fn aborting_every_insert_leaves_one_node_per_level() {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..2000 {
+      txn.insert(&key(idx), format!("value {}", idx)).unwrap();
+    }
+    let (_, num_levels) = count_nodes(&btree);
+    assert!(num_levels >= 3);
+    txn.abort().unwrap();
+
+    btree.validate().unwrap();
+    assert_eq!(count_nodes(&btree), (num_levels, num_levels));
+    assert!(btree.retired_nodes.lock().is_empty());
+  }
+
+  #[test]
+  fn emptied_subtrees_are_removed() {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    insert_keys(&btree, 2000);
+    let (num_nodes, num_levels) = count_nodes(&btree);
+
+    // Empty out the middle of the tree, a transaction at a time.
+    for start in (500..1500).step_by(100) {
+      let mut txn = btree.begin(TransactionMode::ReadWrite);
+      for idx in start..start + 100 {
+        txn.delete(&key(idx)).unwrap();
+      }
+      txn.commit().unwrap();
+    }
+
+    btree.validate().unwrap();
+    let (num_nodes_left, _) = count_nodes(&btree);
+    // Half the keys are gone, and so are (nearly) half the nodes. Only
+    // the nodes on the edges of the hole may be left over.
+    assert!(num_nodes_left <= num_nodes / 2 + 2 * num_levels);
+
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let keys: Vec<String> = txn
+      .range(..)
+      .unwrap()
+      .map(|entry| entry.unwrap().0)
+      .collect();
+    let expected: Vec<String> =
+      (0..500).chain(1500..2000).map(key).collect();
+    assert_eq!(keys, expected);
+  }
+
+  // A transaction that began before the nodes were retired may still
+  // arrive at them, so they are kept until it ends.
+  #[test]
+  fn retired_nodes_outlive_older_transactions() {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    insert_keys(&btree, 2000);
+
+    let older_txn = btree.begin(TransactionMode::ReadOnly);
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..1000 {
+      txn.delete(&key(idx)).unwrap();
+    }
+    txn.commit().unwrap();
+    assert!(!btree.retired_nodes.lock().is_empty());
+
+    drop(older_txn);
+    assert!(btree.retired_nodes.lock().is_empty());
+    btree.validate().unwrap();
+  }
+}
@@ -1,4 +1,6 @@
 use super::InsertPathEntry;
+use error::{Error, Result};
+use key::{Key, Value};
 use locking::LockSet;
 use node::{Node, TraversalDirection};
 
@@ -9,13 +11,13 @@ pub enum DescentDecision {
 }
 
 // Descends toward the key. We may stop early if instructed.
-pub fn descend_toward_key<F>(
-  lock_set: &mut LockSet,
-  key: &str,
+pub fn descend_toward_key<K: Key, V: Value, F>(
+  lock_set: &mut LockSet<K, V>,
+  key: &K,
   stop_early: F,
-) -> Vec<InsertPathEntry>
+) -> Result<Vec<InsertPathEntry>>
 where
-  F: Fn(&Node) -> DescentDecision,
+  F: Fn(&Node<K, V>) -> DescentDecision,
 {
   let mut insert_path = vec![];
 
@@ -24,10 +26,10 @@ where
     // First get the root's identifier.
     let root_node_identifier = {
       let root_node_identifier_guard =
-        lock_set.temp_root_identifier_read_guard();
+        lock_set.temp_root_identifier_read_guard()?;
       let root_node_identifier_ref =
-        root_node_identifier_guard.identifier();
-      root_node_identifier_ref.clone()
+        root_node_identifier_guard.identifier()?;
+      *root_node_identifier_ref
     };
 
     insert_path.push(InsertPathEntry::RootLevelNode {
@@ -36,32 +38,33 @@ where
   }
 
   loop {
-    // First get the current identifier/guard.
-    let (current_identifier, current_guard) = {
-      let entry = insert_path
-        .last()
-        .expect("insert path must never be empty as we descend");
-      let current_identifier = entry.current_node_identifier().clone();
-      let current_guard =
-        lock_set.temp_node_read_guard(&current_identifier);
+    // First get the current identifier.
+    let current_identifier = insert_path
+      .last()
+      .ok_or(Error::InvariantViolation(
+        "insert path must never be empty as we descend",
+      ))?
+      .current_node_identifier();
 
-      (current_identifier, current_guard)
+    // Read the node optimistically. Let them stop early if they feel
+    // they have descended far enough. Else, decide which direction to
+    // move in.
+    let direction = lock_set
+      .optimistic_node_read(current_identifier, |node_ref| {
+        if stop_early(node_ref) == DescentDecision::StopEarly {
+          None
+        } else {
+          Some(node_ref.traverse_toward(key))
+        }
+      })?;
+    let direction = match direction {
+      None => return Ok(insert_path),
+      Some(direction) => direction?,
     };
 
-    // Unwrap node.
-    let node_ref = current_guard.unwrap_node_ref();
-
-    // Let them stop early if they feel they have descended far enough.
-    if stop_early(&node_ref) == DescentDecision::StopEarly {
-      return insert_path;
-    }
-
-    // Decide which direction to move in.
-    let direction = node_ref.traverse_toward(key);
-
     match direction {
       // We made it all the way to the bottom! Rejoice!
-      TraversalDirection::Arrived => return insert_path,
+      TraversalDirection::Arrived => return Ok(insert_path),
 
       // Move down toward the leaves.
       TraversalDirection::MoveDown {
@@ -69,7 +72,7 @@ where
       } => {
         insert_path.push(InsertPathEntry::ParentChild {
           parent_node_identifier: current_identifier,
-          current_node_identifier: String::from(child_node_identifier),
+          current_node_identifier: child_node_identifier,
         });
       }
 
@@ -78,10 +81,11 @@ where
       TraversalDirection::MoveRight {
         next_node_identifier,
       } => {
-        let mut last_entry = insert_path.last_mut().unwrap();
-        last_entry.update_current_node_identifier(String::from(
-          next_node_identifier,
-        ));
+        let last_entry =
+          insert_path.last_mut().ok_or(Error::InvariantViolation(
+            "insert path must never be empty as we descend",
+          ))?;
+        last_entry.update_current_node_identifier(next_node_identifier);
       }
     }
   }
@@ -3,26 +3,43 @@ use super::{
   DescentDecision,
 };
 use btree::BTree;
+use error::{Error, Result};
+use key::{Key, Value};
 use locking::LockSet;
 use node::InsertionResult;
 use std::sync::Arc;
 
-pub fn insert(
-  btree: &Arc<BTree>,
-  lock_set: &mut LockSet,
-  key_to_insert: &str,
-) {
-  // Build a path to the leaf where we should do the inserting.
-  let insert_path = descend_toward_key(lock_set, key_to_insert, |_| {
-    DescentDecision::ContinueDescending
-  });
+// `on_leaf_change` is called with the previous value as soon as the
+// leaf has changed. After that, the change stays even if we fail.
+pub fn insert<K: Key, V: Value, F>(
+  btree: &Arc<BTree<K, V>>,
+  lock_set: &mut LockSet<K, V>,
+  key_to_insert: &K,
+  value_to_insert: V,
+  on_leaf_change: F,
+) -> Result<Option<V>>
+where
+  F: FnOnce(Option<&V>),
+{
+  // A node must always be able to split into two that fit.
+  let entry_size =
+    key_to_insert.encoded_size() + value_to_insert.encoded_size();
+  if entry_size > btree.max_entry_size() {
+    return Err(Error::EntryTooLarge(entry_size));
+  }
+
+  let (insert_path, leaf_guard) = loop {
+    // Build a path to the leaf where we should do the inserting.
+    let insert_path =
+      descend_toward_key(lock_set, key_to_insert, |_| {
+        DescentDecision::ContinueDescending
+      })?;
 
-  // Perform the insert at the leaf node, possibly splitting that leaf.
-  let split_info = {
     // Got to the leaf. Now acquire write-style!
-    let leaf_entry = insert_path
-      .last()
-      .expect("lock_path_identifiers must never be empty");
+    let leaf_entry =
+      insert_path.last().ok_or(Error::InvariantViolation(
+        "lock_path_identifiers must never be empty",
+      ))?;
     let leaf_identifier = leaf_entry.current_node_identifier();
     // Must keep in mind that when we acquire the write guard, the
     // target may have split in the meantime.
@@ -30,37 +47,99 @@ pub fn insert(
       lock_set,
       leaf_identifier,
       key_to_insert,
-    );
+    )?;
 
-    // We have the write guard! Let's hold onto it for 2PL since we
-    // are updating data stored here.
-    lock_set.hold_node_write_guard(&leaf_guard);
+    // We have the write guard! We only keep it for this insert. It's
+    // the lock on the key that we hold until the end of the
+    // transaction, so that no one else can have read the gap we insert
+    // into. If we must wait for that lock, we start over once we have
+    // it.
+    let lock_wait = BTree::lock_key_for_insert(
+      lock_set,
+      leaf_guard
+        .unwrap_node_ref()?
+        .unwrap_leaf_node_ref("final node is always LeafNode")?,
+      key_to_insert,
+    )?;
+    match lock_wait {
+      None => break (insert_path, leaf_guard),
+      Some(lock_wait) => {
+        drop(leaf_guard);
+        lock_wait.wait(lock_set)?;
+      }
+    }
+  };
 
-    // Perform the insertion.
+  // Perform the insert at the leaf node, possibly splitting that leaf.
+  let (previous_value, split_info) = {
     let mut leaf_node = leaf_guard
-      .unwrap_leaf_node_mut_ref("final node is always LeafNode");
-    let insertion_result =
-      leaf_node.insert_key(btree, String::from(key_to_insert));
+      .unwrap_leaf_node_mut_ref("final node is always LeafNode")?;
+    btree.log_leaf_insert(
+      lock_set,
+      &mut leaf_node,
+      key_to_insert,
+      &value_to_insert,
+    )?;
+    let insertion_result = leaf_node.insert_key(
+      btree,
+      key_to_insert.clone(),
+      value_to_insert,
+    )?;
+
+    match insertion_result {
+      InsertionResult::DidInsert => (None, None),
+      InsertionResult::KeyWasUpdated(previous_value) => {
+        (Some(previous_value), None)
+      }
+      InsertionResult::DidInsertWithSplit(split_info) => {
+        (None, Some(split_info))
+      }
+      InsertionResult::KeyWasUpdatedWithSplit(
+        previous_value,
+        split_info,
+      ) => (Some(previous_value), Some(split_info)),
+    }
+  };
+  on_leaf_change(previous_value.as_ref());
+  btree.version_leaf_change(
+    lock_set,
+    key_to_insert,
+    previous_value.as_ref(),
+  );
 
-    // If there was no splitting, then there is nothing else to do.
-    let mut split_info = match insertion_result {
-      InsertionResult::DidInsertWithSplit(split_info) => split_info,
-      _ => return,
-    };
+  // If there was no splitting, then there is nothing else to do.
+  let split_info = match split_info {
+    None => return Ok(previous_value),
+    Some(split_info) => split_info,
+  };
 
-    // May have to hold the sibbling; since the inserted key could have
-    // ended up there. (In that case, it *may* be safe to release the
-    // write lock on the node we originally inserted at. However, that
-    // lock may already be held for other 2PL inserts previously
-    // performed there... Easiest just to hold both locks).
+  // The leaf split. Log the split before anyone else can see the
+  // sibbling.
+  {
     let sibbling_guard =
-      lock_set.node_write_guard(&split_info.new_right_identifier);
-    lock_set.hold_node_write_guard(&sibbling_guard);
+      lock_set.node_write_guard(split_info.new_right_identifier)?;
+    btree.log_structure_change(
+      &mut [
+        &mut *leaf_guard.unwrap_node_mut_ref()?,
+        &mut *sibbling_guard.unwrap_node_mut_ref()?,
+      ],
+      Some(&split_info),
+      None,
+      None,
+    )?;
+  }
 
-    split_info
-  };
+  // That is all we need the leaf for. Anyone who arrives before the
+  // parent learns of the sibbling follows the leaf's link to it. So we
+  // let go of the leaf before we climb, rather than make everyone who
+  // wants it wait for the splits above.
+  drop(leaf_guard);
 
   // Now ascend back up the tree to handle the split of the leaf node.
-  // We may have to perform more splits as we move up.
-  unwind_insert_path(btree, lock_set, insert_path, split_info);
+  // We may have to perform more splits as we move up. This only ever
+  // waits on latches, so (short of an I/O error) it can't be
+  // interrupted partway.
+  unwind_insert_path(btree, lock_set, insert_path, split_info)?;
+
+  Ok(previous_value)
 }
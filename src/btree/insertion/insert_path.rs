@@ -1,10 +1,12 @@
+use storage::NodeIdentifier;
+
 pub enum InsertPathEntry {
   // For each node we visit, we also keep track of its parent for split
   // unwinding purposes.
   ParentChild {
-    parent_node_identifier: String,
+    parent_node_identifier: NodeIdentifier,
     // This may change if we walk right.
-    current_node_identifier: String,
+    current_node_identifier: NodeIdentifier,
   },
 
   // When we start our descent, we start from a node we *think* is the
@@ -20,21 +22,21 @@ pub enum InsertPathEntry {
   RootLevelNode {
     // This can be different from the starting root if we walk right
     // from the root.
-    alleged_root_identifier: String,
+    alleged_root_identifier: NodeIdentifier,
   },
 }
 
 impl InsertPathEntry {
-  pub fn current_node_identifier(&self) -> &String {
+  pub fn current_node_identifier(&self) -> NodeIdentifier {
     match self {
       InsertPathEntry::RootLevelNode {
-        alleged_root_identifier: ref current_node_identifier,
+        alleged_root_identifier: current_node_identifier,
         ..
       }
       | InsertPathEntry::ParentChild {
-        ref current_node_identifier,
+        current_node_identifier,
         ..
-      } => current_node_identifier,
+      } => *current_node_identifier,
     }
   }
 
@@ -42,7 +44,7 @@ impl InsertPathEntry {
   // we didn't know about a split.
   pub fn update_current_node_identifier(
     &mut self,
-    new_current_node_identifier: String,
+    new_current_node_identifier: NodeIdentifier,
   ) {
     match self {
       InsertPathEntry::RootLevelNode {
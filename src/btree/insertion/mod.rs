@@ -4,21 +4,63 @@ mod insert_path;
 mod scan_right_for_write_guard;
 mod unwinding;
 
-pub(self) use self::descend_toward_key::*;
-pub(self) use self::insert_path::*;
-pub(self) use self::scan_right_for_write_guard::*;
 pub(self) use self::unwinding::*;
 
+// Deletion descends and scans right just the way insertion does.
+pub(in btree) use self::descend_toward_key::*;
+pub(in btree) use self::insert_path::*;
+pub(in btree) use self::scan_right_for_write_guard::*;
+
 use btree::BTree;
+use error::Result;
+use key::{Key, Value};
 use locking::LockSet;
+use node::SplitInfo;
 use std::sync::Arc;
 
-impl BTree {
-  pub fn insert(
-    btree: &Arc<BTree>,
-    lock_set: &mut LockSet,
-    insert_key: &str,
-  ) {
-    insert::insert(btree, lock_set, insert_key)
+impl<K: Key, V: Value> BTree<K, V> {
+  // Inserts the key with the given value. If the key was already
+  // present, its value is replaced and the previous value is returned.
+  //
+  // If a split fails after the leaf changed (say, on an I/O error), we
+  // return the error, but the leaf keeps the change. Use
+  // `insert_reporting_change` to find out.
+  pub(crate) fn insert(
+    btree: &Arc<BTree<K, V>>,
+    lock_set: &mut LockSet<K, V>,
+    insert_key: &K,
+    insert_value: V,
+  ) -> Result<Option<V>> {
+    insert::insert(btree, lock_set, insert_key, insert_value, |_| ())
+  }
+
+  // Like `insert`, but calls `on_leaf_change` with the previous value
+  // as soon as the leaf has changed, whether or not we fail after.
+  pub(crate) fn insert_reporting_change<F>(
+    btree: &Arc<BTree<K, V>>,
+    lock_set: &mut LockSet<K, V>,
+    insert_key: &K,
+    insert_value: V,
+    on_leaf_change: F,
+  ) -> Result<Option<V>>
+  where
+    F: FnOnce(Option<&V>),
+  {
+    insert::insert(
+      btree,
+      lock_set,
+      insert_key,
+      insert_value,
+      on_leaf_change,
+    )
+  }
+
+  // Finishes a split that a crash interrupted. See `recovery.rs`.
+  pub(in btree) fn complete_split(
+    btree: &BTree<K, V>,
+    lock_set: &mut LockSet<K, V>,
+    split_info: SplitInfo<K>,
+  ) -> Result<()> {
+    complete_split(btree, lock_set, split_info)
   }
 }
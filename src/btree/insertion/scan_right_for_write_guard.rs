@@ -1,35 +1,39 @@
+use error::Result;
+use key::{Key, Value};
 use locking::{LockSet, LockSetNodeWriteGuard};
 use node::TraversalDirection;
+use storage::NodeIdentifier;
 
-// This method tries to acquire a write lock on `start_identifier`, but
+// This method tries to acquire a write latch on `start_identifier`, but
 // in the case that the node has split, will move right to the
 // appropriate node.
 //
 // This method *does not* move down the tree. It *only* scans right.
-pub fn scan_right_for_write_guard(
-  lock_set: &mut LockSet,
-  start_identifier: &str,
-  key: &str,
-) -> LockSetNodeWriteGuard {
-  let mut current_identifier = String::from(start_identifier);
+pub fn scan_right_for_write_guard<K: Key, V: Value>(
+  lock_set: &mut LockSet<K, V>,
+  start_identifier: NodeIdentifier,
+  key: &K,
+) -> Result<LockSetNodeWriteGuard<K, V>> {
+  let mut current_identifier = start_identifier;
   loop {
-    let current_guard = lock_set.node_write_guard(&current_identifier);
+    let current_guard =
+      lock_set.node_write_guard(current_identifier)?;
     let direction = {
-      let node_ref = current_guard.unwrap_node_ref();
-      node_ref.traverse_toward(key).as_val()
+      let node_ref = current_guard.unwrap_node_ref()?;
+      node_ref.traverse_toward(key)?
     };
 
     match direction {
       TraversalDirection::Arrived => {
         // If we're scanning at leaf level, we'll know to stop because
         // we'll have arrived at the leaf node.
-        return current_guard;
+        return Ok(current_guard);
       }
 
       TraversalDirection::MoveDown { .. } => {
         // If we are scanning at interior level, we'll know to stop
         // because we are told to move down a level.
-        return current_guard;
+        return Ok(current_guard);
       }
 
       TraversalDirection::MoveRight {
@@ -0,0 +1,17 @@
+use super::{redescend_toward_last_split, unwind_insert_path};
+use btree::BTree;
+use error::Result;
+use key::{Key, Value};
+use locking::LockSet;
+use node::SplitInfo;
+
+// Finishes a split whose parent never learned of the new right
+// sibbling. Recovery calls this for splits that a crash interrupted.
+pub fn complete_split<K: Key, V: Value>(
+  btree: &BTree<K, V>,
+  lock_set: &mut LockSet<K, V>,
+  split_info: SplitInfo<K>,
+) -> Result<()> {
+  let insert_path = redescend_toward_last_split(lock_set, &split_info)?;
+  unwind_insert_path(btree, lock_set, insert_path, split_info)
+}
@@ -1,3 +1,4 @@
+mod complete_split;
 mod redescend_toward_last_split;
 mod unwind_insert_path;
 mod unwind_insert_path_entry;
@@ -10,4 +11,5 @@ pub(self) use self::unwind_parent_child_entry::*;
 pub(self) use self::unwind_root_level_entry::*;
 pub(self) use self::unwinding_result::*;
 
+pub use self::complete_split::complete_split;
 pub use self::unwind_insert_path::unwind_insert_path;
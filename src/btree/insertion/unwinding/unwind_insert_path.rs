@@ -1,31 +1,37 @@
 use super::{redescend_toward_last_split, UnwindingResult};
 use btree::{insertion::InsertPathEntry, BTree};
+use error::{Error, Result};
+use key::{Key, Value};
 use locking::LockSet;
 use node::SplitInfo;
 
 // Unwinds a path, propagating splits up the tree.
-pub fn unwind_insert_path(
-  btree: &BTree,
-  lock_set: &mut LockSet,
+pub fn unwind_insert_path<K: Key, V: Value>(
+  btree: &BTree<K, V>,
+  lock_set: &mut LockSet<K, V>,
   mut insert_path: Vec<InsertPathEntry>,
-  mut split_info: SplitInfo,
-) {
+  mut split_info: SplitInfo<K>,
+) -> Result<()> {
   loop {
     // Pop one entry as we scroll back up the tree.
     let path_entry = match insert_path.pop() {
-      None => panic!("Shouldn't ever run out of entries without at least reaching an alleged root node..."),
+      None => {
+        return Err(Error::InvariantViolation(
+          "Shouldn't ever run out of entries without at least reaching an alleged root node...",
+        ))
+      }
       Some(path_entry) => path_entry,
     };
 
     // Unwind the entry.
     let unwinding_result =
-      path_entry.unwind_entry(btree, lock_set, split_info);
+      path_entry.unwind_entry(btree, lock_set, split_info)?;
 
     // Handle the result of unwinding the entry.
     match unwinding_result {
       // Either the parent didn't split when it handled the child's
       // split, OR
-      UnwindingResult::FinishedUnwinding => return,
+      UnwindingResult::FinishedUnwinding => return Ok(()),
 
       // We must continue unwinding, OR
       UnwindingResult::MustContinueUnwinding(new_split_info) => {
@@ -38,7 +44,7 @@ pub fn unwind_insert_path(
       UnwindingResult::MustRedescend(original_split_info) => {
         split_info = original_split_info;
         insert_path =
-          redescend_toward_last_split(lock_set, &split_info);
+          redescend_toward_last_split(lock_set, &split_info)?;
       }
     }
   }
@@ -2,17 +2,19 @@ use super::{
   unwind_parent_child_entry, unwind_root_level_entry, UnwindingResult,
 };
 use btree::{insertion::InsertPathEntry, BTree};
+use error::Result;
+use key::{Key, Value};
 use locking::LockSet;
 use node::SplitInfo;
 
 impl InsertPathEntry {
   // Unwind one entry of the path, handling a split that occurred.
-  pub fn unwind_entry(
+  pub fn unwind_entry<K: Key, V: Value>(
     self,
-    btree: &BTree,
-    lock_set: &mut LockSet,
-    split_info: SplitInfo,
-  ) -> UnwindingResult {
+    btree: &BTree<K, V>,
+    lock_set: &mut LockSet<K, V>,
+    split_info: SplitInfo<K>,
+  ) -> Result<UnwindingResult<K>> {
     match self {
       InsertPathEntry::ParentChild {
         parent_node_identifier,
@@ -20,7 +22,7 @@ impl InsertPathEntry {
       } => unwind_parent_child_entry(
         btree,
         lock_set,
-        &parent_node_identifier,
+        parent_node_identifier,
         split_info,
       ),
 
@@ -1,34 +1,64 @@
 use super::UnwindingResult;
 use btree::{insertion::scan_right_for_write_guard, BTree};
+use error::Result;
+use key::{Key, Value};
 use locking::LockSet;
 use node::SplitInfo;
+use storage::NodeIdentifier;
 
 // Handle the split of the child at the parent. This may split the
 // parent, requiring further unwinding.
-pub fn unwind_parent_child_entry(
-  btree: &BTree,
-  lock_set: &mut LockSet,
-  parent_node_identifier: &str,
-  split_info: SplitInfo,
-) -> UnwindingResult {
+pub fn unwind_parent_child_entry<K: Key, V: Value>(
+  btree: &BTree<K, V>,
+  lock_set: &mut LockSet<K, V>,
+  parent_node_identifier: NodeIdentifier,
+  split_info: SplitInfo<K>,
+) -> Result<UnwindingResult<K>> {
   // Acquire write guard on the parent; or wherever we should be
   // inserting this newly split child.
   let parent_guard = scan_right_for_write_guard(
     lock_set,
     parent_node_identifier,
     &split_info.new_median,
-  );
+  )?;
 
-  // Unwrap the parent node.
-  let mut parent_node = parent_guard
-    .unwrap_interior_node_mut_ref("only interior nodes can be parents");
+  // Handle the split at the node.
+  let completed_split = split_info.new_right_identifier;
+  let new_split_info = parent_guard
+    .unwrap_interior_node_mut_ref("only interior nodes can be parents")?
+    .handle_split(btree, split_info)?;
 
-  // Handle the split at the node. Maybe we have to continue unwinding.
-  match parent_node.handle_split(btree, split_info) {
-    None => UnwindingResult::FinishedUnwinding,
+  // Log the change. If the parent split too, no one else can see its
+  // new sibbling yet, so we can lock it without waiting.
+  match &new_split_info {
+    None => btree.log_structure_change(
+      &mut [&mut *parent_guard.unwrap_node_mut_ref()?],
+      None,
+      Some(completed_split),
+      None,
+    )?,
 
     Some(new_split_info) => {
-      UnwindingResult::MustContinueUnwinding(new_split_info)
+      let sibbling_guard = lock_set
+        .node_write_guard(new_split_info.new_right_identifier)?;
+      btree.log_structure_change(
+        &mut [
+          &mut *parent_guard.unwrap_node_mut_ref()?,
+          &mut *sibbling_guard.unwrap_node_mut_ref()?,
+        ],
+        Some(new_split_info),
+        Some(completed_split),
+        None,
+      )?;
+    }
+  }
+
+  // Maybe we have to continue unwinding.
+  match new_split_info {
+    None => Ok(UnwindingResult::FinishedUnwinding),
+
+    Some(new_split_info) => {
+      Ok(UnwindingResult::MustContinueUnwinding(new_split_info))
     }
   }
 }
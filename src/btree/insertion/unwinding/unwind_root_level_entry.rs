@@ -1,26 +1,29 @@
 use super::UnwindingResult;
 use btree::BTree;
+use error::Result;
+use key::{Key, Value};
 use locking::LockSet;
 use node::{InteriorNode, SplitInfo};
+use storage::NodeIdentifier;
 
 // We have split a node that we thought of as "root level." If this is
 // the root, then we should update the root identifier. But if it no
 // longer is, we must redescend to find the path further back to
 // continue propagation up.
-pub fn unwind_root_level_entry(
-  btree: &BTree,
-  lock_set: &mut LockSet,
-  alleged_root_identifier: String,
-  split_info: SplitInfo,
-) -> UnwindingResult {
+pub fn unwind_root_level_entry<K: Key, V: Value>(
+  btree: &BTree<K, V>,
+  lock_set: &mut LockSet<K, V>,
+  alleged_root_identifier: NodeIdentifier,
+  split_info: SplitInfo<K>,
+) -> Result<UnwindingResult<K>> {
   // First, acquire a write guard on the root identifier since we may
   // have to mutate it.
   //
   // TODO: It may be worth seeing if getting a temp read guard to check
   // if the root identifier changed before write locking decreases lock
   // contention.
-  let root_id_guard = lock_set.root_identifier_write_guard();
-  let mut root_identifier = root_id_guard.identifier_mut();
+  let root_id_guard = lock_set.root_identifier_write_guard()?;
+  let mut root_identifier = root_id_guard.identifier_mut()?;
 
   // Did we actually reach the root? If not, let them know we must
   // continue unwinding.
@@ -28,15 +31,26 @@ pub fn unwind_root_level_entry(
     // Root split on us! Uh-oh! We have to redescend before we can
     // continue propagating splits further up. Let them have the
     // SplitInfo back for possible reuse.
-    return UnwindingResult::MustRedescend(split_info);
+    return Ok(UnwindingResult::MustRedescend(split_info));
   }
 
   // Okay! We actually are spliting the root for reals! Special day!
+  let completed_split = split_info.new_right_identifier;
   *root_identifier = InteriorNode::store_new_root(
     btree,
     alleged_root_identifier,
     split_info,
   );
 
-  UnwindingResult::FinishedUnwinding
+  // No one else can see the new root until we let go of the root
+  // identifier, so we can lock it without waiting.
+  let new_root_guard = lock_set.node_write_guard(*root_identifier)?;
+  btree.log_structure_change(
+    &mut [&mut *new_root_guard.unwrap_node_mut_ref()?],
+    None,
+    Some(completed_split),
+    Some(*root_identifier),
+  )?;
+
+  Ok(UnwindingResult::FinishedUnwinding)
 }
@@ -1,7 +1,8 @@
+use key::Key;
 use node::SplitInfo;
 
-pub enum UnwindingResult {
+pub enum UnwindingResult<K: Key> {
   FinishedUnwinding,
-  MustContinueUnwinding(SplitInfo),
-  MustRedescend(SplitInfo),
+  MustContinueUnwinding(SplitInfo<K>),
+  MustRedescend(SplitInfo<K>),
 }
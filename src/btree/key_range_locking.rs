@@ -0,0 +1,621 @@
+use btree::BTree;
+use error::Result;
+use key::{Key, Value};
+use locking::LockSet;
+use node::{ComparisonValue, LeafNode};
+use std::ops::Bound;
+
+// To lock the gap a key would fall into, we lock the range named by the
+// next key that *is* present (see `KeyLockTable`). That is next-key
+// locking:
+//
+// * A lookup locks the first key at or after the key it looks for. If
+//   the key is there, that is the key itself.
+// * A scan locks each key it yields, and then the first key past its
+//   end. Together those cover every key the scan could have seen.
+// * An insert of a new key checks the range it splits, then holds the
+//   new key. A delete holds the key and the range after it, which
+//   absorbs the deleted key's range.
+//
+// The next key may live in a leaf to the right, which we don't have
+// locked. Someone may insert a nearer key there before we get our lock.
+// So once we have the lock, we look again, and lock the nearer key if
+// need be. After that, an insert into our range must first get past our
+// lock.
+//
+// The range is locked by key, not by leaf. So it stays locked when the
+// leaf splits, or is retired and its range handed to its right
+// sibbling.
+//
+// We never wait for a key-range lock while we have a leaf latched. The
+// transaction that holds the key-range lock may need that very leaf
+// before it can finish. So we only *try* to get the lock. If it isn't
+// free, we give back a LockWait. The caller lets go of the leaf, waits
+// for the lock, and then starts over, since the leaf may have changed
+// in the meantime.
+impl<K: Key, V: Value> BTree<K, V> {
+  // Must be called while we hold the write latch on the leaf, before we
+  // insert `key` into it.
+  pub(in btree) fn lock_key_for_insert(
+    lock_set: &mut LockSet<K, V>,
+    leaf_node: &LeafNode<K, V>,
+    key: &K,
+  ) -> Result<Option<LockWait<K>>> {
+    if !leaf_node.contains_key(key) {
+      let lock_wait = BTree::lock_next_key_range(
+        lock_set,
+        leaf_node,
+        Bound::Excluded(key),
+        KeyRangeLock::CheckWrite,
+      )?;
+      if lock_wait.is_some() {
+        return Ok(lock_wait);
+      }
+    }
+
+    KeyRangeLock::Write
+      .try_acquire(lock_set, ComparisonValue::DefiniteValue(key))
+  }
+
+  // Likewise, before we delete `key`. Deleting a missing key changes
+  // nothing, so that is just a lookup.
+  pub(in btree) fn lock_key_for_delete(
+    lock_set: &mut LockSet<K, V>,
+    leaf_node: &LeafNode<K, V>,
+    key: &K,
+  ) -> Result<Option<LockWait<K>>> {
+    if !leaf_node.contains_key(key) {
+      return BTree::lock_next_key_range(
+        lock_set,
+        leaf_node,
+        Bound::Included(key),
+        KeyRangeLock::Read,
+      );
+    }
+
+    let lock_wait = KeyRangeLock::Write
+      .try_acquire(lock_set, ComparisonValue::DefiniteValue(key))?;
+    if lock_wait.is_some() {
+      return Ok(lock_wait);
+    }
+
+    BTree::lock_next_key_range(
+      lock_set,
+      leaf_node,
+      Bound::Excluded(key),
+      KeyRangeLock::Write,
+    )
+  }
+
+  // Locks the range of the first key in or after `leaf_node` that is
+  // past `lower_bound`.
+  pub(in btree) fn lock_next_key_range(
+    lock_set: &mut LockSet<K, V>,
+    leaf_node: &LeafNode<K, V>,
+    lower_bound: Bound<&K>,
+    lock: KeyRangeLock,
+  ) -> Result<Option<LockWait<K>>> {
+    if !lock_set.takes_key_range_locks() {
+      return Ok(None);
+    }
+
+    let mut next_key =
+      BTree::next_key(lock_set, leaf_node, lower_bound)?;
+    loop {
+      let lock_wait = lock.try_acquire(lock_set, next_key.as_ref())?;
+      if lock_wait.is_some() {
+        return Ok(lock_wait);
+      }
+
+      let new_next_key =
+        BTree::next_key(lock_set, leaf_node, lower_bound)?;
+      if new_next_key.as_ref() == next_key.as_ref() {
+        return Ok(None);
+      }
+      next_key = new_next_key;
+    }
+  }
+
+  // Infinity if there is no such key. Leaves to the right are only read
+  // under temp guards, one at a time.
+  fn next_key(
+    lock_set: &mut LockSet<K, V>,
+    leaf_node: &LeafNode<K, V>,
+    lower_bound: Bound<&K>,
+  ) -> Result<ComparisonValue<K>> {
+    let idx = leaf_node.start_idx(lower_bound);
+    if idx < leaf_node.num_keys() {
+      let key = leaf_node.keys()[idx].clone();
+      return Ok(ComparisonValue::DefiniteValue(key));
+    }
+
+    let mut next_node_identifier = leaf_node.next_node_identifier();
+    while let Some(identifier) = next_node_identifier {
+      let guard = lock_set.temp_node_read_guard(identifier)?;
+      let leaf_node =
+        guard.unwrap_leaf_node_ref("leaves only link to leaves")?;
+
+      let idx = leaf_node.start_idx(lower_bound);
+      if idx < leaf_node.num_keys() {
+        let key = leaf_node.keys()[idx].clone();
+        return Ok(ComparisonValue::DefiniteValue(key));
+      }
+      next_node_identifier = leaf_node.next_node_identifier();
+    }
+
+    Ok(ComparisonValue::Infinity)
+  }
+}
+
+// Which of the LockSet's key-range locks to take.
+#[derive(Clone, Copy)]
+pub(in btree) enum KeyRangeLock {
+  Read,
+  Write,
+  // Taken only to wait out anyone who read the range. See
+  // `LockSet::try_check_key_range_write_lock`.
+  CheckWrite,
+}
+
+impl KeyRangeLock {
+  // A key can also be locked on its own, without looking for the next
+  // key.
+  pub fn try_acquire<K: Key, V: Value>(
+    self,
+    lock_set: &mut LockSet<K, V>,
+    top: ComparisonValue<&K>,
+  ) -> Result<Option<LockWait<K>>> {
+    let is_acquired = match self {
+      KeyRangeLock::Read => {
+        lock_set.try_hold_key_range_read_lock(top)?
+      }
+      KeyRangeLock::Write => {
+        lock_set.try_hold_key_range_write_lock(top)?
+      }
+      KeyRangeLock::CheckWrite => {
+        lock_set.try_check_key_range_write_lock(top)?
+      }
+    };
+
+    if is_acquired {
+      Ok(None)
+    } else {
+      Ok(Some(LockWait {
+        lock: self,
+        top: top.cloned(),
+      }))
+    }
+  }
+}
+
+// A key-range lock we couldn't get without waiting.
+pub(in btree) struct LockWait<K: Key> {
+  lock: KeyRangeLock,
+  top: ComparisonValue<K>,
+}
+
+impl<K: Key> LockWait<K> {
+  // Must only be called once we have let go of the leaf. Once we have
+  // waited for a lock, we keep it rather than risk waiting for it
+  // again; so even a check becomes a lock we hold.
+  pub fn wait<V: Value>(
+    self,
+    lock_set: &mut LockSet<K, V>,
+  ) -> Result<()> {
+    match self.lock {
+      KeyRangeLock::Read => {
+        lock_set.hold_key_range_read_lock(self.top.as_ref())
+      }
+      KeyRangeLock::Write | KeyRangeLock::CheckWrite => {
+        lock_set.hold_key_range_write_lock(self.top.as_ref())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use constants::KEY_LOCK_ESCALATION_THRESHOLD;
+  use error::Error;
+  use locking::LockSet;
+  use std::sync::{Arc, Barrier};
+  use std::thread;
+  use std::time::Duration;
+  use test_util::{count_nodes, key};
+  use transaction::TransactionMode;
+
+  // Every tenth key, up to `max_idx`.
+  fn btree_with_every_tenth_key(
+    max_idx: usize,
+  ) -> Arc<BTree<String, String>> {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in (0..=max_idx).step_by(10) {
+      txn.insert(&key(idx), String::from("v")).unwrap();
+    }
+    txn.commit().unwrap();
+    btree
+  }
+
+  fn try_insert_is_busy(
+    btree: &Arc<BTree<String, String>>,
+    idx: usize,
+  ) -> bool {
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    match txn.try_insert(&key(idx), String::from("phantom")) {
+      Err(Error::LockTimeout) => true,
+      result => {
+        result.unwrap();
+        false
+      }
+    }
+  }
+
+  // Not finding a key locks the gap it would go into, up to the next
+  // key, so that no one can insert it until we are done.
+  #[test]
+  fn a_missing_key_locks_its_gap() {
+    let btree = btree_with_every_tenth_key(100);
+
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.get(&key(55)), Ok(None));
+    assert!(try_insert_is_busy(&btree, 51));
+    assert!(try_insert_is_busy(&btree, 59));
+    // Other gaps are free.
+    assert!(!try_insert_is_busy(&btree, 45));
+    assert!(!try_insert_is_busy(&btree, 65));
+
+    // Others can still read the gap, and the key that bounds it.
+    let mut other_reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(other_reader.try_get(&key(55)), Ok(None));
+    assert_eq!(
+      other_reader.try_get(&key(60)),
+      Ok(Some(String::from("v")))
+    );
+    other_reader.commit().unwrap();
+
+    reader.commit().unwrap();
+    assert!(!try_insert_is_busy(&btree, 55));
+  }
+
+  // A scan sees no phantoms: no key can be inserted between the keys
+  // it yields, nor between its last key and the first key past its
+  // end.
+  #[test]
+  fn a_scan_locks_out_phantoms() {
+    let btree = btree_with_every_tenth_key(100);
+
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    let keys: Vec<String> = reader
+      .range(&key(20)..=&key(40))
+      .unwrap()
+      .map(|entry| entry.unwrap().0)
+      .collect();
+    assert_eq!(keys, vec![key(20), key(30), key(40)]);
+
+    for &idx in &[25, 35, 45] {
+      assert!(try_insert_is_busy(&btree, idx));
+    }
+    let mut writer = btree.begin(TransactionMode::ReadWrite);
+    assert_eq!(writer.try_delete(&key(30)), Err(Error::LockTimeout));
+    assert_eq!(writer.try_delete(&key(50)), Err(Error::LockTimeout));
+    drop(writer);
+    assert!(!try_insert_is_busy(&btree, 55));
+
+    // Scanning again finds the same keys.
+    let keys_again: Vec<String> = reader
+      .range(&key(20)..=&key(40))
+      .unwrap()
+      .map(|entry| entry.unwrap().0)
+      .collect();
+    assert_eq!(keys_again, keys);
+    reader.commit().unwrap();
+
+    assert!(!try_insert_is_busy(&btree, 35));
+  }
+
+  // A delete holds the deleted key and the range after it, which now
+  // takes in the deleted key's gap too. No one may read or refill any
+  // of it until the delete commits.
+  #[test]
+  fn a_delete_locks_the_deleted_key_and_the_next_range() {
+    let btree = btree_with_every_tenth_key(100);
+
+    let mut deleter = btree.begin(TransactionMode::ReadWrite);
+    assert_eq!(deleter.delete(&key(50)), Ok(Some(String::from("v"))));
+
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.try_get(&key(50)), Err(Error::LockTimeout));
+    assert_eq!(reader.try_get(&key(55)), Err(Error::LockTimeout));
+    assert_eq!(reader.try_get(&key(45)), Err(Error::LockTimeout));
+    assert_eq!(reader.try_get(&key(35)), Ok(None));
+    reader.commit().unwrap();
+    for &idx in &[45, 50, 55] {
+      assert!(try_insert_is_busy(&btree, idx));
+    }
+    assert!(!try_insert_is_busy(&btree, 65));
+
+    deleter.commit().unwrap();
+    assert!(!try_insert_is_busy(&btree, 50));
+  }
+
+  // A range is locked by key, not by leaf. It stays locked while the
+  // leaves around it split.
+  #[test]
+  fn a_gap_stays_locked_when_its_leaf_splits() {
+    let btree = btree_with_every_tenth_key(1000);
+
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.get(&key(505)), Ok(None));
+
+    let mut writer = btree.begin(TransactionMode::ReadWrite);
+    for idx in (0..1000).filter(|idx| idx % 10 != 0) {
+      if idx / 10 != 50 {
+        writer.insert(&key(idx), String::from("v")).unwrap();
+      }
+    }
+    writer.commit().unwrap();
+    btree.validate().unwrap();
+
+    for idx in 501..510 {
+      assert!(try_insert_is_busy(&btree, idx));
+    }
+    reader.commit().unwrap();
+    assert!(!try_insert_is_busy(&btree, 505));
+  }
+
+  // Each operation lets go of its latches before it returns. Only the
+  // key locks stay.
+  #[test]
+  fn operations_hold_no_latches_once_they_return() {
+    let btree = btree_with_every_tenth_key(1000);
+    let mut lock_set = LockSet::new(&btree, TransactionMode::ReadWrite);
+
+    assert_eq!(BTree::get(&mut lock_set, &key(55)), Ok(None));
+    assert_eq!(lock_set.num_latches(), 0);
+    BTree::insert(&btree, &mut lock_set, &key(105), String::from("v"))
+      .unwrap();
+    assert_eq!(lock_set.num_latches(), 0);
+    BTree::delete(&btree, &mut lock_set, &key(200)).unwrap();
+    assert_eq!(lock_set.num_latches(), 0);
+
+    // Nor does a scan hold a latch between keys, even while the leaf
+    // it is in splits.
+    {
+      let mut range =
+        BTree::range(&mut lock_set, &key(300)..&key(400)).unwrap();
+      assert_eq!(range.next().unwrap().unwrap().0, key(300));
+      let mut writer = btree.begin(TransactionMode::ReadWrite);
+      for idx in 401..500 {
+        writer.insert(&key(idx), String::from("v")).unwrap();
+      }
+      writer.commit().unwrap();
+      assert_eq!(range.count(), 9);
+    }
+    assert_eq!(lock_set.num_latches(), 0);
+
+    for &idx in &[55, 105, 200, 350] {
+      assert!(try_insert_is_busy(&btree, idx));
+    }
+  }
+
+  // An insert that must wait for a key lock lets go of the leaf first.
+  // Meanwhile, others insert into (and split) that very leaf.
+  #[test]
+  fn a_waiting_insert_holds_no_latch() {
+    let btree = btree_with_every_tenth_key(1000);
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.get(&key(505)), Ok(None));
+
+    let waiting_btree = Arc::clone(&btree);
+    let waiting_thread = thread::spawn(move || {
+      let mut txn = waiting_btree.begin(TransactionMode::ReadWrite);
+      txn.insert(&key(505), String::from("v")).unwrap();
+      txn.commit().unwrap();
+    });
+    // Give the insert time to start waiting.
+    thread::sleep(Duration::from_millis(50));
+
+    let mut writer = btree.begin(TransactionMode::ReadWrite);
+    writer.set_lock_timeout(Some(Duration::from_secs(5)));
+    for idx in (400..600).filter(|idx| idx % 10 != 0) {
+      if idx / 10 != 50 {
+        writer.insert(&key(idx), String::from("v")).unwrap();
+      }
+    }
+    writer.commit().unwrap();
+
+    reader.commit().unwrap();
+    waiting_thread.join().unwrap();
+    btree.validate().unwrap();
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.get(&key(505)), Ok(Some(String::from("v"))));
+  }
+
+  // Two writers of different keys in the same leaf don't wait for each
+  // other: each locks its own keys (and the gaps next to them), not the
+  // leaf.
+  #[test]
+  fn writers_of_different_keys_in_one_leaf_dont_wait() {
+    let btree = btree_with_every_tenth_key(60);
+    assert_eq!(count_nodes(&btree), (1, 1));
+
+    let mut first_writer = btree.begin(TransactionMode::ReadWrite);
+    first_writer.insert(&key(11), String::from("first")).unwrap();
+    first_writer.insert(&key(20), String::from("first")).unwrap();
+    first_writer.delete(&key(40)).unwrap();
+
+    // Should any of these wait for a lock, it fails right away.
+    let mut second_writer = btree.begin(TransactionMode::ReadWrite);
+    for &idx in &[5, 25, 55] {
+      second_writer
+        .try_insert(&key(idx), String::from("second"))
+        .unwrap();
+    }
+    second_writer
+      .try_insert(&key(30), String::from("second"))
+      .unwrap();
+    second_writer.try_delete(&key(60)).unwrap();
+
+    first_writer.commit().unwrap();
+    second_writer.commit().unwrap();
+    btree.validate().unwrap();
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    let keys: Vec<String> = reader
+      .range(..)
+      .unwrap()
+      .map(|entry| entry.unwrap().0)
+      .collect();
+    let expected: Vec<String> = [0, 5, 10, 11, 20, 25, 30, 50, 55]
+      .iter()
+      .map(|&idx| key(idx))
+      .collect();
+    assert_eq!(keys, expected);
+  }
+
+  // Splits take only latches. So a transaction that holds key locks in
+  // a leaf, and never finishes, doesn't stop the leaf (or its parent)
+  // from splitting.
+  #[test]
+  fn splits_are_not_blocked_behind_key_locks() {
+    let btree = btree_with_every_tenth_key(1000);
+
+    let mut holder = btree.begin(TransactionMode::ReadWrite);
+    for idx in 501..510 {
+      holder.insert(&key(idx), String::from("held")).unwrap();
+    }
+    assert_eq!(holder.get(&key(555)), Ok(None));
+
+    // Should any of these wait for a key lock, it fails right away.
+    let mut writer = btree.begin(TransactionMode::ReadWrite);
+    for idx in (0..1000).filter(|idx| idx % 10 != 0) {
+      if idx / 10 != 50 && idx / 10 != 55 {
+        writer.try_insert(&key(idx), String::from("v")).unwrap();
+      }
+    }
+    writer.commit().unwrap();
+    btree.validate().unwrap();
+
+    holder.commit().unwrap();
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    let num_keys = reader.range(..).unwrap().count();
+    assert_eq!(num_keys, 1001 - 9);
+  }
+
+  // A ReadWrite transaction that has only read escalates to a shared
+  // lock on the tree. Others can still read any key, but nobody else
+  // can write one. We still can.
+  #[test]
+  fn reading_escalates_to_a_shared_tree_lock() {
+    let num_keys = KEY_LOCK_ESCALATION_THRESHOLD + 100;
+    let btree = btree_with_every_tenth_key(10 * num_keys);
+
+    let mut reader = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..KEY_LOCK_ESCALATION_THRESHOLD {
+      assert!(reader.get(&key(10 * idx)).unwrap().is_some());
+    }
+
+    let mut other_reader = btree.begin(TransactionMode::ReadOnly);
+    let untouched_key = key(10 * num_keys);
+    assert_eq!(
+      other_reader.try_get(&untouched_key),
+      Ok(Some(String::from("v")))
+    );
+    other_reader.commit().unwrap();
+    assert!(try_insert_is_busy(&btree, 10 * num_keys - 5));
+
+    reader.insert(&key(5), String::from("v")).unwrap();
+    reader.commit().unwrap();
+    assert!(!try_insert_is_busy(&btree, 10 * num_keys - 5));
+  }
+
+  // Once it has written, a transaction escalates to an exclusive lock,
+  // which keeps readers out too.
+  #[test]
+  fn writing_escalates_to_an_exclusive_tree_lock() {
+    let btree = btree_with_every_tenth_key(10);
+
+    let mut writer = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..KEY_LOCK_ESCALATION_THRESHOLD {
+      writer.insert(&key(100 + idx), String::from("v")).unwrap();
+    }
+
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.try_get(&key(0)), Err(Error::LockTimeout));
+
+    writer.commit().unwrap();
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.try_get(&key(0)), Ok(Some(String::from("v"))));
+  }
+
+  // A ReadWrite transaction reads a key under a shared lock, which
+  // others may share. Writing the key upgrades that lock (and the
+  // latch on the leaf) in place, once the others are gone.
+  #[test]
+  fn a_read_key_is_upgraded_for_a_write() {
+    let btree = btree_with_every_tenth_key(100);
+
+    let mut writer = btree.begin(TransactionMode::ReadWrite);
+    assert_eq!(writer.get(&key(50)), Ok(Some(String::from("v"))));
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.try_get(&key(50)), Ok(Some(String::from("v"))));
+    assert_eq!(
+      writer.try_insert(&key(50), String::from("new")),
+      Err(Error::LockTimeout)
+    );
+    reader.commit().unwrap();
+
+    assert_eq!(
+      writer.insert(&key(50), String::from("new")),
+      Ok(Some(String::from("v")))
+    );
+    assert_eq!(writer.get(&key(50)), Ok(Some(String::from("new"))));
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.try_get(&key(50)), Err(Error::LockTimeout));
+    drop(reader);
+    writer.commit().unwrap();
+
+    btree.validate().unwrap();
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.get(&key(50)), Ok(Some(String::from("new"))));
+  }
+
+  // Two transactions that read the same key and then both write it
+  // each wait for the other to let go of its shared lock. One of them
+  // must give up, so that the other can go on.
+  #[test]
+  fn upgrading_a_shared_key_from_both_sides_deadlocks() {
+    let btree = btree_with_every_tenth_key(100);
+    let barrier = Arc::new(Barrier::new(2));
+
+    let read_then_write = {
+      let btree = Arc::clone(&btree);
+      let barrier = Arc::clone(&barrier);
+      move |value: &str| {
+        let mut txn = btree.begin(TransactionMode::ReadWrite);
+        assert_eq!(txn.get(&key(50)), Ok(Some(String::from("v"))));
+        barrier.wait();
+        let result = txn.insert(&key(50), String::from(value));
+        if result.is_ok() {
+          txn.commit().unwrap();
+        }
+        result
+      }
+    };
+    let other_read_then_write = read_then_write.clone();
+    let other_thread =
+      thread::spawn(move || other_read_then_write("other"));
+    let result = read_then_write("ours");
+    let other_result = other_thread.join().unwrap();
+
+    let (winner, loser_result) = match (result, other_result) {
+      (Ok(_), other_result) => ("ours", other_result),
+      (result, Ok(_)) => ("other", result),
+      results => panic!("both writes failed: {:?}", results),
+    };
+    assert_eq!(loser_result, Err(Error::Deadlock));
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(reader.get(&key(50)), Ok(Some(String::from(winner))));
+  }
+}
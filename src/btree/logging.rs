@@ -0,0 +1,235 @@
+use btree::BTree;
+use error::Result;
+use key::{Key, Value};
+use locking::LockSet;
+use node::{LeafNode, Node, SplitInfo};
+use std::sync::Arc;
+use storage::NodeIdentifier;
+use transaction::{Commit, TransactionId};
+use wal::{LogRecord, WriteAheadLog};
+
+// These methods append records to the WriteAheadLog. A BTree without a
+// log (say, one that lives only in memory) skips all of this.
+//
+// A change to a leaf is logged just *before* it is made. A change to the
+// structure of the tree is logged just *after*, as after-images of the
+// nodes. Either way, we hold the write latch on every node involved, so
+// none of them can be written back before its record is in the log.
+// Each node remembers the LSN of the last record that changed it.
+//
+// Appending fails only if the log is broken. Then nothing more can be
+// made durable, and no node will be written back again, so even a
+// structural change we already made stays out of the NodeStore.
+//
+// We also keep track of which records a checkpoint must keep: those of
+// running transactions, and those that started unfinished splits.
+impl<K: Key, V: Value> BTree<K, V> {
+  pub fn wal(&self) -> Option<&Arc<WriteAheadLog>> {
+    self.wal.as_ref()
+  }
+
+  pub(in btree) fn log_leaf_insert(
+    &self,
+    lock_set: &LockSet<K, V>,
+    leaf_node: &mut LeafNode<K, V>,
+    key: &K,
+    value: &V,
+  ) -> Result<()> {
+    let wal = match &self.wal {
+      None => return Ok(()),
+      Some(wal) => wal,
+    };
+
+    self.note_running_transaction(wal, lock_set);
+    let lsn = wal.append(&LogRecord::Insert {
+      transaction_id: lock_set.transaction_id(),
+      is_compensation: lock_set.is_rolling_back(),
+      identifier: leaf_node.identifier(),
+      key: key.clone(),
+      value: value.clone(),
+      previous_value: leaf_node.get(key).cloned(),
+    })?;
+    leaf_node.set_lsn(lsn);
+
+    Ok(())
+  }
+
+  // Nothing is logged if the key isn't there to delete.
+  pub(in btree) fn log_leaf_delete(
+    &self,
+    lock_set: &LockSet<K, V>,
+    leaf_node: &mut LeafNode<K, V>,
+    key: &K,
+  ) -> Result<()> {
+    let wal = match &self.wal {
+      None => return Ok(()),
+      Some(wal) => wal,
+    };
+    let deleted_value = match leaf_node.get(key) {
+      None => return Ok(()),
+      Some(deleted_value) => deleted_value.clone(),
+    };
+
+    self.note_running_transaction(wal, lock_set);
+    let lsn = wal.append(&LogRecord::Delete {
+      transaction_id: lock_set.transaction_id(),
+      is_compensation: lock_set.is_rolling_back(),
+      identifier: leaf_node.identifier(),
+      key: key.clone(),
+      deleted_value,
+    })?;
+    leaf_node.set_lsn(lsn);
+
+    Ok(())
+  }
+
+  // We note the transaction before we append its first record, so that
+  // a checkpoint that doesn't see it yet can't throw the record away.
+  // Compensation records are never undone, so they don't count.
+  fn note_running_transaction(
+    &self,
+    wal: &WriteAheadLog,
+    lock_set: &LockSet<K, V>,
+  ) {
+    if !lock_set.is_rolling_back() {
+      self
+        .running_transaction_lsns
+        .lock()
+        .entry(lock_set.transaction_id())
+        .or_insert_with(|| wal.next_lsn());
+    }
+  }
+
+  // `started_split` is a split of one of the `nodes` that its parent
+  // doesn't know about yet. `completed_split` is the new right sibbling
+  // of a split that the parent has now learned of.
+  pub fn log_structure_change(
+    &self,
+    nodes: &mut [&mut Node<K, V>],
+    started_split: Option<&SplitInfo<K>>,
+    completed_split: Option<NodeIdentifier>,
+    root_identifier: Option<NodeIdentifier>,
+  ) -> Result<()> {
+    let wal = match &self.wal {
+      None => return Ok(()),
+      Some(wal) => wal,
+    };
+
+    let lsn = wal.append(&LogRecord::<K, V>::StructureChange {
+      images: nodes.iter().map(|node| node.serialize()).collect(),
+      started_split: started_split.cloned(),
+      completed_split,
+      root_identifier,
+    })?;
+    for node in nodes.iter_mut() {
+      node.set_lsn(lsn);
+    }
+
+    // We still hold the nodes, so none of them can be written back yet,
+    // and a checkpoint keeps this record meanwhile.
+    let mut unfinished_split_lsns = self.unfinished_split_lsns.lock();
+    if let Some(completed_split) = completed_split {
+      unfinished_split_lsns.remove(&completed_split);
+    }
+    if let Some(started_split) = started_split {
+      unfinished_split_lsns
+        .insert(started_split.new_right_identifier, lsn);
+    }
+
+    Ok(())
+  }
+
+  // How long we wait for the commit record depends on `commit`.
+  pub fn log_commit(
+    &self,
+    transaction_id: TransactionId,
+    commit: Commit,
+  ) -> Result<()> {
+    let wal = match &self.wal {
+      None => return Ok(()),
+      Some(wal) => wal,
+    };
+
+    let lsn =
+      wal.append(&LogRecord::<K, V>::Commit { transaction_id })?;
+    // A checkpoint flushes the log before it throws anything away, so
+    // we are done with our records even if the commit isn't durable
+    // yet.
+    self.running_transaction_lsns.lock().remove(&transaction_id);
+    match commit {
+      Commit::Sync => wal.flush_to(lsn),
+      Commit::Async => {
+        wal.request_flush(lsn);
+        Ok(())
+      }
+      Commit::None => Ok(()),
+    }
+  }
+
+  // The transaction has finished rolling back. There's no hurry to
+  // flush this: if it is lost, recovery finds nothing left to undo.
+  pub fn log_end(&self, transaction_id: TransactionId) -> Result<()> {
+    if let Some(wal) = &self.wal {
+      wal.append(&LogRecord::<K, V>::End { transaction_id })?;
+      self.running_transaction_lsns.lock().remove(&transaction_id);
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use constants::ASYNC_COMMIT_INTERVAL;
+  use std::sync::Arc;
+  use std::thread;
+  use test_util::{data_path, key, remove_files};
+  use transaction::{Commit, TransactionMode};
+
+  const PAGE_SIZE: usize = 512;
+
+  // Commits an insert, then waits `num_intervals` times
+  // `ASYNC_COMMIT_INTERVAL`. Returns whether the commit record has been
+  // flushed by then.
+  fn commit_and_wait(
+    name: &str,
+    commit: Commit,
+    num_intervals: u32,
+  ) -> bool {
+    let path = data_path(name);
+    let btree: Arc<BTree<String, String>> =
+      Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    txn.set_commit(commit);
+    txn.insert(&key(0), String::from("value")).unwrap();
+    txn.commit().unwrap();
+
+    let wal = btree.wal().unwrap();
+    // Nothing was logged after the commit record.
+    let commit_lsn = wal.next_lsn() - 1;
+    thread::sleep(ASYNC_COMMIT_INTERVAL * num_intervals);
+    let is_flushed = commit_lsn < wal.flushed_lsn();
+
+    drop(btree);
+    remove_files(&path);
+    is_flushed
+  }
+
+  #[test]
+  fn a_sync_commit_is_flushed_when_it_returns() {
+    assert!(commit_and_wait("sync-commit", Commit::Sync, 0));
+  }
+
+  // The background flusher wakes up every `ASYNC_COMMIT_INTERVAL`. We
+  // give it a few more, in case it doesn't get scheduled right away.
+  #[test]
+  fn an_async_commit_is_flushed_in_the_background() {
+    assert!(commit_and_wait("async-commit", Commit::Async, 5));
+  }
+
+  #[test]
+  fn a_commit_without_durability_isnt_flushed() {
+    assert!(!commit_and_wait("no-commit", Commit::None, 5));
+  }
+}
@@ -1,39 +1,88 @@
-use btree::BTree;
+use btree::{BTree, KeyRangeLock};
+use error::{Error, Result};
+use key::{Key, Value};
 use locking::{LockSet, LockSetNodeReadGuard};
 use node::TraversalDirection;
+use std::ops::Bound;
+use storage::NodeIdentifier;
 
-impl BTree {
-  pub fn contains_key(lock_set: &mut LockSet, key: &str) -> bool {
-    let guard = BTree::find_leaf_for_key(lock_set, key);
-    let node = guard
-      .unwrap_leaf_node_ref("find_leaf_for_key must return leaf node");
+impl<K: Key, V: Value> BTree<K, V> {
+  pub(crate) fn contains_key(
+    lock_set: &mut LockSet<K, V>,
+    key: &K,
+  ) -> Result<bool> {
+    Ok(BTree::get(lock_set, key)?.is_some())
+  }
+
+  pub(crate) fn get(
+    lock_set: &mut LockSet<K, V>,
+    key: &K,
+  ) -> Result<Option<V>> {
+    loop {
+      let guard = BTree::find_leaf_for_key(lock_set, key)?;
+      let node = guard.unwrap_leaf_node_ref(
+        "find_leaf_for_key must return leaf node",
+      )?;
+
+      if let Some(value) = BTree::value_as_of_snapshot(lock_set, key) {
+        return Ok(value);
+      }
+
+      // If the key is missing, this keeps it missing. If we must wait
+      // for the lock, we look again once we have it.
+      let lock_wait = BTree::lock_next_key_range(
+        lock_set,
+        &node,
+        Bound::Included(key),
+        KeyRangeLock::Read,
+      )?;
+      match lock_wait {
+        None => return Ok(node.get(key).cloned()),
+        Some(lock_wait) => {
+          drop(node);
+          drop(guard);
+          lock_wait.wait(lock_set)?;
+        }
+      }
+    }
+  }
 
-    node.contains_key(key)
+  // In Snapshot mode, the value of the key as of the snapshot, if it
+  // has changed since. We must look while we still have the leaf
+  // locked, or the key could change in between.
+  fn value_as_of_snapshot(
+    lock_set: &LockSet<K, V>,
+    key: &K,
+  ) -> Option<Option<V>> {
+    let snapshot_timestamp = lock_set.snapshot_timestamp()?;
+    lock_set
+      .btree()
+      .version_store()
+      .value_as_of(key, snapshot_timestamp)
   }
 
-  pub fn find_leaf_for_key(
-    lock_set: &mut LockSet,
-    key: &str,
-  ) -> LockSetNodeReadGuard {
+  pub(in btree) fn find_leaf_for_key(
+    lock_set: &mut LockSet<K, V>,
+    key: &K,
+  ) -> Result<LockSetNodeReadGuard<K, V>> {
     let mut current_identifier = {
       let root_identifier_guard =
-        lock_set.temp_root_identifier_read_guard();
-      let root_identifier = root_identifier_guard.identifier();
-      root_identifier.clone()
+        lock_set.temp_root_identifier_read_guard()?;
+      let root_identifier = root_identifier_guard.identifier()?;
+      *root_identifier
     };
 
     // Move down the tree toward the leaf node.
     loop {
-      // Note how I do *not* do hand-over-hand locking! The lock on the
-      // parent has long been released when I take this temp read guard.
-      let guard = lock_set.temp_node_read_guard(&current_identifier);
-
+      // Note how I do *not* do hand-over-hand locking! I don't even
+      // latch interior nodes: I read them optimistically.
+      //
       // It is possible that we must move *right*, if the child we are
       // moving toward split.
-      let direction = {
-        let node_ref = guard.unwrap_node_ref();
-        node_ref.traverse_toward(key).as_val()
-      };
+      let direction = lock_set
+        .optimistic_node_read(current_identifier, |node_ref| {
+          node_ref.traverse_toward(key)
+        })??;
       match direction {
         TraversalDirection::Arrived => break,
 
@@ -52,25 +101,36 @@ impl BTree {
     }
 
     // Once we make our way all the way to the bottom, it is time to
-    // take a guard that we can hold on to. In a ReadWrite transaction,
-    // for instance, we must take a write guard.
+    // take the guard we read the leaf under. We don't hold on to it
+    // past this operation; the key locks do that job.
+    BTree::scan_right_for_read_guard(lock_set, current_identifier, key)
+  }
+
+  // Like `scan_right_for_write_guard`, but for the guard we read a leaf
+  // under. In a ReadWrite transaction, for instance, that is a guard we
+  // can upgrade to a write guard, should we decide to change the leaf.
+  pub(in btree) fn scan_right_for_read_guard(
+    lock_set: &mut LockSet<K, V>,
+    start_identifier: NodeIdentifier,
+    key: &K,
+  ) -> Result<LockSetNodeReadGuard<K, V>> {
+    let mut current_identifier = start_identifier;
     loop {
-      let guard = lock_set.node_read_guard(&current_identifier);
+      let guard = lock_set.node_read_guard(current_identifier)?;
 
       // The leaf node may have split in the meantime!
       let direction = {
-        let node_ref = guard.unwrap_node_ref();
-        node_ref.traverse_toward(key).as_val()
+        let node_ref = guard.unwrap_node_ref()?;
+        node_ref.traverse_toward(key)?
       };
 
       match direction {
-        TraversalDirection::Arrived => {
-          lock_set.hold_node_read_guard(&guard);
-          return guard;
-        }
+        TraversalDirection::Arrived => return Ok(guard),
 
         TraversalDirection::MoveDown { .. } => {
-          panic!("LeafNode can never become parent...")
+          return Err(Error::InvariantViolation(
+            "LeafNode can never become parent...",
+          ));
         }
 
         TraversalDirection::MoveRight {
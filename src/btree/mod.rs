@@ -1,8 +1,18 @@
 #[allow(clippy::module_inception)]
 mod btree;
+mod bulk_load;
+mod deletion;
 mod insertion;
+mod key_range_locking;
+mod logging;
 mod lookup;
+mod recovery;
+mod scanning;
 mod storage;
 mod validate;
+mod versioning;
+
+pub(self) use self::key_range_locking::{KeyRangeLock, LockWait};
 
 pub use self::btree::BTree;
+pub use self::scanning::{RangeIterator, ReverseRangeIterator};
@@ -0,0 +1,481 @@
+use btree::BTree;
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::LockSet;
+use node::{LeafNode, Node, SplitInfo};
+use std::collections::HashSet;
+use std::sync::Arc;
+use storage::NodeIdentifier;
+use transaction::{TransactionId, TransactionMode};
+use wal::{LogRecord, Lsn};
+
+// Recovery runs when a BTree with a WriteAheadLog is opened. If we
+// crashed, the NodeStore may be missing changes that made it into the
+// log, and may hold changes of transactions that never committed. We
+// follow ARIES:
+//
+// 1. Analysis. We find the *losers*: transactions that changed a leaf
+//    but never committed or finished rolling back. We also find the
+//    splits that were started but never completed.
+// 2. Redo. We repeat history, replaying every record whose change
+//    isn't already in its node. A node's LSN tells us which changes it
+//    has.
+// 3. We complete the unfinished splits, just the way an insert would
+//    have.
+// 4. Undo. We roll back each loser, newest change first. Changes made
+//    while rolling back are compensation records, which are never
+//    undone themselves.
+//
+// Last, we checkpoint, which empties the log.
+//
+// Undo is logical, like a Transaction's rollback: it goes through the
+// BTree, and it is logged too. If we crash while recovering, recovery
+// simply undoes the same changes again.
+impl<K: Key, V: Value> BTree<K, V> {
+  pub(super) fn recover(self) -> Result<BTree<K, V>> {
+    let records = match self.wal() {
+      None => return Ok(self),
+      Some(wal) => wal.take_recovered_records()?,
+    };
+    let btree = Arc::new(self);
+
+    let analysis = Analysis::of(&records);
+    btree
+      .lock_manager()
+      .skip_transaction_ids_through(analysis.max_transaction_id);
+
+    for (lsn, record) in &records {
+      btree.redo(*lsn, record)?;
+    }
+
+    // Nothing else is running, but the LockSet mustn't give up all the
+    // same.
+    let mut lock_set = LockSet::new(&btree, TransactionMode::ReadWrite);
+    lock_set.begin_rollback();
+
+    for split_info in analysis.unfinished_splits {
+      BTree::complete_split(&btree, &mut lock_set, split_info)?;
+    }
+    undo_losers(&btree, &mut lock_set, &records, &analysis.losers)?;
+    for transaction_id in &analysis.losers {
+      btree.log_end(*transaction_id)?;
+    }
+    drop(lock_set);
+
+    let btree = Arc::try_unwrap(btree).map_err(|_| {
+      Error::InvariantViolation("recovery must own the BTree")
+    })?;
+    btree.checkpoint()?;
+
+    Ok(btree)
+  }
+
+  fn redo(&self, lsn: Lsn, record: &LogRecord<K, V>) -> Result<()> {
+    match record {
+      LogRecord::Insert {
+        identifier,
+        key,
+        value,
+        ..
+      } => self.redo_leaf_change(lsn, *identifier, |leaf_node| {
+        leaf_node.redo_insert(key.clone(), value.clone());
+      }),
+
+      LogRecord::Delete {
+        identifier, key, ..
+      } => self.redo_leaf_change(lsn, *identifier, |leaf_node| {
+        leaf_node.delete(key);
+      }),
+
+      LogRecord::StructureChange {
+        images,
+        root_identifier,
+        ..
+      } => {
+        for image in images {
+          self.redo_image(lsn, Node::deserialize(image)?)?;
+        }
+
+        if let Some(root_identifier) = root_identifier {
+          *self.root_identifier_lock.write() = *root_identifier;
+          self.mark_root_identifier_dirty();
+        }
+
+        Ok(())
+      }
+
+      LogRecord::Commit { .. } | LogRecord::End { .. } => Ok(()),
+    }
+  }
+
+  fn redo_leaf_change<F>(
+    &self,
+    lsn: Lsn,
+    identifier: NodeIdentifier,
+    change: F,
+  ) -> Result<()>
+  where
+    F: FnOnce(&mut LeafNode<K, V>),
+  {
+    // The leaf was retired and freed since. Its retired image comes
+    // later in the log, if it is needed at all.
+    let pin = match self.pin_node(identifier) {
+      Err(Error::NodeNotFound(_)) => return Ok(()),
+      result => result?,
+    };
+    let mut node = pin.node().write();
+    if node.lsn() >= lsn {
+      return Ok(());
+    }
+
+    pin.mark_dirty_since(lsn);
+    let leaf_node = node
+      .unwrap_leaf_node_mut_ref("only leaves have logged changes")?;
+    change(leaf_node);
+    leaf_node.set_lsn(lsn);
+
+    Ok(())
+  }
+
+  // An image may be of a node that never made it to the NodeStore. We
+  // write it there directly, so that its identifier is never handed
+  // out again.
+  //
+  // A retired node is freed at the checkpoint that ends recovery. But
+  // it may have been freed before, and its identifier handed out again,
+  // so a later image of another node takes it back off the list.
+  fn redo_image(&self, lsn: Lsn, mut image: Node<K, V>) -> Result<()> {
+    image.set_lsn(lsn);
+    let mut retired_nodes = self.retired_nodes.lock();
+    if image.is_retired() {
+      retired_nodes.insert(image.identifier(), (0, lsn));
+    } else {
+      retired_nodes.remove(&image.identifier());
+    }
+    drop(retired_nodes);
+
+    let pin = match self.pin_node(image.identifier()) {
+      Err(Error::NodeNotFound(identifier)) => {
+        return self
+          .buffer_pool
+          .node_store()
+          .write_node(identifier, &image.serialize());
+      }
+      result => result?,
+    };
+
+    let mut node = pin.node().write();
+    if node.lsn() < lsn {
+      pin.mark_dirty_since(lsn);
+      *node = image;
+    }
+
+    Ok(())
+  }
+}
+
+struct Analysis<K: Key> {
+  losers: HashSet<TransactionId>,
+  // In the order they were started.
+  unfinished_splits: Vec<SplitInfo<K>>,
+  max_transaction_id: TransactionId,
+}
+
+impl<K: Key> Analysis<K> {
+  fn of<V: Value>(records: &[(Lsn, LogRecord<K, V>)]) -> Analysis<K> {
+    let mut analysis = Analysis {
+      losers: HashSet::new(),
+      unfinished_splits: vec![],
+      max_transaction_id: 0,
+    };
+
+    for (_, record) in records {
+      match record {
+        LogRecord::Insert { transaction_id, .. }
+        | LogRecord::Delete { transaction_id, .. } => {
+          analysis.losers.insert(*transaction_id);
+          analysis.saw_transaction_id(*transaction_id);
+        }
+
+        LogRecord::Commit { transaction_id }
+        | LogRecord::End { transaction_id } => {
+          analysis.losers.remove(transaction_id);
+          analysis.saw_transaction_id(*transaction_id);
+        }
+
+        LogRecord::StructureChange {
+          started_split,
+          completed_split,
+          ..
+        } => {
+          if let Some(completed_split) = completed_split {
+            analysis.unfinished_splits.retain(|split_info| {
+              split_info.new_right_identifier != *completed_split
+            });
+          }
+          if let Some(started_split) = started_split {
+            analysis.unfinished_splits.push(started_split.clone());
+          }
+        }
+      }
+    }
+
+    analysis
+  }
+
+  fn saw_transaction_id(&mut self, transaction_id: TransactionId) {
+    self.max_transaction_id =
+      self.max_transaction_id.max(transaction_id);
+  }
+}
+
+// We walk the log backward, undoing every change a loser made. Its
+// rollback may already have undone some of them, but undoing a change
+// puts back the value the key held before it, whatever it holds now. So
+// undoing a change twice does no harm.
+//
+// Undoing an insert may leave a leaf the loser split empty. Once
+// everything is undone, we reclaim those leaves, as a rollback would.
+//
+// We don't try to match compensation records with the changes they
+// undid. A change can be logged without making it into the
+// Transaction's undo log (if the insert failed while splitting), and an
+// undo that finds nothing to do logs nothing. Either would throw the
+// count off.
+fn undo_losers<K: Key, V: Value>(
+  btree: &Arc<BTree<K, V>>,
+  lock_set: &mut LockSet<K, V>,
+  records: &[(Lsn, LogRecord<K, V>)],
+  losers: &HashSet<TransactionId>,
+) -> Result<()> {
+  let mut undone_insert_keys = vec![];
+  for (_, record) in records.iter().rev() {
+    let (transaction_id, is_compensation) = match record {
+      LogRecord::Insert {
+        transaction_id,
+        is_compensation,
+        ..
+      }
+      | LogRecord::Delete {
+        transaction_id,
+        is_compensation,
+        ..
+      } => (*transaction_id, *is_compensation),
+      _ => continue,
+    };
+    if is_compensation || !losers.contains(&transaction_id) {
+      continue;
+    }
+
+    match record {
+      LogRecord::Insert {
+        key,
+        previous_value: Some(previous_value),
+        ..
+      } => {
+        BTree::insert(btree, lock_set, key, previous_value.clone())?;
+      }
+
+      LogRecord::Insert {
+        key,
+        previous_value: None,
+        ..
+      } => {
+        BTree::delete(btree, lock_set, key)?;
+        undone_insert_keys.push(key);
+      }
+
+      LogRecord::Delete {
+        key, deleted_value, ..
+      } => {
+        BTree::insert(btree, lock_set, key, deleted_value.clone())?;
+      }
+
+      _ => {}
+    }
+  }
+
+  for key in undone_insert_keys {
+    BTree::reclaim_leaf_if_empty(btree, lock_set, key)?;
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use std::fs;
+  use std::mem;
+  use std::sync::Arc;
+  use std::thread;
+  use test_util::{count_nodes, data_path, key, remove_files, wal_path};
+  use transaction::TransactionMode;
+
+  const PAGE_SIZE: usize = 512;
+
+  fn contents(
+    btree: &Arc<BTree<String, String>>,
+  ) -> Vec<(String, String)> {
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let range = txn.range(..).unwrap();
+    range.map(|entry| entry.unwrap()).collect()
+  }
+
+  // We "crash" by leaking a Transaction that is still running. It holds
+  // the BTree, so the BTree is never dropped, and never checkpoints.
+  #[test]
+  fn uncommitted_changes_are_undone() {
+    let path = data_path("uncommitted-changes");
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..100 {
+      txn.insert(&key(idx), format!("committed {}", idx)).unwrap();
+    }
+    txn.commit().unwrap();
+    let committed = contents(&btree);
+
+    // Enough changes to split leaves, some of them to keys we change
+    // more than once.
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 50..200 {
+      txn.insert(&key(idx), format!("uncommitted {}", idx)).unwrap();
+    }
+    for idx in (0..200).step_by(3) {
+      txn.delete(&key(idx)).unwrap();
+    }
+    for idx in (0..200).step_by(6) {
+      txn.insert(&key(idx), format!("reinserted {}", idx)).unwrap();
+    }
+    // Write the uncommitted changes to the data file, so that recovery
+    // must undo them there too.
+    btree.flush().unwrap();
+    mem::forget(txn);
+    drop(btree);
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    assert_eq!(contents(&btree), committed);
+    btree.validate().unwrap();
+    drop(btree);
+
+    // Recovery checkpointed, so there is nothing left to undo.
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    assert_eq!(contents(&btree), committed);
+    drop(btree);
+    remove_files(&path);
+  }
+
+  #[test]
+  fn rolled_back_changes_stay_undone() {
+    let path = data_path("rolled-back-changes");
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..100 {
+      txn.insert(&key(idx), format!("committed {}", idx)).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..100 {
+      txn.delete(&key(idx)).unwrap();
+    }
+    txn.abort().unwrap();
+
+    // A later commit flushes the rollback to the log, too.
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    txn.insert(&key(100), String::from("committed 100")).unwrap();
+    txn.commit().unwrap();
+    let committed = contents(&btree);
+
+    // A Transaction that changes nothing still holds the BTree.
+    mem::forget(btree.begin(TransactionMode::ReadOnly));
+    drop(btree);
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    assert_eq!(contents(&btree), committed);
+    assert_eq!(committed.len(), 101);
+    drop(btree);
+    remove_files(&path);
+  }
+
+  // Undoing a loser's inserts empties the leaves it split off. Recovery
+  // reclaims them, as a rollback would.
+  #[test]
+  fn leaves_emptied_by_undo_are_reclaimed() {
+    let path = data_path("leaves-emptied-by-undo");
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..1000 {
+      txn.insert(&key(idx), format!("uncommitted {}", idx)).unwrap();
+    }
+    let (_, num_levels) = count_nodes(&btree);
+    assert!(num_levels >= 3);
+    btree.flush().unwrap();
+    mem::forget(txn);
+    drop(btree);
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    assert_eq!(contents(&btree), vec![]);
+    btree.validate().unwrap();
+    assert_eq!(count_nodes(&btree), (num_levels, num_levels));
+    drop(btree);
+    remove_files(&path);
+  }
+
+  // We crash after a leaf split was logged, but before its parent heard
+  // of it. To hold the split up there, we latch the parent (the root),
+  // and copy the files while the insert waits for it.
+  #[test]
+  fn unfinished_splits_are_completed() {
+    let path = data_path("unfinished-split");
+    let crash_path = data_path("unfinished-split-crash");
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in (0..80).step_by(2) {
+      txn.insert(&key(idx), format!("committed {}", idx)).unwrap();
+    }
+    txn.commit().unwrap();
+    let committed = contents(&btree);
+    let (num_nodes, num_levels) = count_nodes(&btree);
+    assert_eq!(num_levels, 2);
+
+    let root_identifier = *btree.root_identifier_lock().read();
+    let root_pin = btree.pin_node(root_identifier).unwrap();
+    let root = root_pin.node().read();
+    let inserter = {
+      let btree = Arc::clone(&btree);
+      thread::spawn(move || {
+        let mut txn = btree.begin(TransactionMode::ReadWrite);
+        for idx in (1..80).step_by(2) {
+          let value = format!("uncommitted {}", idx);
+          txn.insert(&key(idx), value).unwrap();
+        }
+        txn.commit().unwrap();
+      })
+    };
+    while btree.unfinished_split_lsns.lock().is_empty() {
+      thread::yield_now();
+    }
+    btree.wal().unwrap().flush().unwrap();
+    fs::copy(&path, &crash_path).unwrap();
+    fs::copy(wal_path(&path), wal_path(&crash_path)).unwrap();
+
+    drop(root);
+    inserter.join().unwrap();
+    drop(root_pin);
+    drop(btree);
+    remove_files(&path);
+
+    let btree = Arc::new(BTree::open(&crash_path, PAGE_SIZE).unwrap());
+    assert_eq!(contents(&btree), committed);
+    btree.validate().unwrap();
+    // The root learned of the new leaf.
+    assert!(count_nodes(&btree).0 > num_nodes);
+    drop(btree);
+    remove_files(&crash_path);
+  }
+}
@@ -0,0 +1,122 @@
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::{LockSet, LockSetNodeReadGuard};
+use node::{ComparisonValue, Node};
+use storage::NodeIdentifier;
+
+// Where one step of the descent takes us, and the lower bound we learn
+// on the way, if any.
+struct DescentStep<K: Key> {
+  lower_bound: Option<ComparisonValue<K>>,
+  next_identifier: NodeIdentifier,
+}
+
+// Leaves only link rightward. To move left, we must redescend toward
+// the key just below the current leaf's range. To know that key, we
+// keep track of each node's lower bound as we descend.
+//
+// The lower bound we learn may be stale. A leaf's lower bound only ever
+// moves down (when its left sibbling is retired), so a stale lower
+// bound is too high, never too low. The caller must be prepared for
+// that.
+pub fn find_leaf_with_lower_bound<K: Key, V: Value>(
+  lock_set: &mut LockSet<K, V>,
+  target: ComparisonValue<&K>,
+) -> Result<(LockSetNodeReadGuard<K, V>, ComparisonValue<K>)> {
+  let mut current_identifier = {
+    let root_identifier_guard =
+      lock_set.temp_root_identifier_read_guard()?;
+    let root_identifier = root_identifier_guard.identifier()?;
+    *root_identifier
+  };
+  let mut lower_bound = ComparisonValue::NegativeInfinity;
+
+  // Move down the tree toward the leaf node, reading interior nodes
+  // optimistically.
+  loop {
+    let step = lock_set.optimistic_node_read(
+      current_identifier,
+      |node_ref| -> Result<Option<DescentStep<K>>> {
+        let interior_node = match node_ref {
+          Node::LeafNode(..) => return Ok(None),
+          Node::InteriorNode(interior_node) => interior_node,
+        };
+
+        if interior_node.max_value() < target {
+          // We missed a split; move right. Everything in the sibbling
+          // is greater than our max_value. A retired node has
+          // NegativeInfinity as its max_value, and its sibbling
+          // inherits our lower bound, just like a retired leaf's.
+          let next_identifier = interior_node
+            .next_node_identifier()
+            .ok_or(Error::InvariantViolation(
+              "node with definite max value must have next",
+            ))?;
+          let lower_bound = if interior_node.is_retired() {
+            None
+          } else {
+            Some(interior_node.max_value().cloned())
+          };
+          return Ok(Some(DescentStep {
+            lower_bound,
+            next_identifier,
+          }));
+        }
+
+        let child_idx = interior_node.child_idx_by_value(target);
+        Ok(Some(DescentStep {
+          lower_bound: interior_node
+            .split_below_child_idx(child_idx)
+            .map(|split| ComparisonValue::DefiniteValue(split.clone())),
+          next_identifier: interior_node
+            .child_identifier_by_idx(child_idx),
+        }))
+      },
+    )??;
+
+    let step = match step {
+      None => break,
+      Some(step) => step,
+    };
+    if let Some(new_lower_bound) = step.lower_bound {
+      lower_bound = new_lower_bound;
+    }
+    current_identifier = step.next_identifier;
+  }
+
+  // Now take a guard on the leaf that we can read under. The leaf may
+  // have split in the meantime!
+  loop {
+    let guard = lock_set.node_read_guard(current_identifier)?;
+
+    let next_node_identifier = {
+      let leaf_node = guard
+        .unwrap_leaf_node_ref("LeafNode can never become parent...")?;
+
+      if target <= leaf_node.max_value() {
+        None
+      } else {
+        // A retired leaf has NegativeInfinity as its max_value. Its
+        // range was given to its right sibbling, so that sibbling
+        // inherits our lower bound.
+        if lower_bound.as_ref() < leaf_node.max_value() {
+          lower_bound = leaf_node.max_value().cloned();
+        }
+
+        Some(leaf_node.next_node_identifier().ok_or(
+          Error::InvariantViolation(
+            "node with definite max value must have next",
+          ),
+        )?)
+      }
+    };
+
+    match next_node_identifier {
+      None => return Ok((guard, lower_bound)),
+
+      Some(next_node_identifier) => {
+        current_identifier = next_node_identifier;
+      }
+    }
+  }
+}
@@ -0,0 +1,91 @@
+mod find_leaf_with_lower_bound;
+mod range_iterator;
+mod reverse_range_iterator;
+mod snapshot_scan;
+
+// Deletion must find a leaf's left sibbling too.
+pub(in btree) use self::find_leaf_with_lower_bound::*;
+pub(self) use self::snapshot_scan::SnapshotScan;
+
+pub use self::range_iterator::RangeIterator;
+pub use self::reverse_range_iterator::ReverseRangeIterator;
+
+use btree::BTree;
+use error::Result;
+use key::{Key, Value};
+use locking::LockSet;
+use std::ops::{Bound, RangeBounds};
+
+impl<K: Key, V: Value> BTree<K, V> {
+  // Iterates the key/value pairs in the range, in ascending key order.
+  // Every key range the scan covers is held for 2PL, so repeating the
+  // scan within a transaction gives the same results. (In Snapshot
+  // mode, nothing is held; the results are the same anyway.)
+  //
+  // Moving to the next leaf may fail, so the iterator yields Results.
+  // After an error, the iterator is exhausted.
+  pub(crate) fn range<'a, 'b, R>(
+    lock_set: &'a mut LockSet<K, V>,
+    range: R,
+  ) -> Result<RangeIterator<'a, K, V>>
+  where
+    R: RangeBounds<&'b K>,
+  {
+    let start_bound = to_owned_bound(range.start_bound());
+    let end_bound = to_owned_bound(range.end_bound());
+
+    RangeIterator::new(lock_set, start_bound, end_bound)
+  }
+
+  // Like `range`, but iterates in descending key order.
+  pub(crate) fn range_rev<'a, 'b, R>(
+    lock_set: &'a mut LockSet<K, V>,
+    range: R,
+  ) -> Result<ReverseRangeIterator<'a, K, V>>
+  where
+    R: RangeBounds<&'b K>,
+  {
+    let start_bound = to_owned_bound(range.start_bound());
+    let end_bound = to_owned_bound(range.end_bound());
+
+    ReverseRangeIterator::new(lock_set, start_bound, end_bound)
+  }
+
+  // The entry with the smallest key.
+  pub(crate) fn first(
+    lock_set: &mut LockSet<K, V>,
+  ) -> Result<Option<(K, V)>> {
+    BTree::range(lock_set, ..)?.next().transpose()
+  }
+
+  // The entry with the largest key.
+  pub(crate) fn last(
+    lock_set: &mut LockSet<K, V>,
+  ) -> Result<Option<(K, V)>> {
+    BTree::range_rev(lock_set, ..)?.next().transpose()
+  }
+
+  // The entry with the largest key less than or equal to `key`.
+  pub(crate) fn floor(
+    lock_set: &mut LockSet<K, V>,
+    key: &K,
+  ) -> Result<Option<(K, V)>> {
+    BTree::range_rev(lock_set, ..=key)?.next().transpose()
+  }
+
+  // The entry with the smallest key greater than or equal to `key`.
+  pub(crate) fn ceiling(
+    lock_set: &mut LockSet<K, V>,
+    key: &K,
+  ) -> Result<Option<(K, V)>> {
+    BTree::range(lock_set, key..)?.next().transpose()
+  }
+}
+
+fn to_owned_bound<K: Key>(bound: Bound<&&K>) -> Bound<K> {
+  match bound {
+    Bound::Included(key) => Bound::Included((*key).clone()),
+    Bound::Excluded(key) => Bound::Excluded((*key).clone()),
+    Bound::Unbounded => Bound::Unbounded,
+  }
+}
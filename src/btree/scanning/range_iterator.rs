@@ -0,0 +1,313 @@
+use super::{find_leaf_with_lower_bound, SnapshotScan};
+use btree::{BTree, KeyRangeLock, LockWait};
+use error::Result;
+use key::{Key, Value};
+use locking::LockSet;
+use node::{ComparisonValue, LeafNode};
+use std::cmp::Ordering;
+use std::ops::Bound;
+use storage::NodeIdentifier;
+
+// A RangeIterator walks right along the leaves, starting at the leaf
+// where the start bound would live. We only lock a leaf for as long as
+// it takes to yield one key, so between calls to `next` the leaf may
+// split (or be retired). We remember which leaf we were in and the last
+// key we yielded, and pick up after that key.
+//
+// What keeps the scan consistent are the key locks (see
+// `key_range_locking.rs`). Each key we yield is locked before we read
+// its value, and so is the range just past the end. So keys can't come
+// or go within the range until we are done.
+//
+// When we run off the end of a leaf, we lock the next key before we let
+// go of the leaf. Nobody can insert a key in between after that, so
+// following the leaf's `next_node_identifier` can't miss a key, even if
+// the leaf splits right after.
+//
+// A Snapshot transaction takes no key locks, so it scans differently.
+// See `SnapshotScan`.
+pub struct RangeIterator<'a, K: Key, V: Value> {
+  lock_set: &'a mut LockSet<K, V>,
+  end_bound: Bound<K>,
+  // Every key up to this bound has been yielded.
+  lower_bound: Bound<K>,
+  // The leaf to look in next. None once the scan is exhausted.
+  current_identifier: Option<NodeIdentifier>,
+  // Set only in Snapshot mode.
+  snapshot_scan: Option<SnapshotScan<K, V>>,
+}
+
+// What to do after looking at the current leaf.
+enum ScanStep<K: Key, V: Value> {
+  Yield(K, V),
+  MoveRight(NodeIdentifier),
+  // Let go of the leaf, wait for the lock, and look again.
+  Wait(LockWait<K>),
+  Finished,
+}
+
+impl<'a, K: Key, V: Value> RangeIterator<'a, K, V> {
+  pub(in btree) fn new(
+    lock_set: &'a mut LockSet<K, V>,
+    start_bound: Bound<K>,
+    end_bound: Bound<K>,
+  ) -> Result<RangeIterator<'a, K, V>> {
+    if lock_set.snapshot_timestamp().is_some() {
+      return Ok(RangeIterator {
+        lock_set,
+        end_bound: Bound::Unbounded,
+        lower_bound: Bound::Unbounded,
+        current_identifier: None,
+        snapshot_scan: Some(SnapshotScan::new(
+          start_bound,
+          end_bound,
+          false,
+        )),
+      });
+    }
+
+    // An unbounded scan starts from the leftmost leaf. We only ever
+    // move right, so we have no use for the lower bound.
+    let current_identifier = {
+      let target = match &start_bound {
+        Bound::Included(key) | Bound::Excluded(key) => {
+          ComparisonValue::DefiniteValue(key)
+        }
+        Bound::Unbounded => ComparisonValue::NegativeInfinity,
+      };
+      let (guard, _) = find_leaf_with_lower_bound(lock_set, target)?;
+      let identifier = guard.unwrap_node_ref()?.identifier();
+      identifier
+    };
+
+    Ok(RangeIterator {
+      lock_set,
+      end_bound,
+      lower_bound: start_bound,
+      current_identifier: Some(current_identifier),
+      snapshot_scan: None,
+    })
+  }
+
+  fn is_past_end(&self, key: &K) -> bool {
+    match &self.end_bound {
+      Bound::Included(end_key) => {
+        end_key.compare(key) == Ordering::Less
+      }
+      Bound::Excluded(end_key) => {
+        end_key.compare(key) != Ordering::Greater
+      }
+      Bound::Unbounded => false,
+    }
+  }
+
+  // Once we run past the end, we lock the range of the first key past
+  // it. With the locks on the keys we yielded, that covers every key
+  // from the start bound to the end bound.
+  fn finish(
+    &mut self,
+    leaf_node: &LeafNode<K, V>,
+  ) -> Result<ScanStep<K, V>> {
+    let lock_wait = match &self.end_bound {
+      Bound::Included(end_key) => BTree::lock_next_key_range(
+        self.lock_set,
+        leaf_node,
+        Bound::Excluded(end_key),
+        KeyRangeLock::Read,
+      )?,
+      Bound::Excluded(end_key) => BTree::lock_next_key_range(
+        self.lock_set,
+        leaf_node,
+        Bound::Included(end_key),
+        KeyRangeLock::Read,
+      )?,
+      // We ran out of keys.
+      Bound::Unbounded => KeyRangeLock::Read
+        .try_acquire(self.lock_set, ComparisonValue::Infinity)?,
+    };
+
+    match lock_wait {
+      None => Ok(ScanStep::Finished),
+      Some(lock_wait) => Ok(ScanStep::Wait(lock_wait)),
+    }
+  }
+
+  fn next_step(
+    &mut self,
+    identifier: NodeIdentifier,
+  ) -> Result<ScanStep<K, V>> {
+    let guard = self.lock_set.node_read_guard(identifier)?;
+    let leaf_node =
+      guard.unwrap_leaf_node_ref("range scans only visit leaves")?;
+
+    // Yield the next key in this leaf, if it is within the range. We
+    // read its value only once we have the key locked, so that we never
+    // see a change that may yet be rolled back.
+    let idx = leaf_node.start_idx(self.lower_bound.as_ref());
+    if idx < leaf_node.num_keys() {
+      let key = &leaf_node.keys()[idx];
+      if self.is_past_end(key) {
+        return self.finish(&leaf_node);
+      }
+
+      let lock_wait = KeyRangeLock::Read.try_acquire(
+        self.lock_set,
+        ComparisonValue::DefiniteValue(key),
+      )?;
+      if let Some(lock_wait) = lock_wait {
+        return Ok(ScanStep::Wait(lock_wait));
+      }
+      let value = leaf_node.values()[idx].clone();
+      return Ok(ScanStep::Yield(key.clone(), value));
+    }
+
+    // We've run off the end of this leaf. If every key in the next leaf
+    // is past the end bound, there's no need to look at it.
+    let next_node_identifier = match leaf_node.next_node_identifier() {
+      None => return self.finish(&leaf_node),
+      Some(next_node_identifier) => next_node_identifier,
+    };
+    if let Bound::Included(end_key) | Bound::Excluded(end_key) =
+      &self.end_bound
+    {
+      if leaf_node.max_value().is_ge_to(end_key) {
+        return self.finish(&leaf_node);
+      }
+    }
+
+    // Lock the next key before we let go of this leaf. (If it is past
+    // the end, that's the lock we would take anyway.)
+    let lock_wait = BTree::lock_next_key_range(
+      self.lock_set,
+      &leaf_node,
+      self.lower_bound.as_ref(),
+      KeyRangeLock::Read,
+    )?;
+    match lock_wait {
+      None => Ok(ScanStep::MoveRight(next_node_identifier)),
+      Some(lock_wait) => Ok(ScanStep::Wait(lock_wait)),
+    }
+  }
+}
+
+impl<'a, K: Key, V: Value> Iterator for RangeIterator<'a, K, V> {
+  type Item = Result<(K, V)>;
+
+  fn next(&mut self) -> Option<Result<(K, V)>> {
+    if let Some(snapshot_scan) = &mut self.snapshot_scan {
+      return snapshot_scan.next(self.lock_set);
+    }
+
+    loop {
+      let identifier = self.current_identifier?;
+      let step = match self.next_step(identifier) {
+        Ok(step) => step,
+        Err(error) => {
+          self.current_identifier = None;
+          return Some(Err(error));
+        }
+      };
+
+      match step {
+        ScanStep::Yield(key, value) => {
+          self.lower_bound = Bound::Excluded(key.clone());
+          return Some(Ok((key, value)));
+        }
+
+        ScanStep::MoveRight(next_node_identifier) => {
+          self.current_identifier = Some(next_node_identifier);
+        }
+
+        ScanStep::Wait(lock_wait) => {
+          if let Err(error) = lock_wait.wait(self.lock_set) {
+            self.current_identifier = None;
+            return Some(Err(error));
+          }
+        }
+
+        ScanStep::Finished => {
+          self.current_identifier = None;
+          return None;
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use std::ops::{Bound, RangeBounds};
+  use std::sync::Arc;
+  use test_util::key;
+  use transaction::{Transaction, TransactionMode};
+
+  // Every tenth key, up to `max_idx`. The keys span many leaves.
+  fn btree_with_every_tenth_key(
+    max_idx: usize,
+  ) -> Arc<BTree<String, String>> {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in (0..=max_idx).step_by(10) {
+      txn.insert(&key(idx), format!("value {}", idx)).unwrap();
+    }
+    txn.commit().unwrap();
+    btree
+  }
+
+  fn idxs<'a, R>(
+    txn: &mut Transaction<String, String>,
+    range: R,
+  ) -> Vec<usize>
+  where
+    R: RangeBounds<&'a String>,
+  {
+    txn
+      .range(range)
+      .unwrap()
+      .map(|entry| {
+        let (key, value) = entry.unwrap();
+        let idx = key[3..].parse().unwrap();
+        assert_eq!(value, format!("value {}", idx));
+        idx
+      })
+      .collect()
+  }
+
+  // The multiples of ten from `start` up to `end`.
+  fn tenths(start: usize, end: usize) -> Vec<usize> {
+    (start..=end).filter(|idx| idx % 10 == 0).collect()
+  }
+
+  #[test]
+  fn scans_respect_their_bounds() {
+    let btree = btree_with_every_tenth_key(1000);
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let (k200, k205) = (key(200), key(205));
+    let (k500, k505) = (key(500), key(505));
+
+    assert_eq!(idxs(&mut txn, &k200..=&k500), tenths(200, 500));
+    assert_eq!(idxs(&mut txn, &k200..&k500), tenths(200, 490));
+    let exclusive = (Bound::Excluded(&k200), Bound::Excluded(&k500));
+    assert_eq!(idxs(&mut txn, exclusive), tenths(210, 490));
+    // Bounds between keys.
+    assert_eq!(idxs(&mut txn, &k205..&k505), tenths(210, 500));
+    assert_eq!(idxs(&mut txn, ..=&k205), tenths(0, 200));
+    assert_eq!(
+      idxs(&mut txn, (Bound::Excluded(&k505), Bound::Unbounded)),
+      tenths(510, 1000)
+    );
+    assert_eq!(idxs(&mut txn, &k505..), tenths(510, 1000));
+    assert_eq!(idxs(&mut txn, ..), tenths(0, 1000));
+    // Bounds past either end of the tree.
+    let (before, after) = (String::from("a"), key(2000));
+    assert_eq!(idxs(&mut txn, ..&before), vec![]);
+    assert_eq!(idxs(&mut txn, &after..), vec![]);
+    assert_eq!(idxs(&mut txn, &before..&after), tenths(0, 1000));
+    // Empty and inverted ranges yield nothing.
+    assert_eq!(idxs(&mut txn, &k200..&k200), vec![]);
+    assert_eq!(idxs(&mut txn, &k205..&k205), vec![]);
+    assert_eq!(idxs(&mut txn, &k500..=&k200), vec![]);
+    txn.commit().unwrap();
+  }
+}
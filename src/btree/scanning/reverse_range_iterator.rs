@@ -0,0 +1,417 @@
+use super::{find_leaf_with_lower_bound, SnapshotScan};
+use btree::{BTree, KeyRangeLock, LockWait};
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::LockSet;
+use node::ComparisonValue;
+use std::cmp::Ordering;
+use std::ops::Bound;
+use storage::NodeIdentifier;
+
+// A ReverseRangeIterator walks left along the leaves, starting at the
+// leaf where the end bound would live. Since leaves only link
+// rightward, each time we run off the left end of a leaf we redescend
+// toward the key just below it.
+//
+// As with RangeIterator, we only lock a leaf for as long as it takes to
+// yield one key. If the leaf splits in between, the keys just below the
+// last one we yielded may have moved to its right, so we follow them.
+//
+// Keys are locked just as in a RangeIterator, except that we lock the
+// range past the end first. The lock on the last key we yielded keeps
+// anyone from inserting just below it, so nothing can slip in behind us
+// as we move left.
+//
+// In Snapshot mode, we use a `SnapshotScan` instead.
+pub struct ReverseRangeIterator<'a, K: Key, V: Value> {
+  lock_set: &'a mut LockSet<K, V>,
+  start_bound: Bound<K>,
+  // Every key down to this bound has been yielded.
+  upper_bound: Bound<K>,
+  // The leaf to look in next. None once the scan is exhausted.
+  current_identifier: Option<NodeIdentifier>,
+  // Every key in the current leaf is greater than this.
+  current_lower_bound: ComparisonValue<K>,
+  // Set only in Snapshot mode.
+  snapshot_scan: Option<SnapshotScan<K, V>>,
+}
+
+// What to do after looking at the current leaf.
+enum ScanStep<K: Key, V: Value> {
+  Yield(K, V),
+  MoveRight(NodeIdentifier),
+  MoveLeft(K),
+  // Let go of the leaf, wait for the lock, and look again.
+  Wait(LockWait<K>),
+  Finished,
+}
+
+impl<'a, K: Key, V: Value> ReverseRangeIterator<'a, K, V> {
+  pub(in btree) fn new(
+    lock_set: &'a mut LockSet<K, V>,
+    start_bound: Bound<K>,
+    end_bound: Bound<K>,
+  ) -> Result<ReverseRangeIterator<'a, K, V>> {
+    if lock_set.snapshot_timestamp().is_some() {
+      return Ok(ReverseRangeIterator {
+        lock_set,
+        start_bound: Bound::Unbounded,
+        upper_bound: Bound::Unbounded,
+        current_identifier: None,
+        current_lower_bound: ComparisonValue::NegativeInfinity,
+        snapshot_scan: Some(SnapshotScan::new(
+          start_bound,
+          end_bound,
+          true,
+        )),
+      });
+    }
+
+    let target = match &end_bound {
+      Bound::Included(key) | Bound::Excluded(key) => {
+        ComparisonValue::DefiniteValue(key)
+      }
+      Bound::Unbounded => ComparisonValue::Infinity,
+    };
+
+    // We lock the range of the first key past the end before we start.
+    // As we go, we lock each key we yield.
+    let (current_identifier, current_lower_bound) = loop {
+      let (guard, current_lower_bound) =
+        find_leaf_with_lower_bound(lock_set, target)?;
+      let leaf_node = guard.unwrap_leaf_node_ref(
+        "find_leaf_with_lower_bound returns leaves",
+      )?;
+
+      let lock_wait = match &end_bound {
+        Bound::Included(end_key) => BTree::lock_next_key_range(
+          lock_set,
+          &leaf_node,
+          Bound::Excluded(end_key),
+          KeyRangeLock::Read,
+        )?,
+        Bound::Excluded(end_key) => BTree::lock_next_key_range(
+          lock_set,
+          &leaf_node,
+          Bound::Included(end_key),
+          KeyRangeLock::Read,
+        )?,
+        Bound::Unbounded => KeyRangeLock::Read
+          .try_acquire(lock_set, ComparisonValue::Infinity)?,
+      };
+
+      match lock_wait {
+        None => break (leaf_node.identifier(), current_lower_bound),
+        Some(lock_wait) => {
+          drop(leaf_node);
+          drop(guard);
+          lock_wait.wait(lock_set)?;
+        }
+      }
+    };
+
+    Ok(ReverseRangeIterator {
+      lock_set,
+      start_bound,
+      upper_bound: end_bound,
+      current_identifier: Some(current_identifier),
+      current_lower_bound,
+      snapshot_scan: None,
+    })
+  }
+
+  fn is_past_start(&self, key: &K) -> bool {
+    match &self.start_bound {
+      Bound::Included(start_key) => {
+        key.compare(start_key) == Ordering::Less
+      }
+      Bound::Excluded(start_key) => {
+        key.compare(start_key) != Ordering::Greater
+      }
+      Bound::Unbounded => false,
+    }
+  }
+
+  fn next_step(
+    &mut self,
+    identifier: NodeIdentifier,
+  ) -> Result<ScanStep<K, V>> {
+    let guard = self.lock_set.node_read_guard(identifier)?;
+    let leaf_node =
+      guard.unwrap_leaf_node_ref("range scans only visit leaves")?;
+
+    // If the leaf split (or was retired) since we last looked, the keys
+    // just below our upper bound may be to the right.
+    let target = match &self.upper_bound {
+      Bound::Included(key) | Bound::Excluded(key) => {
+        ComparisonValue::DefiniteValue(key)
+      }
+      Bound::Unbounded => ComparisonValue::Infinity,
+    };
+    if leaf_node.max_value() < target {
+      // A retired leaf has NegativeInfinity as its max_value. Its range
+      // was given to its right sibbling, along with its lower bound.
+      if self.current_lower_bound.as_ref() < leaf_node.max_value() {
+        self.current_lower_bound = leaf_node.max_value().cloned();
+      }
+
+      let next_node_identifier = leaf_node
+        .next_node_identifier()
+        .ok_or(Error::InvariantViolation(
+          "node with definite max value must have next",
+        ))?;
+      return Ok(ScanStep::MoveRight(next_node_identifier));
+    }
+
+    // Yield the previous key in this leaf, if it is within the range.
+    // As in a RangeIterator, we lock the key before we read its value.
+    let idx = leaf_node.end_idx(self.upper_bound.as_ref());
+    if 0 < idx {
+      let key = &leaf_node.keys()[idx - 1];
+      if self.is_past_start(key) {
+        return Ok(ScanStep::Finished);
+      }
+
+      let lock_wait = KeyRangeLock::Read.try_acquire(
+        self.lock_set,
+        ComparisonValue::DefiniteValue(key),
+      )?;
+      if let Some(lock_wait) = lock_wait {
+        return Ok(ScanStep::Wait(lock_wait));
+      }
+      let value = leaf_node.values()[idx - 1].clone();
+      return Ok(ScanStep::Yield(key.clone(), value));
+    }
+
+    // We've run off the start of this leaf. Every key to the left is
+    // less than or equal to our lower bound.
+    let lower_bound = match &self.current_lower_bound {
+      ComparisonValue::DefiniteValue(lower_bound) => lower_bound,
+      // There is nothing to the left of the first leaf.
+      _ => return Ok(ScanStep::Finished),
+    };
+
+    if self.is_past_start(lower_bound) {
+      return Ok(ScanStep::Finished);
+    }
+
+    Ok(ScanStep::MoveLeft(lower_bound.clone()))
+  }
+
+  fn move_left(&mut self, lower_bound: K) -> Result<()> {
+    // The leaf to our left is the one responsible for our lower bound.
+    let (guard, new_lower_bound) = find_leaf_with_lower_bound(
+      self.lock_set,
+      ComparisonValue::DefiniteValue(&lower_bound),
+    )?;
+
+    // If our lower bound was stale, the descent will have led us right
+    // back to the current leaf. We learned a lower lower bound, though,
+    // so we will make progress on the next step.
+    self.current_identifier =
+      Some(guard.unwrap_node_ref()?.identifier());
+    self.current_lower_bound = new_lower_bound;
+    // There is nothing between our lower bound and the last key we
+    // yielded.
+    self.upper_bound = Bound::Included(lower_bound);
+
+    Ok(())
+  }
+}
+
+impl<'a, K: Key, V: Value> Iterator for ReverseRangeIterator<'a, K, V> {
+  type Item = Result<(K, V)>;
+
+  fn next(&mut self) -> Option<Result<(K, V)>> {
+    if let Some(snapshot_scan) = &mut self.snapshot_scan {
+      return snapshot_scan.next(self.lock_set);
+    }
+
+    loop {
+      let identifier = self.current_identifier?;
+      let step = match self.next_step(identifier) {
+        Ok(step) => step,
+        Err(error) => {
+          self.current_identifier = None;
+          return Some(Err(error));
+        }
+      };
+
+      match step {
+        ScanStep::Yield(key, value) => {
+          self.upper_bound = Bound::Excluded(key.clone());
+          return Some(Ok((key, value)));
+        }
+
+        ScanStep::MoveRight(next_node_identifier) => {
+          self.current_identifier = Some(next_node_identifier);
+        }
+
+        ScanStep::MoveLeft(lower_bound) => {
+          if let Err(error) = self.move_left(lower_bound) {
+            self.current_identifier = None;
+            return Some(Err(error));
+          }
+        }
+
+        ScanStep::Wait(lock_wait) => {
+          if let Err(error) = lock_wait.wait(self.lock_set) {
+            self.current_identifier = None;
+            return Some(Err(error));
+          }
+        }
+
+        ScanStep::Finished => {
+          self.current_identifier = None;
+          return None;
+        }
+      }
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use std::ops::{Bound, RangeBounds};
+  use std::sync::Arc;
+  use test_util::key;
+  use transaction::{Transaction, TransactionMode};
+
+  // Every tenth key, up to `max_idx`. The keys span many leaves.
+  fn btree_with_every_tenth_key(
+    max_idx: usize,
+  ) -> Arc<BTree<String, String>> {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in (0..=max_idx).step_by(10) {
+      txn.insert(&key(idx), format!("value {}", idx)).unwrap();
+    }
+    txn.commit().unwrap();
+    btree
+  }
+
+  fn idx(key: &str) -> usize {
+    key[3..].parse().unwrap()
+  }
+
+  fn idxs_rev<'a, R>(
+    txn: &mut Transaction<String, String>,
+    range: R,
+  ) -> Vec<usize>
+  where
+    R: RangeBounds<&'a String>,
+  {
+    txn
+      .range_rev(range)
+      .unwrap()
+      .map(|entry| idx(&entry.unwrap().0))
+      .collect()
+  }
+
+  // The multiples of ten from `end` down to `start`.
+  fn tenths_rev(start: usize, end: usize) -> Vec<usize> {
+    (start..=end).rev().filter(|idx| idx % 10 == 0).collect()
+  }
+
+  #[test]
+  fn reverse_scans_respect_their_bounds() {
+    let btree = btree_with_every_tenth_key(1000);
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let (k200, k205) = (key(200), key(205));
+    let (k500, k505) = (key(500), key(505));
+
+    assert_eq!(idxs_rev(&mut txn, &k200..=&k500), tenths_rev(200, 500));
+    let exclusive = (Bound::Excluded(&k200), Bound::Excluded(&k500));
+    assert_eq!(idxs_rev(&mut txn, exclusive), tenths_rev(210, 490));
+    // Bounds between keys.
+    assert_eq!(idxs_rev(&mut txn, &k205..&k505), tenths_rev(210, 500));
+    assert_eq!(idxs_rev(&mut txn, ..=&k205), tenths_rev(0, 200));
+    assert_eq!(
+      idxs_rev(&mut txn, (Bound::Excluded(&k505), Bound::Unbounded)),
+      tenths_rev(510, 1000)
+    );
+    assert_eq!(idxs_rev(&mut txn, ..), tenths_rev(0, 1000));
+    // Empty and inverted ranges yield nothing.
+    assert_eq!(idxs_rev(&mut txn, &k200..&k200), vec![]);
+    assert_eq!(idxs_rev(&mut txn, &k500..=&k200), vec![]);
+    txn.commit().unwrap();
+  }
+
+  #[test]
+  fn first_last_floor_and_ceiling() {
+    let btree = btree_with_every_tenth_key(1000);
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let idx_of = |entry: Option<(String, String)>| {
+      entry.map(|(key, value)| {
+        assert_eq!(value, format!("value {}", idx(&key)));
+        idx(&key)
+      })
+    };
+
+    assert_eq!(idx_of(txn.first().unwrap()), Some(0));
+    assert_eq!(idx_of(txn.last().unwrap()), Some(1000));
+    assert_eq!(idx_of(txn.floor(&key(500)).unwrap()), Some(500));
+    assert_eq!(idx_of(txn.floor(&key(505)).unwrap()), Some(500));
+    assert_eq!(idx_of(txn.floor(&key(2000)).unwrap()), Some(1000));
+    assert_eq!(idx_of(txn.ceiling(&key(500)).unwrap()), Some(500));
+    assert_eq!(idx_of(txn.ceiling(&key(505)).unwrap()), Some(510));
+    assert_eq!(idx_of(txn.ceiling(&key(1005)).unwrap()), None);
+    assert_eq!(idx_of(txn.floor(&String::from("a")).unwrap()), None);
+    txn.commit().unwrap();
+
+    let btree = Arc::new(BTree::<String, String>::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(txn.first(), Ok(None));
+    assert_eq!(txn.last(), Ok(None));
+    assert_eq!(txn.floor(&key(0)), Ok(None));
+    assert_eq!(txn.ceiling(&key(0)), Ok(None));
+  }
+
+  // While a reverse scan is paused, others split the leaf it is in (so
+  // that the keys just below it move right), and empty out the leaves
+  // to its left (so that its lower bound goes stale). Their changes are
+  // all below the scan, and commit before it gets there, so it must
+  // see exactly what they leave behind.
+  #[test]
+  fn reverse_scans_follow_splits_and_merges() {
+    let btree = btree_with_every_tenth_key(2000);
+    let mut reader = btree.begin(TransactionMode::ReadOnly);
+    let mut scanned = vec![];
+    {
+      let mut range = reader.range_rev(..).unwrap();
+      let mut next_idx = || idx(&range.next().unwrap().unwrap().0);
+
+      while scanned.last() != Some(&1500) {
+        scanned.push(next_idx());
+      }
+      // The scan holds the lock on 1500, and so on the gap below it.
+      let mut writer = btree.begin(TransactionMode::ReadWrite);
+      for idx in (1000..1490).filter(|idx| idx % 10 != 0) {
+        writer.try_insert(&key(idx), String::from("new")).unwrap();
+      }
+      writer.commit().unwrap();
+
+      while scanned.last() != Some(&1001) {
+        scanned.push(next_idx());
+      }
+      let mut deleter = btree.begin(TransactionMode::ReadWrite);
+      for idx in (500..1000).step_by(10) {
+        deleter.try_delete(&key(idx)).unwrap();
+      }
+      deleter.commit().unwrap();
+
+      for entry in range {
+        scanned.push(idx(&entry.unwrap().0));
+      }
+    }
+    reader.commit().unwrap();
+
+    btree.validate().unwrap();
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(scanned, idxs_rev(&mut txn, ..));
+    assert!(scanned.contains(&1489));
+    assert!(!scanned.contains(&990));
+  }
+}
@@ -0,0 +1,290 @@
+use super::find_leaf_with_lower_bound;
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::{LockSet, LockSetNodeReadGuard};
+use node::{ComparisonValue, LeafNode};
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::vec;
+use storage::NodeIdentifier;
+
+// In Snapshot mode, a range scan doesn't hold the leaves it reads, so a
+// leaf may split (or be retired) as soon as we let go of it. We can't
+// trust its `next_node_identifier` after that.
+//
+// So we read a whole leaf at a time. While we have the leaf latched, we
+// copy out its entries that are in the range, as of the snapshot. Then
+// we let go of the leaf, and narrow the range to what is left. To read
+// the next leaf, we descend again toward the narrowed range.
+//
+// A key that was deleted after the snapshot began is no longer in any
+// leaf, only in the VersionStore. So is a key's value before it was
+// overwritten. We look up the VersionStore while we still have the
+// leaf latched, for just the keys that leaf is responsible for.
+pub(super) struct SnapshotScan<K: Key, V: Value> {
+  // The part of the range we haven't read yet.
+  lower_bound: Bound<K>,
+  upper_bound: Bound<K>,
+  is_reverse: bool,
+  // Entries we have read but not yet yielded.
+  entries: vec::IntoIter<(K, V)>,
+  is_finished: bool,
+}
+
+impl<K: Key, V: Value> SnapshotScan<K, V> {
+  pub fn new(
+    lower_bound: Bound<K>,
+    upper_bound: Bound<K>,
+    is_reverse: bool,
+  ) -> SnapshotScan<K, V> {
+    SnapshotScan {
+      lower_bound,
+      upper_bound,
+      is_reverse,
+      entries: vec![].into_iter(),
+      is_finished: false,
+    }
+  }
+
+  pub fn next(
+    &mut self,
+    lock_set: &mut LockSet<K, V>,
+  ) -> Option<Result<(K, V)>> {
+    loop {
+      if let Some(entry) = self.entries.next() {
+        return Some(Ok(entry));
+      }
+      if self.is_finished {
+        return None;
+      }
+
+      let result = if self.is_reverse {
+        self.read_previous_leaf(lock_set)
+      } else {
+        self.read_next_leaf(lock_set)
+      };
+      if let Err(error) = result {
+        self.is_finished = true;
+        return Some(Err(error));
+      }
+    }
+  }
+
+  fn read_next_leaf(
+    &mut self,
+    lock_set: &mut LockSet<K, V>,
+  ) -> Result<()> {
+    let target = match &self.lower_bound {
+      Bound::Included(key) | Bound::Excluded(key) => {
+        ComparisonValue::DefiniteValue(key)
+      }
+      Bound::Unbounded => ComparisonValue::NegativeInfinity,
+    };
+    let (mut guard, _) = find_leaf_with_lower_bound(lock_set, target)?;
+
+    // If the lower bound is excluded, we may have landed on the leaf
+    // that ends with it, which holds nothing we want. Nor does a retired
+    // leaf. Either way we move right. We let go of each leaf before we
+    // take the next, so that we never wait while holding a latch. (Even
+    // if the leaf splits in between, its old right sibbling still
+    // starts just past the keys we skip.)
+    while let Some(next_node_identifier) =
+      self.identifier_to_skip_to(&guard)?
+    {
+      drop(guard);
+      guard = lock_set.node_read_guard(next_node_identifier)?;
+    }
+
+    let leaf_node =
+      guard.unwrap_leaf_node_ref("range scans only visit leaves")?;
+    let upper_bound = lesser_upper_bound(
+      self.upper_bound.as_ref(),
+      leaf_node.max_value(),
+    );
+    let entries = entries_as_of_snapshot(
+      lock_set,
+      &leaf_node,
+      self.lower_bound.as_ref(),
+      upper_bound,
+    )?;
+    self.entries = entries.into_iter();
+
+    // We're done once this leaf reaches the upper bound.
+    let max_value = match leaf_node.max_value() {
+      ComparisonValue::DefiniteValue(max_value) => max_value,
+      _ => {
+        self.is_finished = true;
+        return Ok(());
+      }
+    };
+    self.is_finished = match &self.upper_bound {
+      Bound::Included(upper_key) | Bound::Excluded(upper_key) => {
+        upper_key.compare(max_value) != Ordering::Greater
+      }
+      Bound::Unbounded => false,
+    };
+    self.lower_bound = Bound::Excluded(max_value.clone());
+
+    Ok(())
+  }
+
+  fn identifier_to_skip_to(
+    &self,
+    guard: &LockSetNodeReadGuard<K, V>,
+  ) -> Result<Option<NodeIdentifier>> {
+    let leaf_node =
+      guard.unwrap_leaf_node_ref("range scans only visit leaves")?;
+    let has_nothing_above_lower_bound = match &self.lower_bound {
+      Bound::Excluded(lower_key) => {
+        leaf_node.max_value()
+          <= ComparisonValue::DefiniteValue(lower_key)
+      }
+      _ => leaf_node.is_retired(),
+    };
+    if !has_nothing_above_lower_bound {
+      return Ok(None);
+    }
+
+    let next_node_identifier = leaf_node.next_node_identifier().ok_or(
+      Error::InvariantViolation(
+        "node with definite max value must have next",
+      ),
+    )?;
+    Ok(Some(next_node_identifier))
+  }
+
+  fn read_previous_leaf(
+    &mut self,
+    lock_set: &mut LockSet<K, V>,
+  ) -> Result<()> {
+    let target = match &self.upper_bound {
+      Bound::Included(key) | Bound::Excluded(key) => {
+        ComparisonValue::DefiniteValue(key)
+      }
+      Bound::Unbounded => ComparisonValue::Infinity,
+    };
+    let (guard, leaf_lower_bound) =
+      find_leaf_with_lower_bound(lock_set, target)?;
+
+    // The lower bound may be stale (too high). Then next time we land
+    // on this same leaf again, with a lower lower bound.
+    let leaf_node =
+      guard.unwrap_leaf_node_ref("range scans only visit leaves")?;
+    let lower_bound = greater_lower_bound(
+      self.lower_bound.as_ref(),
+      leaf_lower_bound.as_ref(),
+    );
+    let mut entries = entries_as_of_snapshot(
+      lock_set,
+      &leaf_node,
+      lower_bound,
+      self.upper_bound.as_ref(),
+    )?;
+    entries.reverse();
+    self.entries = entries.into_iter();
+
+    // We're done once the leaf's lower bound reaches ours.
+    let leaf_lower_key = match leaf_lower_bound {
+      ComparisonValue::DefiniteValue(leaf_lower_key) => leaf_lower_key,
+      _ => {
+        self.is_finished = true;
+        return Ok(());
+      }
+    };
+    self.is_finished = match &self.lower_bound {
+      Bound::Included(lower_key) => {
+        leaf_lower_key.compare(lower_key) == Ordering::Less
+      }
+      Bound::Excluded(lower_key) => {
+        leaf_lower_key.compare(lower_key) != Ordering::Greater
+      }
+      Bound::Unbounded => false,
+    };
+    self.upper_bound = Bound::Included(leaf_lower_key);
+
+    Ok(())
+  }
+}
+
+// The leaf's entries between the bounds, as of the snapshot, in
+// ascending order.
+fn entries_as_of_snapshot<K: Key, V: Value>(
+  lock_set: &LockSet<K, V>,
+  leaf_node: &LeafNode<K, V>,
+  lower_bound: Bound<&K>,
+  upper_bound: Bound<&K>,
+) -> Result<Vec<(K, V)>> {
+  let start_idx = leaf_node.start_idx(lower_bound);
+  let end_idx = leaf_node.end_idx(upper_bound).max(start_idx);
+  let mut entries = leaf_node.keys()[start_idx..end_idx]
+    .iter()
+    .cloned()
+    .zip(leaf_node.values()[start_idx..end_idx].iter().cloned())
+    .collect::<Vec<_>>();
+
+  let snapshot_timestamp = match lock_set.snapshot_timestamp() {
+    None => {
+      return Err(Error::InvariantViolation(
+        "only Snapshot transactions scan snapshots",
+      ))
+    }
+    Some(snapshot_timestamp) => snapshot_timestamp,
+  };
+  let changed_values = lock_set.btree().version_store().values_as_of(
+    lower_bound,
+    upper_bound,
+    snapshot_timestamp,
+  );
+  for (key, value) in changed_values {
+    let search_result = entries
+      .binary_search_by(|(entry_key, _)| entry_key.compare(&key));
+    match (search_result, value) {
+      (Ok(idx), Some(value)) => entries[idx].1 = value,
+      (Ok(idx), None) => {
+        entries.remove(idx);
+      }
+      (Err(idx), Some(value)) => entries.insert(idx, (key, value)),
+      (Err(_), None) => {}
+    }
+  }
+
+  Ok(entries)
+}
+
+fn lesser_upper_bound<'a, K: Key>(
+  upper_bound: Bound<&'a K>,
+  max_value: ComparisonValue<&'a K>,
+) -> Bound<&'a K> {
+  let max_value = match max_value {
+    ComparisonValue::DefiniteValue(max_value) => max_value,
+    _ => return upper_bound,
+  };
+
+  match upper_bound {
+    Bound::Included(upper_key) | Bound::Excluded(upper_key)
+      if upper_key.compare(max_value) != Ordering::Greater =>
+    {
+      upper_bound
+    }
+    _ => Bound::Included(max_value),
+  }
+}
+
+fn greater_lower_bound<'a, K: Key>(
+  lower_bound: Bound<&'a K>,
+  leaf_lower_bound: ComparisonValue<&'a K>,
+) -> Bound<&'a K> {
+  let leaf_lower_key = match leaf_lower_bound {
+    ComparisonValue::DefiniteValue(leaf_lower_key) => leaf_lower_key,
+    _ => return lower_bound,
+  };
+
+  match lower_bound {
+    Bound::Included(lower_key) | Bound::Excluded(lower_key)
+      if lower_key.compare(leaf_lower_key) == Ordering::Greater =>
+    {
+      lower_bound
+    }
+    _ => Bound::Excluded(leaf_lower_key),
+  }
+}
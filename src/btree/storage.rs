@@ -1,37 +1,516 @@
 use btree::BTree;
-use constants::IDENTIFIER_LENGTH;
+use error::Result;
+use key::{Key, Value};
 use node::Node;
-use parking_lot::RwLock;
-use rand::{distributions::Alphanumeric, prelude::*};
-use std::iter;
-use std::sync::Arc;
-
-// TODO: eventually this should become its own kind of storage class.
-impl BTree {
-  // Selects an identifier for a node. Relies on being sufficiently
-  // random for no collision.
-  pub fn get_new_identifier(&self) -> String {
-    // Even though this doesn't need to be a method today, it *should*
-    // so that we can eliminate the collision possibility.
-
-    let mut rng = thread_rng();
-    let chars: String = iter::repeat(())
-      .map(|()| rng.sample(Alphanumeric))
-      .take(IDENTIFIER_LENGTH)
+use std::sync::atomic::Ordering;
+use storage::NodeIdentifier;
+use wal::{Lsn, WriteAheadLog};
+
+// These methods create nodes and write the tree back to the NodeStore.
+//
+// The BufferPool moves nodes between memory and the NodeStore. Anyone
+// who takes a write guard on a node marks it dirty; a dirty node is
+// written back when it is evicted, or by `flush`.
+impl<K: Key, V: Value> BTree<K, V> {
+  // The NodeStore never hands out an identifier that is in use.
+  pub fn get_new_identifier(&self) -> NodeIdentifier {
+    self.buffer_pool.node_store().allocate_identifier()
+  }
+
+  pub fn store_node(&self, node: Node<K, V>) {
+    self.buffer_pool.insert_new_node(node);
+  }
+
+  pub fn mark_root_identifier_dirty(&self) {
+    self.root_identifier_is_dirty.store(true, Ordering::SeqCst);
+  }
+
+  // Writes every dirty node back to the NodeStore, and then syncs it.
+  //
+  // A node that is write locked right now may be halfway through a
+  // change, so it is left dirty for next time. That means `flush` only
+  // writes a consistent tree if no transaction is running.
+  pub fn flush(&self) -> Result<()> {
+    self.buffer_pool.flush()?;
+
+    // A new root may have been logged but not yet flushed.
+    if let Some(wal) = self.wal() {
+      wal.flush()?;
+    }
+
+    let node_store = self.buffer_pool.node_store();
+    if self.root_identifier_is_dirty.swap(false, Ordering::SeqCst) {
+      match self.root_identifier_lock.try_read() {
+        None => self.mark_root_identifier_dirty(),
+        Some(root_identifier) => {
+          if let Err(error) =
+            node_store.write_root_identifier(*root_identifier)
+          {
+            self.mark_root_identifier_dirty();
+            return Err(error);
+          }
+        }
+      }
+    }
+
+    node_store.sync()
+  }
+
+  // Writes back every node we can, and then throws away the records at
+  // the front of the log that recovery no longer needs. Transactions
+  // keep running meanwhile: a node that is write locked stays dirty,
+  // and we keep the records that may have changed it.
+  //
+  // Recovery needs a record if
+  //
+  // * it changed a node that hasn't been written back since,
+  // * it was made by a running transaction, which we may yet undo,
+  // * it started a split that isn't complete, or
+  // * it retired a node that hasn't been freed.
+  //
+  // We look at the next LSN first. Whatever starts to need the log
+  // after that needs only records from there on.
+  //
+  // The log is flushed before we throw anything away. Otherwise the
+  // commit of a transaction we took as finished could be lost, and
+  // recovery would undo only the changes we kept.
+  //
+  // With nothing running, this writes everything back and empties the
+  // log.
+  pub fn checkpoint(&self) -> Result<()> {
+    self.free_retired_nodes();
+    self.buffer_pool.flush()?;
+
+    let wal = match self.wal() {
+      None => return self.flush(),
+      Some(wal) => wal,
+    };
+    let oldest_needed_lsn = self.oldest_needed_lsn(wal);
+    self.flush()?;
+
+    // We don't know which record last changed the root identifier. If
+    // a new root kept us from writing it back, we keep the log for now.
+    if !self.root_identifier_is_dirty.load(Ordering::SeqCst) {
+      wal.truncate_before(oldest_needed_lsn)?;
+    }
+    self.log_size_at_checkpoint.store(wal.size(), Ordering::SeqCst);
+
+    Ok(())
+  }
+
+  // A Transaction calls this once it has committed and let go of its
+  // locks. If the log has grown by `checkpoint_log_size` bytes since
+  // the last checkpoint, we checkpoint, unless someone already is.
+  //
+  // The commit stands even if the checkpoint fails. An I/O error will
+  // come up again the next time someone needs the NodeStore or the log.
+  pub fn checkpoint_if_log_is_large(&self) {
+    let wal = match self.wal() {
+      None => return,
+      Some(wal) => wal,
+    };
+    let log_size_at_checkpoint =
+      self.log_size_at_checkpoint.load(Ordering::SeqCst);
+    if wal.size() < log_size_at_checkpoint + self.checkpoint_log_size {
+      return;
+    }
+    if self.is_checkpointing.swap(true, Ordering::SeqCst) {
+      return;
+    }
+
+    if self.checkpoint().is_err() {
+      // So that we don't try again at every commit.
+      self.log_size_at_checkpoint.store(wal.size(), Ordering::SeqCst);
+    }
+    self.is_checkpointing.store(false, Ordering::SeqCst);
+  }
+
+  fn oldest_needed_lsn(&self, wal: &WriteAheadLog) -> Lsn {
+    let next_lsn = wal.next_lsn();
+    let running_transaction_lsn =
+      self.running_transaction_lsns.lock().values().min().cloned();
+    let unfinished_split_lsn =
+      self.unfinished_split_lsns.lock().values().min().cloned();
+    let retired_node_lsn =
+      self.retired_nodes.lock().values().map(|(_, lsn)| *lsn).min();
+    let unwritten_lsn = self.buffer_pool.oldest_unwritten_lsn();
+
+    [
+      running_transaction_lsn,
+      unfinished_split_lsn,
+      retired_node_lsn,
+      unwritten_lsn,
+    ]
+    .iter()
+    .flatten()
+    .fold(next_lsn, |oldest_lsn, lsn| oldest_lsn.min(*lsn))
+  }
+
+  // Checkpoints one last time. Dropping the BTree does the same, but
+  // has nowhere to report an error. If you share the BTree in an `Arc`,
+  // every other clone must be gone first (see `Arc::try_unwrap`).
+  pub fn close(mut self) -> Result<()> {
+    self.is_closed = true;
+    self.checkpoint()
+  }
+}
+
+// This is only a best effort: an error has nowhere to go. Call `close`
+// if you need to know that everything was written back. Whatever
+// wasn't is recovered from the log the next time the BTree is opened.
+impl<K: Key, V: Value> Drop for BTree<K, V> {
+  fn drop(&mut self) {
+    if !self.is_closed {
+      let _ = self.checkpoint();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use error::{Error, Result};
+  use node::Node;
+  use std::fs;
+  use std::mem;
+  use std::path::Path;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::sync::Arc;
+  use storage::{MemoryNodeStore, NodeIdentifier, NodeStore};
+  use test_util::{data_path, key, remove_files, wal_path};
+  use transaction::TransactionMode;
+  use wal::WriteAheadLog;
+
+  const PAGE_SIZE: usize = 512;
+
+  fn file_len(path: &Path) -> u64 {
+    fs::metadata(path).unwrap().len()
+  }
+
+  // Every node, level by level from the root, as it is serialized.
+  fn node_images(btree: &BTree<String, String>) -> Vec<Vec<u8>> {
+    let mut images = vec![];
+    let mut level_start = Some(*btree.root_identifier_lock().read());
+    while let Some(first_identifier) = level_start.take() {
+      let mut identifier = Some(first_identifier);
+      while let Some(current_identifier) = identifier {
+        let pin = btree.pin_node(current_identifier).unwrap();
+        let node = pin.node().read();
+        if let Node::InteriorNode(interior_node) = &*node {
+          if current_identifier == first_identifier {
+            level_start =
+              Some(interior_node.child_identifier_by_idx(0));
+          }
+        }
+        images.push(node.serialize());
+        identifier = node.next_node_identifier();
+      }
+    }
+    images
+  }
+
+  fn insert_keys(btree: &Arc<BTree<String, String>>, num_keys: usize) {
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..num_keys {
+      txn.insert(&key(idx), format!("value {}", idx)).unwrap();
+    }
+    txn.commit().unwrap();
+  }
+
+  // Commits keys `start..end`, twenty at a time.
+  fn insert_batches(
+    btree: &Arc<BTree<String, String>>,
+    start: usize,
+    end: usize,
+  ) {
+    for batch_start in (start..end).step_by(20) {
+      let mut txn = btree.begin(TransactionMode::ReadWrite);
+      for idx in batch_start..(batch_start + 20).min(end) {
+        txn.insert(&key(idx), format!("value {}", idx)).unwrap();
+      }
+      txn.commit().unwrap();
+    }
+  }
+
+  fn open_with_small_checkpoints(
+    path: &Path,
+  ) -> Arc<BTree<String, String>> {
+    let mut btree = BTree::open(path, PAGE_SIZE).unwrap();
+    btree.set_checkpoint_log_size(4 * 1024);
+    Arc::new(btree)
+  }
+
+  fn check_keys(
+    btree: &Arc<BTree<String, String>>,
+    start: usize,
+    end: usize,
+  ) {
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    for idx in start..end {
+      assert_eq!(
+        txn.get(&key(idx)).unwrap(),
+        Some(format!("value {}", idx)),
+      );
+    }
+    txn.commit().unwrap();
+  }
+
+  // Fails every write back and sync once it is told to.
+  struct FailingNodeStore {
+    node_store: MemoryNodeStore,
+    is_failing: Arc<AtomicBool>,
+  }
+
+  impl FailingNodeStore {
+    fn check(&self) -> Result<()> {
+      if self.is_failing.load(Ordering::SeqCst) {
+        Err(Error::Io(String::from("the disk is full")))
+      } else {
+        Ok(())
+      }
+    }
+  }
+
+  impl NodeStore for FailingNodeStore {
+    fn read_node(
+      &self,
+      identifier: NodeIdentifier,
+    ) -> Result<Option<Vec<u8>>> {
+      self.node_store.read_node(identifier)
+    }
+
+    fn write_node(
+      &self,
+      identifier: NodeIdentifier,
+      bytes: &[u8],
+    ) -> Result<()> {
+      self.check()?;
+      self.node_store.write_node(identifier, bytes)
+    }
+
+    fn allocate_identifier(&self) -> NodeIdentifier {
+      self.node_store.allocate_identifier()
+    }
+
+    fn free_identifier(&self, identifier: NodeIdentifier) {
+      self.node_store.free_identifier(identifier)
+    }
+
+    fn read_root_identifier(&self) -> Result<Option<NodeIdentifier>> {
+      self.node_store.read_root_identifier()
+    }
+
+    fn write_root_identifier(
+      &self,
+      root_identifier: NodeIdentifier,
+    ) -> Result<()> {
+      self.check()?;
+      self.node_store.write_root_identifier(root_identifier)
+    }
+
+    fn sync(&self) -> Result<()> {
+      self.check()?;
+      self.node_store.sync()
+    }
+
+    fn max_node_size(&self) -> Option<usize> {
+      self.node_store.max_node_size()
+    }
+  }
+
+  #[test]
+  fn close_writes_everything_back_and_empties_the_log() {
+    let path = data_path("close-empties-log");
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let empty_log_len = file_len(&wal_path(&path));
+    insert_keys(&btree, 300);
+    assert!(empty_log_len < file_len(&wal_path(&path)));
+
+    let btree = Arc::try_unwrap(btree).ok().unwrap();
+    btree.close().unwrap();
+    assert_eq!(file_len(&wal_path(&path)), empty_log_len);
+
+    // With the log empty, every key must have come from the data file.
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    for idx in 0..300 {
+      assert_eq!(
+        txn.get(&key(idx)).unwrap(),
+        Some(format!("value {}", idx)),
+      );
+    }
+    txn.commit().unwrap();
+    btree.validate().unwrap();
+
+    drop(btree);
+    remove_files(&path);
+  }
+
+  #[test]
+  fn dropping_also_empties_the_log() {
+    let path = data_path("drop-empties-log");
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    let empty_log_len = file_len(&wal_path(&path));
+    insert_keys(&btree, 300);
+    drop(btree);
+    assert_eq!(file_len(&wal_path(&path)), empty_log_len);
+
+    remove_files(&path);
+  }
+
+  // The log must outlive a failed close, or the changes that weren't
+  // written back would be lost.
+  #[test]
+  fn close_reports_errors_and_keeps_the_log() {
+    let path = data_path("close-reports-errors");
+    let is_failing = Arc::new(AtomicBool::new(false));
+    let node_store = FailingNodeStore {
+      node_store: MemoryNodeStore::new(),
+      is_failing: Arc::clone(&is_failing),
+    };
+    let wal = WriteAheadLog::open(wal_path(&path)).unwrap();
+    let btree = Arc::new(
+      BTree::with_node_store(
+        Box::new(node_store),
+        Some(wal),
+        PAGE_SIZE,
+        usize::MAX,
+      )
+      .unwrap(),
+    );
+    let empty_log_len = file_len(&wal_path(&path));
+    insert_keys(&btree, 300);
+
+    is_failing.store(true, Ordering::SeqCst);
+    let btree = Arc::try_unwrap(btree).ok().unwrap();
+    assert!(matches!(btree.close(), Err(Error::Io(_))));
+    assert!(empty_log_len < file_len(&wal_path(&path)));
+
+    remove_files(&path);
+  }
+
+  #[test]
+  fn commits_checkpoint_once_the_log_grows() {
+    let path = data_path("commits-checkpoint");
+
+    let btree = open_with_small_checkpoints(&path);
+    let empty_log_len = file_len(&wal_path(&path));
+    insert_batches(&btree, 0, 2000);
+    // Without checkpoints, the log would hold every one of the inserts.
+    assert!(file_len(&wal_path(&path)) < empty_log_len + 16 * 1024);
+
+    // Crash, so that recovery has only what is left of the log.
+    let txn = btree.begin(TransactionMode::ReadWrite);
+    mem::forget(txn);
+    drop(btree);
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    check_keys(&btree, 0, 2000);
+    btree.validate().unwrap();
+
+    drop(btree);
+    remove_files(&path);
+  }
+
+  // A transaction that runs across checkpoints may still have to be
+  // undone, so its records must stay in the log.
+  #[test]
+  fn checkpoints_keep_the_records_of_running_transactions() {
+    let path = data_path("checkpoints-keep-running");
+
+    let btree = open_with_small_checkpoints(&path);
+    insert_batches(&btree, 0, 500);
+    let mut running_txn = btree.begin(TransactionMode::ReadWrite);
+    // This sorts before every other key, so no one waits for its lock.
+    running_txn
+      .insert(&String::from("aaa"), String::from("uncommitted"))
+      .unwrap();
+    let log_len = file_len(&wal_path(&path));
+    insert_batches(&btree, 500, 2000);
+    assert!(file_len(&wal_path(&path)) > log_len);
+    // There was a checkpoint meanwhile.
+    assert!(btree.log_size_at_checkpoint.load(Ordering::SeqCst) > 0);
+
+    mem::forget(running_txn);
+    drop(btree);
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    check_keys(&btree, 0, 2000);
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(txn.get(&String::from("aaa")).unwrap(), None);
+    txn.commit().unwrap();
+    btree.validate().unwrap();
+
+    drop(btree);
+    remove_files(&path);
+  }
+
+  // A third of a node is the limit, so a 4 KB page takes 1 KB keys, and
+  // values of 600 bytes.
+  #[test]
+  fn big_keys_and_values_fit() {
+    let path = data_path("big-entries");
+    let entries: Vec<(String, String)> = (0..200)
+      .map(|idx| {
+        if idx % 2 == 0 {
+          (format!("{:04}{}", idx, "k".repeat(1020)), String::from("v"))
+        } else {
+          (format!("{:04}", idx), "v".repeat(600))
+        }
+      })
       .collect();
 
-    chars
+    {
+      let btree = Arc::new(BTree::open(&path, 4096).unwrap());
+      let mut txn = btree.begin(TransactionMode::ReadWrite);
+      // Out of order, so that nodes split in the middle too.
+      for idx in 0..entries.len() {
+        let (key, value) = &entries[(idx * 7) % entries.len()];
+        txn.insert(key, value.clone()).unwrap();
+      }
+      txn.commit().unwrap();
+    }
+
+    let btree = Arc::new(BTree::open(&path, 4096).unwrap());
+    btree.validate().unwrap();
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let contents: Vec<(String, String)> =
+      txn.range(..).unwrap().map(|entry| entry.unwrap()).collect();
+    assert_eq!(contents, entries);
+    txn.commit().unwrap();
+
+    drop(btree);
+    remove_files(&path);
   }
 
-  pub fn store_node(&self, node: Node) {
-    // First put node in Arc and RwLock.
-    let identifier = String::from(node.identifier());
-    let node = Arc::new(RwLock::new(node));
+  #[test]
+  fn prefix_compressed_nodes_round_trip_through_the_page_file() {
+    let path = data_path("prefix-round-trip");
+    let prefix = "a/long/prefix/that/every/key/shares/";
+    let entries: Vec<(String, String)> = (0..2000)
+      .map(|idx| (format!("{}{:05}", prefix, idx), format!("{}", idx)))
+      .collect();
+
+    let mut btree = BTree::open(&path, PAGE_SIZE).unwrap();
+    btree.bulk_load(entries.clone(), 1.0).unwrap();
+    let images = node_images(&btree);
+    // Written in full, the keys alone would take up this many pages.
+    let key_bytes = entries.len() * (prefix.len() + 5);
+    assert!(images.len() < key_bytes / PAGE_SIZE);
+    btree.close().unwrap();
+
+    let btree = Arc::new(BTree::open(&path, PAGE_SIZE).unwrap());
+    assert_eq!(node_images(&btree), images);
+    btree.validate().unwrap();
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let contents: Vec<(String, String)> =
+      txn.range(..).unwrap().map(|entry| entry.unwrap()).collect();
+    assert_eq!(contents, entries);
+    txn.commit().unwrap();
 
-    // And then store it.
-    self
-      .identifier_to_node_arc_lock_map
-      .write()
-      .insert(identifier, node);
+    drop(btree);
+    remove_files(&path);
   }
 }
@@ -1,13 +1,20 @@
 use super::BTree;
+use error::Result;
+use key::{Key, Value};
 use locking::LockSet;
 use node::Node;
+use std::sync::Arc;
+use transaction::TransactionMode;
+
+impl<K: Key, V: Value> BTree<K, V> {
+  pub fn validate(self: &Arc<Self>) -> Result<()> {
+    let mut lock_set = LockSet::new(self, TransactionMode::ReadOnly);
 
-impl BTree {
-  pub fn validate(&self, lock_set: &mut LockSet) {
     // Checking starts at the root.
-    let root_identifier_guard = lock_set.temp_root_identifier_read_guard();
-    let root_identifier = root_identifier_guard.identifier();
+    let root_identifier_guard =
+      lock_set.temp_root_identifier_read_guard()?;
+    let root_identifier = root_identifier_guard.identifier()?;
 
-    Node::validate_root(lock_set, root_identifier.as_str());
+    Node::validate_root(&mut lock_set, *root_identifier)
   }
 }
@@ -0,0 +1,35 @@
+use btree::BTree;
+use key::{Key, Value};
+use locking::LockSet;
+
+// Once a Transaction has changed a key in a leaf (and put the change in
+// its undo log), the value it replaced is saved in the VersionStore,
+// for the Snapshot transactions that must not see the change. Like
+// logging, this happens while we hold the write latch on the leaf, so
+// no snapshot can read the new value before the old one is saved.
+//
+// Without a snapshot running, nothing is saved. A snapshot that begins
+// later finds the replaced values in the undo log instead.
+//
+// A rollback restores the saved values itself, and then throws away
+// its versions with `VersionStore::abort`. So nothing is saved for
+// compensating changes.
+impl<K: Key, V: Value> BTree<K, V> {
+  pub(in btree) fn version_leaf_change(
+    &self,
+    lock_set: &LockSet<K, V>,
+    key: &K,
+    previous_value: Option<&V>,
+  ) {
+    if lock_set.is_rolling_back() || !self.version_store.is_versioning()
+    {
+      return;
+    }
+
+    self.version_store.save_previous_version(
+      lock_set.transaction_id(),
+      key,
+      previous_value.cloned(),
+    );
+  }
+}
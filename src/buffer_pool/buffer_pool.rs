@@ -0,0 +1,672 @@
+use super::{Frame, FrameEntry, FrameTable, NodeLatch, NodePin};
+use error::{Error, Result};
+use key::{Key, Value};
+use node::Node;
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use storage::{NodeIdentifier, NodeStore};
+use wal::{Lsn, WriteAheadLog};
+
+// The BufferPool sits between the guards and the NodeStore. It keeps at
+// most `capacity` nodes in memory, reading a node from the NodeStore
+// when someone pins it and it isn't already here.
+//
+// To make room, we evict a node that nobody has pinned, chosen by the
+// CLOCK policy. A dirty node is written back to the NodeStore before it
+// is evicted. If there is a WriteAheadLog, we first flush the log up to
+// the node's LSN, so that every change in the node can be undone.
+//
+// For a checkpoint's sake, each dirty Frame also remembers how old a
+// record may have changed it since it was last written back. See
+// `oldest_unwritten_lsn`.
+//
+// If every node is pinned, there is nothing we can evict. Rather than
+// fail, we let the pool grow past its capacity; it shrinks back as pins
+// are released and more nodes are read in. Failing would abandon a
+// transaction partway through a split.
+//
+// Pinning a node that is already here takes no lock at all: we find its
+// Frame in the FrameTable, and count the pin in the Frame. Everything
+// else is bookkeeping, under the mutex. We let go of it to read or
+// write a node (and to flush the log), so that one slow read doesn't
+// hold up everyone. While a node is being read in, its identifier is
+// *loading*, and anyone else who pins it waits for us. While a Frame is
+// being written back, it can't be evicted, and no one else writes it
+// back at the same time.
+pub struct BufferPool<K: Key, V: Value> {
+  capacity: usize,
+  node_store: Box<dyn NodeStore>,
+  wal: Option<Arc<WriteAheadLog>>,
+  frame_table: FrameTable<K, V>,
+  frames: Mutex<Frames<K, V>>,
+  // Notified whenever a node has been read in or written back, or let
+  // go of by the last who had it pinned.
+  io_finished: Condvar,
+  // Unpinning doesn't take the mutex, so it only notifies `io_finished`
+  // if someone is waiting for a node to be unpinned.
+  num_waiting_for_unpin: AtomicUsize,
+  // Set while there are more frames than `capacity`. Then unpinning
+  // takes the mutex after all, to shrink the pool back down.
+  is_over_capacity: AtomicBool,
+}
+
+struct Frames<K: Key, V: Value> {
+  frames: Vec<FrameEntry<K, V>>,
+  frame_idxs: HashMap<NodeIdentifier, usize>,
+  // Evicted Frames, to be reused. (See `Frame`.)
+  free_frames: Vec<&'static Frame<K, V>>,
+  loading: HashSet<NodeIdentifier>,
+  clock_hand: usize,
+}
+
+impl<K: Key, V: Value> BufferPool<K, V> {
+  pub fn new(
+    node_store: Box<dyn NodeStore>,
+    wal: Option<Arc<WriteAheadLog>>,
+    capacity: usize,
+  ) -> BufferPool<K, V> {
+    BufferPool {
+      capacity,
+      node_store,
+      wal,
+      frame_table: FrameTable::new(),
+      frames: Mutex::new(Frames {
+        frames: vec![],
+        frame_idxs: HashMap::new(),
+        free_frames: vec![],
+        loading: HashSet::new(),
+        clock_hand: 0,
+      }),
+      io_finished: Condvar::new(),
+      num_waiting_for_unpin: AtomicUsize::new(0),
+      is_over_capacity: AtomicBool::new(false),
+    }
+  }
+
+  pub fn node_store(&self) -> &dyn NodeStore {
+    &*self.node_store
+  }
+
+  pub fn pin(
+    self: &Arc<Self>,
+    identifier: NodeIdentifier,
+  ) -> Result<NodePin<K, V>> {
+    if let Some(pin) = self.try_pin_cached(identifier) {
+      return Ok(pin);
+    }
+
+    let mut frames = self.frames.lock();
+    self.shrink(&mut frames)?;
+
+    // If someone else is reading the node in, we wait for them.
+    loop {
+      if let Some(frame_idx) =
+        frames.frame_idxs.get(&identifier).cloned()
+      {
+        // Nothing evicts the Frame while we hold the mutex.
+        let frame = frames.frames[frame_idx].frame;
+        if frame.try_pin() {
+          return Ok(NodePin::new(frame, Arc::clone(self)));
+        }
+      }
+      if !frames.loading.contains(&identifier) {
+        break;
+      }
+      self.io_finished.wait(&mut frames);
+    }
+
+    // Make room for one more.
+    frames.loading.insert(identifier);
+    let mut result = Ok(true);
+    while frames.frames.len() >= self.capacity {
+      result = self.evict(&mut frames);
+      if result != Ok(true) {
+        break;
+      }
+    }
+
+    let result = result.and_then(|_| {
+      MutexGuard::unlocked(&mut frames, || {
+        match self.node_store.read_node(identifier)? {
+          None => Err(Error::NodeNotFound(identifier)),
+          Some(bytes) => Node::deserialize(&bytes),
+        }
+      })
+    });
+    frames.loading.remove(&identifier);
+    self.io_finished.notify_all();
+
+    let frame = self.push_frame(&mut frames, result?, false);
+    if !frame.try_pin() {
+      return Err(Error::InvariantViolation(
+        "a Frame just read in can't be evicted",
+      ));
+    }
+    Ok(NodePin::new(frame, Arc::clone(self)))
+  }
+
+  // Without the mutex, the Frame we find may be evicted (or even reused
+  // for another node) as we pin it. Then we leave it to `pin`.
+  fn try_pin_cached(
+    self: &Arc<Self>,
+    identifier: NodeIdentifier,
+  ) -> Option<NodePin<K, V>> {
+    let frame = self.frame_table.get(identifier)?;
+    if !frame.try_pin() {
+      return None;
+    }
+    if frame.identifier() != identifier {
+      self.unpin(frame);
+      return None;
+    }
+
+    Some(NodePin::new(frame, Arc::clone(self)))
+  }
+
+  // Puts the node in a Frame (a free one, if there is one), and the
+  // Frame in the FrameTable.
+  fn push_frame(
+    &self,
+    frames: &mut Frames<K, V>,
+    node: Node<K, V>,
+    is_dirty: bool,
+  ) -> &'static Frame<K, V> {
+    let identifier = node.identifier();
+    let frame = match frames.free_frames.pop() {
+      Some(frame) => {
+        frame.reuse(node);
+        frame
+      }
+      None => Box::leak(Box::new(Frame::new(node))),
+    };
+
+    let mut frame_entry = FrameEntry::new(frame, is_dirty);
+    if is_dirty {
+      frame_entry.oldest_unwritten_lsn = self.next_lsn();
+    }
+    frames.push(frame_entry);
+    self.frame_table.insert(identifier, frame);
+    if frames.frames.len() > self.capacity {
+      self.is_over_capacity.store(true, Ordering::SeqCst);
+    }
+    frame
+  }
+
+  // Evicts down to capacity, if we grew past it earlier and there is
+  // anything we can evict now.
+  fn shrink(
+    &self,
+    frames: &mut MutexGuard<'_, Frames<K, V>>,
+  ) -> Result<()> {
+    while frames.frames.len() > self.capacity {
+      if !self.evict(frames)? {
+        return Ok(());
+      }
+    }
+    self.is_over_capacity.store(false, Ordering::SeqCst);
+    Ok(())
+  }
+
+  // A brand new node has never been written back, so it starts dirty.
+  // We don't evict anything to make room; the next pin will.
+  pub fn insert_new_node(&self, node: Node<K, V>) {
+    let mut frames = self.frames.lock();
+    self.push_frame(&mut frames, node, true);
+  }
+
+  // Forgets the node without writing it back. Nobody can reach the
+  // node any more, but someone who reached it before may still have it
+  // pinned. We wait for them to let go, and for any write back to
+  // finish, so that neither can land after the identifier is handed
+  // out again.
+  pub fn discard(&self, identifier: NodeIdentifier) {
+    self.num_waiting_for_unpin.fetch_add(1, Ordering::SeqCst);
+    let mut frames = self.frames.lock();
+    while let Some(frame_idx) =
+      frames.frame_idxs.get(&identifier).cloned()
+    {
+      let frame_entry = &frames.frames[frame_idx];
+      if !frame_entry.is_writing_back && frame_entry.frame.try_evict() {
+        self.remove_frame(&mut frames, frame_idx);
+        break;
+      }
+      self.io_finished.wait(&mut frames);
+    }
+    drop(frames);
+    self.num_waiting_for_unpin.fetch_sub(1, Ordering::SeqCst);
+  }
+
+  pub(in buffer_pool) fn unpin(&self, frame: &Frame<K, V>) {
+    if !frame.unpin() {
+      return;
+    }
+
+    // Whoever waits checks under the mutex, so we must notify under it
+    // too, or they could miss it.
+    if self.num_waiting_for_unpin.load(Ordering::SeqCst) > 0 {
+      let _frames = self.frames.lock();
+      self.io_finished.notify_all();
+    }
+
+    // Pins of cached nodes don't take the mutex, so they can't shrink
+    // the pool. If we have grown, we shrink it here instead. Should a
+    // write back fail, the next pin that reads a node in retries it.
+    if self.is_over_capacity.load(Ordering::SeqCst) {
+      let mut frames = self.frames.lock();
+      let _ = self.shrink(&mut frames);
+    }
+  }
+
+  // The change about to be made will be logged with an LSN no older
+  // than `since_lsn`. If the node was already dirty, older changes may
+  // not have been written back yet. The same goes while it is being
+  // written back, since the write may fail.
+  pub(in buffer_pool) fn mark_dirty(
+    &self,
+    identifier: NodeIdentifier,
+    since_lsn: Option<Lsn>,
+  ) {
+    let since_lsn = since_lsn.unwrap_or_else(|| self.next_lsn());
+    let mut frames = self.frames.lock();
+    let frame_idx = frames.frame_idxs[&identifier];
+    let frame_entry = &mut frames.frames[frame_idx];
+    if frame_entry.is_dirty || frame_entry.is_writing_back {
+      frame_entry.oldest_unwritten_lsn =
+        frame_entry.oldest_unwritten_lsn.min(since_lsn);
+    } else {
+      frame_entry.oldest_unwritten_lsn = since_lsn;
+    }
+    frame_entry.is_dirty = true;
+  }
+
+  // No change older than this can be missing from the NodeStore (once
+  // it is synced). None if every node has been written back.
+  pub fn oldest_unwritten_lsn(&self) -> Option<Lsn> {
+    self
+      .frames
+      .lock()
+      .frames
+      .iter()
+      .filter(|frame_entry| {
+        frame_entry.is_dirty || frame_entry.is_writing_back
+      })
+      .map(|frame_entry| frame_entry.oldest_unwritten_lsn)
+      .min()
+  }
+
+  fn next_lsn(&self) -> Lsn {
+    match &self.wal {
+      None => 0,
+      Some(wal) => wal.next_lsn(),
+    }
+  }
+
+  // Writes every dirty node back to the NodeStore. A node that is write
+  // locked right now may be halfway through a change, so we leave it
+  // dirty for next time. A node that someone else is writing back is
+  // written by the time we return.
+  pub fn flush(&self) -> Result<()> {
+    let mut frames = self.frames.lock();
+    let identifiers: Vec<NodeIdentifier> = frames
+      .frames
+      .iter()
+      .filter(|frame_entry| {
+        frame_entry.is_dirty || frame_entry.is_writing_back
+      })
+      .map(|frame_entry| frame_entry.identifier())
+      .collect();
+
+    for identifier in identifiers {
+      while let Some(frame_idx) =
+        frames.frame_idxs.get(&identifier).cloned()
+      {
+        let frame_entry = &frames.frames[frame_idx];
+        if frame_entry.is_writing_back {
+          self.io_finished.wait(&mut frames);
+          continue;
+        }
+        if frame_entry.is_dirty {
+          self.write_back(&mut frames, frame_idx)?;
+        }
+        break;
+      }
+    }
+
+    Ok(())
+  }
+
+  // Writes the Frame back without holding the mutex. We call the Frame
+  // clean before we start, so that a change made meanwhile makes it
+  // dirty again. Returns false if the node was write locked, and so
+  // wasn't written.
+  fn write_back(
+    &self,
+    frames: &mut MutexGuard<'_, Frames<K, V>>,
+    frame_idx: usize,
+  ) -> Result<bool> {
+    let frame_entry = &mut frames.frames[frame_idx];
+    frame_entry.is_dirty = false;
+    frame_entry.is_writing_back = true;
+    let identifier = frame_entry.identifier();
+    let node = Arc::clone(frame_entry.frame.node());
+
+    let result = MutexGuard::unlocked(frames, || {
+      self.write_node(identifier, &node)
+    });
+
+    // Nobody evicts or discards a Frame being written back, but others
+    // may have moved it.
+    let frame_idx = frames.frame_idxs[&identifier];
+    let frame_entry = &mut frames.frames[frame_idx];
+    frame_entry.is_writing_back = false;
+    if result != Ok(true) {
+      frame_entry.is_dirty = true;
+    }
+    self.io_finished.notify_all();
+
+    result
+  }
+
+  fn write_node(
+    &self,
+    identifier: NodeIdentifier,
+    node: &NodeLatch<K, V>,
+  ) -> Result<bool> {
+    let node = match node.try_read() {
+      None => return Ok(false),
+      Some(node) => node,
+    };
+
+    if let Some(wal) = &self.wal {
+      wal.flush_to(node.lsn())?;
+    }
+    self.node_store.write_node(identifier, &node.serialize())?;
+    Ok(true)
+  }
+
+  // Sweeps the CLOCK hand around until it finds an unpinned frame whose
+  // reference bit is clear. Returns false if every frame is pinned.
+  //
+  // Writing back a dirty frame lets go of the mutex, so by the time it
+  // is written, someone may have pinned it again. Even without letting
+  // go, someone may pin it at any time (see `try_pin_cached`), so we
+  // only know it is unpinned once `try_evict` succeeds.
+  fn evict(
+    &self,
+    frames: &mut MutexGuard<'_, Frames<K, V>>,
+  ) -> Result<bool> {
+    // Two full sweeps are enough: the first clears every reference bit.
+    for _ in 0..(2 * frames.frames.len()) {
+      if frames.clock_hand >= frames.frames.len() {
+        frames.clock_hand = 0;
+      }
+      let frame_idx = frames.clock_hand;
+      frames.clock_hand += 1;
+
+      let frame_entry = &frames.frames[frame_idx];
+      if frame_entry.frame.is_pinned() || frame_entry.is_writing_back {
+        continue;
+      }
+      if frame_entry.frame.take_reference_bit() {
+        continue;
+      }
+
+      let identifier = frame_entry.identifier();
+      if frame_entry.is_dirty && !self.write_back(frames, frame_idx)? {
+        continue;
+      }
+
+      let frame_idx = match frames.frame_idxs.get(&identifier) {
+        None => continue,
+        Some(frame_idx) => *frame_idx,
+      };
+      let frame_entry = &frames.frames[frame_idx];
+      if frame_entry.is_dirty
+        || frame_entry.is_writing_back
+        || !frame_entry.frame.try_evict()
+      {
+        continue;
+      }
+
+      self.remove_frame(frames, frame_idx);
+      return Ok(true);
+    }
+
+    Ok(false)
+  }
+
+  // The Frame must be evicted already. It goes on the free list.
+  fn remove_frame(&self, frames: &mut Frames<K, V>, frame_idx: usize) {
+    let frame = frames.remove(frame_idx);
+    self.frame_table.remove(frame.identifier());
+    frames.free_frames.push(frame);
+  }
+}
+
+impl<K: Key, V: Value> Frames<K, V> {
+  fn push(&mut self, frame_entry: FrameEntry<K, V>) -> usize {
+    let frame_idx = self.frames.len();
+    self.frame_idxs.insert(frame_entry.identifier(), frame_idx);
+    self.frames.push(frame_entry);
+    frame_idx
+  }
+
+  // The last frame moves into the removed frame's slot.
+  fn remove(&mut self, frame_idx: usize) -> &'static Frame<K, V> {
+    let frame_entry = self.frames.swap_remove(frame_idx);
+    self.frame_idxs.remove(&frame_entry.identifier());
+
+    if let Some(moved_frame_entry) = self.frames.get(frame_idx) {
+      self
+        .frame_idxs
+        .insert(moved_frame_entry.identifier(), frame_idx);
+    }
+    frame_entry.frame
+  }
+}
+
+// Every NodePin holds on to the BufferPool, so nobody has a Frame
+// pinned (or is about to pin one) any more.
+impl<K: Key, V: Value> Drop for BufferPool<K, V> {
+  fn drop(&mut self) {
+    let frames = self.frames.get_mut();
+    let all_frames = frames
+      .frames
+      .drain(..)
+      .map(|frame_entry| frame_entry.frame)
+      .chain(frames.free_frames.drain(..));
+    for frame in all_frames {
+      let frame = frame as *const Frame<K, V> as *mut Frame<K, V>;
+      drop(unsafe { Box::from_raw(frame) });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::BufferPool;
+  use btree::BTree;
+  use node::LeafNode;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::sync::mpsc;
+  use std::sync::Arc;
+  use std::thread;
+  use std::time::Duration;
+  use storage::{MemoryNodeStore, NodeIdentifier};
+
+  // A BTree whose BufferPool holds `capacity` nodes, plus
+  // `num_leaves` empty leaves that nothing points to. The leaves have
+  // never been written back.
+  fn btree_with_leaves(
+    capacity: usize,
+    num_leaves: usize,
+  ) -> (BTree<String, String>, Vec<NodeIdentifier>) {
+    let btree = BTree::with_node_store(
+      Box::new(MemoryNodeStore::new()),
+      None,
+      256,
+      capacity,
+    )
+    .unwrap();
+    let leaves = (0..num_leaves)
+      .map(|_| LeafNode::empty(&btree))
+      .collect();
+    (btree, leaves)
+  }
+
+  fn is_cached(
+    buffer_pool: &BufferPool<String, String>,
+    identifier: NodeIdentifier,
+  ) -> bool {
+    buffer_pool.frames.lock().frame_idxs.contains_key(&identifier)
+  }
+
+  fn num_frames(buffer_pool: &BufferPool<String, String>) -> usize {
+    buffer_pool.frames.lock().frames.len()
+  }
+
+  #[test]
+  fn pool_grows_while_everything_is_pinned_then_shrinks() {
+    let (btree, leaves) = btree_with_leaves(4, 8);
+    let buffer_pool = &btree.buffer_pool;
+
+    let pins = leaves
+      .iter()
+      .map(|&identifier| buffer_pool.pin(identifier).unwrap())
+      .collect::<Vec<_>>();
+    assert!(num_frames(buffer_pool) >= leaves.len());
+    drop(pins);
+
+    // The next pin evicts down to capacity. The leaves were dirty, so
+    // they had to be written back first: we can still read each one.
+    drop(buffer_pool.pin(leaves[0]).unwrap());
+    assert!(num_frames(buffer_pool) <= 4);
+    for &identifier in &leaves {
+      drop(buffer_pool.pin(identifier).unwrap());
+      assert!(num_frames(buffer_pool) <= 4);
+    }
+  }
+
+  #[test]
+  fn pinned_nodes_are_never_evicted() {
+    let (btree, leaves) = btree_with_leaves(2, 6);
+    let buffer_pool = &btree.buffer_pool;
+
+    let pin = buffer_pool.pin(leaves[0]).unwrap();
+    for _ in 0..3 {
+      for &identifier in &leaves[1..] {
+        drop(buffer_pool.pin(identifier).unwrap());
+        assert!(is_cached(buffer_pool, leaves[0]));
+      }
+    }
+
+    // Pinning again hands out the very same node.
+    let second_pin = buffer_pool.pin(leaves[0]).unwrap();
+    assert!(Arc::ptr_eq(pin.node(), second_pin.node()));
+  }
+
+  #[test]
+  fn clock_gives_recently_pinned_nodes_a_second_chance() {
+    let (btree, leaves) = btree_with_leaves(usize::MAX, 3);
+    let buffer_pool = &btree.buffer_pool;
+    buffer_pool.flush().unwrap();
+    for frame_entry in &buffer_pool.frames.lock().frames {
+      frame_entry.frame.take_reference_bit();
+    }
+
+    // Only leaves[1] has been pinned since the hand last came around.
+    // So even with the hand pointing at it, it is passed over.
+    drop(buffer_pool.pin(leaves[1]).unwrap());
+    let mut frames = buffer_pool.frames.lock();
+    let frame_idx = frames.frame_idxs[&leaves[1]];
+    frames.clock_hand = frame_idx;
+    assert_eq!(buffer_pool.evict(&mut frames), Ok(true));
+    assert_eq!(frames.frames.len(), 3);
+    assert!(frames.frame_idxs.contains_key(&leaves[1]));
+
+    // Its second chance used up, it goes next time the hand comes by.
+    let frame_idx = frames.frame_idxs[&leaves[1]];
+    frames.clock_hand = frame_idx;
+    assert_eq!(buffer_pool.evict(&mut frames), Ok(true));
+    assert!(!frames.frame_idxs.contains_key(&leaves[1]));
+  }
+
+  #[test]
+  fn discard_waits_for_pins_to_be_released() {
+    let (btree, leaves) = btree_with_leaves(usize::MAX, 1);
+    let buffer_pool = Arc::clone(&btree.buffer_pool);
+    let pin = buffer_pool.pin(leaves[0]).unwrap();
+
+    let is_discarded = Arc::new(AtomicBool::new(false));
+    let handle = {
+      let buffer_pool = Arc::clone(&buffer_pool);
+      let is_discarded = Arc::clone(&is_discarded);
+      let identifier = leaves[0];
+      thread::spawn(move || {
+        buffer_pool.discard(identifier);
+        is_discarded.store(true, Ordering::SeqCst);
+      })
+    };
+
+    thread::sleep(Duration::from_millis(50));
+    assert!(!is_discarded.load(Ordering::SeqCst));
+    assert!(is_cached(&buffer_pool, leaves[0]));
+
+    drop(pin);
+    handle.join().unwrap();
+    assert!(!is_cached(&buffer_pool, leaves[0]));
+  }
+
+  #[test]
+  fn pinning_a_cached_node_takes_no_lock() {
+    let (btree, leaves) = btree_with_leaves(usize::MAX, 1);
+    let buffer_pool = Arc::clone(&btree.buffer_pool);
+    drop(buffer_pool.pin(leaves[0]).unwrap());
+
+    let _frames = buffer_pool.frames.lock();
+    let (sender, receiver) = mpsc::channel();
+    let handle = {
+      let buffer_pool = Arc::clone(&buffer_pool);
+      let identifier = leaves[0];
+      thread::spawn(move || {
+        let pin = buffer_pool.pin(identifier).unwrap();
+        sender.send(pin.node().read().identifier()).unwrap();
+      })
+    };
+
+    let identifier = receiver.recv_timeout(Duration::from_secs(5));
+    assert_eq!(identifier, Ok(leaves[0]));
+    handle.join().unwrap();
+  }
+
+  #[test]
+  fn concurrent_pins_and_evictions_hand_out_the_right_nodes() {
+    let (btree, leaves) = btree_with_leaves(4, 16);
+    let buffer_pool = Arc::clone(&btree.buffer_pool);
+
+    // Each thread strides through the leaves at its own pace, so they
+    // keep evicting each other's nodes.
+    let handles = (0..4)
+      .map(|thread_idx| {
+        let buffer_pool = Arc::clone(&buffer_pool);
+        let leaves = leaves.clone();
+        thread::spawn(move || {
+          for idx in 0..2000 {
+            let leaf_idx = (idx * (2 * thread_idx + 1)) % leaves.len();
+            let identifier = leaves[leaf_idx];
+            let pin = buffer_pool.pin(identifier).unwrap();
+            assert_eq!(pin.node().read().identifier(), identifier);
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+    for handle in handles {
+      handle.join().unwrap();
+    }
+
+    // Once everyone has let go, we are back down to capacity.
+    assert!(num_frames(&buffer_pool) <= 4);
+  }
+}
@@ -0,0 +1,186 @@
+use super::NodeLatch;
+use key::{Key, Value};
+use node::Node;
+use std::cell::UnsafeCell;
+use std::hint;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use storage::NodeIdentifier;
+use wal::Lsn;
+
+// A Frame is one slot in the BufferPool. Readers find it through the
+// FrameTable and pin it without the BufferPool's mutex, so the Frame
+// itself keeps the pin count.
+//
+// When a Frame is evicted, someone may have just found it in the
+// FrameTable, and be about to pin it. So Frames are never freed while
+// the BufferPool lives; an evicted Frame is reused for the next node
+// read in. Pinning a Frame that was evicted fails, and pinning one that
+// was reused for another node is noticed (and undone) by checking the
+// identifier afterward. See `BufferPool::try_pin_cached`.
+pub struct Frame<K: Key, V: Value> {
+  identifier: AtomicU64,
+  // How many NodePins are out for the node, plus the flags below.
+  pin_state: AtomicUsize,
+  // Set whenever the node is pinned. The CLOCK hand clears it as it
+  // passes, so a Frame is only evicted if nobody pinned it since the
+  // hand last came around.
+  reference_bit: AtomicBool,
+  // Only changed while the Frame is evicted. So anyone who has the
+  // Frame pinned (or who holds the BufferPool's mutex, which every
+  // eviction takes) may read it.
+  node: UnsafeCell<Option<Arc<NodeLatch<K, V>>>>,
+}
+
+// Set once the Frame is evicted, until it is reused.
+const EVICTED: usize = 1 << (usize::BITS - 1);
+// Set while the last to unpin the node frees the copies that writers
+// replaced. Nobody may pin the node meanwhile; they wait. (See
+// `NodeLatch::reclaim_snapshots`.)
+const RECLAIMING: usize = 1 << (usize::BITS - 2);
+
+// What the BufferPool knows about a Frame that holds a node. Only
+// touched under the BufferPool's mutex.
+pub struct FrameEntry<K: Key, V: Value> {
+  pub frame: &'static Frame<K, V>,
+  // Set if the node may have changed since it was last written back.
+  pub is_dirty: bool,
+  // While the Frame is dirty (or being written back), no record older
+  // than this has changed the node since it was last written back. A
+  // checkpoint must keep the records from here on.
+  pub oldest_unwritten_lsn: Lsn,
+  // Set while someone writes the node back, without the BufferPool's
+  // mutex. The Frame can't be evicted or discarded until they're done.
+  pub is_writing_back: bool,
+}
+
+unsafe impl<K: Key, V: Value> Sync for Frame<K, V> {}
+
+impl<K: Key, V: Value> Frame<K, V> {
+  pub fn new(node: Node<K, V>) -> Frame<K, V> {
+    Frame {
+      identifier: AtomicU64::new(node.identifier()),
+      pin_state: AtomicUsize::new(0),
+      reference_bit: AtomicBool::new(true),
+      node: UnsafeCell::new(Some(Arc::new(NodeLatch::new(node)))),
+    }
+  }
+
+  pub fn identifier(&self) -> NodeIdentifier {
+    self.identifier.load(Ordering::Acquire)
+  }
+
+  // You must have the Frame pinned, or hold the BufferPool's mutex.
+  pub fn node(&self) -> &Arc<NodeLatch<K, V>> {
+    unsafe { &*self.node.get() }
+      .as_ref()
+      .expect("only an evicted Frame has no node")
+  }
+
+  // Fails if the Frame was evicted. Succeeds even if it was reused for
+  // another node; the caller must check the identifier.
+  pub fn try_pin(&self) -> bool {
+    let mut pin_state = self.pin_state.load(Ordering::Acquire);
+    loop {
+      if pin_state & EVICTED != 0 {
+        return false;
+      }
+      if pin_state & RECLAIMING != 0 {
+        hint::spin_loop();
+        pin_state = self.pin_state.load(Ordering::Acquire);
+        continue;
+      }
+
+      match self.pin_state.compare_exchange_weak(
+        pin_state,
+        pin_state + 1,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+      ) {
+        Ok(_) => break,
+        Err(new_pin_state) => pin_state = new_pin_state,
+      }
+    }
+
+    self.reference_bit.store(true, Ordering::Relaxed);
+    true
+  }
+
+  // The last to unpin the node frees the copies that writers replaced:
+  // only a pinned node can be read optimistically, so nobody is reading
+  // them. Returns whether we were the last.
+  pub fn unpin(&self) -> bool {
+    if self.pin_state.fetch_sub(1, Ordering::AcqRel) != 1 {
+      return false;
+    }
+
+    // Someone may have pinned it again already. Then they reclaim the
+    // copies when they unpin.
+    if self
+      .pin_state
+      .compare_exchange(
+        0,
+        RECLAIMING,
+        Ordering::AcqRel,
+        Ordering::Relaxed,
+      )
+      .is_ok()
+    {
+      self.node().reclaim_snapshots();
+      self.pin_state.store(0, Ordering::Release);
+    }
+    true
+  }
+
+  pub fn is_pinned(&self) -> bool {
+    self.pin_state.load(Ordering::Acquire) != 0
+  }
+
+  // Clears the reference bit, and says whether it was set.
+  pub fn take_reference_bit(&self) -> bool {
+    self.reference_bit.swap(false, Ordering::Relaxed)
+  }
+
+  // Must be called under the BufferPool's mutex. Fails if the node is
+  // pinned. Once evicted, nobody can pin the Frame until it is reused,
+  // so we can let go of the node.
+  pub fn try_evict(&self) -> bool {
+    let is_evicted = self
+      .pin_state
+      .compare_exchange(0, EVICTED, Ordering::AcqRel, Ordering::Relaxed)
+      .is_ok();
+    if is_evicted {
+      unsafe { *self.node.get() = None };
+    }
+    is_evicted
+  }
+
+  // Must be called under the BufferPool's mutex, on an evicted Frame,
+  // before it is put back in the FrameTable.
+  pub fn reuse(&self, node: Node<K, V>) {
+    self.identifier.store(node.identifier(), Ordering::Release);
+    unsafe {
+      *self.node.get() = Some(Arc::new(NodeLatch::new(node)));
+    }
+    self.reference_bit.store(true, Ordering::Relaxed);
+    self.pin_state.store(0, Ordering::Release);
+  }
+}
+
+impl<K: Key, V: Value> FrameEntry<K, V> {
+  pub fn new(
+    frame: &'static Frame<K, V>,
+    is_dirty: bool,
+  ) -> FrameEntry<K, V> {
+    FrameEntry {
+      frame,
+      is_dirty,
+      oldest_unwritten_lsn: 0,
+      is_writing_back: false,
+    }
+  }
+
+  pub fn identifier(&self) -> NodeIdentifier {
+    self.frame.identifier()
+  }
+}
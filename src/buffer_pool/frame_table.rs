@@ -0,0 +1,155 @@
+use super::Frame;
+use constants::FRAME_TABLE_CHUNK_SIZE;
+use key::{Key, Value};
+use parking_lot::Mutex;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use storage::NodeIdentifier;
+
+// The FrameTable finds the Frame holding a node without any lock, so
+// that pinning a node that is already in the BufferPool never waits on
+// the BufferPool's mutex.
+//
+// NodeIdentifiers count up from one, so the table is simply indexed by
+// identifier. It is split into chunks, which we only allocate once an
+// identifier in them is used. The directory of chunks is replaced by
+// one twice as big when identifiers outgrow it. Someone may still be
+// reading an old directory, so we keep those until the table is
+// dropped; they add up to less than the one in use.
+//
+// Only the BufferPool changes the table, and only while it holds its
+// mutex. Chunks and Frames are never freed while the table lives. (See
+// `Frame`.)
+pub struct FrameTable<K: Key, V: Value> {
+  directory: AtomicPtr<Directory<K, V>>,
+  // Boxed, since readers may still hold their address.
+  #[allow(clippy::vec_box)]
+  retired_directories: Mutex<Vec<Box<Directory<K, V>>>>,
+}
+
+struct Directory<K: Key, V: Value> {
+  chunks: Vec<AtomicPtr<Chunk<K, V>>>,
+}
+
+struct Chunk<K: Key, V: Value> {
+  frames: Vec<AtomicPtr<Frame<K, V>>>,
+}
+
+impl<K: Key, V: Value> FrameTable<K, V> {
+  pub fn new() -> FrameTable<K, V> {
+    FrameTable {
+      directory: AtomicPtr::new(Box::into_raw(Box::new(Directory {
+        chunks: vec![],
+      }))),
+      retired_directories: Mutex::new(vec![]),
+    }
+  }
+
+  pub fn get(
+    &self,
+    identifier: NodeIdentifier,
+  ) -> Option<&'static Frame<K, V>> {
+    let (chunk_idx, frame_idx) = Self::idxs(identifier);
+    let directory = unsafe { &*self.directory.load(Ordering::Acquire) };
+    let chunk =
+      directory.chunks.get(chunk_idx)?.load(Ordering::Acquire);
+    if chunk.is_null() {
+      return None;
+    }
+
+    let frame =
+      unsafe { &*chunk }.frames[frame_idx].load(Ordering::Acquire);
+    if frame.is_null() {
+      None
+    } else {
+      Some(unsafe { &*frame })
+    }
+  }
+
+  // Must be called under the BufferPool's mutex.
+  pub fn insert(
+    &self,
+    identifier: NodeIdentifier,
+    frame: &'static Frame<K, V>,
+  ) {
+    let (chunk_idx, frame_idx) = Self::idxs(identifier);
+    let chunk = self.chunk(chunk_idx);
+    chunk.frames[frame_idx]
+      .store(frame as *const _ as *mut _, Ordering::Release);
+  }
+
+  // Must be called under the BufferPool's mutex.
+  pub fn remove(&self, identifier: NodeIdentifier) {
+    let (chunk_idx, frame_idx) = Self::idxs(identifier);
+    let directory = unsafe { &*self.directory.load(Ordering::Acquire) };
+    if let Some(chunk) = directory.chunks.get(chunk_idx) {
+      let chunk = chunk.load(Ordering::Acquire);
+      if !chunk.is_null() {
+        unsafe { &*chunk }.frames[frame_idx]
+          .store(ptr::null_mut(), Ordering::Release);
+      }
+    }
+  }
+
+  fn idxs(identifier: NodeIdentifier) -> (usize, usize) {
+    let identifier = identifier as usize;
+    (
+      identifier / FRAME_TABLE_CHUNK_SIZE,
+      identifier % FRAME_TABLE_CHUNK_SIZE,
+    )
+  }
+
+  // Allocates the chunk (and grows the directory) if need be.
+  fn chunk(&self, chunk_idx: usize) -> &Chunk<K, V> {
+    let mut directory =
+      unsafe { &*self.directory.load(Ordering::Acquire) };
+    if chunk_idx >= directory.chunks.len() {
+      let num_chunks = (chunk_idx + 1).max(2 * directory.chunks.len());
+      let chunks = (0..num_chunks)
+        .map(|chunk_idx| match directory.chunks.get(chunk_idx) {
+          None => AtomicPtr::new(ptr::null_mut()),
+          Some(chunk) => AtomicPtr::new(chunk.load(Ordering::Acquire)),
+        })
+        .collect();
+      let new_directory = Box::into_raw(Box::new(Directory { chunks }));
+      let old_directory =
+        self.directory.swap(new_directory, Ordering::AcqRel);
+      self
+        .retired_directories
+        .lock()
+        .push(unsafe { Box::from_raw(old_directory) });
+      directory = unsafe { &*new_directory };
+    }
+
+    let chunk = &directory.chunks[chunk_idx];
+    if chunk.load(Ordering::Acquire).is_null() {
+      let frames = (0..FRAME_TABLE_CHUNK_SIZE)
+        .map(|_| AtomicPtr::new(ptr::null_mut()))
+        .collect();
+      let new_chunk = Box::into_raw(Box::new(Chunk { frames }));
+      chunk.store(new_chunk, Ordering::Release);
+    }
+    unsafe { &*chunk.load(Ordering::Acquire) }
+  }
+}
+
+impl<K: Key, V: Value> Default for FrameTable<K, V> {
+  fn default() -> FrameTable<K, V> {
+    FrameTable::new()
+  }
+}
+
+// Every chunk is in the directory in use. The Frames belong to the
+// BufferPool, which frees them itself.
+impl<K: Key, V: Value> Drop for FrameTable<K, V> {
+  fn drop(&mut self) {
+    let directory =
+      unsafe { Box::from_raw(*self.directory.get_mut()) };
+    for chunk in &directory.chunks {
+      let chunk = chunk.load(Ordering::Acquire);
+      if !chunk.is_null() {
+        drop(unsafe { Box::from_raw(chunk) });
+      }
+    }
+  }
+}
@@ -0,0 +1,13 @@
+#[allow(clippy::module_inception)]
+mod buffer_pool;
+mod frame;
+mod frame_table;
+mod node_latch;
+mod node_pin;
+
+pub(self) use self::frame::{Frame, FrameEntry};
+pub(self) use self::frame_table::FrameTable;
+
+pub use self::buffer_pool::BufferPool;
+pub use self::node_latch::{NodeLatch, NodeLatchWriteGuard};
+pub use self::node_pin::NodePin;
@@ -0,0 +1,297 @@
+use constants::MAX_RETIRED_SNAPSHOTS;
+use key::{Key, Value};
+use node::Node;
+use parking_lot::{
+  Mutex, RwLock, RwLockReadGuard, RwLockUpgradableReadGuard,
+  RwLockWriteGuard,
+};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+// A NodeLatch is the `RwLock` on a node, plus what we need to read the
+// node without taking the lock at all.
+//
+// The version is odd while someone holds the write lock, and is bumped
+// again when they let go. Readers that don't want to bounce the lock's
+// cache line read the version, read the node, and then check that the
+// version hasn't moved. If it has, they try again.
+//
+// We can't read the node itself while a writer changes it: its `Vec`s
+// may be reallocated under us. So optimistic readers read a copy of the
+// node instead. We only copy interior nodes. They change only when a
+// child splits (or is merged away), and they are what every descent
+// passes through.
+//
+// A writer doesn't make a copy; most writes are never read that way
+// before the next one. It just throws the old copy away. The first
+// reader to find no copy reads the node under the lock, and leaves a
+// copy behind for the readers after it.
+//
+// An old copy may still be read by whoever loaded it before the writer
+// replaced it. Readers always hold a NodePin, so we keep old copies
+// until the node is next unpinned by everyone. See `Frame::unpin`.
+//
+// A hot node may never be unpinned by everyone. So once
+// `MAX_RETIRED_SNAPSHOTS` old copies pile up, readers stop making new
+// ones, and latch the node instead, until the old ones are freed.
+pub struct NodeLatch<K: Key, V: Value> {
+  lock: RwLock<Node<K, V>>,
+  version: AtomicU64,
+  snapshot: AtomicPtr<Node<K, V>>,
+  retired_snapshots: Mutex<Vec<Box<Node<K, V>>>>,
+  // A node never turns from a leaf into an interior node, or back.
+  is_leaf: bool,
+}
+
+impl<K: Key, V: Value> NodeLatch<K, V> {
+  pub fn new(node: Node<K, V>) -> NodeLatch<K, V> {
+    let is_leaf = match node {
+      Node::LeafNode(..) => true,
+      Node::InteriorNode(..) => false,
+    };
+    NodeLatch {
+      lock: RwLock::new(node),
+      version: AtomicU64::new(0),
+      snapshot: AtomicPtr::new(ptr::null_mut()),
+      retired_snapshots: Mutex::new(vec![]),
+      is_leaf,
+    }
+  }
+
+  pub fn read(&self) -> RwLockReadGuard<'_, Node<K, V>> {
+    self.lock.read()
+  }
+
+  pub fn try_read(&self) -> Option<RwLockReadGuard<'_, Node<K, V>>> {
+    self.lock.try_read()
+  }
+
+  pub fn upgradable_read(
+    &self,
+  ) -> RwLockUpgradableReadGuard<'_, Node<K, V>> {
+    self.lock.upgradable_read()
+  }
+
+  pub fn write(&self) -> NodeLatchWriteGuard<'_, K, V> {
+    self.begin_write(self.lock.write())
+  }
+
+  pub fn upgrade<'a>(
+    &'a self,
+    guard: RwLockUpgradableReadGuard<'a, Node<K, V>>,
+  ) -> NodeLatchWriteGuard<'a, K, V> {
+    self.begin_write(RwLockUpgradableReadGuard::upgrade(guard))
+  }
+
+  // Calls `f` on the node without waiting for its lock. Returns None if
+  // the node is a leaf, or if it kept changing while we read it. You
+  // must hold a NodePin on the node.
+  pub fn read_optimistically<R, F>(
+    &self,
+    attempts: usize,
+    f: F,
+  ) -> Option<R>
+  where
+    F: Fn(&Node<K, V>) -> R,
+  {
+    if self.is_leaf {
+      return None;
+    }
+
+    for _ in 0..attempts {
+      let version = self.version.load(Ordering::Acquire);
+      if version % 2 == 1 {
+        // Someone is writing the node right now.
+        continue;
+      }
+
+      let snapshot = self.snapshot.load(Ordering::Acquire);
+      if snapshot.is_null() {
+        return self.read_and_publish_snapshot(&f);
+      }
+
+      // The copy is never changed, and our NodePin keeps it from being
+      // freed. (See `reclaim_snapshots`.)
+      let result = f(unsafe { &*snapshot });
+
+      if self.version.load(Ordering::Acquire) == version {
+        return Some(result);
+      }
+    }
+
+    None
+  }
+
+  // Nobody has made a copy since the last write. We make one, unless
+  // too many old ones are waiting to be freed. Holding the read lock
+  // keeps writers out meanwhile, but other readers may beat us to it.
+  // We don't wait for the lock: a writer has it, and will throw away
+  // whatever copy we'd make.
+  fn read_and_publish_snapshot<R, F>(&self, f: &F) -> Option<R>
+  where
+    F: Fn(&Node<K, V>) -> R,
+  {
+    let node = self.lock.try_read()?;
+    let interior_node = match &*node {
+      Node::InteriorNode(interior_node) => interior_node,
+      Node::LeafNode(..) => return None,
+    };
+    if self.retired_snapshots.lock().len() < MAX_RETIRED_SNAPSHOTS {
+      let snapshot = Box::into_raw(Box::new(Node::InteriorNode(
+        interior_node.clone(),
+      )));
+      let is_published = self
+        .snapshot
+        .compare_exchange(
+          ptr::null_mut(),
+          snapshot,
+          Ordering::AcqRel,
+          Ordering::Relaxed,
+        )
+        .is_ok();
+      if !is_published {
+        drop(unsafe { Box::from_raw(snapshot) });
+      }
+    }
+
+    Some(f(&node))
+  }
+
+  // Frees the copies that writers replaced. You may only call this when
+  // nobody has the node pinned, and so nobody can be reading them.
+  pub fn reclaim_snapshots(&self) {
+    self.retired_snapshots.lock().clear();
+  }
+
+  #[cfg(test)]
+  pub fn has_snapshot(&self) -> bool {
+    !self.snapshot.load(Ordering::Acquire).is_null()
+  }
+
+  fn begin_write<'a>(
+    &'a self,
+    guard: RwLockWriteGuard<'a, Node<K, V>>,
+  ) -> NodeLatchWriteGuard<'a, K, V> {
+    self.version.fetch_add(1, Ordering::AcqRel);
+    NodeLatchWriteGuard { latch: self, guard }
+  }
+
+  // Called while we still hold the write lock.
+  fn end_write(&self) {
+    let old_snapshot =
+      self.snapshot.swap(ptr::null_mut(), Ordering::AcqRel);
+    if !old_snapshot.is_null() {
+      let old_snapshot = unsafe { Box::from_raw(old_snapshot) };
+      self.retired_snapshots.lock().push(old_snapshot);
+    }
+
+    self.version.fetch_add(1, Ordering::Release);
+  }
+}
+
+impl<K: Key, V: Value> Drop for NodeLatch<K, V> {
+  fn drop(&mut self) {
+    let snapshot = *self.snapshot.get_mut();
+    if !snapshot.is_null() {
+      drop(unsafe { Box::from_raw(snapshot) });
+    }
+  }
+}
+
+// Throws away the copy of the node, and bumps the version, when
+// dropped.
+pub struct NodeLatchWriteGuard<'a, K: Key, V: Value> {
+  latch: &'a NodeLatch<K, V>,
+  guard: RwLockWriteGuard<'a, Node<K, V>>,
+}
+
+impl<'a, K: Key, V: Value> Deref for NodeLatchWriteGuard<'a, K, V> {
+  type Target = Node<K, V>;
+
+  fn deref(&self) -> &Node<K, V> {
+    &self.guard
+  }
+}
+
+impl<'a, K: Key, V: Value> DerefMut for NodeLatchWriteGuard<'a, K, V> {
+  fn deref_mut(&mut self) -> &mut Node<K, V> {
+    &mut self.guard
+  }
+}
+
+impl<'a, K: Key, V: Value> Drop for NodeLatchWriteGuard<'a, K, V> {
+  fn drop(&mut self) {
+    self.latch.end_write();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use node::Node;
+  use std::sync::Arc;
+  use transaction::TransactionMode;
+
+  // A BTree whose root is an interior node.
+  fn btree_with_interior_root() -> Arc<BTree<String, String>> {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..100 {
+      txn.insert(&format!("key{:04}", idx), String::from("v")).unwrap();
+    }
+    txn.commit().unwrap();
+    btree
+  }
+
+  fn is_leaf(node: &Node<String, String>) -> bool {
+    match node {
+      Node::LeafNode(..) => true,
+      Node::InteriorNode(..) => false,
+    }
+  }
+
+  #[test]
+  fn writes_leave_no_copy_until_a_reader_makes_one() {
+    let btree = btree_with_interior_root();
+    let root_identifier = *btree.root_identifier_lock().read();
+    let pin = btree.pin_node(root_identifier).unwrap();
+    let node = pin.node();
+
+    drop(node.write());
+    assert!(!node.has_snapshot());
+
+    // The first reader reads under the lock, and leaves a copy for the
+    // readers after it.
+    assert_eq!(node.read_optimistically(1, is_leaf), Some(false));
+    assert!(node.has_snapshot());
+    assert_eq!(node.read_optimistically(1, is_leaf), Some(false));
+
+    drop(node.write());
+    assert!(!node.has_snapshot());
+  }
+
+  #[test]
+  fn no_copy_is_made_while_a_writer_holds_the_node() {
+    let btree = btree_with_interior_root();
+    let root_identifier = *btree.root_identifier_lock().read();
+    let pin = btree.pin_node(root_identifier).unwrap();
+    let node = pin.node();
+
+    let guard = node.write();
+    assert_eq!(node.read_optimistically(3, is_leaf), None);
+    assert!(!node.has_snapshot());
+    drop(guard);
+  }
+
+  #[test]
+  fn leaves_are_never_copied() {
+    let btree = BTree::<String, String>::new(256).unwrap();
+    let root_identifier = *btree.root_identifier_lock().read();
+    let pin = btree.pin_node(root_identifier).unwrap();
+    let node = pin.node();
+
+    assert_eq!(node.read_optimistically(1, is_leaf), None);
+    assert!(!node.has_snapshot());
+  }
+}
@@ -0,0 +1,55 @@
+use super::{BufferPool, Frame, NodeLatch};
+use key::{Key, Value};
+use std::sync::Arc;
+use storage::NodeIdentifier;
+use wal::Lsn;
+
+// A NodePin keeps its node in the BufferPool until the pin is dropped.
+// Guards hold a NodePin for as long as they hold the node's lock.
+//
+// The pin holds on to the BufferPool, which keeps the Frame alive. And
+// while the node is pinned, its Frame can't be evicted, so the Frame
+// keeps the node.
+pub struct NodePin<K: Key, V: Value> {
+  frame: &'static Frame<K, V>,
+  buffer_pool: Arc<BufferPool<K, V>>,
+}
+
+impl<K: Key, V: Value> NodePin<K, V> {
+  // The Frame must already be pinned for us.
+  pub(in buffer_pool) fn new(
+    frame: &'static Frame<K, V>,
+    buffer_pool: Arc<BufferPool<K, V>>,
+  ) -> NodePin<K, V> {
+    NodePin {
+      frame,
+      buffer_pool,
+    }
+  }
+
+  pub fn node(&self) -> &Arc<NodeLatch<K, V>> {
+    self.frame.node()
+  }
+
+  fn identifier(&self) -> NodeIdentifier {
+    self.frame.identifier()
+  }
+
+  // You must call this while holding the node's write lock. Then no
+  // flush can call the node clean until you are done changing it.
+  pub fn mark_dirty(&self) {
+    self.buffer_pool.mark_dirty(self.identifier(), None);
+  }
+
+  // Recovery replays old records. This says the node has been dirty
+  // since the record at `lsn`, rather than since now.
+  pub fn mark_dirty_since(&self, lsn: Lsn) {
+    self.buffer_pool.mark_dirty(self.identifier(), Some(lsn));
+  }
+}
+
+impl<K: Key, V: Value> Drop for NodePin<K, V> {
+  fn drop(&mut self) {
+    self.buffer_pool.unpin(self.frame);
+  }
+}
@@ -1 +1,52 @@
-pub const IDENTIFIER_LENGTH: usize = 8;
+use std::time::Duration;
+
+// The size of a page in a newly created data file.
+pub const PAGE_SIZE: usize = 4096;
+
+// No node may be smaller than this many bytes. (See `BTree::new`.)
+pub const MIN_NODE_SIZE: usize = 256;
+
+// An entry (a key and its value) may take up at most this fraction of a
+// node. A node's max value is a key too, so this is the least that
+// leaves room for a header, a max value and one entry: splitting an
+// overfull node must leave two nodes that fit. Any more, and a 1 KB key
+// wouldn't fit in a 4 KB page.
+pub const MIN_ENTRIES_PER_NODE: usize = 3;
+
+// Under `SplitPolicy::RightmostAppend`, the share of keys that stays in
+// the left node when an append splits the rightmost node.
+pub const RIGHTMOST_APPEND_SPLIT_FRACTION: f64 = 0.9;
+
+// How many nodes `BTree::open` keeps in memory.
+pub const DEFAULT_BUFFER_POOL_CAPACITY: usize = 1024;
+
+// How many Frames each chunk of the BufferPool's FrameTable points to.
+pub const FRAME_TABLE_CHUNK_SIZE: usize = 1024;
+
+// A BTree checkpoints once its log has grown by this many bytes. (See
+// `BTree::set_checkpoint_log_size`.)
+pub const DEFAULT_CHECKPOINT_LOG_SIZE: u64 = 16 * 1024 * 1024;
+
+// How often the log is flushed for transactions that commit with
+// `Commit::Async`.
+pub const ASYNC_COMMIT_INTERVAL: Duration = Duration::from_millis(10);
+
+// How many shards the VersionStore splits its versions into. Writers
+// running at the same time mostly save their versions to different
+// ones.
+pub const NUM_VERSION_STORE_SHARDS: u64 = 16;
+
+// How long a waiting transaction sleeps between checks for deadlock.
+pub const DEADLOCK_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+// Once a transaction holds this many key-range locks, it tries to lock
+// the whole tree instead.
+pub const KEY_LOCK_ESCALATION_THRESHOLD: usize = 4096;
+
+// How many times we try to read a node optimistically before we give
+// up and latch it.
+pub const OPTIMISTIC_READ_ATTEMPTS: usize = 3;
+
+// How many replaced copies of a node we keep for optimistic readers
+// before we stop making new ones. (See `NodeLatch`.)
+pub const MAX_RETIRED_SNAPSHOTS: usize = 8;
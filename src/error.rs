@@ -0,0 +1,131 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+use storage::NodeIdentifier;
+
+// These are the errors a query can run into. Whenever a query returns
+// an error, the Transaction it ran in has already been rolled back (or
+// tried to, see `RollbackFailed`).
+//
+// The first three are expected in normal operation; you may simply
+// retry the transaction. The rest mean that nedbase was misused, or
+// that something is badly wrong inside the tree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+  // The transaction was chosen as the victim to break a deadlock.
+  Deadlock,
+  // The transaction waited longer than its lock timeout for a lock.
+  LockTimeout,
+  // The transaction was already rolled back because of an earlier
+  // error. You must begin a new one.
+  TransactionAborted,
+
+  // Tried to take a write lock in a ReadOnly or Snapshot transaction.
+  ReadOnlyTransaction,
+  // Tried to hold a lock on a node that we only have a temporary read
+  // lock on. We can't upgrade the lock without deadlocking ourself.
+  TempLockHeld,
+  // No node has this identifier.
+  NodeNotFound(NodeIdentifier),
+  // A node or guard wasn't the kind we expected. The message says what
+  // we did expect.
+  InvariantViolation(&'static str),
+  // `BTree::validate` found a problem with the tree.
+  InvalidTree(String),
+
+  // Reading or writing the NodeStore failed.
+  Io(String),
+  // Something read back from the NodeStore doesn't make sense.
+  CorruptPage(String),
+  // The node with this identifier is too big to fit in a page.
+  PageOverflow(NodeIdentifier),
+  // `decode_key` was given bytes that no KeyEncoder wrote.
+  MalformedKey(String),
+  // An entry (a key and its value) took up this many bytes, which is
+  // more than `BTree::max_entry_size`.
+  EntryTooLarge(usize),
+  // Nodes would be allowed only this many bytes (say, because the
+  // pages are small), which is less than `MIN_NODE_SIZE`.
+  NodeSizeTooSmall(usize),
+  // `BTree::bulk_load` can't load these entries into this tree. The
+  // message says why.
+  InvalidBulkLoad(&'static str),
+  // A Transaction ran into `error`, and then failed to roll back with
+  // `rollback_error`. The changes it didn't undo are left for recovery,
+  // the next time the BTree is opened.
+  RollbackFailed {
+    error: Box<Error>,
+    rollback_error: Box<Error>,
+  },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Deadlock => {
+        write!(f, "transaction was aborted to break a deadlock")
+      }
+      Error::LockTimeout => write!(f, "timed out waiting for a lock"),
+      Error::TransactionAborted => {
+        write!(f, "transaction was already aborted")
+      }
+      Error::ReadOnlyTransaction => {
+        write!(
+          f,
+          "cannot acquire write locks in a read-only transaction"
+        )
+      }
+      Error::TempLockHeld => {
+        write!(f, "cannot hold a lock that is held as a temp read lock")
+      }
+      Error::NodeNotFound(identifier) => {
+        write!(f, "no node has identifier {}", identifier)
+      }
+      Error::InvariantViolation(message) => {
+        write!(f, "invariant violated: {}", message)
+      }
+      Error::InvalidTree(message) => {
+        write!(f, "invalid tree: {}", message)
+      }
+      Error::Io(message) => write!(f, "I/O error: {}", message),
+      Error::CorruptPage(message) => {
+        write!(f, "corrupt page: {}", message)
+      }
+      Error::PageOverflow(identifier) => {
+        write!(f, "node {} does not fit in a page", identifier)
+      }
+      Error::MalformedKey(message) => {
+        write!(f, "malformed key: {}", message)
+      }
+      Error::EntryTooLarge(entry_size) => {
+        write!(f, "entry of {} bytes is too large", entry_size)
+      }
+      Error::NodeSizeTooSmall(max_node_size) => {
+        write!(f, "nodes of {} bytes are too small", max_node_size)
+      }
+      Error::InvalidBulkLoad(message) => {
+        write!(f, "cannot bulk load: {}", message)
+      }
+      Error::RollbackFailed {
+        error,
+        rollback_error,
+      } => write!(
+        f,
+        "{}, and then rolling back failed: {}",
+        error, rollback_error
+      ),
+    }
+  }
+}
+
+impl error::Error for Error {}
+
+// io::Error isn't Clone, so we keep only its message.
+impl From<io::Error> for Error {
+  fn from(error: io::Error) -> Error {
+    Error::Io(error.to_string())
+  }
+}
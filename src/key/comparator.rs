@@ -0,0 +1,127 @@
+use super::{Key, Value};
+use error::Result;
+use std::cmp::Ordering;
+use std::fmt;
+use std::marker::PhantomData;
+use storage::{ByteReader, ByteWriter};
+
+// A Comparator sorts keys some other way than their own `compare`
+// does. Say, case-insensitively, or in descending order. It is never
+// instantiated; it only names the order.
+pub trait Comparator<K>: 'static {
+  fn compare(left: &K, right: &K) -> Ordering;
+}
+
+// To use a Comparator, wrap each key in an OrderedBy. For example, a
+// `BTree<OrderedBy<String, CaseInsensitive>, V>`.
+//
+// The Comparator is part of the key's type, so every node in a BTree
+// is sorted the same way. Like any key order, it must never change
+// once nodes were written with it.
+pub struct OrderedBy<K, C> {
+  key: K,
+  comparator: PhantomData<fn() -> C>,
+}
+
+impl<K, C> OrderedBy<K, C> {
+  pub fn new(key: K) -> OrderedBy<K, C> {
+    OrderedBy {
+      key,
+      comparator: PhantomData,
+    }
+  }
+
+  pub fn key(&self) -> &K {
+    &self.key
+  }
+
+  pub fn into_key(self) -> K {
+    self.key
+  }
+}
+
+// Deriving these would demand that the Comparator be Clone and Debug
+// too.
+impl<K: Clone, C> Clone for OrderedBy<K, C> {
+  fn clone(&self) -> OrderedBy<K, C> {
+    OrderedBy::new(self.key.clone())
+  }
+}
+
+impl<K: fmt::Debug, C> fmt::Debug for OrderedBy<K, C> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.key.fmt(f)
+  }
+}
+
+impl<K: Value, C: Comparator<K>> Value for OrderedBy<K, C> {
+  fn serialize(&self, writer: &mut ByteWriter) {
+    self.key.serialize(writer);
+  }
+
+  fn deserialize(reader: &mut ByteReader) -> Result<OrderedBy<K, C>> {
+    Ok(OrderedBy::new(K::deserialize(reader)?))
+  }
+
+  fn encoded_size(&self) -> usize {
+    self.key.encoded_size()
+  }
+}
+
+impl<K: Value, C: Comparator<K>> Key for OrderedBy<K, C> {
+  fn compare(&self, other: &OrderedBy<K, C>) -> Ordering {
+    C::compare(&self.key, &other.key)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Comparator, OrderedBy};
+  use btree::BTree;
+  use std::cmp::Ordering;
+  use std::sync::Arc;
+  use transaction::TransactionMode;
+
+  struct Descending;
+
+  impl Comparator<u64> for Descending {
+    fn compare(left: &u64, right: &u64) -> Ordering {
+      right.cmp(left)
+    }
+  }
+
+  type DescendingKey = OrderedBy<u64, Descending>;
+
+  // Everything the BTree does with keys, from searching nodes to
+  // scanning ranges, goes by the Comparator.
+  #[test]
+  fn a_btree_sorts_keys_by_their_comparator() {
+    let btree: Arc<BTree<DescendingKey, u64>> =
+      Arc::new(BTree::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..1000 {
+      let key = (idx * 7) % 1000;
+      txn.insert(&OrderedBy::new(key), key).unwrap();
+    }
+    txn.commit().unwrap();
+    btree.validate().unwrap();
+
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(txn.get(&OrderedBy::new(500)), Ok(Some(500)));
+    let keys: Vec<u64> = txn
+      .range(..)
+      .unwrap()
+      .map(|entry| entry.unwrap().0.into_key())
+      .collect();
+    assert_eq!(keys, (0..1000).rev().collect::<Vec<u64>>());
+
+    // A range runs from the greater key down to the lesser one.
+    let (start, end) = (OrderedBy::new(500), OrderedBy::new(490));
+    let keys: Vec<u64> = txn
+      .range(&start..&end)
+      .unwrap()
+      .map(|entry| entry.unwrap().0.into_key())
+      .collect();
+    assert_eq!(keys, (491..=500).rev().collect::<Vec<u64>>());
+  }
+}
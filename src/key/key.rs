@@ -0,0 +1,312 @@
+use super::Value;
+use error::Result;
+use std::cmp::Ordering;
+use storage::{ByteReader, ByteWriter};
+
+// Keys are kept sorted by `compare`. It must be a total order, and it
+// must never change: the order is baked into every node in the
+// NodeStore.
+//
+// Integers compare as integers (so 9 sorts before 10), and tuples
+// compare lexicographically, field by field. To sort keys some other
+// way, see `OrderedBy`.
+//
+// The other methods have defaults. They let a node store its keys more
+// compactly.
+//
+// When a leaf splits, the key promoted to the parent needn't be a key
+// of the leaf at all. Any `separator` will do, so long as
+// `left <= separator < right`, where `left` is the last key staying in
+// the leaf and `right` the first key moving out. A short separator
+// lets an interior node hold more children.
+//
+// A node writes the prefix that all its keys share only once, with its
+// first key. The others are written with `serialize_suffix`, and read
+// back with `deserialize_suffix`. A key type that can't share prefixes
+// keeps `common_prefix_len` at zero, and the suffix of a key is then
+// the whole key.
+pub trait Key: Value {
+  fn compare(&self, other: &Self) -> Ordering;
+
+  fn separator(left: &Self, _right: &Self) -> Self {
+    left.clone()
+  }
+
+  fn common_prefix_len(&self, _other: &Self) -> usize {
+    0
+  }
+
+  fn serialize_suffix(
+    &self,
+    _prefix_len: usize,
+    writer: &mut ByteWriter,
+  ) {
+    self.serialize(writer);
+  }
+
+  fn deserialize_suffix(
+    _prefix_key: &Self,
+    _prefix_len: usize,
+    reader: &mut ByteReader,
+  ) -> Result<Self> {
+    Self::deserialize(reader)
+  }
+
+  fn encoded_suffix_size(&self, _prefix_len: usize) -> usize {
+    self.encoded_size()
+  }
+}
+
+// Strings and byte strings sort byte by byte, so they can share
+// prefixes. A String only shares whole characters.
+impl Key for String {
+  fn compare(&self, other: &String) -> Ordering {
+    self.cmp(other)
+  }
+
+  // The shortest prefix of `right` that sorts after `left`, if it is
+  // shorter than `right` itself.
+  fn separator(left: &String, right: &String) -> String {
+    let prefix_len = left.common_prefix_len(right);
+    if let Some(next_char) = right[prefix_len..].chars().next() {
+      let separator_len = prefix_len + next_char.len_utf8();
+      if separator_len < right.len() {
+        return String::from(&right[..separator_len]);
+      }
+    }
+
+    left.clone()
+  }
+
+  fn common_prefix_len(&self, other: &String) -> usize {
+    let mut prefix_len =
+      common_prefix_len(self.as_bytes(), other.as_bytes());
+    while !self.is_char_boundary(prefix_len) {
+      prefix_len -= 1;
+    }
+    prefix_len
+  }
+
+  fn serialize_suffix(
+    &self,
+    prefix_len: usize,
+    writer: &mut ByteWriter,
+  ) {
+    writer.write_str(&self[prefix_len..]);
+  }
+
+  fn deserialize_suffix(
+    prefix_key: &String,
+    prefix_len: usize,
+    reader: &mut ByteReader,
+  ) -> Result<String> {
+    let mut key = String::from(&prefix_key[..prefix_len]);
+    key.push_str(&reader.read_string()?);
+    Ok(key)
+  }
+
+  fn encoded_suffix_size(&self, prefix_len: usize) -> usize {
+    self.encoded_size() - prefix_len
+  }
+}
+
+impl Key for Vec<u8> {
+  fn compare(&self, other: &Vec<u8>) -> Ordering {
+    self.cmp(other)
+  }
+
+  // The shortest prefix of `right` that sorts after `left`, if it is
+  // shorter than `right` itself.
+  fn separator(left: &Vec<u8>, right: &Vec<u8>) -> Vec<u8> {
+    let separator_len = left.common_prefix_len(right) + 1;
+    if separator_len < right.len() {
+      right[..separator_len].to_vec()
+    } else {
+      left.clone()
+    }
+  }
+
+  fn common_prefix_len(&self, other: &Vec<u8>) -> usize {
+    common_prefix_len(self, other)
+  }
+
+  fn serialize_suffix(
+    &self,
+    prefix_len: usize,
+    writer: &mut ByteWriter,
+  ) {
+    writer.write_bytes(&self[prefix_len..]);
+  }
+
+  fn deserialize_suffix(
+    prefix_key: &Vec<u8>,
+    prefix_len: usize,
+    reader: &mut ByteReader,
+  ) -> Result<Vec<u8>> {
+    let mut key = prefix_key[..prefix_len].to_vec();
+    key.extend_from_slice(reader.read_bytes()?);
+    Ok(key)
+  }
+
+  fn encoded_suffix_size(&self, prefix_len: usize) -> usize {
+    self.encoded_size() - prefix_len
+  }
+}
+
+impl Key for u32 {
+  fn compare(&self, other: &u32) -> Ordering {
+    self.cmp(other)
+  }
+}
+
+impl Key for u64 {
+  fn compare(&self, other: &u64) -> Ordering {
+    self.cmp(other)
+  }
+}
+
+impl Key for i32 {
+  fn compare(&self, other: &i32) -> Ordering {
+    self.cmp(other)
+  }
+}
+
+impl Key for i64 {
+  fn compare(&self, other: &i64) -> Ordering {
+    self.cmp(other)
+  }
+}
+
+impl<A: Key, B: Key> Key for (A, B) {
+  fn compare(&self, other: &(A, B)) -> Ordering {
+    self
+      .0
+      .compare(&other.0)
+      .then_with(|| self.1.compare(&other.1))
+  }
+}
+
+impl<A: Key, B: Key, C: Key> Key for (A, B, C) {
+  fn compare(&self, other: &(A, B, C)) -> Ordering {
+    self
+      .0
+      .compare(&other.0)
+      .then_with(|| self.1.compare(&other.1))
+      .then_with(|| self.2.compare(&other.2))
+  }
+}
+
+fn common_prefix_len(left: &[u8], right: &[u8]) -> usize {
+  left
+    .iter()
+    .zip(right)
+    .take_while(|(left_byte, right_byte)| left_byte == right_byte)
+    .count()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Key;
+  use btree::BTree;
+  use std::cmp::Ordering;
+  use std::fmt::Debug;
+  use std::sync::Arc;
+  use transaction::TransactionMode;
+
+  fn assert_separates<K: Key + Debug>(left: K, right: K) -> K {
+    let separator = K::separator(&left, &right);
+    assert_ne!(left.compare(&separator), Ordering::Greater);
+    assert_eq!(separator.compare(&right), Ordering::Less);
+    separator
+  }
+
+  #[test]
+  fn string_separators_are_truncated() {
+    let separator = |left: &str, right: &str| {
+      assert_separates(String::from(left), String::from(right))
+    };
+
+    assert_eq!(separator("apple", "banana"), "b");
+    assert_eq!(separator("apple", "apricot"), "apr");
+    // A separator never splits a character.
+    assert_eq!(separator("aéx", "aüy"), "aü");
+    // When only `right` itself would do, we fall back on `left`.
+    assert_eq!(separator("abc", "abcd"), "abc");
+    assert_eq!(separator("abc", "abd"), "abc");
+  }
+
+  #[test]
+  fn byte_string_separators_are_truncated() {
+    let separator = assert_separates(vec![1, 2, 3], vec![1, 5, 6]);
+    assert_eq!(separator, vec![1, 5]);
+    let separator = assert_separates(vec![1, 2], vec![1, 2, 0]);
+    assert_eq!(separator, vec![1, 2]);
+  }
+
+  #[test]
+  fn other_separators_are_the_left_key() {
+    assert_eq!(assert_separates(9u64, 10u64), 9);
+  }
+
+  // Integer keys sort as integers, so 9 comes before 10, whatever
+  // order they were inserted in.
+  #[test]
+  fn a_btree_sorts_integer_keys_as_integers() {
+    let btree = Arc::new(BTree::<u64, u64>::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..2000 {
+      let key = (idx * 7) % 2000;
+      txn.insert(&key, key * 2).unwrap();
+    }
+    txn.commit().unwrap();
+    btree.validate().unwrap();
+
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(txn.get(&1000), Ok(Some(2000)));
+    let entries: Vec<(u64, u64)> =
+      txn.range(&9..&11).unwrap().map(Result::unwrap).collect();
+    assert_eq!(entries, vec![(9, 18), (10, 20)]);
+    let keys: Vec<u64> = txn
+      .range(..)
+      .unwrap()
+      .map(|entry| entry.unwrap().0)
+      .collect();
+    assert_eq!(keys, (0..2000).collect::<Vec<u64>>());
+  }
+
+  // Tuples sort by their first field, then by their second. So a range
+  // over the first field alone finds every key that starts with it.
+  #[test]
+  fn a_btree_sorts_tuple_keys_field_by_field() {
+    let names = ["bob", "alice", "carol"];
+    let btree: Arc<BTree<(u32, String), u32>> =
+      Arc::new(BTree::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for group in (0..200).rev() {
+      for name in &names {
+        txn.insert(&(group, name.to_string()), group).unwrap();
+      }
+    }
+    txn.commit().unwrap();
+    btree.validate().unwrap();
+
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let (start, end) = ((9, String::new()), (11, String::new()));
+    let keys: Vec<(u32, String)> = txn
+      .range(&start..&end)
+      .unwrap()
+      .map(|entry| entry.unwrap().0)
+      .collect();
+    let expected: Vec<(u32, String)> = [9, 10]
+      .iter()
+      .flat_map(|&group| {
+        ["alice", "bob", "carol"]
+          .iter()
+          .map(move |name| (group, name.to_string()))
+      })
+      .collect();
+    assert_eq!(keys, expected);
+    assert_eq!(txn.get(&(150, String::from("carol"))), Ok(Some(150)));
+    assert_eq!(txn.get(&(150, String::from("dave"))), Ok(None));
+  }
+}
@@ -0,0 +1,442 @@
+use error::{Error, Result};
+
+// A memcomparable encoding turns a logical key into bytes whose
+// lexicographic order is the key's logical order. A `BTree<Vec<u8>, V>`
+// then sorts composite keys correctly without a custom Comparator.
+//
+// * Unsigned integers are written big-endian.
+// * Signed integers are written big-endian with the sign bit flipped,
+//   so that negative numbers sort first.
+// * Floats flip the sign bit when positive and every bit when negative.
+//   That sorts -0.0 just before 0.0, and NaNs at either end.
+// * Byte strings (and strings) can't simply be written out, or "a"
+//   followed by "b" would be confused with "ab". Each 0x00 byte becomes
+//   0x00 0xFF, and the string ends with 0x00 0x01. A shorter string
+//   thus sorts before a longer one that it is a prefix of.
+//
+// Fields are written one after another, so a tuple sorts field by
+// field.
+#[derive(Default)]
+pub struct KeyEncoder {
+  bytes: Vec<u8>,
+}
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+const SIGN_BIT_32: u32 = 1 << 31;
+const SIGN_BIT_64: u64 = 1 << 63;
+
+impl KeyEncoder {
+  pub fn new() -> KeyEncoder {
+    KeyEncoder::default()
+  }
+
+  pub fn write_u8(&mut self, value: u8) {
+    self.bytes.push(value);
+  }
+
+  pub fn write_u32(&mut self, value: u32) {
+    self.bytes.extend_from_slice(&value.to_be_bytes());
+  }
+
+  pub fn write_u64(&mut self, value: u64) {
+    self.bytes.extend_from_slice(&value.to_be_bytes());
+  }
+
+  pub fn write_i32(&mut self, value: i32) {
+    self.write_u32((value as u32) ^ SIGN_BIT_32);
+  }
+
+  pub fn write_i64(&mut self, value: i64) {
+    self.write_u64((value as u64) ^ SIGN_BIT_64);
+  }
+
+  pub fn write_f64(&mut self, value: f64) {
+    let bits = value.to_bits();
+    if bits & SIGN_BIT_64 == 0 {
+      self.write_u64(bits ^ SIGN_BIT_64);
+    } else {
+      self.write_u64(!bits);
+    }
+  }
+
+  pub fn write_bytes(&mut self, value: &[u8]) {
+    for &byte in value {
+      self.bytes.push(byte);
+      if byte == ESCAPE {
+        self.bytes.push(ESCAPED_ZERO);
+      }
+    }
+    self.bytes.push(ESCAPE);
+    self.bytes.push(TERMINATOR);
+  }
+
+  pub fn write_str(&mut self, value: &str) {
+    self.write_bytes(value.as_bytes());
+  }
+
+  pub fn into_bytes(self) -> Vec<u8> {
+    self.bytes
+  }
+}
+
+// Reads back what a KeyEncoder wrote. The fields must be read in the
+// order they were written; the encoding doesn't record their types.
+pub struct KeyDecoder<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl<'a> KeyDecoder<'a> {
+  pub fn new(bytes: &'a [u8]) -> KeyDecoder<'a> {
+    KeyDecoder { bytes, position: 0 }
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+    if self.bytes.len() - self.position < len {
+      return Err(Error::MalformedKey(String::from(
+        "ran off the end of the key",
+      )));
+    }
+
+    let taken = &self.bytes[self.position..(self.position + len)];
+    self.position += len;
+    Ok(taken)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.position == self.bytes.len()
+  }
+
+  pub fn read_u8(&mut self) -> Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  pub fn read_u32(&mut self) -> Result<u32> {
+    let mut value = [0; 4];
+    value.copy_from_slice(self.take(4)?);
+    Ok(u32::from_be_bytes(value))
+  }
+
+  pub fn read_u64(&mut self) -> Result<u64> {
+    let mut value = [0; 8];
+    value.copy_from_slice(self.take(8)?);
+    Ok(u64::from_be_bytes(value))
+  }
+
+  pub fn read_i32(&mut self) -> Result<i32> {
+    Ok((self.read_u32()? ^ SIGN_BIT_32) as i32)
+  }
+
+  pub fn read_i64(&mut self) -> Result<i64> {
+    Ok((self.read_u64()? ^ SIGN_BIT_64) as i64)
+  }
+
+  pub fn read_f64(&mut self) -> Result<f64> {
+    let bits = self.read_u64()?;
+    if bits & SIGN_BIT_64 != 0 {
+      Ok(f64::from_bits(bits ^ SIGN_BIT_64))
+    } else {
+      Ok(f64::from_bits(!bits))
+    }
+  }
+
+  pub fn read_bytes(&mut self) -> Result<Vec<u8>> {
+    let mut value = Vec::new();
+    loop {
+      let byte = self.read_u8()?;
+      if byte != ESCAPE {
+        value.push(byte);
+        continue;
+      }
+
+      match self.read_u8()? {
+        ESCAPED_ZERO => value.push(ESCAPE),
+        TERMINATOR => return Ok(value),
+        _ => {
+          return Err(Error::MalformedKey(String::from(
+            "unknown escape in byte string",
+          )))
+        }
+      }
+    }
+  }
+
+  pub fn read_string(&mut self) -> Result<String> {
+    match String::from_utf8(self.read_bytes()?) {
+      Ok(value) => Ok(value),
+      Err(_) => {
+        Err(Error::MalformedKey(String::from("string is not UTF-8")))
+      }
+    }
+  }
+}
+
+// A Memcomparable type knows which KeyEncoder fields make it up. Tuples
+// of Memcomparable types are Memcomparable, so
+// `encode_key(&(customer_id, -balance, name))` is all it takes to build
+// a composite key.
+pub trait Memcomparable: Sized {
+  fn encode(&self, encoder: &mut KeyEncoder);
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<Self>;
+}
+
+pub fn encode_key<T: Memcomparable>(value: &T) -> Vec<u8> {
+  let mut encoder = KeyEncoder::new();
+  value.encode(&mut encoder);
+  encoder.into_bytes()
+}
+
+pub fn decode_key<T: Memcomparable>(bytes: &[u8]) -> Result<T> {
+  let mut decoder = KeyDecoder::new(bytes);
+  let value = T::decode(&mut decoder)?;
+  if !decoder.is_empty() {
+    return Err(Error::MalformedKey(String::from(
+      "bytes left over after decoding the key",
+    )));
+  }
+
+  Ok(value)
+}
+
+impl Memcomparable for u32 {
+  fn encode(&self, encoder: &mut KeyEncoder) {
+    encoder.write_u32(*self);
+  }
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<u32> {
+    decoder.read_u32()
+  }
+}
+
+impl Memcomparable for u64 {
+  fn encode(&self, encoder: &mut KeyEncoder) {
+    encoder.write_u64(*self);
+  }
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<u64> {
+    decoder.read_u64()
+  }
+}
+
+impl Memcomparable for i32 {
+  fn encode(&self, encoder: &mut KeyEncoder) {
+    encoder.write_i32(*self);
+  }
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<i32> {
+    decoder.read_i32()
+  }
+}
+
+impl Memcomparable for i64 {
+  fn encode(&self, encoder: &mut KeyEncoder) {
+    encoder.write_i64(*self);
+  }
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<i64> {
+    decoder.read_i64()
+  }
+}
+
+impl Memcomparable for f64 {
+  fn encode(&self, encoder: &mut KeyEncoder) {
+    encoder.write_f64(*self);
+  }
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<f64> {
+    decoder.read_f64()
+  }
+}
+
+impl Memcomparable for String {
+  fn encode(&self, encoder: &mut KeyEncoder) {
+    encoder.write_str(self);
+  }
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<String> {
+    decoder.read_string()
+  }
+}
+
+impl Memcomparable for Vec<u8> {
+  fn encode(&self, encoder: &mut KeyEncoder) {
+    encoder.write_bytes(self);
+  }
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<Vec<u8>> {
+    decoder.read_bytes()
+  }
+}
+
+impl<A: Memcomparable, B: Memcomparable> Memcomparable for (A, B) {
+  fn encode(&self, encoder: &mut KeyEncoder) {
+    self.0.encode(encoder);
+    self.1.encode(encoder);
+  }
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<(A, B)> {
+    Ok((A::decode(decoder)?, B::decode(decoder)?))
+  }
+}
+
+impl<A: Memcomparable, B: Memcomparable, C: Memcomparable> Memcomparable
+  for (A, B, C)
+{
+  fn encode(&self, encoder: &mut KeyEncoder) {
+    self.0.encode(encoder);
+    self.1.encode(encoder);
+    self.2.encode(encoder);
+  }
+
+  fn decode(decoder: &mut KeyDecoder) -> Result<(A, B, C)> {
+    Ok((
+      A::decode(decoder)?,
+      B::decode(decoder)?,
+      C::decode(decoder)?,
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{decode_key, encode_key, Memcomparable};
+  use error::Error;
+  use std::fmt::Debug;
+
+  fn assert_round_trips<T>(value: T)
+  where
+    T: Memcomparable + PartialEq + Debug,
+  {
+    let decoded: T = decode_key(&encode_key(&value)).unwrap();
+    assert_eq!(decoded, value);
+  }
+
+  // Each value must encode to bytes that sort strictly after the last.
+  fn assert_sorted<T: Memcomparable + Debug>(values: &[T]) {
+    for pair in values.windows(2) {
+      assert!(
+        encode_key(&pair[0]) < encode_key(&pair[1]),
+        "{:?} should sort before {:?}",
+        pair[0],
+        pair[1]
+      );
+    }
+  }
+
+  fn assert_malformed<T: Memcomparable + Debug>(bytes: &[u8]) {
+    match decode_key::<T>(bytes) {
+      Err(Error::MalformedKey(..)) => {}
+      result => panic!("expected MalformedKey, got {:?}", result),
+    }
+  }
+
+  #[test]
+  fn signed_integers() {
+    let values = [i32::MIN, -256, -1, 0, 1, 255, i32::MAX];
+    for &value in &values {
+      assert_round_trips(value);
+    }
+    assert_sorted(&values);
+
+    let values = [i64::MIN, -(1 << 40), -1, 0, 1, 1 << 40, i64::MAX];
+    for &value in &values {
+      assert_round_trips(value);
+    }
+    assert_sorted(&values);
+  }
+
+  #[test]
+  fn unsigned_integers() {
+    let values = [0u64, 1, 255, 256, u64::MAX];
+    for &value in &values {
+      assert_round_trips(value);
+    }
+    assert_sorted(&values);
+  }
+
+  #[test]
+  fn floats() {
+    let values = [
+      f64::NEG_INFINITY,
+      -1.5,
+      -f64::MIN_POSITIVE,
+      -0.0,
+      0.0,
+      f64::MIN_POSITIVE,
+      1.5,
+      f64::INFINITY,
+    ];
+    for &value in &values {
+      let decoded: f64 = decode_key(&encode_key(&value)).unwrap();
+      assert_eq!(decoded.to_bits(), value.to_bits());
+    }
+    assert_sorted(&values);
+
+    // A NaN keeps its bits, and sorts at the end its sign bit says.
+    let nan = f64::NAN;
+    let decoded: f64 = decode_key(&encode_key(&nan)).unwrap();
+    assert_eq!(decoded.to_bits(), nan.to_bits());
+    assert_sorted(&[f64::INFINITY, nan]);
+    assert_sorted(&[-nan, f64::NEG_INFINITY]);
+  }
+
+  #[test]
+  fn strings() {
+    let values = ["", "\0", "a", "a\0", "a\0\0", "a\x01", "ab", "b"];
+    for value in &values {
+      assert_round_trips(String::from(*value));
+    }
+    let values: Vec<String> =
+      values.iter().map(|value| String::from(*value)).collect();
+    assert_sorted(&values);
+
+    assert_round_trips(vec![0u8, 0xFF, 0x00, 0x01]);
+  }
+
+  #[test]
+  fn tuples() {
+    let values = [
+      (-1, String::from("b")),
+      (0, String::from("")),
+      (0, String::from("a")),
+      (0, String::from("a\0")),
+      (1, String::from("")),
+    ];
+    for value in &values {
+      assert_round_trips(value.clone());
+    }
+    assert_sorted(&values);
+
+    // A string field ends where its terminator says, so a longer
+    // first field doesn't run into the second.
+    assert_sorted(&[
+      (String::from("a"), String::from("z")),
+      (String::from("ab"), String::from("")),
+    ]);
+    assert_round_trips((7u32, -2i64, String::from("x\0y")));
+  }
+
+  #[test]
+  fn malformed_keys() {
+    // Trailing bytes.
+    let mut bytes = encode_key(&7u32);
+    bytes.push(0);
+    assert_malformed::<u32>(&bytes);
+    assert_malformed::<String>(&[b'a', 0x00, 0x01, b'b']);
+
+    // Truncated keys.
+    assert_malformed::<u32>(&[0, 0, 0]);
+    assert_malformed::<i64>(&[]);
+    assert_malformed::<String>(b"abc");
+    assert_malformed::<String>(&[b'a', 0x00]);
+    assert_malformed::<(u32, String)>(&encode_key(&5u32));
+
+    // An escape that nothing writes, and bytes that aren't UTF-8.
+    assert_malformed::<Vec<u8>>(&[0x00, 0x02]);
+    assert_malformed::<String>(&[0xFF, 0x00, 0x01]);
+  }
+}
@@ -0,0 +1,12 @@
+mod comparator;
+#[allow(clippy::module_inception)]
+mod key;
+mod memcomparable;
+mod value;
+
+pub use self::comparator::{Comparator, OrderedBy};
+pub use self::key::Key;
+pub use self::memcomparable::{
+  decode_key, encode_key, KeyDecoder, KeyEncoder, Memcomparable,
+};
+pub use self::value::Value;
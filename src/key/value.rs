@@ -0,0 +1,146 @@
+use error::Result;
+use std::fmt::Debug;
+use storage::{ByteReader, ByteWriter};
+
+// A Value is anything a LeafNode can store. Since nodes are written to
+// the NodeStore and the WriteAheadLog, a Value must know how to lay
+// itself out as bytes (and read itself back).
+//
+// Nodes are shared between threads through the BufferPool, and guards
+// keep them alive without a lifetime. Hence `Send + Sync + 'static`.
+//
+// Nodes split by how many bytes they take up, so we often ask for the
+// `encoded_size`: how many bytes `serialize` writes. The default
+// serializes to find out; most types can say more cheaply.
+pub trait Value: Clone + Debug + Send + Sync + 'static {
+  fn serialize(&self, writer: &mut ByteWriter);
+
+  fn deserialize(reader: &mut ByteReader) -> Result<Self>;
+
+  fn encoded_size(&self) -> usize {
+    let mut writer = ByteWriter::new();
+    self.serialize(&mut writer);
+    writer.into_bytes().len()
+  }
+}
+
+impl Value for String {
+  fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_str(self);
+  }
+
+  fn deserialize(reader: &mut ByteReader) -> Result<String> {
+    reader.read_string()
+  }
+
+  fn encoded_size(&self) -> usize {
+    4 + self.len()
+  }
+}
+
+impl Value for Vec<u8> {
+  fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_bytes(self);
+  }
+
+  fn deserialize(reader: &mut ByteReader) -> Result<Vec<u8>> {
+    Ok(reader.read_bytes()?.to_vec())
+  }
+
+  fn encoded_size(&self) -> usize {
+    4 + self.len()
+  }
+}
+
+impl Value for u32 {
+  fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_u32(*self);
+  }
+
+  fn deserialize(reader: &mut ByteReader) -> Result<u32> {
+    reader.read_u32()
+  }
+
+  fn encoded_size(&self) -> usize {
+    4
+  }
+}
+
+impl Value for u64 {
+  fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_u64(*self);
+  }
+
+  fn deserialize(reader: &mut ByteReader) -> Result<u64> {
+    reader.read_u64()
+  }
+
+  fn encoded_size(&self) -> usize {
+    8
+  }
+}
+
+impl Value for i32 {
+  fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_u32(*self as u32);
+  }
+
+  fn deserialize(reader: &mut ByteReader) -> Result<i32> {
+    Ok(reader.read_u32()? as i32)
+  }
+
+  fn encoded_size(&self) -> usize {
+    4
+  }
+}
+
+impl Value for i64 {
+  fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_u64(*self as u64);
+  }
+
+  fn deserialize(reader: &mut ByteReader) -> Result<i64> {
+    Ok(reader.read_u64()? as i64)
+  }
+
+  fn encoded_size(&self) -> usize {
+    8
+  }
+}
+
+impl<A: Value, B: Value> Value for (A, B) {
+  fn serialize(&self, writer: &mut ByteWriter) {
+    self.0.serialize(writer);
+    self.1.serialize(writer);
+  }
+
+  fn deserialize(reader: &mut ByteReader) -> Result<(A, B)> {
+    Ok((A::deserialize(reader)?, B::deserialize(reader)?))
+  }
+
+  fn encoded_size(&self) -> usize {
+    self.0.encoded_size() + self.1.encoded_size()
+  }
+}
+
+impl<A: Value, B: Value, C: Value> Value for (A, B, C) {
+  fn serialize(&self, writer: &mut ByteWriter) {
+    self.0.serialize(writer);
+    self.1.serialize(writer);
+    self.2.serialize(writer);
+  }
+
+  fn deserialize(reader: &mut ByteReader) -> Result<(A, B, C)> {
+    Ok((
+      A::deserialize(reader)?,
+      B::deserialize(reader)?,
+      C::deserialize(reader)?,
+    ))
+  }
+
+  fn encoded_size(&self) -> usize {
+    self.0.encoded_size()
+      + self.1.encoded_size()
+      + self.2.encoded_size()
+  }
+}
@@ -1,14 +1,32 @@
+#![allow(clippy::needless_pub_self)]
+
 extern crate parking_lot;
-extern crate rand;
 
 // Allow submodules to access the public contents of other submodules.
 pub(self) mod btree;
+pub(self) mod buffer_pool;
 pub(self) mod constants;
+pub(self) mod error;
+pub(self) mod key;
 pub(self) mod locking;
+pub(self) mod mvcc;
 pub(self) mod node;
+pub(self) mod storage;
+#[cfg(test)]
+pub(self) mod test_util;
+pub(self) mod transaction;
+pub(self) mod wal;
 
-pub use btree::BTree;
-// TODO: I would rather present something named more like a
-// `Transaction` object. The `Transaction` would presumably manage the
-// `LockSet`.
-pub use locking::{LockSet, TransactionMode};
+pub use btree::{BTree, RangeIterator, ReverseRangeIterator};
+pub use error::{Error, Result};
+pub use key::{
+  decode_key, encode_key, Comparator, Key, KeyDecoder, KeyEncoder,
+  Memcomparable, OrderedBy, Value,
+};
+pub use node::SplitPolicy;
+pub use storage::{
+  ByteReader, ByteWriter, MemoryNodeStore, NodeIdentifier, NodeStore,
+  PageFileNodeStore,
+};
+pub use transaction::{Commit, Transaction, TransactionMode};
+pub use wal::WriteAheadLog;
@@ -1,43 +1,67 @@
 use super::{ReadGuard, WriteGuard};
-use locking::LockTarget;
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::LatchTarget;
 use node::Node;
+use storage::NodeIdentifier;
 
-pub enum Guard {
-  Read(ReadGuard),
-  Write(WriteGuard),
+pub enum Guard<K: Key, V: Value> {
+  Read(ReadGuard<K, V>),
+  Write(WriteGuard<K, V>),
 }
 
-impl Guard {
-  pub fn target(&self) -> LockTarget {
+impl<K: Key, V: Value> Guard<K, V> {
+  pub fn target(&self) -> LatchTarget {
     match self {
       Guard::Read(read_guard) => read_guard.target(),
       Guard::Write(write_guard) => write_guard.target(),
     }
   }
 
+  // Trades an upgradable read guard for a write guard on the same node.
+  // Waits for any plain readers to leave.
+  pub fn upgrade(&mut self) -> Result<()> {
+    let write_guard = match self {
+      Guard::Read(read_guard) => read_guard
+        .unwrap_node_upgradable_read_guard_mut_ref(
+          "only an upgradable read guard can be upgraded",
+        )?
+        .upgrade(),
+      Guard::Write(_) => return Ok(()),
+    };
+
+    *self = Guard::Write(write_guard.upcast());
+    Ok(())
+  }
+
   pub fn unwrap_node_mut_ref(
     &mut self,
     msg: &'static str,
-  ) -> &mut Node {
+  ) -> Result<&mut Node<K, V>> {
     match self {
-      Guard::Read(_) => {
-        panic!("Cannot unwrap a mutable reference to a ReadGuard!")
-      }
+      Guard::Read(_) => Err(Error::InvariantViolation(
+        "Cannot unwrap a mutable reference to a ReadGuard!",
+      )),
 
       Guard::Write(write_guard) => {
-        write_guard.unwrap_node_write_guard_mut_ref(msg)
+        let node_write_guard =
+          write_guard.unwrap_node_write_guard_mut_ref(msg)?;
+        Ok(node_write_guard)
       }
     }
   }
 
-  pub fn unwrap_node_ref(&self, msg: &'static str) -> &Node {
+  pub fn unwrap_node_ref(
+    &self,
+    msg: &'static str,
+  ) -> Result<&Node<K, V>> {
     match self {
-      Guard::Read(read_guard) => {
-        read_guard.unwrap_node_read_guard_ref(msg)
-      }
+      Guard::Read(read_guard) => read_guard.unwrap_node_ref(msg),
 
       Guard::Write(write_guard) => {
-        write_guard.unwrap_node_write_guard_ref(msg)
+        let node_write_guard =
+          write_guard.unwrap_node_write_guard_ref(msg)?;
+        Ok(node_write_guard)
       }
     }
   }
@@ -45,14 +69,16 @@ impl Guard {
   pub fn unwrap_root_identifier_mut_ref(
     &mut self,
     msg: &'static str,
-  ) -> &mut String {
+  ) -> Result<&mut NodeIdentifier> {
     match self {
-      Guard::Read(_) => {
-        panic!("Cannot unwrap a mutable reference to a ReadGuard!")
-      }
+      Guard::Read(_) => Err(Error::InvariantViolation(
+        "Cannot unwrap a mutable reference to a ReadGuard!",
+      )),
 
       Guard::Write(write_guard) => {
-        write_guard.unwrap_root_identifier_write_guard_mut_ref(msg)
+        let root_identifier_write_guard = write_guard
+          .unwrap_root_identifier_write_guard_mut_ref(msg)?;
+        Ok(root_identifier_write_guard)
       }
     }
   }
@@ -60,14 +86,18 @@ impl Guard {
   pub fn unwrap_root_identifier_ref(
     &self,
     msg: &'static str,
-  ) -> &String {
+  ) -> Result<&NodeIdentifier> {
     match self {
       Guard::Read(read_guard) => {
-        read_guard.unwrap_root_identifier_read_guard_ref(msg)
+        let root_identifier_read_guard =
+          read_guard.unwrap_root_identifier_read_guard_ref(msg)?;
+        Ok(root_identifier_read_guard)
       }
 
       Guard::Write(write_guard) => {
-        write_guard.unwrap_root_identifier_write_guard_ref(msg)
+        let root_identifier_write_guard =
+          write_guard.unwrap_root_identifier_write_guard_ref(msg)?;
+        Ok(root_identifier_write_guard)
       }
     }
   }
@@ -75,22 +105,22 @@ impl Guard {
   pub fn unwrap_write_guard_ref(
     &self,
     msg: &'static str,
-  ) -> &WriteGuard {
+  ) -> Result<&WriteGuard<K, V>> {
     match self {
-      Guard::Read(_) => panic!(msg),
+      Guard::Read(_) => Err(Error::InvariantViolation(msg)),
 
-      Guard::Write(write_guard) => write_guard,
+      Guard::Write(write_guard) => Ok(write_guard),
     }
   }
 
   pub fn unwrap_write_guard_mut_ref(
     &mut self,
     msg: &'static str,
-  ) -> &mut WriteGuard {
+  ) -> Result<&mut WriteGuard<K, V>> {
     match self {
-      Guard::Read(_) => panic!(msg),
+      Guard::Read(_) => Err(Error::InvariantViolation(msg)),
 
-      Guard::Write(write_guard) => write_guard,
+      Guard::Write(write_guard) => Ok(write_guard),
     }
   }
 }
@@ -3,9 +3,5 @@ mod reading;
 mod writing;
 
 pub use self::base_guard::Guard;
-pub use self::reading::{
-  NodeReadGuard, ReadGuard, RootIdentifierReadGuard,
-};
-pub use self::writing::{
-  NodeWriteGuard, RootIdentifierWriteGuard, WriteGuard,
-};
+pub use self::reading::ReadGuard;
+pub use self::writing::WriteGuard;
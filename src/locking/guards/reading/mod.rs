@@ -1,7 +1,9 @@
 mod node_read_guard;
+mod node_upgradable_read_guard;
 mod read_guard;
 mod root_identifier_read_guard;
 
 pub use self::node_read_guard::NodeReadGuard;
+pub use self::node_upgradable_read_guard::NodeUpgradableReadGuard;
 pub use self::read_guard::ReadGuard;
 pub use self::root_identifier_read_guard::RootIdentifierReadGuard;
@@ -1,72 +1,77 @@
 use super::ReadGuard;
 use btree::BTree;
+use buffer_pool::NodePin;
+use error::Result;
+use key::{Key, Value};
 use node::{InteriorNode, LeafNode, Node};
-use parking_lot::{RwLock, RwLockReadGuard};
+use parking_lot::RwLockReadGuard;
 use std::ops::Deref;
-use std::sync::Arc;
+use storage::NodeIdentifier;
 
-pub struct NodeReadGuard {
-  _lock: Arc<RwLock<Node>>,
-  guard: RwLockReadGuard<'static, Node>,
+// Fields are dropped in order: we must release the guard before the
+// NodePin that keeps the `NodeLatch` alive (and in the BufferPool).
+pub struct NodeReadGuard<K: Key, V: Value> {
+  guard: RwLockReadGuard<'static, Node<K, V>>,
+  _pin: NodePin<K, V>,
 }
 
-impl Deref for NodeReadGuard {
-  type Target = Node;
+impl<K: Key, V: Value> Deref for NodeReadGuard<K, V> {
+  type Target = Node<K, V>;
 
-  fn deref(&self) -> &Node {
+  fn deref(&self) -> &Node<K, V> {
     &self.guard
   }
 }
 
-impl NodeReadGuard {
+impl<K: Key, V: Value> NodeReadGuard<K, V> {
   pub(in locking) fn acquire(
-    btree: &BTree,
-    identifier: &str,
-  ) -> NodeReadGuard {
+    btree: &BTree<K, V>,
+    identifier: NodeIdentifier,
+  ) -> Result<NodeReadGuard<K, V>> {
     // This is trickery. `RwLockReadGuard` wants a lifetime: it doesn't
-    // want to outlive the `RwLock`. But the `RwLock` *cannot* be lost,
-    // because I hold onto it via `Arc`.
+    // want to outlive the `NodeLatch`. But the latch *cannot* be lost,
+    // because I hold onto it via the NodePin.
     //
     // However, Rust won't understand this. Therefore, I resort to this
     // unsafe code.
     unsafe {
-      let lock: Arc<RwLock<Node>> =
-        btree.get_node_arc_lock(&identifier);
+      let pin = btree.pin_node(identifier)?;
+      let lock = pin.node();
 
-      let guard: RwLockReadGuard<'static, Node> =
+      let guard: RwLockReadGuard<'static, Node<K, V>> =
         std::mem::transmute(lock.read());
 
-      NodeReadGuard { _lock: lock, guard }
+      Ok(NodeReadGuard { guard, _pin: pin })
     }
   }
 
   pub fn is_interior_node(&self) -> bool {
-    let self_node: &Node = self;
+    let self_node: &Node<K, V> = self;
     self_node.is_interior_node()
   }
 
   pub fn is_leaf_node(&self) -> bool {
-    let self_node: &Node = self;
+    let self_node: &Node<K, V> = self;
     self_node.is_leaf_node()
   }
 
   pub fn unwrap_interior_node_ref(
     &self,
     message: &'static str,
-  ) -> &InteriorNode {
-    let self_node: &Node = self;
+  ) -> Result<&InteriorNode<K>> {
+    let self_node: &Node<K, V> = self;
     self_node.unwrap_interior_node_ref(message)
   }
 
   pub fn unwrap_leaf_node_ref(
     &self,
     message: &'static str,
-  ) -> &LeafNode {
-    let self_node: &Node = self;
+  ) -> Result<&LeafNode<K, V>> {
+    let self_node: &Node<K, V> = self;
     self_node.unwrap_leaf_node_ref(message)
   }
 
-  pub fn upcast(self) -> ReadGuard {
+  pub fn upcast(self) -> ReadGuard<K, V> {
     ReadGuard::NodeReadGuard(self)
   }
 }
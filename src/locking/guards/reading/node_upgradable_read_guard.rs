@@ -0,0 +1,75 @@
+use super::ReadGuard;
+use btree::BTree;
+use buffer_pool::{NodeLatchWriteGuard, NodePin};
+use error::Result;
+use key::{Key, Value};
+use locking::guards::writing::NodeWriteGuard;
+use node::Node;
+use parking_lot::RwLockUpgradableReadGuard;
+use std::ops::Deref;
+use storage::NodeIdentifier;
+
+// An upgradable read latch shares the node with plain readers, but not
+// with writers or with another upgradable reader. So whoever holds it
+// can always trade it for a write latch, by waiting for the plain
+// readers to leave.
+//
+// Upgrading consumes the latch, so its parts are taken out of their
+// `Option`s then. The guard is never used after that.
+//
+// Fields are dropped in order: we must release the guard before the
+// NodePin that keeps the `NodeLatch` alive (and in the BufferPool).
+pub struct NodeUpgradableReadGuard<K: Key, V: Value> {
+  guard: Option<RwLockUpgradableReadGuard<'static, Node<K, V>>>,
+  pin: Option<NodePin<K, V>>,
+}
+
+impl<K: Key, V: Value> Deref for NodeUpgradableReadGuard<K, V> {
+  type Target = Node<K, V>;
+
+  fn deref(&self) -> &Node<K, V> {
+    self
+      .guard
+      .as_ref()
+      .expect("guard is only taken to upgrade it")
+  }
+}
+
+impl<K: Key, V: Value> NodeUpgradableReadGuard<K, V> {
+  pub(in locking) fn acquire(
+    btree: &BTree<K, V>,
+    identifier: NodeIdentifier,
+  ) -> Result<NodeUpgradableReadGuard<K, V>> {
+    // The same trickery as in `NodeReadGuard::acquire`.
+    unsafe {
+      let pin = btree.pin_node(identifier)?;
+      let lock = pin.node();
+
+      let guard: RwLockUpgradableReadGuard<'static, Node<K, V>> =
+        std::mem::transmute(lock.upgradable_read());
+
+      Ok(NodeUpgradableReadGuard {
+        guard: Some(guard),
+        pin: Some(pin),
+      })
+    }
+  }
+
+  pub(in locking) fn upgrade(&mut self) -> NodeWriteGuard<K, V> {
+    let msg = "a guard is only upgraded once";
+    let guard = self.guard.take().expect(msg);
+    let pin = self.pin.take().expect(msg);
+
+    // The same trickery again: the upgraded guard can't outlive the
+    // NodePin we hand over with it.
+    unsafe {
+      let guard: NodeLatchWriteGuard<'static, K, V> =
+        std::mem::transmute(pin.node().upgrade(guard));
+      NodeWriteGuard::from_upgraded(guard, pin)
+    }
+  }
+
+  pub fn upcast(self) -> ReadGuard<K, V> {
+    ReadGuard::NodeUpgradableReadGuard(self)
+  }
+}
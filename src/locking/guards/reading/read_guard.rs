@@ -1,50 +1,70 @@
-use super::{NodeReadGuard, RootIdentifierReadGuard};
+use super::{
+  NodeReadGuard, NodeUpgradableReadGuard, RootIdentifierReadGuard,
+};
 use btree::BTree;
-use locking::LockTarget;
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::LatchTarget;
+use node::Node;
 use std::sync::Arc;
+use storage::NodeIdentifier;
 
-pub enum ReadGuard {
-  RootIdentifierReadGuard(RootIdentifierReadGuard),
-  NodeReadGuard(NodeReadGuard),
+// Each variant is named after the guard it holds.
+#[allow(clippy::enum_variant_names)]
+pub enum ReadGuard<K: Key, V: Value> {
+  RootIdentifierReadGuard(RootIdentifierReadGuard<K, V>),
+  NodeReadGuard(NodeReadGuard<K, V>),
+  NodeUpgradableReadGuard(NodeUpgradableReadGuard<K, V>),
 }
 
-impl ReadGuard {
+impl<K: Key, V: Value> ReadGuard<K, V> {
   pub(in locking) fn acquire_read_guard(
-    btree: &Arc<BTree>,
-    lock_target: &LockTarget,
-  ) -> ReadGuard {
-    match lock_target {
-      LockTarget::Node(identifier) => {
-        Self::acquire_node_read_guard(btree, identifier)
+    btree: &Arc<BTree<K, V>>,
+    latch_target: &LatchTarget,
+  ) -> Result<ReadGuard<K, V>> {
+    match latch_target {
+      LatchTarget::Node(identifier) => {
+        Self::acquire_node_read_guard(btree, *identifier)
       }
-      LockTarget::RootIdentifier => {
-        Self::acquire_root_identifier_read_guard(btree)
+      LatchTarget::RootIdentifier => {
+        Ok(Self::acquire_root_identifier_read_guard(btree))
       }
     }
   }
 
   pub(in locking) fn acquire_node_read_guard(
-    btree: &Arc<BTree>,
-    identifier: &str,
-  ) -> ReadGuard {
-    ReadGuard::NodeReadGuard(NodeReadGuard::acquire(btree, identifier))
+    btree: &Arc<BTree<K, V>>,
+    identifier: NodeIdentifier,
+  ) -> Result<ReadGuard<K, V>> {
+    let guard = NodeReadGuard::acquire(btree, identifier)?;
+    Ok(ReadGuard::NodeReadGuard(guard))
+  }
+
+  pub(in locking) fn acquire_node_upgradable_read_guard(
+    btree: &Arc<BTree<K, V>>,
+    identifier: NodeIdentifier,
+  ) -> Result<ReadGuard<K, V>> {
+    let guard = NodeUpgradableReadGuard::acquire(btree, identifier)?;
+    Ok(ReadGuard::NodeUpgradableReadGuard(guard))
   }
 
   pub(in locking) fn acquire_root_identifier_read_guard(
-    btree: &Arc<BTree>,
-  ) -> ReadGuard {
-    ReadGuard::RootIdentifierReadGuard(
-      RootIdentifierReadGuard::acquire(btree),
-    )
+    btree: &Arc<BTree<K, V>>,
+  ) -> ReadGuard<K, V> {
+    let guard = RootIdentifierReadGuard::acquire(btree);
+    ReadGuard::RootIdentifierReadGuard(guard)
   }
 
-  pub fn target(&self) -> LockTarget {
+  pub fn target(&self) -> LatchTarget {
     match self {
       ReadGuard::RootIdentifierReadGuard(..) => {
-        LockTarget::RootIdentifier
+        LatchTarget::RootIdentifier
       }
       ReadGuard::NodeReadGuard(guard) => {
-        LockTarget::Node(String::from(guard.identifier()))
+        LatchTarget::Node(guard.identifier())
+      }
+      ReadGuard::NodeUpgradableReadGuard(guard) => {
+        LatchTarget::Node(guard.identifier())
       }
     }
   }
@@ -52,30 +72,50 @@ impl ReadGuard {
   pub fn unwrap_node_read_guard(
     self,
     message: &'static str,
-  ) -> NodeReadGuard {
+  ) -> Result<NodeReadGuard<K, V>> {
     match self {
-      ReadGuard::RootIdentifierReadGuard(..) => panic!(message),
-      ReadGuard::NodeReadGuard(node_guard) => node_guard,
+      ReadGuard::RootIdentifierReadGuard(..)
+      | ReadGuard::NodeUpgradableReadGuard(..) => {
+        Err(Error::InvariantViolation(message))
+      }
+      ReadGuard::NodeReadGuard(node_guard) => Ok(node_guard),
     }
   }
 
-  pub fn unwrap_node_read_guard_ref(
+  // Either kind of node read guard will do to read the node.
+  pub fn unwrap_node_ref(
     &self,
     message: &'static str,
-  ) -> &NodeReadGuard {
+  ) -> Result<&Node<K, V>> {
+    match self {
+      ReadGuard::RootIdentifierReadGuard(..) => {
+        Err(Error::InvariantViolation(message))
+      }
+      ReadGuard::NodeReadGuard(node_guard) => Ok(node_guard),
+      ReadGuard::NodeUpgradableReadGuard(node_guard) => Ok(node_guard),
+    }
+  }
+
+  pub fn unwrap_node_upgradable_read_guard_mut_ref(
+    &mut self,
+    message: &'static str,
+  ) -> Result<&mut NodeUpgradableReadGuard<K, V>> {
     match self {
-      ReadGuard::RootIdentifierReadGuard(..) => panic!(message),
-      ReadGuard::NodeReadGuard(node_guard) => node_guard,
+      ReadGuard::NodeUpgradableReadGuard(node_guard) => Ok(node_guard),
+      _ => Err(Error::InvariantViolation(message)),
     }
   }
 
   pub fn unwrap_root_identifier_read_guard_ref(
     &self,
     message: &'static str,
-  ) -> &RootIdentifierReadGuard {
+  ) -> Result<&RootIdentifierReadGuard<K, V>> {
     match self {
-      ReadGuard::NodeReadGuard(..) => panic!(message),
-      ReadGuard::RootIdentifierReadGuard(root_guard) => root_guard,
+      ReadGuard::NodeReadGuard(..)
+      | ReadGuard::NodeUpgradableReadGuard(..) => {
+        Err(Error::InvariantViolation(message))
+      }
+      ReadGuard::RootIdentifierReadGuard(root_guard) => Ok(root_guard),
     }
   }
 }
@@ -1,26 +1,30 @@
 use super::ReadGuard;
 use btree::BTree;
+use key::{Key, Value};
 use parking_lot::RwLockReadGuard;
 use std::ops::Deref;
 use std::sync::Arc;
+use storage::NodeIdentifier;
 
-pub struct RootIdentifierReadGuard {
-  _btree: Arc<BTree>,
-  guard: RwLockReadGuard<'static, String>,
+// Fields are dropped in order: we must release the guard before the
+// `Arc` that keeps the `BTree` alive.
+pub struct RootIdentifierReadGuard<K: Key, V: Value> {
+  guard: RwLockReadGuard<'static, NodeIdentifier>,
+  _btree: Arc<BTree<K, V>>,
 }
 
-impl Deref for RootIdentifierReadGuard {
-  type Target = String;
+impl<K: Key, V: Value> Deref for RootIdentifierReadGuard<K, V> {
+  type Target = NodeIdentifier;
 
-  fn deref(&self) -> &String {
+  fn deref(&self) -> &NodeIdentifier {
     &self.guard
   }
 }
 
-impl RootIdentifierReadGuard {
+impl<K: Key, V: Value> RootIdentifierReadGuard<K, V> {
   pub(in locking) fn acquire(
-    btree: &Arc<BTree>,
-  ) -> RootIdentifierReadGuard {
+    btree: &Arc<BTree<K, V>>,
+  ) -> RootIdentifierReadGuard<K, V> {
     // This is trickery. `RwLockReadGuard` wants a lifetime: it doesn't
     // want to outlive the `BTree`. But the `BTree` *cannot* be lost,
     // because I hold onto it via `Arc`.
@@ -29,22 +33,18 @@ impl RootIdentifierReadGuard {
     // unsafe code.
     unsafe {
       let lock = btree.root_identifier_lock();
-      let guard: RwLockReadGuard<'static, String> =
+      let guard: RwLockReadGuard<'static, NodeIdentifier> =
         std::mem::transmute(lock.read());
 
-      let btree: Arc<BTree> = Arc::clone(btree);
+      let btree: Arc<BTree<K, V>> = Arc::clone(btree);
       RootIdentifierReadGuard {
-        _btree: btree,
         guard,
+        _btree: btree,
       }
     }
   }
 
-  pub fn as_str_ref(&self) -> &str {
-    &(*self)
-  }
-
-  pub fn upcast(self) -> ReadGuard {
+  pub fn upcast(self) -> ReadGuard<K, V> {
     ReadGuard::RootIdentifierReadGuard(self)
   }
 }
@@ -1,52 +1,71 @@
 use super::WriteGuard;
 use btree::BTree;
+use buffer_pool::{NodeLatchWriteGuard, NodePin};
+use error::Result;
+use key::{Key, Value};
 use node::Node;
-use parking_lot::{RwLock, RwLockWriteGuard};
 use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
+use storage::NodeIdentifier;
 
-pub struct NodeWriteGuard {
-  _lock: Arc<RwLock<Node>>,
-  guard: RwLockWriteGuard<'static, Node>,
+// Fields are dropped in order: we must release the guard before the
+// NodePin that keeps the `NodeLatch` alive (and in the BufferPool).
+pub struct NodeWriteGuard<K: Key, V: Value> {
+  guard: NodeLatchWriteGuard<'static, K, V>,
+  _pin: NodePin<K, V>,
 }
 
-impl Deref for NodeWriteGuard {
-  type Target = Node;
+impl<K: Key, V: Value> Deref for NodeWriteGuard<K, V> {
+  type Target = Node<K, V>;
 
-  fn deref(&self) -> &Node {
+  fn deref(&self) -> &Node<K, V> {
     &self.guard
   }
 }
 
-impl DerefMut for NodeWriteGuard {
-  fn deref_mut(&mut self) -> &mut Node {
+impl<K: Key, V: Value> DerefMut for NodeWriteGuard<K, V> {
+  fn deref_mut(&mut self) -> &mut Node<K, V> {
     &mut self.guard
   }
 }
 
-impl NodeWriteGuard {
+impl<K: Key, V: Value> NodeWriteGuard<K, V> {
   pub(in locking) fn acquire(
-    btree: &BTree,
-    identifier: &str,
-  ) -> NodeWriteGuard {
-    // This is trickery. `RwLockWriteGuard` wants a lifetime: it doesn't
-    // want to outlive the `RwLock`. But the `RwLock` *cannot* be lost,
-    // because I hold onto it via `Arc`.
+    btree: &BTree<K, V>,
+    identifier: NodeIdentifier,
+  ) -> Result<NodeWriteGuard<K, V>> {
+    // This is trickery. `NodeLatchWriteGuard` wants a lifetime: it
+    // doesn't want to outlive the `NodeLatch`. But the latch *cannot* be
+    // lost, because I hold onto it via the NodePin.
     //
     // However, Rust won't understand this. Therefore, I resort to this
     // unsafe code.
     unsafe {
-      let lock: Arc<RwLock<Node>> =
-        btree.get_node_arc_lock(&identifier);
+      let pin = btree.pin_node(identifier)?;
+      let lock = pin.node();
 
-      let guard: RwLockWriteGuard<'static, Node> =
+      let guard: NodeLatchWriteGuard<'static, K, V> =
         std::mem::transmute(lock.write());
 
-      NodeWriteGuard { _lock: lock, guard }
+      // Whoever writes to the node will do so while we hold the guard,
+      // so the next flush can't miss the change.
+      pin.mark_dirty();
+
+      Ok(NodeWriteGuard { guard, _pin: pin })
     }
   }
 
-  pub fn upcast(self) -> WriteGuard {
+  // The latch we get when we upgrade a `NodeUpgradableReadGuard`.
+  pub(in locking) fn from_upgraded(
+    guard: NodeLatchWriteGuard<'static, K, V>,
+    pin: NodePin<K, V>,
+  ) -> NodeWriteGuard<K, V> {
+    // As in `acquire`, the change can't be missed.
+    pin.mark_dirty();
+
+    NodeWriteGuard { guard, _pin: pin }
+  }
+
+  pub fn upcast(self) -> WriteGuard<K, V> {
     WriteGuard::NodeWriteGuard(self)
   }
 }
@@ -1,29 +1,33 @@
 use super::WriteGuard;
 use btree::BTree;
+use key::{Key, Value};
 use parking_lot::RwLockWriteGuard;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use storage::NodeIdentifier;
 
-pub struct RootIdentifierWriteGuard {
-  _btree: Arc<BTree>,
-  guard: RwLockWriteGuard<'static, String>,
+// Fields are dropped in order: we must release the guard before the
+// `Arc` that keeps the `BTree` alive.
+pub struct RootIdentifierWriteGuard<K: Key, V: Value> {
+  guard: RwLockWriteGuard<'static, NodeIdentifier>,
+  _btree: Arc<BTree<K, V>>,
 }
 
-impl Deref for RootIdentifierWriteGuard {
-  type Target = String;
+impl<K: Key, V: Value> Deref for RootIdentifierWriteGuard<K, V> {
+  type Target = NodeIdentifier;
 
-  fn deref(&self) -> &String {
+  fn deref(&self) -> &NodeIdentifier {
     &self.guard
   }
 }
 
-impl DerefMut for RootIdentifierWriteGuard {
-  fn deref_mut(&mut self) -> &mut String {
+impl<K: Key, V: Value> DerefMut for RootIdentifierWriteGuard<K, V> {
+  fn deref_mut(&mut self) -> &mut NodeIdentifier {
     &mut self.guard
   }
 }
 
-impl RootIdentifierWriteGuard {
+impl<K: Key, V: Value> RootIdentifierWriteGuard<K, V> {
   // This is trickery. `RwLockWriteGuard` wants a lifetime: it doesn't
   // want to outlive the `BTree`. But the `BTree` *cannot* be lost,
   // because I hold onto it via `Arc`.
@@ -31,26 +35,23 @@ impl RootIdentifierWriteGuard {
   // However, Rust won't understand this. Therefore, I resort to this
   // unsafe code.
   pub(in locking) fn acquire(
-    btree: &Arc<BTree>,
-  ) -> RootIdentifierWriteGuard {
+    btree: &Arc<BTree<K, V>>,
+  ) -> RootIdentifierWriteGuard<K, V> {
     unsafe {
       let lock = btree.root_identifier_lock();
-      let guard: RwLockWriteGuard<'static, String> =
+      let guard: RwLockWriteGuard<'static, NodeIdentifier> =
         std::mem::transmute(lock.write());
+      btree.mark_root_identifier_dirty();
 
-      let btree: Arc<BTree> = Arc::clone(btree);
+      let btree: Arc<BTree<K, V>> = Arc::clone(btree);
       RootIdentifierWriteGuard {
-        _btree: btree,
         guard,
+        _btree: btree,
       }
     }
   }
 
-  pub fn as_str_ref(&self) -> &str {
-    &(*self)
-  }
-
-  pub fn upcast(self) -> WriteGuard {
+  pub fn upcast(self) -> WriteGuard<K, V> {
     WriteGuard::RootIdentifierWriteGuard(self)
   }
 }
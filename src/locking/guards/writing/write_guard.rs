@@ -1,52 +1,53 @@
 use super::{NodeWriteGuard, RootIdentifierWriteGuard};
 use btree::BTree;
-use locking::LockTarget;
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::LatchTarget;
 use std::sync::Arc;
+use storage::NodeIdentifier;
 
-pub enum WriteGuard {
-  RootIdentifierWriteGuard(RootIdentifierWriteGuard),
-  NodeWriteGuard(NodeWriteGuard),
+pub enum WriteGuard<K: Key, V: Value> {
+  RootIdentifierWriteGuard(RootIdentifierWriteGuard<K, V>),
+  NodeWriteGuard(NodeWriteGuard<K, V>),
 }
 
-impl WriteGuard {
+impl<K: Key, V: Value> WriteGuard<K, V> {
   pub(in locking) fn acquire_write_guard(
-    btree: &Arc<BTree>,
-    lock_target: &LockTarget,
-  ) -> WriteGuard {
-    match lock_target {
-      LockTarget::Node(identifier) => {
-        Self::acquire_node_write_guard(btree, identifier)
+    btree: &Arc<BTree<K, V>>,
+    latch_target: &LatchTarget,
+  ) -> Result<WriteGuard<K, V>> {
+    match latch_target {
+      LatchTarget::Node(identifier) => {
+        Self::acquire_node_write_guard(btree, *identifier)
       }
-      LockTarget::RootIdentifier => {
-        Self::acquire_root_identifier_write_guard(btree)
+      LatchTarget::RootIdentifier => {
+        Ok(Self::acquire_root_identifier_write_guard(btree))
       }
     }
   }
 
   pub(in locking) fn acquire_node_write_guard(
-    btree: &Arc<BTree>,
-    identifier: &str,
-  ) -> WriteGuard {
-    WriteGuard::NodeWriteGuard(NodeWriteGuard::acquire(
-      btree, identifier,
-    ))
+    btree: &Arc<BTree<K, V>>,
+    identifier: NodeIdentifier,
+  ) -> Result<WriteGuard<K, V>> {
+    let guard = NodeWriteGuard::acquire(btree, identifier)?;
+    Ok(WriteGuard::NodeWriteGuard(guard))
   }
 
   pub(in locking) fn acquire_root_identifier_write_guard(
-    btree: &Arc<BTree>,
-  ) -> WriteGuard {
-    WriteGuard::RootIdentifierWriteGuard(
-      RootIdentifierWriteGuard::acquire(btree),
-    )
+    btree: &Arc<BTree<K, V>>,
+  ) -> WriteGuard<K, V> {
+    let guard = RootIdentifierWriteGuard::acquire(btree);
+    WriteGuard::RootIdentifierWriteGuard(guard)
   }
 
-  pub fn target(&self) -> LockTarget {
+  pub fn target(&self) -> LatchTarget {
     match self {
       WriteGuard::RootIdentifierWriteGuard(..) => {
-        LockTarget::RootIdentifier
+        LatchTarget::RootIdentifier
       }
       WriteGuard::NodeWriteGuard(guard) => {
-        LockTarget::Node(String::from(guard.identifier()))
+        LatchTarget::Node(guard.identifier())
       }
     }
   }
@@ -54,66 +55,84 @@ impl WriteGuard {
   pub fn unwrap_node_write_guard(
     self,
     message: &'static str,
-  ) -> NodeWriteGuard {
+  ) -> Result<NodeWriteGuard<K, V>> {
     match self {
-      WriteGuard::RootIdentifierWriteGuard(..) => panic!(message),
-      WriteGuard::NodeWriteGuard(node_write_guard) => node_write_guard,
+      WriteGuard::RootIdentifierWriteGuard(..) => {
+        Err(Error::InvariantViolation(message))
+      }
+      WriteGuard::NodeWriteGuard(node_write_guard) => {
+        Ok(node_write_guard)
+      }
     }
   }
 
   pub fn unwrap_node_write_guard_mut_ref(
     &mut self,
     message: &'static str,
-  ) -> &mut NodeWriteGuard {
+  ) -> Result<&mut NodeWriteGuard<K, V>> {
     match self {
-      WriteGuard::RootIdentifierWriteGuard(..) => panic!(message),
-      WriteGuard::NodeWriteGuard(node_write_guard) => node_write_guard,
+      WriteGuard::RootIdentifierWriteGuard(..) => {
+        Err(Error::InvariantViolation(message))
+      }
+      WriteGuard::NodeWriteGuard(node_write_guard) => {
+        Ok(node_write_guard)
+      }
     }
   }
 
   pub fn unwrap_node_write_guard_ref(
     &self,
     message: &'static str,
-  ) -> &NodeWriteGuard {
+  ) -> Result<&NodeWriteGuard<K, V>> {
     match self {
-      WriteGuard::RootIdentifierWriteGuard(..) => panic!(message),
-      WriteGuard::NodeWriteGuard(node_write_guard) => node_write_guard,
+      WriteGuard::RootIdentifierWriteGuard(..) => {
+        Err(Error::InvariantViolation(message))
+      }
+      WriteGuard::NodeWriteGuard(node_write_guard) => {
+        Ok(node_write_guard)
+      }
     }
   }
 
   pub fn unwrap_root_identifier_write_guard(
     self,
     message: &'static str,
-  ) -> RootIdentifierWriteGuard {
+  ) -> Result<RootIdentifierWriteGuard<K, V>> {
     match self {
       WriteGuard::RootIdentifierWriteGuard(root_identifier_guard) => {
-        root_identifier_guard
+        Ok(root_identifier_guard)
+      }
+      WriteGuard::NodeWriteGuard(..) => {
+        Err(Error::InvariantViolation(message))
       }
-      WriteGuard::NodeWriteGuard(..) => panic!(message),
     }
   }
 
   pub fn unwrap_root_identifier_write_guard_mut_ref(
     &mut self,
     message: &'static str,
-  ) -> &mut RootIdentifierWriteGuard {
+  ) -> Result<&mut RootIdentifierWriteGuard<K, V>> {
     match self {
       WriteGuard::RootIdentifierWriteGuard(root_identifier_guard) => {
-        root_identifier_guard
+        Ok(root_identifier_guard)
+      }
+      WriteGuard::NodeWriteGuard(..) => {
+        Err(Error::InvariantViolation(message))
       }
-      WriteGuard::NodeWriteGuard(..) => panic!(message),
     }
   }
 
   pub fn unwrap_root_identifier_write_guard_ref(
     &self,
     message: &'static str,
-  ) -> &RootIdentifierWriteGuard {
+  ) -> Result<&RootIdentifierWriteGuard<K, V>> {
     match self {
       WriteGuard::RootIdentifierWriteGuard(root_identifier_guard) => {
-        root_identifier_guard
+        Ok(root_identifier_guard)
+      }
+      WriteGuard::NodeWriteGuard(..) => {
+        Err(Error::InvariantViolation(message))
       }
-      WriteGuard::NodeWriteGuard(..) => panic!(message),
     }
   }
 }
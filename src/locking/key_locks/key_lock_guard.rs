@@ -0,0 +1,87 @@
+use super::KeyLockMode;
+use btree::BTree;
+use error::Result;
+use key::{Key, Value};
+use locking::{LockRecord, LockTarget};
+use node::ComparisonValue;
+use std::sync::Arc;
+use std::time::Duration;
+use transaction::TransactionId;
+
+// A KeyLockGuard is the primitive guard for a lock in the KeyLockTable.
+// Dropping it releases the lock. It holds the `Arc` that keeps the
+// BTree (and its KeyLockTable) alive.
+pub struct KeyLockGuard<K: Key, V: Value> {
+  btree: Arc<BTree<K, V>>,
+  transaction_id: TransactionId,
+  lock_target: LockTarget,
+  lock_mode: KeyLockMode,
+  _lock_record: LockRecord,
+}
+
+impl<K: Key, V: Value> KeyLockGuard<K, V> {
+  pub(in locking) fn acquire_tree_lock(
+    btree: &Arc<BTree<K, V>>,
+    lock_mode: KeyLockMode,
+    transaction_id: TransactionId,
+    timeout: Option<Duration>,
+  ) -> Result<KeyLockGuard<K, V>> {
+    let lock_record = btree.key_lock_table().acquire_tree_lock(
+      btree.lock_manager(),
+      transaction_id,
+      lock_mode,
+      timeout,
+    )?;
+
+    Ok(KeyLockGuard {
+      btree: Arc::clone(btree),
+      transaction_id,
+      lock_target: LockTarget::Tree,
+      lock_mode,
+      _lock_record: lock_record,
+    })
+  }
+
+  pub(in locking) fn acquire_key_range_lock(
+    btree: &Arc<BTree<K, V>>,
+    top: ComparisonValue<&K>,
+    lock_mode: KeyLockMode,
+    transaction_id: TransactionId,
+    timeout: Option<Duration>,
+  ) -> Result<KeyLockGuard<K, V>> {
+    let (identifier, lock_record) =
+      btree.key_lock_table().acquire_key_range_lock(
+        btree.lock_manager(),
+        transaction_id,
+        top,
+        lock_mode,
+        timeout,
+      )?;
+
+    Ok(KeyLockGuard {
+      btree: Arc::clone(btree),
+      transaction_id,
+      lock_target: LockTarget::KeyRange(identifier),
+      lock_mode,
+      _lock_record: lock_record,
+    })
+  }
+
+  pub fn target(&self) -> LockTarget {
+    self.lock_target
+  }
+
+  pub fn lock_mode(&self) -> KeyLockMode {
+    self.lock_mode
+  }
+}
+
+impl<K: Key, V: Value> Drop for KeyLockGuard<K, V> {
+  fn drop(&mut self) {
+    self.btree.key_lock_table().release(
+      self.transaction_id,
+      self.lock_target,
+      self.lock_mode,
+    );
+  }
+}
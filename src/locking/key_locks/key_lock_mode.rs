@@ -0,0 +1,42 @@
+// The modes of the locks in a KeyLockTable. A key range is only ever
+// locked Shared or Exclusive. The whole tree may also be locked in an
+// intention mode, which says that we lock key ranges in the matching
+// mode underneath.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyLockMode {
+  IntentionShared,
+  IntentionExclusive,
+  Shared,
+  Exclusive,
+}
+
+impl KeyLockMode {
+  // Whether two different transactions may hold these modes at once.
+  pub fn is_compatible_with(self, other: KeyLockMode) -> bool {
+    use self::KeyLockMode::*;
+
+    match (self, other) {
+      (Exclusive, _) | (_, Exclusive) => false,
+      (IntentionShared, _) | (_, IntentionShared) => true,
+      (IntentionExclusive, IntentionExclusive) => true,
+      (Shared, Shared) => true,
+      _ => false,
+    }
+  }
+
+  // Whether holding this mode already gives us everything `other`
+  // would.
+  pub fn covers(self, other: KeyLockMode) -> bool {
+    use self::KeyLockMode::*;
+
+    matches!(
+      (self, other),
+      (Exclusive, _)
+        | (Shared, Shared)
+        | (Shared, IntentionShared)
+        | (IntentionExclusive, IntentionExclusive)
+        | (IntentionExclusive, IntentionShared)
+        | (IntentionShared, IntentionShared)
+    )
+  }
+}
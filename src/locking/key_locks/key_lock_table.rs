@@ -0,0 +1,396 @@
+use super::KeyLockMode;
+use error::Result;
+use key::Key;
+use locking::{LockManager, LockRecord, LockTarget};
+use node::ComparisonValue;
+use parking_lot::{Condvar, Mutex};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use transaction::TransactionId;
+
+pub type KeyRangeIdentifier = u64;
+
+// The KeyLockTable grants the logical locks of a BTree: locks on key
+// ranges, and on the tree as a whole. Unlike a node, these have no
+// `RwLock` of their own, so the table keeps track of who holds each one
+// itself.
+//
+// A key range is named by the key at its top: the lock on key K covers
+// every key after the one before K, through K itself. Infinity names
+// the range past the last key. The key needn't be in the tree; it only
+// has to be the next key when the lock is taken.
+//
+// Keys are matched with `Key::compare`, so two keys that compare equal
+// share a lock even if they are written differently.
+//
+// The LockManager knows each locked range by a KeyRangeIdentifier. A
+// range keeps its identifier for as long as anyone holds or waits for
+// its lock, and is forgotten after that.
+//
+// A transaction may hold the same lock more than once, in different
+// modes. Its own locks never conflict with each other.
+//
+// Waiters are served in the order they came. Otherwise a stream of
+// shared lockers could keep an exclusive locker waiting forever: each
+// deadlock victim would come straight back for its shared lock, and get
+// it. Only a transaction that already holds the lock (say, to upgrade
+// it) goes ahead of the others, or it could wait behind someone who is
+// waiting for it.
+//
+// The LockManager only knows who holds each lock, not who is in line.
+// That's enough to find deadlocks: whoever we wait behind is waiting
+// for the same holders we are.
+pub struct KeyLockTable<K: Key> {
+  state: Mutex<KeyLockTableState<K>>,
+  released: Condvar,
+}
+
+struct KeyLockTableState<K: Key> {
+  next_identifier: KeyRangeIdentifier,
+  identifiers: BTreeMap<OrderedKeyRange<K>, KeyRangeIdentifier>,
+  key_ranges: HashMap<KeyRangeIdentifier, KeyRangeState<K>>,
+  tree_queue: LockQueue,
+}
+
+struct KeyRangeState<K: Key> {
+  top: ComparisonValue<K>,
+  // Holders and waiters, both.
+  num_users: usize,
+  queue: LockQueue,
+}
+
+// Who holds a lock, and who is waiting for it, in the order they came.
+// A transaction is only listed as waiting while it is in `try_grant`,
+// or between its calls from the LockManager.
+#[derive(Default)]
+struct LockQueue {
+  holders: Vec<(TransactionId, KeyLockMode)>,
+  waiters: Vec<(TransactionId, KeyLockMode)>,
+}
+
+// Orders key ranges by `Key::compare`, so that they can be kept in a
+// BTreeMap.
+struct OrderedKeyRange<K: Key>(ComparisonValue<K>);
+
+impl<K: Key> KeyLockTable<K> {
+  pub fn new() -> KeyLockTable<K> {
+    KeyLockTable {
+      state: Mutex::new(KeyLockTableState {
+        next_identifier: 0,
+        identifiers: BTreeMap::new(),
+        key_ranges: HashMap::new(),
+        tree_queue: LockQueue::default(),
+      }),
+      released: Condvar::new(),
+    }
+  }
+
+  // We wait for a lock through the LockManager, so that we can be
+  // chosen as a deadlock victim. The caller must `release` the lock
+  // once it is done with it.
+  pub(in locking) fn acquire_tree_lock(
+    &self,
+    lock_manager: &Arc<LockManager>,
+    transaction_id: TransactionId,
+    lock_mode: KeyLockMode,
+    timeout: Option<Duration>,
+  ) -> Result<LockRecord> {
+    let result = lock_manager.acquire(
+      transaction_id,
+      &LockTarget::Tree,
+      timeout,
+      |wait| {
+        self.try_grant(
+          transaction_id,
+          LockTarget::Tree,
+          lock_mode,
+          wait,
+        )
+      },
+    );
+
+    match result {
+      Ok((_, lock_record)) => Ok(lock_record),
+      Err(error) => {
+        let mut state = self.state.lock();
+        self.stop_waiting(&mut state, transaction_id, LockTarget::Tree);
+        Err(error)
+      }
+    }
+  }
+
+  pub(in locking) fn acquire_key_range_lock(
+    &self,
+    lock_manager: &Arc<LockManager>,
+    transaction_id: TransactionId,
+    top: ComparisonValue<&K>,
+    lock_mode: KeyLockMode,
+    timeout: Option<Duration>,
+  ) -> Result<(KeyRangeIdentifier, LockRecord)> {
+    let identifier = self.begin_use(top);
+    let lock_target = LockTarget::KeyRange(identifier);
+
+    let result = lock_manager.acquire(
+      transaction_id,
+      &lock_target,
+      timeout,
+      |wait| {
+        self.try_grant(transaction_id, lock_target, lock_mode, wait)
+      },
+    );
+
+    match result {
+      Ok((_, lock_record)) => Ok((identifier, lock_record)),
+      Err(error) => {
+        let mut state = self.state.lock();
+        self.stop_waiting(&mut state, transaction_id, lock_target);
+        self.end_use(&mut state, identifier);
+        Err(error)
+      }
+    }
+  }
+
+  // Only a range that someone holds or waits for has an identifier.
+  pub fn identifier(
+    &self,
+    top: ComparisonValue<&K>,
+  ) -> Option<KeyRangeIdentifier> {
+    let state = self.state.lock();
+    state
+      .identifiers
+      .get(&OrderedKeyRange(top.cloned()))
+      .cloned()
+  }
+
+  pub(in locking) fn release(
+    &self,
+    transaction_id: TransactionId,
+    lock_target: LockTarget,
+    lock_mode: KeyLockMode,
+  ) {
+    let mut state = self.state.lock();
+    {
+      let holders = &mut state.queue_mut(lock_target).holders;
+      let idx = holders
+        .iter()
+        .position(|holder| *holder == (transaction_id, lock_mode))
+        .expect("released lock should be held by transaction");
+      holders.swap_remove(idx);
+    }
+    if let LockTarget::KeyRange(identifier) = lock_target {
+      self.end_use(&mut state, identifier);
+    }
+
+    self.released.notify_all();
+  }
+
+  fn begin_use(&self, top: ComparisonValue<&K>) -> KeyRangeIdentifier {
+    let mut state = self.state.lock();
+    let state = &mut *state;
+
+    let ordered_top = OrderedKeyRange(top.cloned());
+    let identifier = match state.identifiers.get(&ordered_top) {
+      Some(identifier) => *identifier,
+      None => {
+        let identifier = state.next_identifier;
+        state.next_identifier += 1;
+        state.identifiers.insert(ordered_top, identifier);
+        state.key_ranges.insert(
+          identifier,
+          KeyRangeState {
+            top: top.cloned(),
+            num_users: 0,
+            queue: LockQueue::default(),
+          },
+        );
+        identifier
+      }
+    };
+
+    state
+      .key_ranges
+      .get_mut(&identifier)
+      .expect("key range was just found")
+      .num_users += 1;
+
+    identifier
+  }
+
+  fn end_use(
+    &self,
+    state: &mut KeyLockTableState<K>,
+    identifier: KeyRangeIdentifier,
+  ) {
+    let is_now_unused = {
+      let key_range = state
+        .key_ranges
+        .get_mut(&identifier)
+        .expect("key range should be in use");
+      key_range.num_users -= 1;
+      key_range.num_users == 0
+    };
+
+    if is_now_unused {
+      let key_range = state
+        .key_ranges
+        .remove(&identifier)
+        .expect("key range should be in use");
+      state.identifiers.remove(&OrderedKeyRange(key_range.top));
+    }
+  }
+
+  // Like `RwLock::try_read_for`: waits up to `wait` for the lock. If we
+  // do wait, we keep our place in line even once we give up, since the
+  // LockManager will call again. Our caller takes us out of line if it
+  // stops trying.
+  fn try_grant(
+    &self,
+    transaction_id: TransactionId,
+    lock_target: LockTarget,
+    lock_mode: KeyLockMode,
+    wait: Duration,
+  ) -> Option<()> {
+    let deadline = Instant::now() + wait;
+    let mut state = self.state.lock();
+
+    loop {
+      {
+        let queue = state.queue_mut(lock_target);
+        if queue.can_grant(transaction_id, lock_mode) {
+          let was_waiting = queue.remove_waiter(transaction_id);
+          queue.holders.push((transaction_id, lock_mode));
+          // Someone may have been waiting behind us.
+          if was_waiting {
+            self.released.notify_all();
+          }
+          return Some(());
+        }
+
+        if wait == Duration::from_millis(0) {
+          return None;
+        }
+        if !queue.is_waiting(transaction_id) {
+          queue.waiters.push((transaction_id, lock_mode));
+        }
+      }
+
+      if self.released.wait_until(&mut state, deadline).timed_out() {
+        return None;
+      }
+    }
+  }
+
+  fn stop_waiting(
+    &self,
+    state: &mut KeyLockTableState<K>,
+    transaction_id: TransactionId,
+    lock_target: LockTarget,
+  ) {
+    if state.queue_mut(lock_target).remove_waiter(transaction_id) {
+      self.released.notify_all();
+    }
+  }
+}
+
+impl<K: Key> KeyLockTableState<K> {
+  fn queue_mut(&mut self, lock_target: LockTarget) -> &mut LockQueue {
+    match lock_target {
+      LockTarget::Tree => &mut self.tree_queue,
+      LockTarget::KeyRange(identifier) => {
+        &mut self
+          .key_ranges
+          .get_mut(&identifier)
+          .expect("key range should be in use")
+          .queue
+      }
+    }
+  }
+}
+
+impl LockQueue {
+  // We must get along with every other holder, and with every waiter
+  // ahead of us. Unless we hold the lock already: then we only wait for
+  // the holders.
+  fn can_grant(
+    &self,
+    transaction_id: TransactionId,
+    lock_mode: KeyLockMode,
+  ) -> bool {
+    let gets_along =
+      |&(other_id, other_mode): &(TransactionId, KeyLockMode)| {
+        other_id == transaction_id
+          || other_mode.is_compatible_with(lock_mode)
+      };
+
+    if !self.holders.iter().all(gets_along) {
+      return false;
+    }
+
+    let is_holder = self
+      .holders
+      .iter()
+      .any(|(holder_id, _)| *holder_id == transaction_id);
+    if is_holder {
+      return true;
+    }
+
+    self
+      .waiters
+      .iter()
+      .take_while(|(waiter_id, _)| *waiter_id != transaction_id)
+      .all(gets_along)
+  }
+
+  fn is_waiting(&self, transaction_id: TransactionId) -> bool {
+    self
+      .waiters
+      .iter()
+      .any(|(waiter_id, _)| *waiter_id == transaction_id)
+  }
+
+  // Returns whether we were waiting.
+  fn remove_waiter(&mut self, transaction_id: TransactionId) -> bool {
+    let idx = self
+      .waiters
+      .iter()
+      .position(|(waiter_id, _)| *waiter_id == transaction_id);
+    match idx {
+      None => false,
+      Some(idx) => {
+        self.waiters.remove(idx);
+        true
+      }
+    }
+  }
+}
+
+impl<K: Key> Default for KeyLockTable<K> {
+  fn default() -> KeyLockTable<K> {
+    KeyLockTable::new()
+  }
+}
+
+impl<K: Key> Ord for OrderedKeyRange<K> {
+  fn cmp(&self, other: &OrderedKeyRange<K>) -> Ordering {
+    self.0.as_ref().cmp(&other.0.as_ref())
+  }
+}
+
+impl<K: Key> PartialOrd for OrderedKeyRange<K> {
+  fn partial_cmp(
+    &self,
+    other: &OrderedKeyRange<K>,
+  ) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<K: Key> PartialEq for OrderedKeyRange<K> {
+  fn eq(&self, other: &OrderedKeyRange<K>) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl<K: Key> Eq for OrderedKeyRange<K> {}
@@ -0,0 +1,7 @@
+mod key_lock_guard;
+mod key_lock_mode;
+mod key_lock_table;
+
+pub use self::key_lock_guard::KeyLockGuard;
+pub use self::key_lock_mode::KeyLockMode;
+pub use self::key_lock_table::{KeyLockTable, KeyRangeIdentifier};
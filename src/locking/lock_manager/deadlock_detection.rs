@@ -0,0 +1,240 @@
+use super::lock_manager::LockManagerState;
+use super::LockManager;
+use transaction::TransactionId;
+
+impl LockManager {
+  // Called by a waiting transaction between attempts to acquire its
+  // lock. Returns true if the transaction must give up.
+  pub(super) fn is_deadlock_victim(
+    &self,
+    transaction_id: TransactionId,
+  ) -> bool {
+    let mut state = self.state.lock();
+
+    // Maybe someone else found a cycle and chose us.
+    if state.victims.remove(&transaction_id) {
+      return true;
+    }
+
+    let cycle = match state.find_cycle(transaction_id) {
+      None => return false,
+      Some(cycle) => cycle,
+    };
+
+    // We choose the youngest transaction, since it has probably done
+    // the least work. A transaction that is rolling back is only chosen
+    // if everyone in the cycle is; otherwise no one would ever give up.
+    let victim = cycle
+      .iter()
+      .copied()
+      .filter(|member| !state.rolling_back.contains(member))
+      .max()
+      .or_else(|| cycle.iter().copied().max())
+      .expect("a cycle is never empty");
+
+    if victim == transaction_id {
+      return true;
+    }
+
+    // The victim will notice when it next wakes up.
+    state.victims.insert(victim);
+    false
+  }
+}
+
+impl LockManagerState {
+  // Transaction T1 waits for T2 if T1 waits on a lock that T2 holds. We
+  // search for a path of such edges that leads back to the start.
+  fn find_cycle(
+    &self,
+    start_id: TransactionId,
+  ) -> Option<Vec<TransactionId>> {
+    let mut path = vec![start_id];
+    let mut visited = vec![start_id];
+    if self.extend_path_to_start(&mut path, &mut visited) {
+      Some(path)
+    } else {
+      None
+    }
+  }
+
+  fn extend_path_to_start(
+    &self,
+    path: &mut Vec<TransactionId>,
+    visited: &mut Vec<TransactionId>,
+  ) -> bool {
+    let current_id = *path.last().expect("path is never empty");
+    let lock_target = match self.waiting_for.get(&current_id) {
+      // A transaction that isn't waiting can't be part of a cycle.
+      None => return false,
+      Some(lock_target) => lock_target,
+    };
+    let holders = match self.holders.get(lock_target) {
+      None => return false,
+      Some(holders) => holders,
+    };
+
+    for &holder_id in holders {
+      if holder_id == current_id {
+        continue;
+      }
+
+      if holder_id == path[0] {
+        return true;
+      }
+
+      if visited.contains(&holder_id) {
+        continue;
+      }
+      visited.push(holder_id);
+
+      path.push(holder_id);
+      if self.extend_path_to_start(path, visited) {
+        return true;
+      }
+      path.pop();
+    }
+
+    false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use error::Error;
+  use locking::{LockManager, LockTarget};
+  use std::sync::Arc;
+  use std::time::Duration;
+  use transaction::TransactionId;
+
+  // Makes `transaction_id` hold the lock on `held`, and wait for the
+  // lock on `wanted`.
+  fn hold_and_wait(
+    lock_manager: &LockManager,
+    transaction_id: TransactionId,
+    held: LockTarget,
+    wanted: LockTarget,
+  ) {
+    let mut state = lock_manager.state.lock();
+    state.holders.entry(held).or_default().push(transaction_id);
+    state.waiting_for.insert(transaction_id, wanted);
+  }
+
+  fn begin_transactions(
+    lock_manager: &LockManager,
+    num_transactions: usize,
+  ) -> Vec<TransactionId> {
+    (0..num_transactions)
+      .map(|_| lock_manager.begin_transaction())
+      .collect()
+  }
+
+  #[test]
+  fn no_cycle_means_no_victim() {
+    let lock_manager = LockManager::new();
+    let ids = begin_transactions(&lock_manager, 3);
+    hold_and_wait(
+      &lock_manager,
+      ids[0],
+      LockTarget::KeyRange(0),
+      LockTarget::KeyRange(1),
+    );
+    hold_and_wait(
+      &lock_manager,
+      ids[1],
+      LockTarget::KeyRange(1),
+      LockTarget::KeyRange(2),
+    );
+
+    assert!(!lock_manager.is_deadlock_victim(ids[0]));
+    assert!(!lock_manager.is_deadlock_victim(ids[1]));
+  }
+
+  #[test]
+  fn youngest_in_the_cycle_is_the_victim() {
+    let lock_manager = LockManager::new();
+    let ids = begin_transactions(&lock_manager, 3);
+    for (idx, transaction_id) in ids.iter().enumerate() {
+      hold_and_wait(
+        &lock_manager,
+        *transaction_id,
+        LockTarget::KeyRange(idx as u64),
+        LockTarget::KeyRange(((idx + 1) % 3) as u64),
+      );
+    }
+
+    // The oldest finds the cycle, but isn't chosen. The youngest hears
+    // of it when it next checks.
+    assert!(!lock_manager.is_deadlock_victim(ids[0]));
+    assert!(lock_manager.is_deadlock_victim(ids[2]));
+    // Being told once is enough.
+    assert!(!lock_manager.state.lock().victims.contains(&ids[2]));
+  }
+
+  #[test]
+  fn rolling_back_transactions_are_spared() {
+    let lock_manager = LockManager::new();
+    let ids = begin_transactions(&lock_manager, 2);
+    hold_and_wait(
+      &lock_manager,
+      ids[0],
+      LockTarget::KeyRange(0),
+      LockTarget::KeyRange(1),
+    );
+    hold_and_wait(
+      &lock_manager,
+      ids[1],
+      LockTarget::KeyRange(1),
+      LockTarget::KeyRange(0),
+    );
+    lock_manager.begin_rollback(ids[1]);
+
+    assert!(lock_manager.is_deadlock_victim(ids[0]));
+    assert!(!lock_manager.is_deadlock_victim(ids[1]));
+  }
+
+  #[test]
+  fn acquire_gives_up_after_timeout() {
+    let lock_manager = Arc::new(LockManager::new());
+    let transaction_id = lock_manager.begin_transaction();
+    let result = lock_manager.acquire(
+      transaction_id,
+      &LockTarget::Tree,
+      Some(Duration::from_millis(10)),
+      |_| None::<()>,
+    );
+
+    assert_eq!(result.err(), Some(Error::LockTimeout));
+    // We no longer wait for anything.
+    let state = lock_manager.state.lock();
+    assert!(!state.waiting_for.contains_key(&transaction_id));
+  }
+
+  #[test]
+  fn acquire_gives_up_when_chosen_as_victim() {
+    let lock_manager = Arc::new(LockManager::new());
+    let ids = begin_transactions(&lock_manager, 2);
+    hold_and_wait(
+      &lock_manager,
+      ids[0],
+      LockTarget::KeyRange(0),
+      LockTarget::KeyRange(1),
+    );
+    lock_manager
+      .state
+      .lock()
+      .holders
+      .entry(LockTarget::KeyRange(1))
+      .or_default()
+      .push(ids[1]);
+
+    // The younger transaction closes the cycle, so it gives up.
+    let result = lock_manager.acquire(
+      ids[1],
+      &LockTarget::KeyRange(0),
+      None,
+      |_| None::<()>,
+    );
+    assert_eq!(result.err(), Some(Error::Deadlock));
+  }
+}
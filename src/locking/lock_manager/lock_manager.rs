@@ -0,0 +1,206 @@
+use super::LockRecord;
+use constants::DEADLOCK_CHECK_INTERVAL;
+use error::{Error, Result};
+use locking::LockTarget;
+use parking_lot::Mutex;
+use std::cmp;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use transaction::TransactionId;
+
+// The LockManager is shared by every LockSet of a BTree. It doesn't
+// grant locks itself (the KeyLockTable does that). Instead, it keeps a
+// record of who holds each lock, and who is waiting on which lock. That
+// is the wait-for graph we use to detect deadlocks.
+//
+// Only the logical locks come through here. The latches on nodes are
+// held for a single operation, and are taken in an order that can't
+// deadlock (see `nedbase::locking::guards`), so there is no need.
+//
+// A transaction that can't immediately acquire a lock waits in short
+// intervals. Between intervals, it checks whether it is part of a
+// cycle. If so, the youngest transaction in the cycle is chosen as the
+// victim. The victim gives up on its lock with `Error::Deadlock`.
+//
+// A transaction may also say how long it is willing to wait. If it
+// still doesn't have the lock by then, it gives up with
+// `Error::LockTimeout`.
+
+pub struct LockManager {
+  pub(super) state: Mutex<LockManagerState>,
+}
+
+#[derive(Default)]
+pub(super) struct LockManagerState {
+  pub next_transaction_id: TransactionId,
+  // Every transaction that has begun but not yet ended.
+  pub running: BTreeSet<TransactionId>,
+  // A transaction may (briefly) appear more than once for a target.
+  pub holders: HashMap<LockTarget, Vec<TransactionId>>,
+  pub waiting_for: HashMap<TransactionId, LockTarget>,
+  pub victims: HashSet<TransactionId>,
+  // A transaction that is rolling back is made a victim only as a last
+  // resort; it can't give back the locks it is undoing changes under.
+  pub rolling_back: HashSet<TransactionId>,
+}
+
+impl LockManager {
+  pub fn new() -> LockManager {
+    LockManager {
+      state: Mutex::new(LockManagerState::default()),
+    }
+  }
+
+  pub fn begin_transaction(&self) -> TransactionId {
+    let mut state = self.state.lock();
+    let transaction_id = state.next_transaction_id;
+    state.next_transaction_id += 1;
+    state.running.insert(transaction_id);
+    transaction_id
+  }
+
+  // The transaction that began next would get this id. A transaction
+  // that begins after some change gets at least this id.
+  pub fn next_transaction_id(&self) -> TransactionId {
+    self.state.lock().next_transaction_id
+  }
+
+  // The oldest transaction that is still running, if any. Every
+  // transaction with a smaller id has ended.
+  pub fn oldest_running_transaction(&self) -> Option<TransactionId> {
+    self.state.lock().running.iter().next().cloned()
+  }
+
+  // Recovery calls this so that no new transaction reuses an id that is
+  // still in the log.
+  pub fn skip_transaction_ids_through(
+    &self,
+    transaction_id: TransactionId,
+  ) {
+    let mut state = self.state.lock();
+    state.next_transaction_id =
+      cmp::max(state.next_transaction_id, transaction_id + 1);
+  }
+
+  pub fn begin_rollback(&self, transaction_id: TransactionId) {
+    self.state.lock().rolling_back.insert(transaction_id);
+  }
+
+  pub fn end_transaction(&self, transaction_id: TransactionId) {
+    let mut state = self.state.lock();
+    state.running.remove(&transaction_id);
+    state.victims.remove(&transaction_id);
+    state.rolling_back.remove(&transaction_id);
+  }
+
+  // `try_acquire` should try to acquire the lock for up to the given
+  // duration. We call it over and over until either it succeeds, we
+  // are chosen as a deadlock victim, or we run out of `timeout`. A
+  // `timeout` of None means we will wait as long as it takes.
+  pub fn acquire<G, F>(
+    self: &Arc<Self>,
+    transaction_id: TransactionId,
+    lock_target: &LockTarget,
+    timeout: Option<Duration>,
+    mut try_acquire: F,
+  ) -> Result<(G, LockRecord)>
+  where
+    F: FnMut(Duration) -> Option<G>,
+  {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    // Most of the time there is no contention, so don't bother to
+    // record that we are waiting.
+    if let Some(guard) = try_acquire(Duration::from_millis(0)) {
+      return Ok((
+        guard,
+        self.record_holder(transaction_id, lock_target),
+      ));
+    }
+
+    self
+      .state
+      .lock()
+      .waiting_for
+      .insert(transaction_id, *lock_target);
+
+    loop {
+      // Never wait past the deadline.
+      let wait = match deadline {
+        None => DEADLOCK_CHECK_INTERVAL,
+        Some(deadline) => {
+          let now = Instant::now();
+          if deadline <= now {
+            self.state.lock().waiting_for.remove(&transaction_id);
+            return Err(Error::LockTimeout);
+          }
+
+          cmp::min(DEADLOCK_CHECK_INTERVAL, deadline - now)
+        }
+      };
+
+      if let Some(guard) = try_acquire(wait) {
+        self.state.lock().waiting_for.remove(&transaction_id);
+        return Ok((
+          guard,
+          self.record_holder(transaction_id, lock_target),
+        ));
+      }
+
+      if self.is_deadlock_victim(transaction_id) {
+        self.state.lock().waiting_for.remove(&transaction_id);
+        return Err(Error::Deadlock);
+      }
+    }
+  }
+
+  fn record_holder(
+    self: &Arc<Self>,
+    transaction_id: TransactionId,
+    lock_target: &LockTarget,
+  ) -> LockRecord {
+    let mut state = self.state.lock();
+    state
+      .holders
+      .entry(*lock_target)
+      .or_default()
+      .push(transaction_id);
+    // If we were chosen as a victim but acquired our lock anyway, then
+    // whatever cycle was found has been broken.
+    state.victims.remove(&transaction_id);
+
+    LockRecord::new(self, transaction_id, *lock_target)
+  }
+
+  pub(super) fn release(
+    &self,
+    transaction_id: TransactionId,
+    lock_target: &LockTarget,
+  ) {
+    let mut state = self.state.lock();
+
+    let is_now_unheld = {
+      let holders = state
+        .holders
+        .get_mut(lock_target)
+        .expect("released lock should have holders");
+      let idx = holders
+        .iter()
+        .position(|holder| *holder == transaction_id)
+        .expect("released lock should be held by transaction");
+      holders.swap_remove(idx);
+      holders.is_empty()
+    };
+
+    if is_now_unheld {
+      state.holders.remove(lock_target);
+    }
+  }
+}
+
+impl Default for LockManager {
+  fn default() -> LockManager {
+    LockManager::new()
+  }
+}
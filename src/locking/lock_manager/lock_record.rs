@@ -0,0 +1,35 @@
+use super::LockManager;
+use locking::LockTarget;
+use std::sync::Arc;
+use transaction::TransactionId;
+
+// Each primitive guard owns a LockRecord. When the guard is dropped, so
+// is the LockRecord, which tells the LockManager that the transaction
+// no longer holds the lock.
+pub struct LockRecord {
+  lock_manager: Arc<LockManager>,
+  transaction_id: TransactionId,
+  lock_target: LockTarget,
+}
+
+impl LockRecord {
+  pub(super) fn new(
+    lock_manager: &Arc<LockManager>,
+    transaction_id: TransactionId,
+    lock_target: LockTarget,
+  ) -> LockRecord {
+    LockRecord {
+      lock_manager: Arc::clone(lock_manager),
+      transaction_id,
+      lock_target,
+    }
+  }
+}
+
+impl Drop for LockRecord {
+  fn drop(&mut self) {
+    self
+      .lock_manager
+      .release(self.transaction_id, &self.lock_target);
+  }
+}
@@ -0,0 +1,7 @@
+mod deadlock_detection;
+#[allow(clippy::module_inception)]
+mod lock_manager;
+mod lock_record;
+
+pub use self::lock_manager::LockManager;
+pub use self::lock_record::LockRecord;
@@ -1,5 +1,8 @@
-// An enum for distinguishing a ReadLock from a WriteLock.
+// An enum for distinguishing a ReadLock from a WriteLock. An
+// UpgradableRead lock is read from until it is upgraded to a WriteLock.
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub enum LockMode {
   Read,
+  UpgradableRead,
   Write,
 }
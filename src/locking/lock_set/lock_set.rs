@@ -1,8 +1,13 @@
-use super::{LockSetValue, StrongRefCellGuard};
+use super::LockSetValue;
 use btree::BTree;
-use locking::{LockTarget, TransactionMode};
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::{KeyLockGuard, KeyRangeIdentifier, LatchTarget};
+use mvcc::Timestamp;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
+use transaction::{TransactionId, TransactionMode};
 
 // The LockSet manages all the locks for a transaction. It's important
 // job is that, if within a single transaction, query Q1 wants some
@@ -11,32 +16,200 @@ use std::sync::Arc;
 // in deadlock.
 //
 // When in ReadOnly mode, the LockSet will only acquire read locks. When
-// in ReadWrite mode, the LockSet will mostly acquire write locks. The
-// reason to acquire write locks, even when reading in ReadWrite mode,
-// is because of the possibility that we will have to *reacquire*.
+// in ReadWrite mode, the LockSet reads under read locks too, but must
+// be ready to *reacquire*.
 //
-// By this I mean: If Q1 reads node N, we must hold the lock until the
-// end of the transaction. If Q2 then wants to write node N, it cannot
-// safely acquire this lock because there is already a read lock here...
+// By this I mean: If Q1 reads key K, we must hold the lock until the
+// end of the transaction. If Q2 then wants to write key K, it cannot
+// simply acquire a write lock, because there is already a read lock
+// here... Instead, the LockSet upgrades the lock it holds. See
+// `lock_set_key_range_locking.rs`.
 //
-// However, there is an exception. If a lock is "temporary", we may
-// acquire a ReadLock on it even in ReadWrite mode. These must be locks
-// where we aren't reading a value; we're just descending through them.
-
-pub struct LockSet {
-  pub(super) btree: Arc<BTree>,
-  pub(super) guards: HashMap<LockTarget, LockSetValue>,
-  pub(super) held_guards: HashMap<LockTarget, StrongRefCellGuard>,
+// The locks held to the end are on ranges of keys, so that a key we
+// found missing stays missing. See `lock_set_key_range_locking.rs`.
+//
+// On nodes (and the root identifier), the LockSet only takes latches.
+// A latch lasts as long as the guard for it, which is as long as one
+// operation needs the node. In ReadWrite mode, we read a node under an
+// *upgradable* read latch, which we upgrade in place if we decide to
+// change the node. But if a latch is "temporary", we take a plain read
+// latch. These must be latches where we aren't reading a value; we're
+// just descending through them. A plain read latch can't be upgraded.
+//
+// In Snapshot mode, the LockSet takes no key locks. Instead, it holds
+// the snapshot's timestamp. (See `nedbase::mvcc`.)
+//
+// Each LockSet is a transaction as far as the LockManager is concerned.
+// Acquiring a key lock may fail with `Error::Deadlock` if the
+// LockManager picks this LockSet as a deadlock victim, or with
+// `Error::LockTimeout` if we have a lock timeout and wait too long. The
+// `try_` methods never wait at all; they give back None if the lock
+// isn't free. The LockManager never hears of latches.
+
+pub struct LockSet<K: Key, V: Value> {
+  pub(super) btree: Arc<BTree<K, V>>,
+  pub(super) guards: HashMap<LatchTarget, LockSetValue<K, V>>,
+  pub(super) tree_lock_guards: Vec<KeyLockGuard<K, V>>,
+  pub(super) key_range_guards:
+    HashMap<KeyRangeIdentifier, KeyLockGuard<K, V>>,
   pub(super) tx_mode: TransactionMode,
+  pub(super) transaction_id: TransactionId,
+  pub(super) error: Option<Error>,
+  pub(super) lock_timeout: Option<Duration>,
+  pub(super) is_rolling_back: bool,
+  pub(super) snapshot_timestamp: Option<Timestamp>,
 }
 
-impl LockSet {
-  pub fn new(btree: &Arc<BTree>, tx_mode: TransactionMode) -> LockSet {
+impl<K: Key, V: Value> LockSet<K, V> {
+  pub fn new(
+    btree: &Arc<BTree<K, V>>,
+    tx_mode: TransactionMode,
+  ) -> LockSet<K, V> {
     LockSet {
       btree: Arc::clone(btree),
       guards: HashMap::new(),
-      held_guards: HashMap::new(),
+      tree_lock_guards: vec![],
+      key_range_guards: HashMap::new(),
       tx_mode,
+      transaction_id: btree.lock_manager().begin_transaction(),
+      error: None,
+      lock_timeout: None,
+      is_rolling_back: false,
+      snapshot_timestamp: match tx_mode {
+        TransactionMode::Snapshot => {
+          Some(btree.version_store().begin_snapshot())
+        }
+        _ => None,
+      },
+    }
+  }
+
+  pub fn transaction_id(&self) -> TransactionId {
+    self.transaction_id
+  }
+
+  pub fn tx_mode(&self) -> TransactionMode {
+    self.tx_mode
+  }
+
+  // Set only in Snapshot mode. Reads see every change that committed
+  // by this timestamp, and none after.
+  pub fn snapshot_timestamp(&self) -> Option<Timestamp> {
+    self.snapshot_timestamp
+  }
+
+  pub fn btree(&self) -> &Arc<BTree<K, V>> {
+    &self.btree
+  }
+
+  // A latch lasts only as long as one operation, so between operations
+  // we should hold none. (We keep only Weak refs to guards, so a latch
+  // is held while someone still has its guard.)
+  #[cfg(test)]
+  pub fn num_latches(&self) -> usize {
+    self
+      .guards
+      .values()
+      .filter(|lock_set_value| lock_set_value.guard.upgrade().is_some())
+      .count()
+  }
+
+  // How long to wait for any one key lock. None means wait
+  // indefinitely. Latches are never held for long, so they don't time
+  // out.
+  pub fn set_lock_timeout(&mut self, lock_timeout: Option<Duration>) {
+    self.lock_timeout = lock_timeout;
+  }
+
+  // A rollback must run to completion, so it waits as long as it takes.
+  pub(super) fn lock_timeout(&self) -> Option<Duration> {
+    if self.is_rolling_back {
+      None
+    } else {
+      self.lock_timeout
+    }
+  }
+
+  // The first error any acquisition ran into. A scan may hand its error
+  // to the user without the Transaction seeing it, so the Transaction
+  // checks here before doing anything else.
+  pub fn error(&self) -> Option<Error> {
+    self.error.clone()
+  }
+
+  pub(super) fn record_error<T>(
+    &mut self,
+    result: Result<T>,
+  ) -> Result<T> {
+    if let Err(error) = &result {
+      self.error.get_or_insert_with(|| error.clone());
+    }
+
+    result
+  }
+
+  // The `try_` methods use a timeout of zero. Not getting the lock is
+  // not an error for them, so it isn't recorded.
+  pub(super) fn try_without_waiting<T, F>(
+    &mut self,
+    acquire: F,
+  ) -> Result<Option<T>>
+  where
+    F: FnOnce(&mut LockSet<K, V>, Option<Duration>) -> Result<T>,
+  {
+    match acquire(self, Some(Duration::from_millis(0))) {
+      Ok(guard) => Ok(Some(guard)),
+      Err(Error::LockTimeout) => Ok(None),
+      Err(error) => self.record_error(Err(error)),
+    }
+  }
+
+  // Runs a whole operation with a timeout of zero, so that it fails
+  // with `Error::LockTimeout` rather than wait for any lock. As with
+  // the `try_` methods, that isn't recorded as an error: the operation
+  // gave up before it changed anything.
+  pub fn without_waiting<T, F>(&mut self, operation: F) -> Result<T>
+  where
+    F: FnOnce(&mut LockSet<K, V>) -> Result<T>,
+  {
+    let lock_timeout = self.lock_timeout;
+    let had_error = self.error.is_some();
+    self.lock_timeout = Some(Duration::from_millis(0));
+    let result = operation(self);
+    self.lock_timeout = lock_timeout;
+
+    if matches!(result, Err(Error::LockTimeout)) && !had_error {
+      self.error = None;
+    }
+    result
+  }
+
+  // Changes made while rolling back are logged as compensation.
+  pub fn is_rolling_back(&self) -> bool {
+    self.is_rolling_back
+  }
+
+  // While rolling back we must reacquire latches to undo our changes.
+  // We shouldn't be chosen as a deadlock victim then, nor time out.
+  pub fn begin_rollback(&mut self) {
+    self.is_rolling_back = true;
+    self
+      .btree
+      .lock_manager()
+      .begin_rollback(self.transaction_id);
+  }
+}
+
+impl<K: Key, V: Value> Drop for LockSet<K, V> {
+  fn drop(&mut self) {
+    if let Some(snapshot_timestamp) = self.snapshot_timestamp {
+      self.btree.version_store().end_snapshot(snapshot_timestamp);
     }
+    self
+      .btree
+      .lock_manager()
+      .end_transaction(self.transaction_id);
+    // We may have been the last who could reach a retired node.
+    self.btree.free_retired_nodes_after(self.transaction_id);
   }
 }
@@ -0,0 +1,240 @@
+use super::LockSet;
+use constants::KEY_LOCK_ESCALATION_THRESHOLD;
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::{KeyLockGuard, KeyLockMode, LockTarget};
+use node::ComparisonValue;
+use std::time::Duration;
+use transaction::TransactionMode;
+
+// Key-range locks are what isolate transactions from each other: not
+// only the keys we read, but the keys we *didn't* find. A key range is
+// named by the key at its top (see `KeyLockTable`), so to lock the gap
+// where a key is missing, we lock the next key that is present.
+//
+// A ReadWrite transaction reads under shared locks, like any other. If
+// it later changes a key it read, it upgrades its lock to an exclusive
+// one, once no one else shares it. A Snapshot transaction takes no read
+// locks at all. Every key-range lock is held until the end of the
+// transaction, except for those an insert only checks (see
+// `try_check_key_range_write_lock`).
+//
+// Two transactions that share a lock, and both want to upgrade it,
+// deadlock. The LockManager sees that like any other deadlock.
+//
+// Before we lock any key range, we take an intention lock on the whole
+// tree. Once we hold very many key-range locks, we try to lock the
+// whole tree instead (see `escalate`).
+//
+// A rollback takes no key-range locks. It only ever puts back what the
+// transaction changed, and the transaction still holds the locks it
+// took to make those changes.
+
+impl<K: Key, V: Value> LockSet<K, V> {
+  // If not, the key-range locking methods do nothing.
+  pub fn takes_key_range_locks(&self) -> bool {
+    self.tx_mode != TransactionMode::Snapshot && !self.is_rolling_back
+  }
+
+  pub fn hold_key_range_read_lock(
+    &mut self,
+    top: ComparisonValue<&K>,
+  ) -> Result<()> {
+    let timeout = self.lock_timeout();
+    let result =
+      self.hold_key_range_lock(top, KeyLockMode::Shared, timeout);
+    self.record_error(result)
+  }
+
+  pub fn hold_key_range_write_lock(
+    &mut self,
+    top: ComparisonValue<&K>,
+  ) -> Result<()> {
+    let timeout = self.lock_timeout();
+    let result =
+      self.hold_key_range_lock(top, KeyLockMode::Exclusive, timeout);
+    self.record_error(result)
+  }
+
+  // The `try_` methods give back false if the lock isn't free. We never
+  // wait for a key-range lock while we have a leaf latched (see
+  // `key_range_locking.rs`).
+  pub fn try_hold_key_range_read_lock(
+    &mut self,
+    top: ComparisonValue<&K>,
+  ) -> Result<bool> {
+    let result = self.try_without_waiting(|lock_set, timeout| {
+      lock_set.hold_key_range_lock(top, KeyLockMode::Shared, timeout)
+    })?;
+    Ok(result.is_some())
+  }
+
+  pub fn try_hold_key_range_write_lock(
+    &mut self,
+    top: ComparisonValue<&K>,
+  ) -> Result<bool> {
+    let result = self.try_without_waiting(|lock_set, timeout| {
+      lock_set.hold_key_range_lock(top, KeyLockMode::Exclusive, timeout)
+    })?;
+    Ok(result.is_some())
+  }
+
+  // An insert splits the range it lands in, so it must wait for anyone
+  // who has read that range. But it needn't keep others from the range
+  // afterward: the key it inserts is locked on its own. So we give the
+  // lock back right away, unless we held it already.
+  pub fn try_check_key_range_write_lock(
+    &mut self,
+    top: ComparisonValue<&K>,
+  ) -> Result<bool> {
+    let result = self.try_without_waiting(|lock_set, timeout| {
+      lock_set
+        .key_range_guard(top, KeyLockMode::Exclusive, timeout)
+        .map(drop)
+    })?;
+    Ok(result.is_some())
+  }
+
+  // The lock on the tree must cover every key-range lock we give back.
+  // A shared one does, unless we have written (or are about to write)
+  // a key. A ReadWrite transaction that has only read escalates to a
+  // shared lock too, so that it doesn't lock out other readers. Should
+  // it write later, it takes key-range locks under an intention lock
+  // again, which its own shared lock doesn't conflict with.
+  fn escalation_lock_mode(&self) -> KeyLockMode {
+    let holds_exclusive_lock = self
+      .key_range_guards
+      .values()
+      .any(|guard| guard.lock_mode() == KeyLockMode::Exclusive);
+    if holds_exclusive_lock {
+      KeyLockMode::Exclusive
+    } else {
+      KeyLockMode::Shared
+    }
+  }
+
+  fn hold_key_range_lock(
+    &mut self,
+    top: ComparisonValue<&K>,
+    lock_mode: KeyLockMode,
+    timeout: Option<Duration>,
+  ) -> Result<()> {
+    let guard = match self.key_range_guard(top, lock_mode, timeout)? {
+      None => return Ok(()),
+      Some(guard) => guard,
+    };
+
+    let identifier = match guard.target() {
+      LockTarget::KeyRange(identifier) => identifier,
+      _ => {
+        return Err(Error::InvariantViolation(
+          "key_range_guard locks a key range",
+        ))
+      }
+    };
+    // If this is an upgrade, the shared lock we held is given back.
+    self.key_range_guards.insert(identifier, guard);
+
+    if self
+      .key_range_guards
+      .len()
+      .is_multiple_of(KEY_LOCK_ESCALATION_THRESHOLD)
+    {
+      self.escalate()?;
+    }
+
+    Ok(())
+  }
+
+  // Gives back None if we already hold the lock. If we hold it shared,
+  // and now want it exclusive, gives back the exclusive lock.
+  fn key_range_guard(
+    &mut self,
+    top: ComparisonValue<&K>,
+    lock_mode: KeyLockMode,
+    timeout: Option<Duration>,
+  ) -> Result<Option<KeyLockGuard<K, V>>> {
+    if lock_mode == KeyLockMode::Exclusive
+      && self.tx_mode != TransactionMode::ReadWrite
+    {
+      return Err(Error::ReadOnlyTransaction);
+    }
+
+    if !self.takes_key_range_locks() || self.holds_tree_lock(lock_mode)
+    {
+      return Ok(None);
+    }
+
+    // If anyone holds this lock, the range has an identifier.
+    let held_guard = self
+      .btree
+      .key_lock_table()
+      .identifier(top)
+      .and_then(|identifier| self.key_range_guards.get(&identifier));
+    if let Some(held_guard) = held_guard {
+      if held_guard.lock_mode().covers(lock_mode) {
+        return Ok(None);
+      }
+    }
+
+    let intention_mode = match lock_mode {
+      KeyLockMode::Shared => KeyLockMode::IntentionShared,
+      _ => KeyLockMode::IntentionExclusive,
+    };
+    self.hold_tree_lock(intention_mode, timeout)?;
+
+    let guard = KeyLockGuard::acquire_key_range_lock(
+      &self.btree,
+      top,
+      lock_mode,
+      self.transaction_id,
+      timeout,
+    )?;
+    Ok(Some(guard))
+  }
+
+  fn holds_tree_lock(&self, lock_mode: KeyLockMode) -> bool {
+    self
+      .tree_lock_guards
+      .iter()
+      .any(|guard| guard.lock_mode().covers(lock_mode))
+  }
+
+  fn hold_tree_lock(
+    &mut self,
+    lock_mode: KeyLockMode,
+    timeout: Option<Duration>,
+  ) -> Result<()> {
+    if self.holds_tree_lock(lock_mode) {
+      return Ok(());
+    }
+
+    let guard = KeyLockGuard::acquire_tree_lock(
+      &self.btree,
+      lock_mode,
+      self.transaction_id,
+      timeout,
+    )?;
+    self.tree_lock_guards.push(guard);
+
+    Ok(())
+  }
+
+  // A shared (or exclusive) lock on the whole tree covers every key
+  // range, so once we have it, we can give back our key-range locks.
+  //
+  // We never wait for it: others hold intention locks on the tree
+  // nearly all the time. If we can't get it now, we try again after
+  // another `KEY_LOCK_ESCALATION_THRESHOLD` key-range locks.
+  fn escalate(&mut self) -> Result<()> {
+    let lock_mode = self.escalation_lock_mode();
+    let escalated = self.try_without_waiting(|lock_set, timeout| {
+      lock_set.hold_tree_lock(lock_mode, timeout)
+    })?;
+    if escalated.is_some() {
+      self.key_range_guards.clear();
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,41 @@
+use super::LockSet;
+use constants::OPTIMISTIC_READ_ATTEMPTS;
+use error::Result;
+use key::{Key, Value};
+use node::Node;
+use storage::NodeIdentifier;
+
+// An optimistic read takes no latch at all, and pinning the node takes
+// no lock either. It reads a copy of the node, and checks the node's
+// version to be sure no writer came along meanwhile. (See `NodeLatch`.)
+// If nobody has copied the node since its last write, we read it under
+// its lock, just this once, and leave a copy for the next reader.
+// Nothing is recorded in the LockSet, so like a temporary latch, it is
+// only for descending the tree.
+//
+// Only interior nodes can be read this way. For a leaf, or a node that
+// keeps changing under us, we fall back to a temporary latch. That's
+// also what happens when this transaction has the node write latched
+// itself: the version is odd until we let go.
+
+impl<K: Key, V: Value> LockSet<K, V> {
+  pub fn optimistic_node_read<R, F>(
+    &mut self,
+    identifier: NodeIdentifier,
+    f: F,
+  ) -> Result<R>
+  where
+    F: Fn(&Node<K, V>) -> R,
+  {
+    let result = self.btree.pin_node(identifier).map(|pin| {
+      pin.node().read_optimistically(OPTIMISTIC_READ_ATTEMPTS, &f)
+    });
+    if let Some(result) = self.record_error(result)? {
+      return Ok(result);
+    }
+
+    let guard = self.temp_node_read_guard(identifier)?;
+    let node_ref = guard.unwrap_node_ref()?;
+    Ok(f(&node_ref))
+  }
+}
@@ -1,87 +1,91 @@
 use super::{
   LockSet, LockSetNodeReadGuard, LockSetRootIdentifierReadGuard,
-  LockSetValue,
-};
-use locking::{
-  Guard, LockMode, LockTarget, ReadGuard, TransactionMode, WriteGuard,
+  LockSetValue, StrongRefCellGuard,
 };
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::{Guard, LatchTarget, LockMode, ReadGuard, WriteGuard};
 use std::cell::RefCell;
 use std::rc::Rc;
+use storage::NodeIdentifier;
+use transaction::TransactionMode;
 
-// Acquiring a read guard for *holding* (that is, we'll read a value at
-// the Node so we need to hold it through the transaction) is the most
+// Acquiring a read guard to read a value at the Node is the most
 // complicated scenario. Depending on what transaction mode we are in
-// (ReadWrite), we may actually need to acquire a *write* lock.
+// (ReadWrite), we may need a latch that we can later *upgrade* to a
+// write latch.
 
-impl LockSet {
+impl<K: Key, V: Value> LockSet<K, V> {
   pub fn node_read_guard(
     &mut self,
-    identifier: &str,
-  ) -> LockSetNodeReadGuard {
-    // TODO: This String::from seems wasteful just to do a lookup...
-    let guard =
-      self.read_guard(&LockTarget::Node(String::from(identifier)));
-    LockSetNodeReadGuard::from_guard(guard)
+    identifier: NodeIdentifier,
+  ) -> Result<LockSetNodeReadGuard<K, V>> {
+    let result = self
+      .read_guard(&LatchTarget::Node(identifier))
+      .map(LockSetNodeReadGuard::from_guard);
+    self.record_error(result)
   }
 
   pub fn root_identifier_read_guard(
     &mut self,
-  ) -> LockSetRootIdentifierReadGuard {
-    let guard = self.read_guard(&LockTarget::RootIdentifier);
-    LockSetRootIdentifierReadGuard::from_guard(guard)
-  }
-
-  pub fn hold_node_read_guard(
-    &mut self,
-    node_guard: &LockSetNodeReadGuard,
-  ) {
-    let strong_ref_cell_guard = node_guard.clone_ref_cell_guard();
-    self.held_guards.insert(
-      LockTarget::Node(String::from(
-        node_guard.unwrap_node_ref().identifier(),
-      )),
-      strong_ref_cell_guard,
-    );
+  ) -> Result<LockSetRootIdentifierReadGuard<K, V>> {
+    let result = self
+      .read_guard(&LatchTarget::RootIdentifier)
+      .map(LockSetRootIdentifierReadGuard::from_guard);
+    self.record_error(result)
   }
 
   fn read_guard(
     &mut self,
-    lock_target: &LockTarget,
-  ) -> Rc<RefCell<Guard>> {
+    latch_target: &LatchTarget,
+  ) -> Result<Rc<RefCell<Guard<K, V>>>> {
     // If we don't have a copy of this lock, then it's simple: we must
     // acquire it.
-    if !self.guards.contains_key(lock_target) {
-      return self.acquire_read_guard(lock_target);
+    if !self.guards.contains_key(latch_target) {
+      return self.acquire_read_guard(latch_target);
     }
 
     // If we previously acquired this lock, then we should attempt to
     // upgrade the retained lock.
-    if let Some(guard) = self.upgrade_for_read(lock_target) {
-      return guard;
+    if let Some(guard) = self.upgrade_for_read(latch_target)? {
+      return Ok(guard);
     }
 
     // But if we failed the upgrade, we'll have to reacquire after all.
-    self.acquire_read_guard(lock_target)
+    self.acquire_read_guard(latch_target)
   }
 
   fn acquire_read_guard(
     &mut self,
-    lock_target: &LockTarget,
-  ) -> Rc<RefCell<Guard>> {
+    latch_target: &LatchTarget,
+  ) -> Result<Rc<RefCell<Guard<K, V>>>> {
     // First, acquire the proper guard type. This depends on the
     // transaction mode.
     let (lock_mode, guard) = match self.tx_mode {
-      TransactionMode::ReadOnly => {
+      TransactionMode::ReadOnly | TransactionMode::Snapshot => {
         let guard =
-          ReadGuard::acquire_read_guard(&self.btree, lock_target);
+          ReadGuard::acquire_read_guard(&self.btree, latch_target)?;
         (LockMode::Read, Guard::Read(guard))
       }
 
-      TransactionMode::ReadWrite => {
-        let guard =
-          WriteGuard::acquire_write_guard(&self.btree, lock_target);
-        (LockMode::Write, Guard::Write(guard))
-      }
+      // Other readers may share the node with us. But we may decide to
+      // change the node once we have read it, so we take a latch we
+      // can upgrade. Nobody reads the root identifier with an eye to
+      // changing it, so that is simply write latched.
+      TransactionMode::ReadWrite => match latch_target {
+        LatchTarget::Node(identifier) => {
+          let guard = ReadGuard::acquire_node_upgradable_read_guard(
+            &self.btree,
+            *identifier,
+          )?;
+          (LockMode::UpgradableRead, Guard::Read(guard))
+        }
+        LatchTarget::RootIdentifier => {
+          let guard =
+            WriteGuard::acquire_write_guard(&self.btree, latch_target)?;
+          (LockMode::Write, Guard::Write(guard))
+        }
+      },
     };
 
     // Next, wrap it in RefCell so that someone can borrow a guard for
@@ -99,41 +103,39 @@ impl LockSet {
       lock_mode,
       guard: Rc::downgrade(&guard),
     };
-    self.guards.insert(lock_target.clone(), lock_set_value);
+    self.guards.insert(*latch_target, lock_set_value);
 
-    guard
+    Ok(guard)
   }
 
   fn upgrade_for_read(
     &mut self,
-    lock_target: &LockTarget,
-  ) -> Option<Rc<RefCell<Guard>>> {
+    latch_target: &LatchTarget,
+  ) -> Result<Option<StrongRefCellGuard<K, V>>> {
     // First, get the weak guard we stored earlier.
-    let LockSetValue { lock_mode, guard } = &self.guards[lock_target];
+    let LockSetValue { lock_mode, guard } = &self.guards[latch_target];
 
     // If the upgrade fails, then darn.
     let guard = match guard.upgrade() {
-      None => return None,
+      None => return Ok(None),
       Some(guard) => guard,
     };
 
-    // If we are in ReadOnly mode, we don't really care if this is a
-    // read or a write guard underneath.
-    if self.tx_mode == TransactionMode::ReadOnly {
-      return Some(guard);
+    // If we can't write, we don't really care if this is a read or a
+    // write guard underneath.
+    if self.tx_mode != TransactionMode::ReadWrite {
+      return Ok(Some(guard));
     }
 
     // But here's a problem: what if we acquired a temporary read lock
-    // on this node, and are now forced to take a *write* lock because
-    // we are in ReadWrite mode?
-    //
-    // That would mean we've deadlocked ourself. So we'll panic.
+    // on this node, and are now asked for one we can upgrade because
+    // we are in ReadWrite mode? A plain read latch can't be upgraded,
+    // and we can't take a second latch on the node without deadlocking
+    // ourself. So we'll refuse.
     match lock_mode {
-      LockMode::Read => {
-        panic!("Tried to acquire a write lock when we already had a temp read guard");
-      }
+      LockMode::Read => Err(Error::TempLockHeld),
 
-      LockMode::Write => Some(guard),
+      LockMode::UpgradableRead | LockMode::Write => Ok(Some(guard)),
     }
   }
 }
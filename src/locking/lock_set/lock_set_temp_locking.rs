@@ -2,9 +2,12 @@ use super::{
   LockSet, LockSetNodeReadGuard, LockSetRootIdentifierReadGuard,
   LockSetValue,
 };
-use locking::{Guard, LockMode, LockTarget, ReadGuard};
+use error::Result;
+use key::{Key, Value};
+use locking::{Guard, LatchTarget, LockMode, ReadGuard};
 use std::cell::RefCell;
 use std::rc::Rc;
+use storage::NodeIdentifier;
 
 // A temporary ReadGuard is the exception to the rule. We can take read
 // guards in ReadWrite mode *if* we don't actually read the value there.
@@ -15,62 +18,65 @@ use std::rc::Rc;
 // holding* on a node we currently hold a temporary lock on. Otherwise
 // we would deadlock ourselves.
 
-impl LockSet {
+impl<K: Key, V: Value> LockSet<K, V> {
   pub fn temp_node_read_guard(
     &mut self,
-    identifier: &str,
-  ) -> LockSetNodeReadGuard {
-    // TODO: This String::from seems wasteful just to do a lookup...
-    let guard = self
-      ._temp_read_guard(&LockTarget::Node(String::from(identifier)));
-    LockSetNodeReadGuard::from_guard(guard)
+    identifier: NodeIdentifier,
+  ) -> Result<LockSetNodeReadGuard<K, V>> {
+    let result = self
+      ._temp_read_guard(&LatchTarget::Node(identifier))
+      .map(LockSetNodeReadGuard::from_guard);
+    self.record_error(result)
   }
 
   pub fn temp_root_identifier_read_guard(
     &mut self,
-  ) -> LockSetRootIdentifierReadGuard {
-    let guard = self._temp_read_guard(&LockTarget::RootIdentifier);
-    LockSetRootIdentifierReadGuard::from_guard(guard)
+  ) -> Result<LockSetRootIdentifierReadGuard<K, V>> {
+    let result = self
+      ._temp_read_guard(&LatchTarget::RootIdentifier)
+      .map(LockSetRootIdentifierReadGuard::from_guard);
+    self.record_error(result)
   }
 
   fn _temp_read_guard(
     &mut self,
-    lock_target: &LockTarget,
-  ) -> Rc<RefCell<Guard>> {
+    latch_target: &LatchTarget,
+  ) -> Result<Rc<RefCell<Guard<K, V>>>> {
     // If we don't have a copy of this lock, then it's simple: we must
     // acquire it.
-    if !self.guards.contains_key(lock_target) {
-      return self.acquire_temp_read_guard(lock_target);
+    if !self.guards.contains_key(latch_target) {
+      return self.acquire_temp_read_guard(latch_target);
     }
 
     // If we previously acquired this lock, then we should attempt to
     // upgrade the retained lock.
-    if let Some(guard) = self.upgrade_for_temp_read(lock_target) {
-      return guard;
+    if let Some(guard) = self.upgrade_for_temp_read(latch_target) {
+      return Ok(guard);
     }
 
     // But if we failed the upgrade, we'll have to reacquire after all.
-    self.acquire_temp_read_guard(lock_target)
+    self.acquire_temp_read_guard(latch_target)
   }
 
   fn upgrade_for_temp_read(
     &mut self,
-    lock_target: &LockTarget,
-  ) -> Option<Rc<RefCell<Guard>>> {
+    latch_target: &LatchTarget,
+  ) -> Option<Rc<RefCell<Guard<K, V>>>> {
     // This is a simple scenario: we don't care what mode we're in, and
     // we don't care what kind of lock is held behind the scenes. Any
     // lock will do.
-    let LockSetValue { guard, .. } = &self.guards[lock_target];
+    let LockSetValue { guard, .. } = &self.guards[latch_target];
     guard.upgrade()
   }
 
   fn acquire_temp_read_guard(
     &mut self,
-    lock_target: &LockTarget,
-  ) -> Rc<RefCell<Guard>> {
+    latch_target: &LatchTarget,
+  ) -> Result<Rc<RefCell<Guard<K, V>>>> {
     // First, acquire the read lock. This doesn't depend on the
     // transaction mode!
-    let guard = ReadGuard::acquire_read_guard(&self.btree, lock_target);
+    let guard =
+      ReadGuard::acquire_read_guard(&self.btree, latch_target)?;
     let guard = Guard::Read(guard);
 
     // Next, wrap it in RefCell. No one will want to borrow this lock
@@ -88,8 +94,8 @@ impl LockSet {
       lock_mode: LockMode::Read,
       guard: Rc::downgrade(&guard),
     };
-    self.guards.insert(lock_target.clone(), lock_set_value);
+    self.guards.insert(*latch_target, lock_set_value);
 
-    guard
+    Ok(guard)
   }
 }
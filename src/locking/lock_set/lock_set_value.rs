@@ -1,3 +1,4 @@
+use key::{Key, Value};
 use locking::{Guard, LockMode};
 use std::cell::RefCell;
 use std::rc::{Rc, Weak};
@@ -13,11 +14,11 @@ use std::rc::{Rc, Weak};
 // The LockMode tells us whether the Guard in the RefCell is a ReadGuard
 // or a WriteGuard. In theory we can find this out by borrowing the
 // RefCell, but that feels unnecessary.
-pub(super) type RefCellGuard = RefCell<Guard>;
-pub(super) type StrongRefCellGuard = Rc<RefCellGuard>;
-pub(super) type WeakRefCellGuard = Weak<RefCellGuard>;
+pub(super) type RefCellGuard<K, V> = RefCell<Guard<K, V>>;
+pub(super) type StrongRefCellGuard<K, V> = Rc<RefCellGuard<K, V>>;
+pub(super) type WeakRefCellGuard<K, V> = Weak<RefCellGuard<K, V>>;
 
-pub(super) struct LockSetValue {
+pub(super) struct LockSetValue<K: Key, V: Value> {
   pub lock_mode: LockMode,
-  pub guard: WeakRefCellGuard,
+  pub guard: WeakRefCellGuard<K, V>,
 }
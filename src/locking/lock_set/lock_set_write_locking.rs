@@ -1,81 +1,70 @@
 use super::{
   LockSet, LockSetNodeWriteGuard, LockSetRootIdentifierWriteGuard,
-  LockSetValue, LockSetWriteGuard,
-};
-use locking::{
-  Guard, LockMode, LockTarget, TransactionMode, WriteGuard,
+  LockSetValue, StrongRefCellGuard,
 };
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::{Guard, LatchTarget, LockMode, WriteGuard};
 use std::cell::RefCell;
 use std::rc::Rc;
+use storage::NodeIdentifier;
+use transaction::TransactionMode;
 
 // Acquiring a write guard (which is always for holding) is probably the
 // simplest scenario.
 
-impl LockSet {
+impl<K: Key, V: Value> LockSet<K, V> {
   pub fn node_write_guard(
     &mut self,
-    identifier: &str,
-  ) -> LockSetNodeWriteGuard {
-    // TODO: This String::from seems wasteful just to do a lookup...
-    let guard =
-      self.write_guard(&LockTarget::Node(String::from(identifier)));
-    LockSetNodeWriteGuard::from_guard(guard)
+    identifier: NodeIdentifier,
+  ) -> Result<LockSetNodeWriteGuard<K, V>> {
+    let result = self
+      .write_guard(&LatchTarget::Node(identifier))
+      .map(LockSetNodeWriteGuard::from_guard);
+    self.record_error(result)
   }
 
   pub fn root_identifier_write_guard(
     &mut self,
-  ) -> LockSetRootIdentifierWriteGuard {
-    let guard = self.write_guard(&LockTarget::RootIdentifier);
-    LockSetRootIdentifierWriteGuard::from_guard(guard)
-  }
-
-  pub fn hold_node_write_guard(
-    &mut self,
-    node_guard: &LockSetNodeWriteGuard,
-  ) {
-    let strong_ref_cell_guard = node_guard.clone_ref_cell_guard();
-    let target = strong_ref_cell_guard.borrow().target();
-    self.held_guards.insert(target, strong_ref_cell_guard);
-  }
-
-  pub fn hold_write_guard(&mut self, node_guard: &LockSetWriteGuard) {
-    let strong_ref_cell_guard = node_guard.clone_ref_cell_guard();
-    let target = strong_ref_cell_guard.borrow().target();
-    self.held_guards.insert(target, strong_ref_cell_guard);
+  ) -> Result<LockSetRootIdentifierWriteGuard<K, V>> {
+    let result = self
+      .write_guard(&LatchTarget::RootIdentifier)
+      .map(LockSetRootIdentifierWriteGuard::from_guard);
+    self.record_error(result)
   }
 
   fn write_guard(
     &mut self,
-    lock_target: &LockTarget,
-  ) -> Rc<RefCell<Guard>> {
-    // First: you can't get write locks in ReadOnly mode!
-    if self.tx_mode == TransactionMode::ReadOnly {
-      panic!("cannot acquire read locks in ReadOnly mode!");
+    latch_target: &LatchTarget,
+  ) -> Result<Rc<RefCell<Guard<K, V>>>> {
+    // First: you can't get write locks in ReadOnly (or Snapshot) mode!
+    if self.tx_mode != TransactionMode::ReadWrite {
+      return Err(Error::ReadOnlyTransaction);
     }
 
     // If we don't have a copy of this lock, then it's simple: we must
     // acquire it.
-    if !self.guards.contains_key(lock_target) {
-      return self.acquire_write_guard(lock_target);
+    if !self.guards.contains_key(latch_target) {
+      return self.acquire_write_guard(latch_target);
     }
 
     // If we previously acquired this lock, then we should attempt to
     // upgrade the retained lock.
-    if let Some(guard) = self.upgrade_for_write(lock_target) {
-      return guard;
+    if let Some(guard) = self.upgrade_for_write(latch_target)? {
+      return Ok(guard);
     }
 
     // But if we failed the upgrade, we'll have to reacquire after all.
-    self.acquire_write_guard(lock_target)
+    self.acquire_write_guard(latch_target)
   }
 
   fn acquire_write_guard(
     &mut self,
-    lock_target: &LockTarget,
-  ) -> Rc<RefCell<Guard>> {
+    latch_target: &LatchTarget,
+  ) -> Result<Rc<RefCell<Guard<K, V>>>> {
     // Acquire the write guard.
     let guard =
-      WriteGuard::acquire_write_guard(&self.btree, lock_target);
+      WriteGuard::acquire_write_guard(&self.btree, latch_target)?;
     let guard = Guard::Write(guard);
 
     // Next, wrap it in RefCell so that someone can borrow a guard for
@@ -91,21 +80,22 @@ impl LockSet {
       lock_mode: LockMode::Write,
       guard: Rc::downgrade(&guard),
     };
-    self.guards.insert(lock_target.clone(), lock_set_value);
+    self.guards.insert(*latch_target, lock_set_value);
 
-    guard
+    Ok(guard)
   }
 
   fn upgrade_for_write(
     &mut self,
-    lock_target: &LockTarget,
-  ) -> Option<Rc<RefCell<Guard>>> {
+    latch_target: &LatchTarget,
+  ) -> Result<Option<StrongRefCellGuard<K, V>>> {
     // First, get the weak guard we stored earlier.
-    let LockSetValue { lock_mode, guard } = &self.guards[lock_target];
+    let LockSetValue { lock_mode, guard } = &self.guards[latch_target];
+    let lock_mode = *lock_mode;
 
     // If the upgrade fails, then darn.
     let guard = match guard.upgrade() {
-      None => return None,
+      None => return Ok(None),
       Some(guard) => guard,
     };
 
@@ -113,13 +103,43 @@ impl LockSet {
     // on this node. That is incompatible with our desire to take a
     // write lock now.
     //
-    // That would mean we've deadlocked ourself. So we'll panic.
+    // That would mean we've deadlocked ourself. So we'll refuse.
+    //
+    // An upgradable read lock, though, we trade for a write lock in
+    // place. Everyone in the transaction who holds the guard sees the
+    // write lock from now on.
     match lock_mode {
-      LockMode::Read => {
-        panic!("Can't hold a write guard on a previously temp guard");
+      LockMode::Read => Err(Error::TempLockHeld),
+
+      LockMode::UpgradableRead => {
+        self.upgrade_latch(latch_target, &guard)?;
+        Ok(Some(guard))
       }
 
-      LockMode::Write => Some(guard),
+      LockMode::Write => Ok(Some(guard)),
+    }
+  }
+
+  fn upgrade_latch(
+    &mut self,
+    latch_target: &LatchTarget,
+    guard: &StrongRefCellGuard<K, V>,
+  ) -> Result<()> {
+    // Whoever is reading the node right now would see it change under
+    // them.
+    guard
+      .try_borrow_mut()
+      .map_err(|_| {
+        Error::InvariantViolation(
+          "cannot upgrade a lock while the node is being read",
+        )
+      })?
+      .upgrade()?;
+
+    if let Some(lock_set_value) = self.guards.get_mut(latch_target) {
+      lock_set_value.lock_mode = LockMode::Write;
     }
+
+    Ok(())
   }
 }
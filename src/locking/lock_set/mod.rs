@@ -1,9 +1,12 @@
 #[allow(clippy::module_inception)]
 mod lock_set;
+mod lock_set_key_range_locking;
+mod lock_set_optimistic_reading;
 mod lock_set_read_locking;
 mod lock_set_temp_locking;
 mod lock_set_value;
 mod lock_set_write_locking;
+mod ref_mapping;
 
 mod read_guards;
 mod write_guards;
@@ -12,13 +15,12 @@ mod write_guards;
 pub(self) use self::lock_set_value::{
   LockSetValue, StrongRefCellGuard,
 };
+pub(self) use self::ref_mapping::{try_map_ref, try_map_ref_mut};
 
 pub use self::lock_set::LockSet;
 pub use self::read_guards::{
-  LockSetNodeReadGuard, LockSetReadGuard,
-  LockSetRootIdentifierReadGuard,
+  LockSetNodeReadGuard, LockSetRootIdentifierReadGuard,
 };
 pub use self::write_guards::{
   LockSetNodeWriteGuard, LockSetRootIdentifierWriteGuard,
-  LockSetWriteGuard,
 };
@@ -1,12 +1,19 @@
+use super::try_map_ref;
+use error::{Error, Result};
+use key::{Key, Value};
 use locking::Guard;
 use node::{InteriorNode, LeafNode, Node};
 use std::cell::{Ref, RefCell};
 use std::rc::Rc;
+use storage::NodeIdentifier;
 
 // The idea of the LockSetReadGuards is that when some code asks for a
-// read lock, you *might* give it a write lock instead, if either:
+// read lock, you *might* give it another kind of lock instead, if
+// either:
 //
-// 1. They will do a read to the node, but you are in ReadWrite mode,
+// 1. They will do a read to the node, but you are in ReadWrite mode.
+//    Then you give them an upgradable read lock, which may later be
+//    upgraded to a write lock.
 // 2. They want to do a temporary read to the node, but you already have
 //    a write lock on the node.
 //
@@ -19,22 +26,22 @@ use std::rc::Rc;
 // not *use* the same locks simultaneously.
 
 #[derive(Clone)]
-pub enum LockSetReadGuard {
-  Node(LockSetNodeReadGuard),
-  RootIdentifier(LockSetRootIdentifierReadGuard),
+pub enum LockSetReadGuard<K: Key, V: Value> {
+  Node(LockSetNodeReadGuard<K, V>),
+  RootIdentifier(LockSetRootIdentifierReadGuard<K, V>),
 }
 
 #[derive(Clone)]
-pub struct LockSetNodeReadGuard {
-  guard: Rc<RefCell<Guard>>,
+pub struct LockSetNodeReadGuard<K: Key, V: Value> {
+  guard: Rc<RefCell<Guard<K, V>>>,
 }
 
 #[derive(Clone)]
-pub struct LockSetRootIdentifierReadGuard {
-  guard: Rc<RefCell<Guard>>,
+pub struct LockSetRootIdentifierReadGuard<K: Key, V: Value> {
+  guard: Rc<RefCell<Guard<K, V>>>,
 }
 
-impl LockSetReadGuard {
+impl<K: Key, V: Value> LockSetReadGuard<K, V> {
   // This lets them drop the lock early if they way, without having to
   // use std::mem::drop.
   //
@@ -43,23 +50,26 @@ impl LockSetReadGuard {
   // released!
   pub fn release(self) {}
 
-  pub fn unwrap_node_ref(&self, msg: &'static str) -> Ref<Node> {
+  pub fn unwrap_node_ref(
+    &self,
+    msg: &'static str,
+  ) -> Result<Ref<'_, Node<K, V>>> {
     use self::LockSetReadGuard::*;
 
     match self {
       Node(node_lock) => node_lock.unwrap_node_ref(),
-      RootIdentifier(_) => panic!(msg),
+      RootIdentifier(_) => Err(Error::InvariantViolation(msg)),
     }
   }
 
   pub fn unwrap_root_identifier_ref(
     &self,
     msg: &'static str,
-  ) -> Ref<String> {
+  ) -> Result<Ref<'_, NodeIdentifier>> {
     use self::LockSetReadGuard::*;
 
     match self {
-      Node(_) => panic!(msg),
+      Node(_) => Err(Error::InvariantViolation(msg)),
       RootIdentifier(root_identifier_lock) => {
         root_identifier_lock.identifier()
       }
@@ -67,22 +77,15 @@ impl LockSetReadGuard {
   }
 }
 
-impl LockSetNodeReadGuard {
-  pub fn from_guard(guard: Rc<RefCell<Guard>>) -> LockSetNodeReadGuard {
+impl<K: Key, V: Value> LockSetNodeReadGuard<K, V> {
+  pub fn from_guard(
+    guard: Rc<RefCell<Guard<K, V>>>,
+  ) -> LockSetNodeReadGuard<K, V> {
     LockSetNodeReadGuard { guard }
   }
 
-  pub(super) fn clone_ref_cell_guard(&self) -> Rc<RefCell<Guard>> {
-    Rc::clone(&self.guard)
-  }
-
-  pub fn is_leaf_node(&self) -> bool {
-    let guard = self.guard.borrow();
-    guard
-      .unwrap_node_ref(
-        "Guard ref in LockSetNodeReadGuard doesn't hold Node?",
-      )
-      .is_leaf_node()
+  pub fn is_leaf_node(&self) -> Result<bool> {
+    Ok(self.unwrap_node_ref()?.is_leaf_node())
   }
 
   // This lets them drop the lock early if they way, without having to
@@ -96,57 +99,49 @@ impl LockSetNodeReadGuard {
   pub fn unwrap_interior_node_ref(
     &self,
     msg: &'static str,
-  ) -> Ref<InteriorNode> {
-    Ref::map(self.guard.borrow(), |guard| {
-      guard
-        .unwrap_node_ref(
-          "Guard ref in LockSetNodeReadGuard doesn't hold Node?",
-        )
-        .unwrap_interior_node_ref(msg)
+  ) -> Result<Ref<'_, InteriorNode<K>>> {
+    try_map_ref(self.unwrap_node_ref()?, |node| {
+      node.unwrap_interior_node_ref(msg)
     })
   }
 
   pub fn unwrap_leaf_node_ref(
     &self,
     msg: &'static str,
-  ) -> Ref<LeafNode> {
-    Ref::map(self.guard.borrow(), |guard| {
-      guard
-        .unwrap_node_ref(
-          "Guard ref in LockSetNodeReadGuard doesn't hold Node?",
-        )
-        .unwrap_leaf_node_ref(msg)
+  ) -> Result<Ref<'_, LeafNode<K, V>>> {
+    try_map_ref(self.unwrap_node_ref()?, |node| {
+      node.unwrap_leaf_node_ref(msg)
     })
   }
 
-  pub fn unwrap_node_ref(&self) -> Ref<Node> {
-    Ref::map(self.guard.borrow(), |guard| {
+  pub fn unwrap_node_ref(&self) -> Result<Ref<'_, Node<K, V>>> {
+    try_map_ref(self.guard.borrow(), |guard| {
       guard.unwrap_node_ref(
         "Guard ref in LockSetNodeReadGuard doesn't hold Node?",
       )
     })
   }
 
-  pub fn upcast(self) -> LockSetReadGuard {
+  pub fn upcast(self) -> LockSetReadGuard<K, V> {
     LockSetReadGuard::Node(self)
   }
 }
 
-impl LockSetRootIdentifierReadGuard {
+impl<K: Key, V: Value> LockSetRootIdentifierReadGuard<K, V> {
   pub fn from_guard(
-    guard: Rc<RefCell<Guard>>,
-  ) -> LockSetRootIdentifierReadGuard {
+    guard: Rc<RefCell<Guard<K, V>>>,
+  ) -> LockSetRootIdentifierReadGuard<K, V> {
     LockSetRootIdentifierReadGuard { guard }
   }
 
-  pub fn identifier(&self) -> Ref<String> {
+  pub fn identifier(&self) -> Result<Ref<'_, NodeIdentifier>> {
     let msg = "Guard ref in LockSetRootIdentifierReadGuard doesn't hold RootIdentifier?";
-    Ref::map(self.guard.borrow(), |guard| {
+    try_map_ref(self.guard.borrow(), |guard| {
       guard.unwrap_root_identifier_ref(msg)
     })
   }
 
-  pub fn upcast(self) -> LockSetReadGuard {
+  pub fn upcast(self) -> LockSetReadGuard<K, V> {
     LockSetReadGuard::RootIdentifier(self)
   }
 }
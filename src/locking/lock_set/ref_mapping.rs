@@ -0,0 +1,37 @@
+use error::Result;
+use std::cell::{Ref, RefMut};
+
+// Like `Ref::map`, except the mapping may fail. We hand back the error
+// of the mapping function.
+pub(super) fn try_map_ref<'b, T, U, F>(
+  orig: Ref<'b, T>,
+  f: F,
+) -> Result<Ref<'b, U>>
+where
+  T: ?Sized,
+  U: ?Sized,
+  F: FnOnce(&T) -> Result<&U>,
+{
+  let mut error = None;
+  Ref::filter_map(orig, |value| {
+    f(value).map_err(|e| error = Some(e)).ok()
+  })
+  .map_err(|_| error.expect("filter_map only fails if f does"))
+}
+
+// Like `RefMut::map`, except the mapping may fail.
+pub(super) fn try_map_ref_mut<'b, T, U, F>(
+  orig: RefMut<'b, T>,
+  f: F,
+) -> Result<RefMut<'b, U>>
+where
+  T: ?Sized,
+  U: ?Sized,
+  F: FnOnce(&mut T) -> Result<&mut U>,
+{
+  let mut error = None;
+  RefMut::filter_map(orig, |value| {
+    f(value).map_err(|e| error = Some(e)).ok()
+  })
+  .map_err(|_| error.expect("filter_map only fails if f does"))
+}
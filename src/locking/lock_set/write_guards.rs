@@ -1,10 +1,14 @@
-use locking::{Guard, WriteGuard};
+use super::{try_map_ref, try_map_ref_mut};
+use error::Result;
+use key::{Key, Value};
+use locking::Guard;
 use node::{InteriorNode, LeafNode, Node};
 use std::cell::{Ref, RefCell, RefMut};
 use std::rc::Rc;
+use storage::NodeIdentifier;
 
-// LockSetWriteGuard is simpler than LockSetReadGuard because there is
-// only one kind of primitive `Guard` backing the LockSetWriteGuard: a
+// The LockSet write guards are simpler than the read guards because
+// there is only one kind of primitive `Guard` backing them: a
 // `WriteGuard`.
 //
 // Note that we borrow and borrow_mut from a `RefCell`. Why? That's
@@ -13,163 +17,92 @@ use std::rc::Rc;
 // fine, but they must not *use* the same locks simultaneously.
 
 #[derive(Clone)]
-pub struct LockSetWriteGuard {
-  guard: Rc<RefCell<Guard>>,
+pub struct LockSetNodeWriteGuard<K: Key, V: Value> {
+  guard: Rc<RefCell<Guard<K, V>>>,
 }
 
 #[derive(Clone)]
-pub struct LockSetNodeWriteGuard {
-  guard: Rc<RefCell<Guard>>,
+pub struct LockSetRootIdentifierWriteGuard<K: Key, V: Value> {
+  guard: Rc<RefCell<Guard<K, V>>>,
 }
 
-#[derive(Clone)]
-pub struct LockSetRootIdentifierWriteGuard {
-  guard: Rc<RefCell<Guard>>,
-}
-
-impl LockSetWriteGuard {
-  pub fn from_guard(guard: Rc<RefCell<Guard>>) -> LockSetWriteGuard {
-    LockSetWriteGuard { guard }
-  }
-
-  pub(super) fn clone_ref_cell_guard(&self) -> Rc<RefCell<Guard>> {
-    Rc::clone(&self.guard)
-  }
-
-  // TODO: I don't love that we are handing out the primitive
-  // WriteGuards...
-  pub fn guard(&self) -> Ref<WriteGuard> {
-    Ref::map(self.guard.borrow(), |guard| {
-      guard.unwrap_write_guard_ref(
-        "LockSetWriteGuard must hold WriteGuard",
-      )
-    })
-  }
-
-  pub fn guard_mut(&mut self) -> RefMut<WriteGuard> {
-    RefMut::map(self.guard.borrow_mut(), |guard| {
-      guard.unwrap_write_guard_mut_ref(
-        "LockSetWriteGuard must hold WriteGuard",
-      )
-    })
-  }
-
-  pub fn unwrap_node_ref(&self, msg: &'static str) -> Ref<Node> {
-    Ref::map(self.guard.borrow(), |guard| guard.unwrap_node_ref(msg))
-  }
-
-  pub fn unwrap_node_mut_ref(
-    &mut self,
-    msg: &'static str,
-  ) -> RefMut<Node> {
-    RefMut::map(self.guard.borrow_mut(), |guard| {
-      guard.unwrap_node_mut_ref(msg)
-    })
-  }
-}
-
-impl LockSetNodeWriteGuard {
+impl<K: Key, V: Value> LockSetNodeWriteGuard<K, V> {
   pub fn from_guard(
-    guard: Rc<RefCell<Guard>>,
-  ) -> LockSetNodeWriteGuard {
+    guard: Rc<RefCell<Guard<K, V>>>,
+  ) -> LockSetNodeWriteGuard<K, V> {
     LockSetNodeWriteGuard { guard }
   }
 
-  pub(super) fn clone_ref_cell_guard(&self) -> Rc<RefCell<Guard>> {
-    Rc::clone(&self.guard)
+  pub fn is_leaf_node(&self) -> Result<bool> {
+    Ok(self.unwrap_node_ref()?.is_leaf_node())
   }
 
-  pub fn is_leaf_node(&self) -> bool {
-    let guard = self.guard.borrow();
-    guard
-      .unwrap_node_ref(
-        "Guard ref in LockSetNodeWriteGuard doesn't hold Node?",
-      )
-      .is_leaf_node()
-  }
-
-  pub fn unwrap_node_ref(&self) -> Ref<Node> {
-    Ref::map(self.guard.borrow(), |guard| {
+  pub fn unwrap_node_ref(&self) -> Result<Ref<'_, Node<K, V>>> {
+    try_map_ref(self.guard.borrow(), |guard| {
       guard.unwrap_node_ref(
         "Guard ref in LockSetNodeWriteGuard doesn't hold Node?",
       )
     })
   }
 
-  pub fn unwrap_node_mut_ref(&mut self) -> RefMut<Node> {
-    RefMut::map(self.guard.borrow_mut(), |guard| {
-      guard.unwrap_node_mut_ref(
-        "Guard ref in LockSetNodeWriteGuard doesn't hold Node?",
-      )
-    })
+  pub fn unwrap_node_mut_ref(&self) -> Result<RefMut<'_, Node<K, V>>> {
+    self.node_mut_ref()
   }
 
   pub fn unwrap_interior_node_ref(
     &self,
     msg: &'static str,
-  ) -> Ref<InteriorNode> {
-    Ref::map(self.guard.borrow(), |guard| {
-      guard
-        .unwrap_node_ref(
-          "Guard ref in LockSetNodeWriteGuard doesn't hold Node?",
-        )
-        .unwrap_interior_node_ref(msg)
+  ) -> Result<Ref<'_, InteriorNode<K>>> {
+    try_map_ref(self.unwrap_node_ref()?, |node| {
+      node.unwrap_interior_node_ref(msg)
     })
   }
 
   pub fn unwrap_interior_node_mut_ref(
     &self,
     msg: &'static str,
-  ) -> RefMut<InteriorNode> {
-    RefMut::map(self.guard.borrow_mut(), |guard| {
-      guard
-        .unwrap_node_mut_ref(
-          "Guard ref in LockSetNodeReadGuard doesn't hold Node?",
-        )
-        .unwrap_interior_node_mut_ref(msg)
+  ) -> Result<RefMut<'_, InteriorNode<K>>> {
+    try_map_ref_mut(self.node_mut_ref()?, |node| {
+      node.unwrap_interior_node_mut_ref(msg)
     })
   }
 
   pub fn unwrap_leaf_node_mut_ref(
     &self,
     msg: &'static str,
-  ) -> RefMut<LeafNode> {
-    RefMut::map(self.guard.borrow_mut(), |guard| {
-      guard
-        .unwrap_node_mut_ref(
-          "Guard ref in LockSetNodeReadGuard doesn't hold Node?",
-        )
-        .unwrap_leaf_node_mut_ref(msg)
+  ) -> Result<RefMut<'_, LeafNode<K, V>>> {
+    try_map_ref_mut(self.node_mut_ref()?, |node| {
+      node.unwrap_leaf_node_mut_ref(msg)
     })
   }
 
-  pub fn upcast(self) -> LockSetWriteGuard {
-    LockSetWriteGuard::from_guard(self.guard)
+  fn node_mut_ref(&self) -> Result<RefMut<'_, Node<K, V>>> {
+    try_map_ref_mut(self.guard.borrow_mut(), |guard| {
+      guard.unwrap_node_mut_ref(
+        "Guard ref in LockSetNodeWriteGuard doesn't hold Node?",
+      )
+    })
   }
 }
 
-impl LockSetRootIdentifierWriteGuard {
+impl<K: Key, V: Value> LockSetRootIdentifierWriteGuard<K, V> {
   pub fn from_guard(
-    guard: Rc<RefCell<Guard>>,
-  ) -> LockSetRootIdentifierWriteGuard {
+    guard: Rc<RefCell<Guard<K, V>>>,
+  ) -> LockSetRootIdentifierWriteGuard<K, V> {
     LockSetRootIdentifierWriteGuard { guard }
   }
 
-  pub fn identifier(&self) -> Ref<String> {
+  pub fn identifier(&self) -> Result<Ref<'_, NodeIdentifier>> {
     let msg = "Guard ref in LockSetRootIdentifierWriteGuard doesn't hold RootIdentifier?";
-    Ref::map(self.guard.borrow(), |guard| {
+    try_map_ref(self.guard.borrow(), |guard| {
       guard.unwrap_root_identifier_ref(msg)
     })
   }
 
-  pub fn identifier_mut(&self) -> RefMut<String> {
+  pub fn identifier_mut(&self) -> Result<RefMut<'_, NodeIdentifier>> {
     let msg = "Guard ref in LockSetRootIdentifierWriteGuard doesn't hold RootIdentifier?";
-    RefMut::map(self.guard.borrow_mut(), |guard| {
+    try_map_ref_mut(self.guard.borrow_mut(), |guard| {
       guard.unwrap_root_identifier_mut_ref(msg)
     })
   }
-
-  pub fn upcast(self) -> LockSetWriteGuard {
-    LockSetWriteGuard::from_guard(self.guard)
-  }
 }
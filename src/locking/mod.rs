@@ -1,16 +1,19 @@
 mod guards;
+mod key_locks;
+mod lock_manager;
 mod lock_mode;
 mod lock_set;
 mod target;
-mod transaction_mode;
 
 // TODO: I would like to eliminate exposing primitive guards like this
 // to the world.
 pub use self::guards::{Guard, ReadGuard, WriteGuard};
+pub use self::key_locks::{
+  KeyLockGuard, KeyLockMode, KeyLockTable, KeyRangeIdentifier,
+};
+pub use self::lock_manager::{LockManager, LockRecord};
 pub use self::lock_mode::LockMode;
 pub use self::lock_set::{
   LockSet, LockSetNodeReadGuard, LockSetNodeWriteGuard,
-  LockSetReadGuard, LockSetRootIdentifierWriteGuard, LockSetWriteGuard,
 };
-pub use self::target::LockTarget;
-pub use self::transaction_mode::TransactionMode;
+pub use self::target::{LatchTarget, LockTarget};
@@ -1,8 +1,22 @@
-// This represents a target for lock acquisition. There are two kinds of
-// locks: RootIdentifier and Node locks. In the case of a Node lock, we
-// specify the identifier, which is a String.
-#[derive(Clone, Eq, Hash, PartialEq)]
-pub enum LockTarget {
+use locking::KeyRangeIdentifier;
+use storage::NodeIdentifier;
+
+// This represents a target for latching. RootIdentifier and Node
+// latches protect what is stored in the tree, for as long as one
+// operation needs it. In the case of a Node latch, we specify the
+// identifier.
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub enum LatchTarget {
   RootIdentifier,
-  Node(String),
+  Node(NodeIdentifier),
+}
+
+// This represents a target for lock acquisition. Tree and KeyRange
+// locks are logical locks, granted by the KeyLockTable and held until
+// the transaction ends. A KeyRange lock is known by the identifier the
+// KeyLockTable gave its range.
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub enum LockTarget {
+  Tree,
+  KeyRange(KeyRangeIdentifier),
 }
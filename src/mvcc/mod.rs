@@ -0,0 +1,5 @@
+mod timestamp;
+mod version_store;
+
+pub use self::timestamp::Timestamp;
+pub use self::version_store::VersionStore;
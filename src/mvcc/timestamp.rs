@@ -0,0 +1,4 @@
+// Each Transaction that changed something gets a commit Timestamp when
+// it commits. A Snapshot transaction reads as of the last Timestamp
+// handed out when it began.
+pub type Timestamp = u64;
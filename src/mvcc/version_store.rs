@@ -0,0 +1,610 @@
+use super::Timestamp;
+use constants::NUM_VERSION_STORE_SHARDS;
+use error::{Error, Result};
+use key::{Key, Value};
+use parking_lot::{Mutex, RwLock};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Bound;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+use transaction::{TransactionId, UndoLog};
+
+// The VersionStore keeps the old versions of keys that a Snapshot
+// transaction may still need. The leaves only ever hold the newest
+// version of each key; whenever a Transaction changes a key while a
+// snapshot is running, the value it replaces is kept here too.
+//
+// Each key has a chain of versions, oldest first. A version says what
+// the value of the key was *before* a change, and when that change
+// committed. A change that hasn't committed yet has no timestamp. So
+// the value of a key as of timestamp T is the previous value of the
+// first change that committed after T (or hasn't committed at all). If
+// there is no such change, the leaf's value is the right one.
+//
+// Versions are only needed while a snapshot older than them is
+// running, so they are never logged or written to the NodeStore. A
+// version is dropped as soon as every running snapshot began after it
+// committed.
+//
+// Without a snapshot running, no one needs a version at all, so
+// writers save none. They don't even take a lock: they only look at
+// `num_snapshots`. Instead, a Transaction registers its undo log here
+// with its first change. A snapshot that begins copies the previous
+// values out of every registered undo log, before it reads anything.
+// (See `begin_snapshot`.)
+//
+// The chains are split into shards by TransactionId, so that writers
+// running at the same time save their versions without waiting on each
+// other. A reader looks through every shard for the oldest change that
+// it must not see.
+pub struct VersionStore<K: Key, V: Value> {
+  num_snapshots: AtomicUsize,
+  clock: Mutex<Clock<K, V>>,
+  shards: Vec<RwLock<Shard<K, V>>>,
+}
+
+// Commits, and snapshots beginning or ending, are serialized by the
+// clock. Stamping a Transaction's versions happens under it too, so a
+// snapshot sees either all of a transaction's changes, or none.
+struct Clock<K: Key, V: Value> {
+  // The last commit timestamp handed out.
+  last_timestamp: Timestamp,
+  // How many snapshots are running as of each timestamp.
+  snapshots: BTreeMap<Timestamp, usize>,
+  // The undo log of every running Transaction that changed something.
+  writers: HashMap<TransactionId, Arc<UndoLog<K, V>>>,
+}
+
+struct Shard<K: Key, V: Value> {
+  chains: BTreeMap<OrderedKey<K>, Vec<Version<V>>>,
+  // The keys each running Transaction has changed.
+  uncommitted_keys: HashMap<TransactionId, Vec<K>>,
+  // Every committed version, in the order they committed. This is the
+  // order in which they become garbage.
+  committed_keys: VecDeque<(Timestamp, K)>,
+}
+
+struct Version<V: Value> {
+  // None until the change commits.
+  timestamp: Option<Timestamp>,
+  previous_value: Option<V>,
+}
+
+// Orders keys by `Key::compare`, so that they can be kept in a BTreeMap.
+struct OrderedKey<K: Key>(K);
+
+impl<K: Key, V: Value> VersionStore<K, V> {
+  pub fn new() -> VersionStore<K, V> {
+    VersionStore {
+      num_snapshots: AtomicUsize::new(0),
+      clock: Mutex::new(Clock {
+        last_timestamp: 0,
+        snapshots: BTreeMap::new(),
+        writers: HashMap::new(),
+      }),
+      shards: (0..NUM_VERSION_STORE_SHARDS)
+        .map(|_| {
+          RwLock::new(Shard {
+            chains: BTreeMap::new(),
+            uncommitted_keys: HashMap::new(),
+            committed_keys: VecDeque::new(),
+          })
+        })
+        .collect(),
+    }
+  }
+
+  // A Transaction must register its undo log before it adds the first
+  // entry. It stays registered until it commits or aborts.
+  pub fn register_undo_log(
+    &self,
+    transaction_id: TransactionId,
+    undo_log: &Arc<UndoLog<K, V>>,
+  ) {
+    self
+      .clock
+      .lock()
+      .writers
+      .insert(transaction_id, Arc::clone(undo_log));
+  }
+
+  // Whether a change must save the value it replaces. A change checks
+  // only *after* it is in the undo log, and while it still holds the
+  // write latch on the leaf: a snapshot that began earlier has either
+  // copied the change from the undo log already, or is counted here.
+  pub fn is_versioning(&self) -> bool {
+    self.num_snapshots.load(atomic::Ordering::SeqCst) > 0
+  }
+
+  // Must be called while the Transaction holds the write latch on the
+  // leaf where it changed `key`. Only the first change a Transaction
+  // makes to a key matters: later ones replace a value no one else has
+  // seen.
+  pub fn save_previous_version(
+    &self,
+    transaction_id: TransactionId,
+    key: &K,
+    previous_value: Option<V>,
+  ) {
+    self.shard(transaction_id).write().save_previous_version(
+      transaction_id,
+      key,
+      previous_value,
+    );
+  }
+
+  // Stamps every change the Transaction made with a new commit
+  // timestamp. A snapshot that begins afterward sees the changes; one
+  // that began before doesn't.
+  //
+  // Should a change have no version, we still stamp the rest.
+  pub fn commit(&self, transaction_id: TransactionId) -> Result<()> {
+    let mut clock = self.clock.lock();
+    if clock.writers.remove(&transaction_id).is_none() {
+      return Ok(());
+    }
+
+    let mut shard = self.shard(transaction_id).write();
+    let keys = match shard.uncommitted_keys.remove(&transaction_id) {
+      None => return Ok(()),
+      Some(keys) => keys,
+    };
+
+    clock.last_timestamp += 1;
+    let timestamp = clock.last_timestamp;
+    let mut result = Ok(());
+    for key in keys {
+      let version = match shard
+        .chains
+        .get_mut(&OrderedKey(key.clone()))
+        .and_then(|chain| chain.last_mut())
+      {
+        None => {
+          result = Err(Error::InvariantViolation(
+            "an uncommitted change must have a version",
+          ));
+          continue;
+        }
+        Some(version) => version,
+      };
+      version.timestamp = Some(timestamp);
+      shard.committed_keys.push_back((timestamp, key));
+    }
+
+    shard.collect_garbage(clock.horizon());
+    result
+  }
+
+  // Must be called once the Transaction has rolled back its changes,
+  // but before it releases its locks.
+  pub fn abort(&self, transaction_id: TransactionId) {
+    let mut clock = self.clock.lock();
+    if clock.writers.remove(&transaction_id).is_none() {
+      return;
+    }
+
+    let mut shard = self.shard(transaction_id).write();
+    let keys = match shard.uncommitted_keys.remove(&transaction_id) {
+      None => return,
+      Some(keys) => keys,
+    };
+
+    for key in keys {
+      shard.remove_version(key, |chain| {
+        chain.pop();
+      });
+    }
+  }
+
+  // Writers that are running now may not have saved versions for their
+  // changes. We count ourselves first, so that from here on they do,
+  // and then save the versions for the changes they already made.
+  pub fn begin_snapshot(&self) -> Timestamp {
+    let mut clock = self.clock.lock();
+    self.num_snapshots.fetch_add(1, atomic::Ordering::SeqCst);
+    let timestamp = clock.last_timestamp;
+    *clock.snapshots.entry(timestamp).or_default() += 1;
+
+    for (transaction_id, undo_log) in &clock.writers {
+      let undo_log = undo_log.lock();
+      if undo_log.is_empty() {
+        continue;
+      }
+
+      let mut shard = self.shard(*transaction_id).write();
+      for undo_entry in undo_log.iter() {
+        shard.save_previous_version(
+          *transaction_id,
+          undo_entry.key(),
+          undo_entry.previous_value().cloned(),
+        );
+      }
+    }
+
+    timestamp
+  }
+
+  pub fn end_snapshot(&self, timestamp: Timestamp) {
+    let mut clock = self.clock.lock();
+    if let Some(count) = clock.snapshots.get_mut(&timestamp) {
+      *count -= 1;
+      if *count == 0 {
+        clock.snapshots.remove(&timestamp);
+      }
+    }
+    self.num_snapshots.fetch_sub(1, atomic::Ordering::SeqCst);
+
+    let horizon = clock.horizon();
+    for shard in &self.shards {
+      shard.write().collect_garbage(horizon);
+    }
+  }
+
+  // The value of `key` as of `timestamp`, if it has changed since. None
+  // means it hasn't, so the leaf's value is the right one. Must be
+  // called while holding a latch on the leaf for `key`.
+  pub fn value_as_of(
+    &self,
+    key: &K,
+    timestamp: Timestamp,
+  ) -> Option<Option<V>> {
+    let key = OrderedKey(key.clone());
+    self
+      .shards
+      .iter()
+      .filter_map(|shard| {
+        let shard = shard.read();
+        let chain = shard.chains.get(&key)?;
+        version_as_of(chain, timestamp).map(|version| {
+          (version_order(version), version.previous_value.clone())
+        })
+      })
+      .min_by_key(|(order, _)| *order)
+      .map(|(_, previous_value)| previous_value)
+  }
+
+  // Like `value_as_of`, for every key between the bounds that has
+  // changed since `timestamp`. They are in ascending order.
+  pub fn values_as_of(
+    &self,
+    lower_bound: Bound<&K>,
+    upper_bound: Bound<&K>,
+    timestamp: Timestamp,
+  ) -> Vec<(K, Option<V>)> {
+    let lower_bound = match lower_bound {
+      Bound::Included(key) => Bound::Included(OrderedKey(key.clone())),
+      Bound::Excluded(key) => Bound::Excluded(OrderedKey(key.clone())),
+      Bound::Unbounded => Bound::Unbounded,
+    };
+
+    // For each key, the oldest change (from any shard) that the
+    // snapshot must not see.
+    let mut values = BTreeMap::new();
+    for shard in &self.shards {
+      let shard = shard.read();
+      let chains = shard
+        .chains
+        .range((lower_bound.as_ref(), Bound::Unbounded))
+        .take_while(|(key, _)| match upper_bound {
+          Bound::Included(upper_key) => {
+            key.0.compare(upper_key) != Ordering::Greater
+          }
+          Bound::Excluded(upper_key) => {
+            key.0.compare(upper_key) == Ordering::Less
+          }
+          Bound::Unbounded => true,
+        });
+
+      for (key, chain) in chains {
+        let version = match version_as_of(chain, timestamp) {
+          None => continue,
+          Some(version) => version,
+        };
+        let order = version_order(version);
+        match values.get(key) {
+          Some((older_order, _)) if *older_order <= order => continue,
+          _ => {
+            values.insert(
+              OrderedKey(key.0.clone()),
+              (order, version.previous_value.clone()),
+            );
+          }
+        }
+      }
+    }
+
+    values
+      .into_iter()
+      .map(|(key, (_, previous_value))| (key.0, previous_value))
+      .collect()
+  }
+
+  fn shard(
+    &self,
+    transaction_id: TransactionId,
+  ) -> &RwLock<Shard<K, V>> {
+    &self.shards[(transaction_id % self.shards.len() as u64) as usize]
+  }
+
+  #[cfg(test)]
+  fn num_versions(&self) -> usize {
+    self
+      .shards
+      .iter()
+      .map(|shard| {
+        let shard = shard.read();
+        shard.chains.values().map(|chain| chain.len()).sum::<usize>()
+      })
+      .sum()
+  }
+}
+
+impl<K: Key, V: Value> Default for VersionStore<K, V> {
+  fn default() -> VersionStore<K, V> {
+    VersionStore::new()
+  }
+}
+
+impl<K: Key, V: Value> Clock<K, V> {
+  // No snapshot that is running (or that will ever begin) reads as of
+  // a timestamp before the oldest running snapshot.
+  fn horizon(&self) -> Timestamp {
+    match self.snapshots.keys().next() {
+      None => self.last_timestamp,
+      Some(timestamp) => *timestamp,
+    }
+  }
+}
+
+impl<K: Key, V: Value> Shard<K, V> {
+  fn save_previous_version(
+    &mut self,
+    transaction_id: TransactionId,
+    key: &K,
+    previous_value: Option<V>,
+  ) {
+    // We hold the write lock on the key, so an uncommitted version of
+    // it can only be ours.
+    let chain = self.chains.entry(OrderedKey(key.clone())).or_default();
+    if let Some(Version {
+      timestamp: None, ..
+    }) = chain.last()
+    {
+      return;
+    }
+
+    chain.push(Version {
+      timestamp: None,
+      previous_value,
+    });
+    self
+      .uncommitted_keys
+      .entry(transaction_id)
+      .or_default()
+      .push(key.clone());
+  }
+
+  fn collect_garbage(&mut self, horizon: Timestamp) {
+    while let Some((timestamp, _)) = self.committed_keys.front() {
+      if *timestamp > horizon {
+        break;
+      }
+
+      let (_, key) = self
+        .committed_keys
+        .pop_front()
+        .expect("we just looked at the front");
+      // Versions commit in order, so this is the oldest in its chain.
+      self.remove_version(key, |chain| {
+        chain.remove(0);
+      });
+    }
+  }
+
+  fn remove_version<F>(&mut self, key: K, remove: F)
+  where
+    F: FnOnce(&mut Vec<Version<V>>),
+  {
+    let key = OrderedKey(key);
+    let is_empty = match self.chains.get_mut(&key) {
+      None => return,
+      Some(chain) => {
+        remove(chain);
+        chain.is_empty()
+      }
+    };
+
+    if is_empty {
+      self.chains.remove(&key);
+    }
+  }
+}
+
+fn version_as_of<V: Value>(
+  chain: &[Version<V>],
+  timestamp: Timestamp,
+) -> Option<&Version<V>> {
+  chain.iter().find(|version| match version.timestamp {
+    None => true,
+    Some(version_timestamp) => version_timestamp > timestamp,
+  })
+}
+
+// Versions of one key in different shards are ordered by when they
+// committed. A change that hasn't committed yet came last: whoever made
+// it holds the write lock on the key.
+fn version_order<V: Value>(version: &Version<V>) -> Timestamp {
+  version.timestamp.unwrap_or(Timestamp::MAX)
+}
+
+impl<K: Key> Ord for OrderedKey<K> {
+  fn cmp(&self, other: &OrderedKey<K>) -> Ordering {
+    self.0.compare(&other.0)
+  }
+}
+
+impl<K: Key> PartialOrd for OrderedKey<K> {
+  fn partial_cmp(&self, other: &OrderedKey<K>) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<K: Key> PartialEq for OrderedKey<K> {
+  fn eq(&self, other: &OrderedKey<K>) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl<K: Key> Eq for OrderedKey<K> {}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use std::sync::{mpsc, Arc};
+  use std::thread;
+  use test_util::key;
+  use transaction::TransactionMode;
+
+  // Every key holds `value`.
+  fn btree_with_values(
+    num_keys: usize,
+    value: &str,
+  ) -> Arc<BTree<String, String>> {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..num_keys {
+      txn.insert(&key(idx), String::from(value)).unwrap();
+    }
+    txn.commit().unwrap();
+    btree
+  }
+
+  fn contents(
+    btree: &Arc<BTree<String, String>>,
+    tx_mode: TransactionMode,
+  ) -> Vec<(String, String)> {
+    let mut txn = btree.begin(tx_mode);
+    let contents =
+      txn.range(..).unwrap().map(|entry| entry.unwrap()).collect();
+    txn.commit().unwrap();
+    contents
+  }
+
+  // Without a snapshot running, a writer saves no versions. One that
+  // begins afterward must still see none of the writer's changes, even
+  // once it commits (from another thread). So must the keys the writer
+  // changes after the snapshot began.
+  #[test]
+  fn a_snapshot_keeps_seeing_old_values_after_a_concurrent_commit() {
+    let btree = btree_with_values(100, "old");
+    let old_contents = contents(&btree, TransactionMode::ReadOnly);
+
+    let (changed_sender, changed_receiver) = mpsc::channel();
+    let (snapshot_sender, snapshot_receiver) = mpsc::channel();
+    let writer_btree = Arc::clone(&btree);
+    let writer_thread = thread::spawn(move || {
+      let mut writer = writer_btree.begin(TransactionMode::ReadWrite);
+      for idx in 0..50 {
+        writer.insert(&key(idx), String::from("new")).unwrap();
+      }
+      writer.delete(&key(50)).unwrap();
+      changed_sender.send(()).unwrap();
+
+      snapshot_receiver.recv().unwrap();
+      for idx in 51..100 {
+        writer.insert(&key(idx), String::from("new")).unwrap();
+      }
+      writer.insert(&key(100), String::from("new")).unwrap();
+      writer.commit().unwrap();
+    });
+
+    changed_receiver.recv().unwrap();
+    assert_eq!(btree.version_store().num_versions(), 0);
+    let mut snapshot = btree.begin(TransactionMode::Snapshot);
+    snapshot_sender.send(()).unwrap();
+    writer_thread.join().unwrap();
+
+    for idx in 0..100 {
+      let value = snapshot.get(&key(idx)).unwrap();
+      assert_eq!(value, Some(String::from("old")));
+    }
+    assert_eq!(snapshot.get(&key(100)).unwrap(), None);
+    let snapshot_contents: Vec<(String, String)> = snapshot
+      .range(..)
+      .unwrap()
+      .map(|entry| entry.unwrap())
+      .collect();
+    assert_eq!(snapshot_contents, old_contents);
+    snapshot.commit().unwrap();
+
+    let new_contents = contents(&btree, TransactionMode::Snapshot);
+    assert_eq!(new_contents.len(), 100);
+    assert!(new_contents.iter().all(|(_, value)| value == "new"));
+  }
+
+  // A writer that rolls back leaves no versions behind, and a snapshot
+  // that began meanwhile never sees its changes.
+  #[test]
+  fn a_snapshot_never_sees_a_rolled_back_change() {
+    let btree = btree_with_values(100, "old");
+
+    let mut writer = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..100 {
+      writer.insert(&key(idx), String::from("new")).unwrap();
+    }
+    let mut snapshot = btree.begin(TransactionMode::Snapshot);
+    let value = snapshot.get(&key(0)).unwrap();
+    assert_eq!(value, Some(String::from("old")));
+    writer.abort().unwrap();
+
+    let snapshot_contents: Vec<(String, String)> = snapshot
+      .range(..)
+      .unwrap()
+      .map(|entry| entry.unwrap())
+      .collect();
+    assert_eq!(snapshot_contents.len(), 100);
+    assert!(snapshot_contents.iter().all(|(_, value)| value == "old"));
+    assert_eq!(btree.version_store().num_versions(), 0);
+    snapshot.commit().unwrap();
+  }
+
+  #[test]
+  fn versions_are_dropped_once_the_last_snapshot_ends() {
+    let btree = btree_with_values(10, "v0");
+    let update_all = |value: &str| {
+      let mut txn = btree.begin(TransactionMode::ReadWrite);
+      for idx in 0..10 {
+        txn.insert(&key(idx), String::from(value)).unwrap();
+      }
+      txn.commit().unwrap();
+    };
+
+    update_all("v1");
+    assert_eq!(btree.version_store().num_versions(), 0);
+
+    let mut first_snapshot = btree.begin(TransactionMode::Snapshot);
+    update_all("v2");
+    let mut second_snapshot = btree.begin(TransactionMode::Snapshot);
+    update_all("v3");
+    assert_eq!(btree.version_store().num_versions(), 20);
+
+    // The second snapshot still needs the versions from "v3".
+    assert_eq!(
+      first_snapshot.get(&key(0)).unwrap(),
+      Some(String::from("v1"))
+    );
+    first_snapshot.commit().unwrap();
+    assert_eq!(btree.version_store().num_versions(), 10);
+
+    assert_eq!(
+      second_snapshot.get(&key(0)).unwrap(),
+      Some(String::from("v2"))
+    );
+    second_snapshot.commit().unwrap();
+    assert_eq!(btree.version_store().num_versions(), 0);
+
+    update_all("v4");
+    assert_eq!(btree.version_store().num_versions(), 0);
+  }
+}
@@ -0,0 +1,44 @@
+use super::Node;
+use error::{Error, Result};
+use key::{Key, Value};
+
+// These are methods common to InteriorNode and LeafNode about removing
+// a node from the tree.
+impl<K: Key, V: Value> Node<K, V> {
+  pub fn is_retired(&self) -> bool {
+    match self {
+      Node::LeafNode(leaf_node) => leaf_node.is_retired(),
+      Node::InteriorNode(interior_node) => interior_node.is_retired(),
+    }
+  }
+
+  pub fn retire(&mut self) -> Result<()> {
+    match self {
+      Node::LeafNode(leaf_node) => leaf_node.retire(),
+      Node::InteriorNode(interior_node) => {
+        interior_node.retire();
+        Ok(())
+      }
+    }
+  }
+
+  pub fn unlink_retired_sibbling(
+    &mut self,
+    sibbling: &Node<K, V>,
+  ) -> Result<()> {
+    match (self, sibbling) {
+      (Node::LeafNode(leaf_node), Node::LeafNode(sibbling)) => {
+        leaf_node.unlink_retired_sibbling(sibbling)
+      }
+
+      (
+        Node::InteriorNode(interior_node),
+        Node::InteriorNode(sibbling),
+      ) => interior_node.unlink_retired_sibbling(sibbling),
+
+      _ => Err(Error::InvariantViolation(
+        "sibblings are always on the same level",
+      )),
+    }
+  }
+}
@@ -1,33 +1,52 @@
-use node::{InteriorNode, LeafNode, TraversalDirection};
+use error::Result;
+use key::{Key, Value};
+use node::{ComparisonValue, InteriorNode, LeafNode, TraversalDirection};
+use storage::NodeIdentifier;
+use wal::Lsn;
 
-pub enum Node {
-  LeafNode(LeafNode),
-  InteriorNode(InteriorNode),
+pub enum Node<K: Key, V: Value> {
+  LeafNode(LeafNode<K, V>),
+  InteriorNode(InteriorNode<K>),
 }
 
-impl Node {
-  pub fn identifier(&self) -> &str {
+impl<K: Key, V: Value> Node<K, V> {
+  pub fn identifier(&self) -> NodeIdentifier {
     match self {
-      Node::LeafNode(leaf_node) => &leaf_node.identifier(),
-      Node::InteriorNode(interior_node) => &interior_node.identifier(),
+      Node::LeafNode(leaf_node) => leaf_node.identifier(),
+      Node::InteriorNode(interior_node) => interior_node.identifier(),
     }
   }
 
-  pub fn is_interior_node(&self) -> bool {
+  pub fn lsn(&self) -> Lsn {
+    match self {
+      Node::LeafNode(leaf_node) => leaf_node.lsn(),
+      Node::InteriorNode(interior_node) => interior_node.lsn(),
+    }
+  }
+
+  pub fn set_lsn(&mut self, lsn: Lsn) {
     match self {
-      Node::InteriorNode(..) => true,
-      _ => false,
+      Node::LeafNode(leaf_node) => leaf_node.set_lsn(lsn),
+      Node::InteriorNode(interior_node) => interior_node.set_lsn(lsn),
     }
   }
 
+  pub fn is_interior_node(&self) -> bool {
+    matches!(self, Node::InteriorNode(..))
+  }
+
   pub fn is_leaf_node(&self) -> bool {
+    matches!(self, Node::LeafNode(..))
+  }
+
+  pub fn max_value(&self) -> ComparisonValue<&K> {
     match self {
-      Node::LeafNode(..) => true,
-      _ => false,
+      Node::LeafNode(leaf_node) => leaf_node.max_value(),
+      Node::InteriorNode(interior_node) => interior_node.max_value(),
     }
   }
 
-  pub fn next_node_identifier(&self) -> Option<&String> {
+  pub fn next_node_identifier(&self) -> Option<NodeIdentifier> {
     match self {
       Node::LeafNode(leaf_node) => leaf_node.next_node_identifier(),
       Node::InteriorNode(interior_node) => {
@@ -36,7 +55,10 @@ impl Node {
     }
   }
 
-  pub fn traverse_toward(&self, key: &str) -> TraversalDirection<&str> {
+  pub fn traverse_toward(
+    &self,
+    key: &K,
+  ) -> Result<TraversalDirection> {
     match self {
       Node::LeafNode(leaf_node) => leaf_node.traverse_toward(key),
       Node::InteriorNode(interior_node) => {
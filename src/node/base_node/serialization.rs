@@ -0,0 +1,42 @@
+use super::Node;
+use error::{Error, Result};
+use key::{Key, Value};
+use node::{InteriorNode, LeafNode};
+use storage::{ByteReader, ByteWriter};
+
+const LEAF_NODE_TAG: u8 = 0;
+const INTERIOR_NODE_TAG: u8 = 1;
+
+// A serialized Node starts with a tag saying which kind of node it is.
+impl<K: Key, V: Value> Node<K, V> {
+  pub fn serialize(&self) -> Vec<u8> {
+    let mut writer = ByteWriter::new();
+
+    match self {
+      Node::LeafNode(leaf_node) => {
+        writer.write_u8(LEAF_NODE_TAG);
+        leaf_node.serialize(&mut writer);
+      }
+
+      Node::InteriorNode(interior_node) => {
+        writer.write_u8(INTERIOR_NODE_TAG);
+        interior_node.serialize(&mut writer);
+      }
+    }
+
+    writer.into_bytes()
+  }
+
+  pub fn deserialize(bytes: &[u8]) -> Result<Node<K, V>> {
+    let mut reader = ByteReader::new(bytes);
+
+    match reader.read_u8()? {
+      LEAF_NODE_TAG => Ok(LeafNode::deserialize(&mut reader)?.upcast()),
+      INTERIOR_NODE_TAG => {
+        Ok(InteriorNode::deserialize(&mut reader)?.upcast())
+      }
+      _ => Err(Error::CorruptPage(String::from("unknown node tag"))),
+    }
+  }
+}
+
@@ -1,7 +1,8 @@
 use super::Node;
+use key::{Key, Value};
 
 // These are methods common to InteriorNode and LeafNode about sizing.
-impl Node {
+impl<K: Key, V: Value> Node<K, V> {
   pub fn can_delete_without_becoming_deficient(&self) -> bool {
     match self {
       Node::LeafNode(leaf_node) => {
@@ -29,14 +30,4 @@ impl Node {
       Node::InteriorNode(interior_node) => interior_node.is_deficient(),
     }
   }
-
-  // Helper to determine whether a node is deficient in size.
-  pub(in node) fn _is_deficient(
-    num_keys: usize,
-    max_key_capacity: usize,
-  ) -> bool {
-    // The largest deficient node should be able to merge with the
-    // smallest sufficient node and still obey max_key_capacity.
-    num_keys + (num_keys + 1) <= max_key_capacity
-  }
 }
@@ -1,46 +1,49 @@
 use super::Node;
+use error::{Error, Result};
+use key::{Key, Value};
 use node::{InteriorNode, LeafNode};
 
 // These are all methods that unwrap a Node into either an InteriorNode
-// or LeafNode.
-impl Node {
+// or LeafNode. If the
+// Node is the other kind, that is an invariant violation.
+impl<K: Key, V: Value> Node<K, V> {
   pub fn unwrap_interior_node_ref(
     &self,
     message: &'static str,
-  ) -> &InteriorNode {
+  ) -> Result<&InteriorNode<K>> {
     match self {
-      Node::InteriorNode(interior_node) => interior_node,
-      Node::LeafNode(..) => panic!(message),
+      Node::InteriorNode(interior_node) => Ok(interior_node),
+      Node::LeafNode(..) => Err(Error::InvariantViolation(message)),
     }
   }
 
   pub fn unwrap_interior_node_mut_ref(
     &mut self,
     message: &'static str,
-  ) -> &mut InteriorNode {
+  ) -> Result<&mut InteriorNode<K>> {
     match self {
-      Node::InteriorNode(interior_node) => interior_node,
-      Node::LeafNode(..) => panic!(message),
+      Node::InteriorNode(interior_node) => Ok(interior_node),
+      Node::LeafNode(..) => Err(Error::InvariantViolation(message)),
     }
   }
 
   pub fn unwrap_leaf_node_ref(
     &self,
     message: &'static str,
-  ) -> &LeafNode {
+  ) -> Result<&LeafNode<K, V>> {
     match self {
-      Node::InteriorNode(..) => panic!(message),
-      Node::LeafNode(leaf_node) => leaf_node,
+      Node::InteriorNode(..) => Err(Error::InvariantViolation(message)),
+      Node::LeafNode(leaf_node) => Ok(leaf_node),
     }
   }
 
   pub fn unwrap_leaf_node_mut_ref(
     &mut self,
     message: &'static str,
-  ) -> &mut LeafNode {
+  ) -> Result<&mut LeafNode<K, V>> {
     match self {
-      Node::InteriorNode(..) => panic!(message),
-      Node::LeafNode(leaf_node) => leaf_node,
+      Node::InteriorNode(..) => Err(Error::InvariantViolation(message)),
+      Node::LeafNode(leaf_node) => Ok(leaf_node),
     }
   }
 }
@@ -1,32 +1,40 @@
 use super::Node;
+use error::Result;
+use key::{Key, Value};
 use locking::LockSet;
-use node::StringComparisonValue;
+use node::ComparisonValue;
+use storage::NodeIdentifier;
 
-impl Node {
+impl<K: Key, V: Value> Node<K, V> {
   pub fn validate(
-    lock_set: &mut LockSet,
-    node_identifier: &str,
-    min_value: StringComparisonValue<&str>,
-    max_value: StringComparisonValue<&str>,
-  ) {
-    let child_guard = lock_set.temp_node_read_guard(node_identifier);
-    let child_node_ref = child_guard.unwrap_node_ref();
+    lock_set: &mut LockSet<K, V>,
+    node_identifier: NodeIdentifier,
+    min_value: ComparisonValue<&K>,
+    max_value: ComparisonValue<&K>,
+  ) -> Result<()> {
+    let child_guard = lock_set.temp_node_read_guard(node_identifier)?;
+    let child_node_ref = child_guard.unwrap_node_ref()?;
 
     match &(*child_node_ref) {
       Node::InteriorNode(inode) => {
-        inode.validate(lock_set, min_value, max_value);
+        inode.validate(lock_set, min_value, max_value)?;
       }
 
-      Node::LeafNode(lnode) => lnode.validate(min_value, max_value),
+      Node::LeafNode(lnode) => lnode.validate(min_value, max_value)?,
     }
+
+    Ok(())
   }
 
-  pub fn validate_root(lock_set: &mut LockSet, node_identifier: &str) {
+  pub fn validate_root(
+    lock_set: &mut LockSet<K, V>,
+    node_identifier: NodeIdentifier,
+  ) -> Result<()> {
     Node::validate(
       lock_set,
       node_identifier,
-      StringComparisonValue::NegativeInfinity,
-      StringComparisonValue::Infinity,
-    );
+      ComparisonValue::NegativeInfinity,
+      ComparisonValue::Infinity,
+    )
   }
 }
@@ -0,0 +1,96 @@
+use key::Key;
+use std::cmp::Ordering;
+
+// Used in the B-Link tree InteriorNodes to know the max value that can
+// be stored in the subtree (specifically, the rightmost branch). That
+// can tell us when to move to the right, versus descending.
+//
+// NegativeInfinity is below, and Infinity above, every definite value.
+#[derive(Clone, Copy, Debug)]
+pub enum ComparisonValue<T> {
+  NegativeInfinity,
+  DefiniteValue(T),
+  Infinity,
+}
+
+impl<K: Key> ComparisonValue<K> {
+  pub fn as_ref(&self) -> ComparisonValue<&K> {
+    match self {
+      ComparisonValue::NegativeInfinity => {
+        ComparisonValue::NegativeInfinity
+      }
+
+      ComparisonValue::DefiniteValue(self_value) => {
+        ComparisonValue::DefiniteValue(self_value)
+      }
+
+      ComparisonValue::Infinity => ComparisonValue::Infinity,
+    }
+  }
+}
+
+impl<K: Key> ComparisonValue<&K> {
+  pub fn cloned(self) -> ComparisonValue<K> {
+    match self {
+      ComparisonValue::NegativeInfinity => {
+        ComparisonValue::NegativeInfinity
+      }
+
+      ComparisonValue::DefiniteValue(self_value) => {
+        ComparisonValue::DefiniteValue(self_value.clone())
+      }
+
+      ComparisonValue::Infinity => ComparisonValue::Infinity,
+    }
+  }
+
+  pub fn is_ge_to(self, value: &K) -> bool {
+    match self {
+      ComparisonValue::NegativeInfinity => false,
+
+      ComparisonValue::DefiniteValue(self_value) => {
+        value.compare(self_value) != Ordering::Greater
+      }
+
+      ComparisonValue::Infinity => true,
+    }
+  }
+
+  // Definite values are compared by their Key order.
+  fn rank(self) -> u8 {
+    match self {
+      ComparisonValue::NegativeInfinity => 0,
+      ComparisonValue::DefiniteValue(..) => 1,
+      ComparisonValue::Infinity => 2,
+    }
+  }
+}
+
+// Keys aren't `Ord` (they have `compare` instead), so these can't be
+// derived.
+impl<K: Key> Ord for ComparisonValue<&K> {
+  fn cmp(&self, other: &Self) -> Ordering {
+    match (self, other) {
+      (
+        ComparisonValue::DefiniteValue(self_value),
+        ComparisonValue::DefiniteValue(other_value),
+      ) => self_value.compare(other_value),
+
+      _ => self.rank().cmp(&other.rank()),
+    }
+  }
+}
+
+impl<K: Key> PartialOrd for ComparisonValue<&K> {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<K: Key> PartialEq for ComparisonValue<&K> {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl<K: Key> Eq for ComparisonValue<&K> {}
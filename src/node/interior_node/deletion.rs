@@ -0,0 +1,162 @@
+use super::InteriorNode;
+use error::{Error, Result};
+use key::{Key, Value};
+use node::{util::search_sorted_keys, ComparisonValue, Node};
+
+impl<K: Key> InteriorNode<K> {
+  // Removes an empty child (a leaf, or an interior node whose only
+  // child is being removed with it), so that its right sibbling takes
+  // over its key range. Returns whether the child was removed.
+  //
+  // Our rightmost child is left alone here; see
+  // `remove_empty_last_child`.
+  pub fn remove_empty_child<V: Value>(
+    &mut self,
+    child_node: &Node<K, V>,
+  ) -> bool {
+    // A child with an indefinite max_value is the rightmost node of its
+    // level, which has no right sibbling to take over. (Or it was
+    // already retired.)
+    let child_max_value = match child_node.max_value() {
+      ComparisonValue::DefiniteValue(max_value) => max_value,
+      _ => return false,
+    };
+
+    // The child's max_value must be one of our splits. If it isn't,
+    // then either the child is our rightmost child, or the child split
+    // and we haven't yet been told about the new right sibbling.
+    // Removing the child now would orphan that sibbling.
+    let child_idx =
+      match search_sorted_keys(&self.splits, child_max_value) {
+        Err(_) => return false,
+        Ok(child_idx) => child_idx,
+      };
+
+    if self.child_identifiers[child_idx] != child_node.identifier() {
+      return false;
+    }
+
+    // The split to the right of the child is removed, so that the
+    // right sibbling's range now extends down to the child's lower
+    // bound.
+    self.splits.remove(child_idx);
+    self.child_identifiers.remove(child_idx);
+
+    true
+  }
+
+  // Whether the child is all we have, and covers our whole key range.
+  // Then we can't give the child up, but we can be removed from our own
+  // parent along with it. (If the child split and we haven't been told
+  // yet, its max_value is below ours.)
+  pub fn has_only_child<V: Value>(
+    &self,
+    child_node: &Node<K, V>,
+  ) -> bool {
+    let is_definite = matches!(
+      child_node.max_value(),
+      ComparisonValue::DefiniteValue(_)
+    );
+
+    is_definite
+      && self.child_identifiers.len() == 1
+      && self.child_identifiers[0] == child_node.identifier()
+      && child_node.max_value() == self.max_value()
+  }
+
+  // The right sibbling of our rightmost child lives under a different
+  // parent. To hand it our rightmost child's range, our own max_value
+  // comes down to the split below that child. The child's right
+  // sibbling's range then extends down to meet it, and so does ours.
+  //
+  // Returns what our max_value would become, if the empty child is our
+  // rightmost child and we have another. (An only child goes along with
+  // us instead; see `has_only_child`.) Our parent's split for us must
+  // come down to the same value; see `lower_split_for_child`.
+  pub fn max_value_without_last_child<V: Value>(
+    &self,
+    child_node: &Node<K, V>,
+  ) -> Option<&K> {
+    // Only the rightmost node of a level has an indefinite max_value.
+    let is_definite = matches!(
+      child_node.max_value(),
+      ComparisonValue::DefiniteValue(_)
+    );
+    if !is_definite
+      || child_node.max_value() != self.max_value()
+      || self.child_identifiers.last() != Some(&child_node.identifier())
+    {
+      return None;
+    }
+
+    self.splits.last()
+  }
+
+  // See `max_value_without_last_child`.
+  pub fn remove_empty_last_child(&mut self) -> Result<()> {
+    let new_max_value = self.splits.pop().ok_or(
+      Error::InvariantViolation(
+        "only a node with two children may lose the last",
+      ),
+    )?;
+    self.child_identifiers.pop();
+    self.max_value = ComparisonValue::DefiniteValue(new_max_value);
+
+    Ok(())
+  }
+
+  // Lowers the split that bounds a child, whose rightmost child is
+  // about to be removed. Returns false if the child's max_value isn't
+  // one of our splits: then the child is our own rightmost child, or
+  // it split and we haven't been told yet.
+  pub fn lower_split_for_child(
+    &mut self,
+    child_node: &InteriorNode<K>,
+    new_split: &K,
+  ) -> bool {
+    let child_max_value = match child_node.max_value() {
+      ComparisonValue::DefiniteValue(max_value) => max_value,
+      _ => return false,
+    };
+
+    let child_idx =
+      match search_sorted_keys(&self.splits, child_max_value) {
+        Err(_) => return false,
+        Ok(child_idx) => child_idx,
+      };
+    if self.child_identifiers[child_idx] != child_node.identifier() {
+      return false;
+    }
+
+    self.splits[child_idx] = new_split.clone();
+    true
+  }
+
+  // Like a retired leaf, a retired interior node sends everyone who
+  // still arrives right, to the sibbling that took over its range. It
+  // keeps its child, which is retired along with it.
+  pub fn retire(&mut self) {
+    self.max_value = ComparisonValue::NegativeInfinity;
+  }
+
+  pub fn is_retired(&self) -> bool {
+    matches!(self.max_value, ComparisonValue::NegativeInfinity)
+  }
+
+  // See `LeafNode::unlink_retired_sibbling`.
+  pub fn unlink_retired_sibbling(
+    &mut self,
+    sibbling: &InteriorNode<K>,
+  ) -> Result<()> {
+    if self.next_node_identifier != Some(sibbling.identifier)
+      || !sibbling.is_retired()
+    {
+      return Err(Error::InvariantViolation(
+        "only a retired right sibbling may be unlinked",
+      ));
+    }
+
+    self.next_node_identifier = sibbling.next_node_identifier;
+    Ok(())
+  }
+}
@@ -1,4 +1,6 @@
+mod deletion;
 mod node;
+mod serialization;
 mod sizing;
 mod splitting;
 mod storage;
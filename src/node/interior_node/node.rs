@@ -1,44 +1,58 @@
-use node::{Node, StringComparisonValue};
+use key::{Key, Value};
+use node::{ComparisonValue, Node};
+use storage::NodeIdentifier;
+use wal::Lsn;
 
-#[derive(Debug)]
-pub struct InteriorNode {
+#[derive(Clone, Debug)]
+pub struct InteriorNode<K: Key> {
   // These fields are public in the `interior_node` module, as other
   // methods of InteriorNode will need them and are defined in sibbling
   // modules.
-  pub(super) identifier: String,
+  pub(super) identifier: NodeIdentifier,
   // The rule is that all keys such that `target_key <= keys[idx]` live
   // in child `idx`.
   //
   // Another rule is that for interior nodes the number of child
   // identifiers is always one more than the number of keys.
-  pub(super) splits: Vec<String>,
-  pub(super) child_identifiers: Vec<String>,
-  pub(super) max_value: StringComparisonValue<String>,
-  pub(super) next_node_identifier: Option<String>,
-  pub(super) max_key_capacity: usize,
+  pub(super) splits: Vec<K>,
+  pub(super) child_identifiers: Vec<NodeIdentifier>,
+  pub(super) max_value: ComparisonValue<K>,
+  pub(super) next_node_identifier: Option<NodeIdentifier>,
+  // The most bytes the node may take up once serialized.
+  pub(super) max_size: usize,
+  // The LSN of the last logged change to this node.
+  pub(super) lsn: Lsn,
 }
 
-impl InteriorNode {
-  pub fn identifier(&self) -> &str {
-    &self.identifier
+impl<K: Key> InteriorNode<K> {
+  pub fn identifier(&self) -> NodeIdentifier {
+    self.identifier
   }
 
-  pub fn max_value(&self) -> StringComparisonValue<&str> {
+  pub fn lsn(&self) -> Lsn {
+    self.lsn
+  }
+
+  pub fn set_lsn(&mut self, lsn: Lsn) {
+    self.lsn = lsn;
+  }
+
+  pub fn max_value(&self) -> ComparisonValue<&K> {
     self.max_value.as_ref()
   }
 
-  pub fn next_node_identifier(&self) -> Option<&String> {
-    self.next_node_identifier.as_ref()
+  pub fn next_node_identifier(&self) -> Option<NodeIdentifier> {
+    self.next_node_identifier
   }
 
-  pub fn splits(&self) -> &Vec<String> {
+  pub fn splits(&self) -> &Vec<K> {
     &self.splits
   }
 
   // It is sometimes useful to convert InteriorNode to a Node for
   // purposes of storage in places where you wouldn't otherwise know
   // what kind of Node you need.
-  pub fn upcast(self) -> Node {
+  pub fn upcast<V: Value>(self) -> Node<K, V> {
     Node::InteriorNode(self)
   }
 }
@@ -0,0 +1,35 @@
+use super::InteriorNode;
+use error::Result;
+use key::Key;
+use node::util::{
+  read_comparison_value, read_identifiers, read_keys,
+  write_comparison_value, write_identifiers, write_keys,
+};
+use storage::{ByteReader, ByteWriter};
+
+// These methods lay an InteriorNode out as bytes for the NodeStore.
+impl<K: Key> InteriorNode<K> {
+  pub(in node) fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_u64(self.identifier);
+    writer.write_u64(self.lsn);
+    write_comparison_value(writer, self.max_value());
+    writer.write_optional_u64(self.next_node_identifier());
+    writer.write_u32(self.max_size as u32);
+    write_keys(writer, &self.splits);
+    write_identifiers(writer, &self.child_identifiers);
+  }
+
+  pub(in node) fn deserialize(
+    reader: &mut ByteReader,
+  ) -> Result<InteriorNode<K>> {
+    Ok(InteriorNode {
+      identifier: reader.read_u64()?,
+      lsn: reader.read_u64()?,
+      max_value: read_comparison_value(reader)?,
+      next_node_identifier: reader.read_optional_u64()?,
+      max_size: reader.read_u32()? as usize,
+      splits: read_keys(reader)?,
+      child_identifiers: read_identifiers(reader)?,
+    })
+  }
+}
@@ -1,31 +1,49 @@
 use super::InteriorNode;
-use node::Node;
+use key::Key;
+use node::ComparisonValue;
+use node::util::{
+  is_deficient, max_entry_size, node_overhead, shared_prefix_len,
+  written_key_size,
+};
+use std::mem;
+use storage::NodeIdentifier;
 
-// These methods all pertain to the size of an InteriorNode (number of
-// keys/children).
-impl InteriorNode {
+// Each split key comes with the identifier of the child to its right.
+// The leftmost child's identifier is part of the node's overhead.
+const CHILD_IDENTIFIER_SIZE: usize = mem::size_of::<NodeIdentifier>();
+
+// These methods all pertain to the size of an InteriorNode: how many
+// bytes it takes up once serialized. An entry is a split key and the
+// child to its right.
+impl<K: Key> InteriorNode<K> {
+  // Assumes we delete the biggest entry, since we don't know which.
   pub fn can_delete_without_becoming_deficient(&self) -> bool {
-    if self.splits.is_empty() {
-      // Special case because else subtraction by one is dangerous!
-      return false;
-    }
-
-    !Node::_is_deficient(
-      self.num_split_keys() - 1,
-      self.max_key_capacity,
+    let biggest_entry_size = match self.entry_sizes().max() {
+      // Special case because else there is nothing to delete!
+      None => return false,
+      Some(entry_size) => entry_size,
+    };
+
+    !is_deficient(
+      self.encoded_size() - biggest_entry_size,
+      self.max_size,
     )
   }
 
+  // Any split key we would accept must fit, since we don't know which.
+  // A new split key can shorten the prefix the split keys share, so we
+  // go by the size of the entries before prefix compression.
   pub fn can_grow_without_split(&self) -> bool {
-    self.num_split_keys() < self.max_key_capacity
+    self.uncompressed_size() + max_entry_size(self.max_size)
+      <= self.max_size
   }
 
   pub fn is_deficient(&self) -> bool {
-    Node::_is_deficient(self.num_split_keys(), self.max_key_capacity)
+    is_deficient(self.encoded_size(), self.max_size)
   }
 
   pub fn is_overfull(&self) -> bool {
-    self.num_split_keys() > self.max_key_capacity
+    self.encoded_size() > self.max_size
   }
 
   pub fn num_children(&self) -> usize {
@@ -35,4 +53,50 @@ impl InteriorNode {
   pub fn num_split_keys(&self) -> usize {
     self.splits.len()
   }
+
+  // This is at least as many bytes as `serialize` writes.
+  pub fn encoded_size(&self) -> usize {
+    InteriorNode::encoded_size_of(&self.splits, self.max_value())
+  }
+
+  // How big an interior node with just these split keys would be.
+  pub(super) fn encoded_size_of(
+    splits: &[K],
+    max_value: ComparisonValue<&K>,
+  ) -> usize {
+    let prefix_len = shared_prefix_len(splits);
+    node_overhead(max_value)
+      + CHILD_IDENTIFIER_SIZE
+      + splits
+        .iter()
+        .enumerate()
+        .map(|(idx, split)| {
+          written_key_size(split, idx, prefix_len)
+            + CHILD_IDENTIFIER_SIZE
+        })
+        .sum::<usize>()
+  }
+
+  fn uncompressed_size(&self) -> usize {
+    node_overhead(self.max_value())
+      + CHILD_IDENTIFIER_SIZE
+      + self
+        .splits
+        .iter()
+        .map(InteriorNode::entry_size)
+        .sum::<usize>()
+  }
+
+  // This is the most an entry can take up, before its split key shares
+  // a prefix with the others.
+  pub fn entry_size(split: &K) -> usize {
+    split.encoded_size() + CHILD_IDENTIFIER_SIZE
+  }
+
+  pub(super) fn entry_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+    let prefix_len = shared_prefix_len(&self.splits);
+    self.splits.iter().enumerate().map(move |(idx, split)| {
+      written_key_size(split, idx, prefix_len) + CHILD_IDENTIFIER_SIZE
+    })
+  }
 }
@@ -1,32 +1,41 @@
 use super::InteriorNode;
 use btree::BTree;
-use node::{
-  util::search_sorted_strings_for_str, SplitInfo, StringComparisonValue,
+use error::{Error, Result};
+use key::{Key, Value};
+use node::util::{
+  nearest_fitting_split_idx, search_sorted_keys, split_entry_capacity,
 };
+use node::{ComparisonValue, SplitInfo};
 
-impl InteriorNode {
+impl<K: Key> InteriorNode<K> {
   // This method is used to "handle" the split of a child. Normally that
   // means simply adding a new split key and child to this node. But
   // sometimes we must recursively split the parent node!
-  pub fn handle_split(
+  pub fn handle_split<V: Value>(
     &mut self,
-    btree: &BTree,
-    child_split_info: SplitInfo,
-  ) -> Option<SplitInfo> {
-    if !self.max_value.is_ge_to(&child_split_info.new_median) {
+    btree: &BTree<K, V>,
+    child_split_info: SplitInfo<K>,
+  ) -> Result<Option<SplitInfo<K>>> {
+    if !self.max_value().is_ge_to(&child_split_info.new_median) {
       // This can happen if we split a child, move back to the parent,
       // but the parent has itself split, and the new child should be
       // placed in a node to the right of the parent.
       //
       // The caller should be careful of that situation.
-      panic!("Parent must have split in the meantime...")
+      return Err(Error::InvariantViolation(
+        "Parent must have split in the meantime...",
+      ));
     }
 
-    let split_idx = match search_sorted_strings_for_str(
+    let split_idx = match search_sorted_keys(
       &self.splits,
       &child_split_info.new_median,
     ) {
-      Ok(_) => panic!("median should never be re-inserted"),
+      Ok(_) => {
+        return Err(Error::InvariantViolation(
+          "median should never be re-inserted",
+        ));
+      }
       Err(split_idx) => split_idx,
     };
 
@@ -39,20 +48,52 @@ impl InteriorNode {
 
     if !self.is_overfull() {
       // No further split occurred.
-      None
+      Ok(None)
     } else {
       // Welp. We have to recursively keep splitting.
-      Some(self.split(btree))
+      let is_append = split_idx == self.splits.len() - 1
+        && self.next_node_identifier.is_none();
+      Ok(Some(self.split(btree, is_append)?))
     }
   }
 
-  fn split(&mut self, btree: &BTree) -> SplitInfo {
-    let new_median_idx = self.max_key_capacity / 2;
+  fn split<V: Value>(
+    &mut self,
+    btree: &BTree<K, V>,
+    is_append: bool,
+  ) -> Result<SplitInfo<K>> {
+    // The split after the left ones becomes the new median. We would
+    // rather leave each side a split key too. But big split keys can
+    // make a node overfull with fewer than three, and then a side is
+    // left with just one child. (As when a child is removed; see
+    // `has_only_child`.)
+    if self.splits.is_empty() {
+      return Err(Error::InvariantViolation(
+        "an overfull interior node has a split key",
+      ));
+    }
+    let min_idx = if self.splits.len() >= 3 { 1 } else { 0 };
+    let max_idx = self.splits.len() - 1 - min_idx;
+    let entry_sizes = self.entry_sizes().collect::<Vec<_>>();
+    let entry_capacity =
+      split_entry_capacity(self.max_size, &self.splits);
+    let new_median_idx = btree
+      .split_policy()
+      .split_idx(&entry_sizes, entry_capacity, is_append)
+      .clamp(min_idx, max_idx);
+    let new_median_idx = nearest_fitting_split_idx(
+      new_median_idx,
+      min_idx,
+      max_idx,
+      |idx| self.split_fits(idx),
+    );
 
     // Split the split values into left and right. The last of the left
     // splits is in truth going to be the new median.
     let right_splits = self.splits.split_off(new_median_idx + 1);
-    let new_median = self.splits.pop().unwrap();
+    let new_median = self.splits.pop().ok_or(Error::InvariantViolation(
+      "the left splits include the new median",
+    ))?;
     // When taking lef_child_identifiers, remember that we need one more
     // child_identifier than split key.
     let right_child_identifiers =
@@ -61,11 +102,10 @@ impl InteriorNode {
     // Extract values needed to move to right sibbling.
     let right_max_value = std::mem::replace(
       &mut self.max_value,
-      StringComparisonValue::DefiniteValue(new_median.clone()),
+      ComparisonValue::DefiniteValue(new_median.clone()),
     );
     // Note that None is temporary here.
-    let right_next_node_identifier =
-      std::mem::replace(&mut self.next_node_identifier, None);
+    let right_next_node_identifier = self.next_node_identifier.take();
 
     // Create the right sibbling.
     let new_right_identifier = InteriorNode::store(
@@ -77,12 +117,27 @@ impl InteriorNode {
     );
 
     // Update ourself, connecting us to the newly budded sibbling.
-    self.next_node_identifier = Some(new_right_identifier.clone());
+    self.next_node_identifier = Some(new_right_identifier);
 
     // Return opaque type to user so they can propagate split upward.
-    SplitInfo {
+    Ok(SplitInfo {
       new_median,
       new_right_identifier,
-    }
+    })
+  }
+
+  // Whether both sides would fit if the split key at `new_median_idx`
+  // became the new median.
+  fn split_fits(&self, new_median_idx: usize) -> bool {
+    let new_median = &self.splits[new_median_idx];
+    let left_size = InteriorNode::encoded_size_of(
+      &self.splits[..new_median_idx],
+      ComparisonValue::DefiniteValue(new_median),
+    );
+    let right_size = InteriorNode::encoded_size_of(
+      &self.splits[(new_median_idx + 1)..],
+      self.max_value(),
+    );
+    left_size <= self.max_size && right_size <= self.max_size
   }
 }
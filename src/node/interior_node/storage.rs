@@ -1,44 +1,66 @@
 use super::InteriorNode;
 use btree::BTree;
-use node::{SplitInfo, StringComparisonValue};
+use key::{Key, Value};
+use node::{ComparisonValue, SplitInfo};
+use storage::NodeIdentifier;
 
 // These methods all pertain to storing an InteriorNode.
-impl InteriorNode {
+impl<K: Key> InteriorNode<K> {
   // This method is used internally when splitting an InteriorNode.
-  pub(super) fn store(
-    btree: &BTree,
-    splits: Vec<String>,
-    child_identifiers: Vec<String>,
-    max_value: StringComparisonValue<String>,
-    next_node_identifier: Option<String>,
-  ) -> String {
+  pub(super) fn store<V: Value>(
+    btree: &BTree<K, V>,
+    splits: Vec<K>,
+    child_identifiers: Vec<NodeIdentifier>,
+    max_value: ComparisonValue<K>,
+    next_node_identifier: Option<NodeIdentifier>,
+  ) -> NodeIdentifier {
     let identifier = btree.get_new_identifier();
+    InteriorNode::store_as(
+      btree,
+      identifier,
+      splits,
+      child_identifiers,
+      max_value,
+      next_node_identifier,
+    );
 
+    identifier
+  }
+
+  // This method is used by `BTree::bulk_load`, which needs to know a
+  // node's identifier before it can store the node to its left.
+  pub fn store_as<V: Value>(
+    btree: &BTree<K, V>,
+    identifier: NodeIdentifier,
+    splits: Vec<K>,
+    child_identifiers: Vec<NodeIdentifier>,
+    max_value: ComparisonValue<K>,
+    next_node_identifier: Option<NodeIdentifier>,
+  ) {
     let node = InteriorNode {
-      identifier: identifier.clone(),
+      identifier,
       splits,
       child_identifiers,
       max_value,
       next_node_identifier,
-      max_key_capacity: btree.max_key_capacity(),
+      max_size: btree.max_node_size(),
+      lsn: 0,
     };
 
     btree.store_node(node.upcast());
-
-    identifier
   }
 
   // This method is used *externally* when the root node is split.
-  pub fn store_new_root(
-    btree: &BTree,
-    old_root_identifier: String,
-    split_info: SplitInfo,
-  ) -> String {
+  pub fn store_new_root<V: Value>(
+    btree: &BTree<K, V>,
+    old_root_identifier: NodeIdentifier,
+    split_info: SplitInfo<K>,
+  ) -> NodeIdentifier {
     InteriorNode::store(
       btree,
       vec![split_info.new_median],
       vec![old_root_identifier, split_info.new_right_identifier],
-      StringComparisonValue::Infinity,
+      ComparisonValue::Infinity,
       None,
     )
   }
@@ -1,28 +1,56 @@
 use super::InteriorNode;
-use node::{util::search_sorted_strings_for_str, TraversalDirection};
+use error::{Error, Result};
+use key::Key;
+use node::{
+  util::search_sorted_keys, ComparisonValue, TraversalDirection,
+};
+use storage::NodeIdentifier;
 
 // These methods are all ways to move from an InteriorNode to a child.
-impl InteriorNode {
-  pub fn child_identifier_by_idx(&self, idx: usize) -> &str {
-    &self.child_identifiers[idx]
+impl<K: Key> InteriorNode<K> {
+  pub fn child_identifier_by_idx(&self, idx: usize) -> NodeIdentifier {
+    self.child_identifiers[idx]
   }
 
-  pub fn child_identifier_by_key(&self, key: &str) -> &str {
+  pub fn child_identifier_by_key(&self, key: &K) -> NodeIdentifier {
     let idx = self.child_idx_by_key(key);
-    &self.child_identifiers[idx]
+    self.child_identifiers[idx]
   }
 
-  pub fn child_idx_by_key(&self, key: &str) -> usize {
-    match search_sorted_strings_for_str(&self.splits, key) {
+  pub fn child_idx_by_key(&self, key: &K) -> usize {
+    match search_sorted_keys(&self.splits, key) {
       Ok(idx) => idx,
       Err(idx) => idx,
     }
   }
 
+  // Like child_idx_by_key, except that the target may be infinite. This
+  // lets us find the very end of the tree.
+  pub fn child_idx_by_value(
+    &self,
+    target: ComparisonValue<&K>,
+  ) -> usize {
+    match target {
+      ComparisonValue::NegativeInfinity => 0,
+      ComparisonValue::DefiniteValue(key) => self.child_idx_by_key(key),
+      ComparisonValue::Infinity => self.num_split_keys(),
+    }
+  }
+
+  // Every key in the child at `idx` is greater than this split. The
+  // first child has no split below it.
+  pub fn split_below_child_idx(&self, idx: usize) -> Option<&K> {
+    if 0 < idx {
+      Some(&self.splits[idx - 1])
+    } else {
+      None
+    }
+  }
+
   pub fn sibbling_identifiers_for_idx(
     &self,
     idx: usize,
-  ) -> (Option<&str>, Option<&str>) {
+  ) -> (Option<NodeIdentifier>, Option<NodeIdentifier>) {
     let left_sibbling_identifier = if 0 < idx {
       Some(self.child_identifier_by_idx(idx - 1))
     } else {
@@ -39,19 +67,22 @@ impl InteriorNode {
     (left_sibbling_identifier, right_sibbling_identifier)
   }
 
-  pub fn traverse_toward(&self, key: &str) -> TraversalDirection<&str> {
-    if !self.max_value.is_ge_to(key) {
-      let next_node_identifier = self
-        .next_node_identifier
-        .as_ref()
-        .expect("node with definite max value must have next");
-      TraversalDirection::MoveRight {
+  pub fn traverse_toward(
+    &self,
+    key: &K,
+  ) -> Result<TraversalDirection> {
+    if !self.max_value().is_ge_to(key) {
+      let next_node_identifier =
+        self.next_node_identifier.ok_or(Error::InvariantViolation(
+          "node with definite max value must have next",
+        ))?;
+      Ok(TraversalDirection::MoveRight {
         next_node_identifier,
-      }
+      })
     } else {
-      TraversalDirection::MoveDown {
+      Ok(TraversalDirection::MoveDown {
         child_node_identifier: self.child_identifier_by_key(key),
-      }
+      })
     }
   }
 }
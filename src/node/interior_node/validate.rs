@@ -1,18 +1,33 @@
 use super::InteriorNode;
+use error::{Error, Result};
+use key::{Key, Value};
 use locking::LockSet;
-use node::{Node, StringComparisonValue};
+use node::{ComparisonValue, Node};
 
-impl InteriorNode {
-  pub fn validate(
+impl<K: Key> InteriorNode<K> {
+  pub fn validate<V: Value>(
     &self,
-    lock_set: &mut LockSet,
-    min_value: StringComparisonValue<&str>,
-    max_value: StringComparisonValue<&str>,
-  ) {
+    lock_set: &mut LockSet<K, V>,
+    min_value: ComparisonValue<&K>,
+    max_value: ComparisonValue<&K>,
+  ) -> Result<()> {
     // max_value passed in from parent should equal the max_value of
     // the node.
     if max_value != self.max_value() {
-      panic!("max_value should equal max_value passed in from parent");
+      return Err(Error::InvalidTree(format!(
+        "{}: max_value should equal max_value passed in from parent",
+        self.identifier(),
+      )));
+    }
+
+    // The node must fit in the bytes it is allowed.
+    if self.is_overfull() {
+      return Err(Error::InvalidTree(format!(
+        "{}: Node is overfull! {} bytes is more than {}",
+        self.identifier(),
+        self.encoded_size(),
+        self.max_size,
+      )));
     }
 
     // All keys must be greater than the low limit.
@@ -20,16 +35,22 @@ impl InteriorNode {
     for (idx, split_value) in self.splits().iter().enumerate() {
       // Keys must be in ascending order (with no duplicates).
       if prev_split_value.is_ge_to(split_value) {
-        println!("{}", split_value);
-        println!("{:?}", prev_split_value);
-        panic!("Keys are out of order!");
+        return Err(Error::InvalidTree(format!(
+          "{}: Keys are out of order! {:?} after {:?}",
+          self.identifier(),
+          split_value,
+          prev_split_value,
+        )));
       }
 
       // All values must be less than or equal to the max_value.
       if !max_value.is_ge_to(split_value) {
-        println!("{}", split_value);
-        println!("{:?}", max_value);
-        panic!("max_value disobeyed");
+        return Err(Error::InvalidTree(format!(
+          "{}: max_value disobeyed! {:?} above {:?}",
+          self.identifier(),
+          split_value,
+          max_value,
+        )));
       }
 
       // Recursively check each child node.
@@ -38,11 +59,10 @@ impl InteriorNode {
         lock_set,
         child_identifier,
         prev_split_value,
-        StringComparisonValue::DefiniteValue(split_value),
-      );
+        ComparisonValue::DefiniteValue(split_value),
+      )?;
 
-      prev_split_value =
-        StringComparisonValue::DefiniteValue(split_value);
+      prev_split_value = ComparisonValue::DefiniteValue(split_value);
     }
 
     // There's one last child we didn't check!
@@ -53,6 +73,6 @@ impl InteriorNode {
       child_identifier,
       prev_split_value,
       max_value,
-    );
+    )
   }
 }
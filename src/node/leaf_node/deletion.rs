@@ -1,17 +1,56 @@
 use super::LeafNode;
-use node::util::search_sorted_strings_for_str;
-use node::DeletionResult;
+use error::{Error, Result};
+use key::{Key, Value};
+use node::util::search_sorted_keys;
+use node::{ComparisonValue, DeletionResult};
 
-impl LeafNode {
+impl<K: Key, V: Value> LeafNode<K, V> {
   // Deletion will not perform any rebalancing; that function must be
   // handled by the caller.
-  pub fn delete(&mut self, key_to_delete: &str) -> DeletionResult {
-    match search_sorted_strings_for_str(&self.keys, key_to_delete) {
+  pub fn delete(&mut self, key_to_delete: &K) -> DeletionResult<V> {
+    match search_sorted_keys(&self.keys, key_to_delete) {
       Err(_) => DeletionResult::KeyWasNotPresent,
       Ok(idx) => {
         self.keys.remove(idx);
-        DeletionResult::DidDelete
+        DeletionResult::DidDelete(self.values.remove(idx))
       }
     }
   }
+
+  // Once an empty leaf has been removed from its parent, its right
+  // sibbling is responsible for its keys. A max_value of
+  // NegativeInfinity means that anyone still arriving here will always
+  // be sent right.
+  pub fn retire(&mut self) -> Result<()> {
+    if !self.keys.is_empty() {
+      return Err(Error::InvariantViolation(
+        "only an empty leaf may be retired",
+      ));
+    }
+
+    self.max_value = ComparisonValue::NegativeInfinity;
+    Ok(())
+  }
+
+  pub fn is_retired(&self) -> bool {
+    matches!(self.max_value, ComparisonValue::NegativeInfinity)
+  }
+
+  // Once nobody can arrive at a retired leaf, its left sibbling can
+  // link straight past it. Then the retired leaf can be freed.
+  pub fn unlink_retired_sibbling(
+    &mut self,
+    sibbling: &LeafNode<K, V>,
+  ) -> Result<()> {
+    if self.next_node_identifier != Some(sibbling.identifier)
+      || !sibbling.is_retired()
+    {
+      return Err(Error::InvariantViolation(
+        "only a retired right sibbling may be unlinked",
+      ));
+    }
+
+    self.next_node_identifier = sibbling.next_node_identifier;
+    Ok(())
+  }
 }
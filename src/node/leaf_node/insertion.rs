@@ -1,68 +1,242 @@
 use super::LeafNode;
 use btree::BTree;
+use error::{Error, Result};
+use key::{Key, Value};
 use node::{
-  util::search_sorted_strings_for_str, InsertionResult, SplitInfo,
-  StringComparisonValue,
+  util::{
+    nearest_fitting_split_idx, search_sorted_keys, split_entry_capacity,
+  },
+  ComparisonValue, InsertionResult, SplitInfo,
 };
 
 // These are methods for inserting a value into the LeafNode, and for
 // splitting a LeafNode when it becomes full.
-impl LeafNode {
+impl<K: Key, V: Value> LeafNode<K, V> {
   pub fn insert_key(
     &mut self,
-    btree: &BTree,
-    key_to_insert: String,
-  ) -> InsertionResult {
-    // Is this a no-op? Key already inserted?
-    let insertion_idx =
-      match search_sorted_strings_for_str(&self.keys, &key_to_insert) {
-        Ok(_) => return InsertionResult::KeyWasAlreadyInserted,
-        Err(idx) => idx,
+    btree: &BTree<K, V>,
+    key_to_insert: K,
+    value_to_insert: V,
+  ) -> Result<InsertionResult<K, V>> {
+    // Is the key already inserted? Then we just replace the value.
+    let (insertion_idx, previous_value) =
+      match search_sorted_keys(&self.keys, &key_to_insert) {
+        Ok(idx) => {
+          let previous_value =
+            std::mem::replace(&mut self.values[idx], value_to_insert);
+          (idx, Some(previous_value))
+        }
+        Err(idx) => {
+          self.keys.insert(idx, key_to_insert);
+          self.values.insert(idx, value_to_insert);
+          (idx, None)
+        }
       };
 
-    self.keys.insert(insertion_idx, key_to_insert);
-
     if !self.is_overfull() {
-      InsertionResult::DidInsert
-    } else {
-      // Welp, we have to split after all.
-      InsertionResult::DidInsertWithSplit(self.split(btree))
+      return Ok(match previous_value {
+        None => InsertionResult::DidInsert,
+        Some(previous_value) => {
+          InsertionResult::KeyWasUpdated(previous_value)
+        }
+      });
     }
+
+    // Welp, we have to split after all.
+    let is_append = previous_value.is_none()
+      && insertion_idx == self.keys.len() - 1
+      && self.next_node_identifier.is_none();
+    let split_info = self.split(btree, is_append)?;
+    Ok(match previous_value {
+      None => InsertionResult::DidInsertWithSplit(split_info),
+      Some(previous_value) => InsertionResult::KeyWasUpdatedWithSplit(
+        previous_value,
+        split_info,
+      ),
+    })
   }
 
-  fn split(&mut self, btree: &BTree) -> SplitInfo {
-    // We divide the keys into left/right portions.
-    let right_keys = self.keys.split_off(self.max_key_capacity / 2);
+  // Recovery redoes a logged insert with this. It never splits: if the
+  // insert did cause a split, that was logged (and is redone)
+  // separately.
+  pub fn redo_insert(&mut self, key: K, value: V) {
+    match search_sorted_keys(&self.keys, &key) {
+      Ok(idx) => self.values[idx] = value,
+      Err(idx) => {
+        self.keys.insert(idx, key);
+        self.values.insert(idx, value);
+      }
+    }
+  }
 
-    // We choose a new median.
-    let new_median = self.keys
-      .last()
-      .expect("Just split node must have keys")
-      .clone();
+  fn split(
+    &mut self,
+    btree: &BTree<K, V>,
+    is_append: bool,
+  ) -> Result<SplitInfo<K>> {
+    // We divide the keys (and their values) into left/right portions.
+    // The new median separates the last key on the left from the first
+    // on the right, so each side needs at least one key.
+    if self.keys.len() < 2 {
+      return Err(Error::InvariantViolation(
+        "an overfull leaf node has at least two keys",
+      ));
+    }
+    let entry_sizes = self.entry_sizes().collect::<Vec<_>>();
+    let entry_capacity =
+      split_entry_capacity(self.max_size, &self.keys);
+    let split_idx = btree
+      .split_policy()
+      .split_idx(&entry_sizes, entry_capacity, is_append)
+      .clamp(1, self.keys.len() - 1);
+    let split_idx = nearest_fitting_split_idx(
+      split_idx,
+      1,
+      self.keys.len() - 1,
+      |idx| self.split_fits(idx),
+    );
+    let right_keys = self.keys.split_off(split_idx);
+    let right_values = self.values.split_off(split_idx);
+
+    // We choose a new median: the shortest key that separates the two
+    // halves.
+    let new_median = K::separator(
+      self.keys.last().ok_or(Error::InvariantViolation(
+        "Just split node must have keys",
+      ))?,
+      &right_keys[0],
+    );
 
     // Extract values needed to move to right sibbling.
     let right_max_value = std::mem::replace(
       &mut self.max_value,
-      StringComparisonValue::DefiniteValue(new_median.clone()),
+      ComparisonValue::DefiniteValue(new_median.clone()),
     );
     // Note that None is temporary here.
-    let right_next_node_identifier =
-      std::mem::replace(&mut self.next_node_identifier, None);
+    let right_next_node_identifier = self.next_node_identifier.take();
 
     // Create and store new right sibbilng leaf node.
     let new_right_identifier = LeafNode::store(
       btree,
       right_keys,
+      right_values,
       right_max_value,
       right_next_node_identifier,
     );
-    self.next_node_identifier = Some(new_right_identifier.clone());
+    self.next_node_identifier = Some(new_right_identifier);
 
     // Let the caller know we split so that they can add the new
     // sibbling as a child of the previous level.
-    SplitInfo {
+    Ok(SplitInfo {
       new_right_identifier,
       new_median,
+    })
+  }
+
+  // Whether both sides would fit if we split before `split_idx`.
+  fn split_fits(&self, split_idx: usize) -> bool {
+    let (left_keys, right_keys) = self.keys.split_at(split_idx);
+    let (left_values, right_values) = self.values.split_at(split_idx);
+    let new_median =
+      K::separator(&left_keys[left_keys.len() - 1], &right_keys[0]);
+
+    let left_size = LeafNode::encoded_size_of(
+      left_keys,
+      left_values,
+      ComparisonValue::DefiniteValue(&new_median),
+    );
+    let right_size = LeafNode::encoded_size_of(
+      right_keys,
+      right_values,
+      self.max_value(),
+    );
+    left_size <= self.max_size && right_size <= self.max_size
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use constants::MIN_NODE_SIZE;
+  use std::sync::Arc;
+  use test_util::key;
+  use transaction::TransactionMode;
+
+  // Keys that share a long prefix take up little room in a node. A key
+  // that breaks the prefix makes them all take up their full size, so
+  // the split must leave that key on its own side.
+  #[test]
+  fn split_leaves_no_side_overfull() {
+    let prefix = "P".repeat(45);
+    for &num_keys in &[200, 3000] {
+      // What a 512-byte page leaves for a node.
+      let btree = Arc::new(BTree::new(507).unwrap());
+
+      let mut txn = btree.begin(TransactionMode::ReadWrite);
+      for idx in 0..num_keys {
+        let key = format!("{}{:04}", prefix, idx);
+        txn.insert(&key, String::from("v")).unwrap();
+      }
+      txn.insert(&String::from("a"), String::from("v")).unwrap();
+      txn.insert(&"z".repeat(40), String::from("v")).unwrap();
+      txn.commit().unwrap();
+
+      btree.validate().unwrap();
+    }
+  }
+
+  // Keys that share a long prefix only within each group have long
+  // separators that don't compress. Two of them can make an interior
+  // node overfull.
+  #[test]
+  fn interior_nodes_split_with_few_big_split_keys() {
+    let btree = Arc::new(BTree::new(MIN_NODE_SIZE).unwrap());
+    let filler = "x".repeat(btree.max_entry_size() - 20);
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..400 {
+      let idx = (idx * 7) % 400;
+      let key = format!("{:02}{}{:03}", idx % 20, filler, idx);
+      txn.insert(&key, String::from("v")).unwrap();
+    }
+    txn.commit().unwrap();
+
+    btree.validate().unwrap();
+  }
+
+  // Inserting a key that is already there replaces its value, and
+  // hands back the one it had. That holds when the bigger value makes
+  // the leaf split, too.
+  #[test]
+  fn insert_returns_the_previous_value() {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..200 {
+      assert_eq!(txn.insert(&key(idx), String::from("v")), Ok(None));
+    }
+    assert_eq!(
+      txn.insert(&key(0), String::from("w")),
+      Ok(Some(String::from("v")))
+    );
+    txn.commit().unwrap();
+
+    let big_value = "x".repeat(btree.max_entry_size() - 20);
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 1..200 {
+      assert_eq!(
+        txn.insert(&key(idx), big_value.clone()),
+        Ok(Some(String::from("v")))
+      );
+    }
+    assert_eq!(txn.delete(&key(0)), Ok(Some(String::from("w"))));
+    assert_eq!(txn.insert(&key(0), String::from("v")), Ok(None));
+    txn.commit().unwrap();
+    btree.validate().unwrap();
+
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(txn.get(&key(0)), Ok(Some(String::from("v"))));
+    for idx in 1..200 {
+      assert_eq!(txn.get(&key(idx)), Ok(Some(big_value.clone())));
     }
   }
 }
@@ -1,6 +1,8 @@
 mod deletion;
 mod insertion;
 mod node;
+mod scanning;
+mod serialization;
 mod sizing;
 mod storage;
 mod validate;
@@ -1,51 +1,82 @@
-use node::util::search_sorted_strings_for_str;
-use node::{Node, StringComparisonValue, TraversalDirection};
+use error::{Error, Result};
+use key::{Key, Value};
+use node::util::search_sorted_keys;
+use node::{ComparisonValue, Node, TraversalDirection};
+use storage::NodeIdentifier;
+use wal::Lsn;
 
 #[derive(Debug)]
-pub struct LeafNode {
-  pub(super) identifier: String,
-  pub(super) keys: Vec<String>,
-  pub(super) max_value: StringComparisonValue<String>,
-  pub(super) next_node_identifier: Option<String>,
-  pub(super) max_key_capacity: usize,
+pub struct LeafNode<K: Key, V: Value> {
+  pub(super) identifier: NodeIdentifier,
+  // The value for `keys[idx]` is stored at `values[idx]`.
+  pub(super) keys: Vec<K>,
+  pub(super) values: Vec<V>,
+  pub(super) max_value: ComparisonValue<K>,
+  pub(super) next_node_identifier: Option<NodeIdentifier>,
+  // The most bytes the node may take up once serialized.
+  pub(super) max_size: usize,
+  // The LSN of the last logged change to this node.
+  pub(super) lsn: Lsn,
 }
 
-impl LeafNode {
-  pub fn contains_key(&self, key: &str) -> bool {
-    search_sorted_strings_for_str(&self.keys, key).is_ok()
+impl<K: Key, V: Value> LeafNode<K, V> {
+  pub fn contains_key(&self, key: &K) -> bool {
+    search_sorted_keys(&self.keys, key).is_ok()
   }
 
-  pub fn identifier(&self) -> &str {
-    &self.identifier
+  pub fn get(&self, key: &K) -> Option<&V> {
+    match search_sorted_keys(&self.keys, key) {
+      Err(_) => None,
+      Ok(idx) => Some(&self.values[idx]),
+    }
+  }
+
+  pub fn identifier(&self) -> NodeIdentifier {
+    self.identifier
+  }
+
+  pub fn lsn(&self) -> Lsn {
+    self.lsn
   }
 
-  pub fn keys(&self) -> &Vec<String> {
+  pub fn set_lsn(&mut self, lsn: Lsn) {
+    self.lsn = lsn;
+  }
+
+  pub fn keys(&self) -> &Vec<K> {
     &self.keys
   }
 
-  pub fn max_value(&self) -> StringComparisonValue<&str> {
+  pub fn values(&self) -> &Vec<V> {
+    &self.values
+  }
+
+  pub fn max_value(&self) -> ComparisonValue<&K> {
     self.max_value.as_ref()
   }
 
-  pub fn next_node_identifier(&self) -> Option<&String> {
-    self.next_node_identifier.as_ref()
+  pub fn next_node_identifier(&self) -> Option<NodeIdentifier> {
+    self.next_node_identifier
   }
 
-  pub fn traverse_toward(&self, key: &str) -> TraversalDirection<&str> {
-    if self.max_value.is_ge_to(key) {
-      TraversalDirection::Arrived
+  pub fn traverse_toward(
+    &self,
+    key: &K,
+  ) -> Result<TraversalDirection> {
+    if self.max_value().is_ge_to(key) {
+      Ok(TraversalDirection::Arrived)
     } else {
-      let next_node_identifier = self
-        .next_node_identifier
-        .as_ref()
-        .expect("node with definite max value must have next");
-      TraversalDirection::MoveRight {
+      let next_node_identifier =
+        self.next_node_identifier.ok_or(Error::InvariantViolation(
+          "node with definite max value must have next",
+        ))?;
+      Ok(TraversalDirection::MoveRight {
         next_node_identifier,
-      }
+      })
     }
   }
 
-  pub fn upcast(self) -> Node {
+  pub fn upcast(self) -> Node<K, V> {
     Node::LeafNode(self)
   }
 }
@@ -0,0 +1,48 @@
+use super::LeafNode;
+use key::{Key, Value};
+use node::util::search_sorted_keys;
+use std::ops::Bound;
+
+impl<K: Key, V: Value> LeafNode<K, V> {
+  // Finds the index of the first key that lies after the start bound of
+  // a range scan.
+  pub fn start_idx(&self, start_bound: Bound<&K>) -> usize {
+    match start_bound {
+      Bound::Unbounded => 0,
+
+      Bound::Included(start_key) => {
+        match search_sorted_keys(&self.keys, start_key) {
+          Ok(idx) | Err(idx) => idx,
+        }
+      }
+
+      Bound::Excluded(start_key) => {
+        match search_sorted_keys(&self.keys, start_key) {
+          Ok(idx) => idx + 1,
+          Err(idx) => idx,
+        }
+      }
+    }
+  }
+
+  // Finds the index just past the last key that lies before the end
+  // bound of a range scan. Used when scanning backward.
+  pub fn end_idx(&self, end_bound: Bound<&K>) -> usize {
+    match end_bound {
+      Bound::Unbounded => self.num_keys(),
+
+      Bound::Included(end_key) => {
+        match search_sorted_keys(&self.keys, end_key) {
+          Ok(idx) => idx + 1,
+          Err(idx) => idx,
+        }
+      }
+
+      Bound::Excluded(end_key) => {
+        match search_sorted_keys(&self.keys, end_key) {
+          Ok(idx) | Err(idx) => idx,
+        }
+      }
+    }
+  }
+}
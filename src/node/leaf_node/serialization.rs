@@ -0,0 +1,35 @@
+use super::LeafNode;
+use error::Result;
+use key::{Key, Value};
+use node::util::{
+  read_comparison_value, read_keys, read_values,
+  write_comparison_value, write_keys, write_values,
+};
+use storage::{ByteReader, ByteWriter};
+
+// These methods lay a LeafNode out as bytes for the NodeStore.
+impl<K: Key, V: Value> LeafNode<K, V> {
+  pub(in node) fn serialize(&self, writer: &mut ByteWriter) {
+    writer.write_u64(self.identifier);
+    writer.write_u64(self.lsn);
+    write_comparison_value(writer, self.max_value());
+    writer.write_optional_u64(self.next_node_identifier());
+    writer.write_u32(self.max_size as u32);
+    write_keys(writer, &self.keys);
+    write_values(writer, &self.values);
+  }
+
+  pub(in node) fn deserialize(
+    reader: &mut ByteReader,
+  ) -> Result<LeafNode<K, V>> {
+    Ok(LeafNode {
+      identifier: reader.read_u64()?,
+      lsn: reader.read_u64()?,
+      max_value: read_comparison_value(reader)?,
+      next_node_identifier: reader.read_optional_u64()?,
+      max_size: reader.read_u32()? as usize,
+      keys: read_keys(reader)?,
+      values: read_values(reader)?,
+    })
+  }
+}
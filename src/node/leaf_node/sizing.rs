@@ -1,31 +1,98 @@
 use super::LeafNode;
-use node::Node;
+use key::{Key, Value};
+use node::ComparisonValue;
+use node::util::{
+  is_deficient, max_entry_size, node_overhead, shared_prefix_len,
+  written_key_size,
+};
 
-// These methods all pertain to the size of an LeafNode (number of
-// keys).
-impl LeafNode {
+// These methods all pertain to the size of a LeafNode: how many bytes it
+// takes up once serialized. An entry is a key and its value.
+impl<K: Key, V: Value> LeafNode<K, V> {
+  // Assumes we delete the biggest entry, since we don't know which.
   pub fn can_delete_without_becoming_deficient(&self) -> bool {
-    if self.keys.is_empty() {
-      // Special case because else subtraction by one is dangerous!
-      return false;
-    }
+    let biggest_entry_size = match self.entry_sizes().max() {
+      // Special case because else there is nothing to delete!
+      None => return false,
+      Some(entry_size) => entry_size,
+    };
 
-    !Node::_is_deficient(self.num_keys() - 1, self.max_key_capacity)
+    !is_deficient(
+      self.encoded_size() - biggest_entry_size,
+      self.max_size,
+    )
   }
 
+  // Any entry we would accept must fit, since we don't know which. A
+  // new key can shorten the prefix the keys share, so we go by the size
+  // of the entries before prefix compression.
   pub fn can_grow_without_split(&self) -> bool {
-    self.keys.len() < self.max_key_capacity
+    self.uncompressed_size() + max_entry_size(self.max_size)
+      <= self.max_size
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.keys.is_empty()
   }
 
   pub fn is_deficient(&self) -> bool {
-    Node::_is_deficient(self.num_keys(), self.max_key_capacity)
+    is_deficient(self.encoded_size(), self.max_size)
   }
 
   pub fn is_overfull(&self) -> bool {
-    self.num_keys() > self.max_key_capacity
+    self.encoded_size() > self.max_size
   }
 
   pub fn num_keys(&self) -> usize {
     self.keys.len()
   }
+
+  // This is at least as many bytes as `serialize` writes.
+  pub fn encoded_size(&self) -> usize {
+    let max_value = self.max_value();
+    LeafNode::encoded_size_of(&self.keys, &self.values, max_value)
+  }
+
+  // How big a leaf with just these entries would be.
+  pub fn encoded_size_of(
+    keys: &[K],
+    values: &[V],
+    max_value: ComparisonValue<&K>,
+  ) -> usize {
+    let prefix_len = shared_prefix_len(keys);
+    node_overhead(max_value)
+      + keys
+        .iter()
+        .zip(values)
+        .enumerate()
+        .map(|(idx, (key, value))| {
+          written_key_size(key, idx, prefix_len) + value.encoded_size()
+        })
+        .sum::<usize>()
+  }
+
+  fn uncompressed_size(&self) -> usize {
+    node_overhead(self.max_value())
+      + self
+        .keys
+        .iter()
+        .zip(&self.values)
+        .map(|(key, value)| LeafNode::entry_size(key, value))
+        .sum::<usize>()
+  }
+
+  // This is the most an entry can take up, before its key shares a
+  // prefix with the other keys.
+  pub fn entry_size(key: &K, value: &V) -> usize {
+    key.encoded_size() + value.encoded_size()
+  }
+
+  pub(super) fn entry_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+    let prefix_len = shared_prefix_len(&self.keys);
+    self.keys.iter().zip(&self.values).enumerate().map(
+      move |(idx, (key, value))| {
+        written_key_size(key, idx, prefix_len) + value.encoded_size()
+      },
+    )
+  }
 }
@@ -1,39 +1,64 @@
 use super::LeafNode;
 use btree::BTree;
-use node::StringComparisonValue;
+use key::{Key, Value};
+use node::ComparisonValue;
+use storage::NodeIdentifier;
 
 // These methods all pertain to storing an InteriorNode.
-impl LeafNode {
+impl<K: Key, V: Value> LeafNode<K, V> {
   // This is for public use. It's intended to be used to create an empty
   // starting root node.
-  pub fn empty(btree: &BTree) -> String {
+  pub fn empty(btree: &BTree<K, V>) -> NodeIdentifier {
     LeafNode::store(
       btree,
       vec![],
-      StringComparisonValue::Infinity,
+      vec![],
+      ComparisonValue::Infinity,
       None,
     )
   }
 
   // This is used internally when splitting.
   pub(super) fn store(
-    btree: &BTree,
-    keys: Vec<String>,
-    max_value: StringComparisonValue<String>,
-    next_node_identifier: Option<String>,
-  ) -> String {
+    btree: &BTree<K, V>,
+    keys: Vec<K>,
+    values: Vec<V>,
+    max_value: ComparisonValue<K>,
+    next_node_identifier: Option<NodeIdentifier>,
+  ) -> NodeIdentifier {
     let identifier = btree.get_new_identifier();
+    LeafNode::store_as(
+      btree,
+      identifier,
+      keys,
+      values,
+      max_value,
+      next_node_identifier,
+    );
 
+    identifier
+  }
+
+  // This is used by `BTree::bulk_load`, which needs to know a leaf's
+  // identifier before it can store the leaf to its left.
+  pub fn store_as(
+    btree: &BTree<K, V>,
+    identifier: NodeIdentifier,
+    keys: Vec<K>,
+    values: Vec<V>,
+    max_value: ComparisonValue<K>,
+    next_node_identifier: Option<NodeIdentifier>,
+  ) {
     let node = LeafNode {
-      identifier: identifier.clone(),
+      identifier,
       keys,
+      values,
       max_value,
       next_node_identifier,
-      max_key_capacity: btree.max_key_capacity(),
+      max_size: btree.max_node_size(),
+      lsn: 0,
     };
 
     btree.store_node(node.upcast());
-
-    identifier
   }
 }
@@ -1,16 +1,39 @@
 use super::LeafNode;
-use node::StringComparisonValue;
+use error::{Error, Result};
+use key::{Key, Value};
+use node::ComparisonValue;
 
-impl LeafNode {
+impl<K: Key, V: Value> LeafNode<K, V> {
   pub fn validate(
     &self,
-    min_value: StringComparisonValue<&str>,
-    max_value: StringComparisonValue<&str>,
-  ) {
+    min_value: ComparisonValue<&K>,
+    max_value: ComparisonValue<&K>,
+  ) -> Result<()> {
     // max_value passed in from parent should equal the max_value of
     // the node.
     if max_value != self.max_value() {
-      panic!("max_value should equal max_value passed in from parent");
+      return Err(Error::InvalidTree(format!(
+        "{}: max_value should equal max_value passed in from parent",
+        self.identifier(),
+      )));
+    }
+
+    // The node must fit in the bytes it is allowed.
+    if self.is_overfull() {
+      return Err(Error::InvalidTree(format!(
+        "{}: Node is overfull! {} bytes is more than {}",
+        self.identifier(),
+        self.encoded_size(),
+        self.max_size,
+      )));
+    }
+
+    // Every key must have exactly one value.
+    if self.keys().len() != self.values().len() {
+      return Err(Error::InvalidTree(format!(
+        "{}: Keys and values are out of step!",
+        self.identifier(),
+      )));
     }
 
     // All keys must be greater than the low limit.
@@ -18,19 +41,27 @@ impl LeafNode {
     for key in self.keys() {
       // Keys must be in ascending order (with no duplicates).
       if prev_value.is_ge_to(key) {
-        println!("{}", key);
-        println!("{:?}", prev_value);
-        panic!("Keys are out of order!");
+        return Err(Error::InvalidTree(format!(
+          "{}: Keys are out of order! {:?} after {:?}",
+          self.identifier(),
+          key,
+          prev_value,
+        )));
       }
 
       // All values must be less than or equal to the high limit.
       if !max_value.is_ge_to(key) {
-        println!("{}", key);
-        println!("{:?}", max_value);
-        panic!("High limit disobeyed");
+        return Err(Error::InvalidTree(format!(
+          "{}: High limit disobeyed! {:?} above {:?}",
+          self.identifier(),
+          key,
+          max_value,
+        )));
       }
 
-      prev_value = StringComparisonValue::DefiniteValue(key);
+      prev_value = ComparisonValue::DefiniteValue(key);
     }
+
+    Ok(())
   }
 }
@@ -1,15 +1,17 @@
 mod base_node;
+mod comparison_value;
 mod interior_node;
 mod leaf_node;
 mod result_types;
-mod string_comparison_value;
+mod split_policy;
 mod util;
 
-pub(self) use self::string_comparison_value::StringComparisonValue;
-
 pub use self::base_node::Node;
+pub use self::comparison_value::ComparisonValue;
 pub use self::interior_node::InteriorNode;
 pub use self::leaf_node::LeafNode;
 pub use self::result_types::{
   DeletionResult, InsertionResult, SplitInfo, TraversalDirection,
 };
+pub use self::split_policy::SplitPolicy;
+pub use self::util::entry_capacity;
@@ -1,4 +1,6 @@
-pub enum DeletionResult {
-  DidDelete,
+use key::Value;
+
+pub enum DeletionResult<V: Value> {
+  DidDelete(V),
   KeyWasNotPresent,
 }
@@ -1,7 +1,12 @@
+use key::{Key, Value};
 use node::SplitInfo;
 
-pub enum InsertionResult {
+pub enum InsertionResult<K: Key, V: Value> {
   DidInsert,
-  DidInsertWithSplit(SplitInfo),
-  KeyWasAlreadyInserted,
+  DidInsertWithSplit(SplitInfo<K>),
+  // The key was already present. Its value was replaced, and we hand
+  // back the previous value.
+  KeyWasUpdated(V),
+  // Same, but the new value was big enough to make the node split.
+  KeyWasUpdatedWithSplit(V, SplitInfo<K>),
 }
@@ -1,5 +1,8 @@
-#[derive(Clone)]
-pub struct SplitInfo {
-  pub new_median: String,
-  pub new_right_identifier: String,
+use key::Key;
+use storage::NodeIdentifier;
+
+#[derive(Clone, Debug)]
+pub struct SplitInfo<K: Key> {
+  pub new_median: K,
+  pub new_right_identifier: NodeIdentifier,
 }
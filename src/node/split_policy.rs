@@ -0,0 +1,152 @@
+use constants::RIGHTMOST_APPEND_SPLIT_FRACTION;
+
+// A SplitPolicy decides where an overfull node splits. It is set per
+// BTree (see `BTree::set_split_policy`).
+//
+// Splits are logged with images of the nodes, so recovery never asks
+// the policy again. That means the policy can change between runs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SplitPolicy {
+  // Always split down the middle, by bytes. Random inserts leave nodes
+  // about two-thirds full on average.
+  #[default]
+  Midpoint,
+  // Like Midpoint, except when the key that made the node overfull was
+  // appended to the end of the rightmost node at its level. Then we
+  // keep most of the bytes on the left (see
+  // `RIGHTMOST_APPEND_SPLIT_FRACTION`). If keys are inserted in
+  // increasing order, every node but the rightmost is left nearly full,
+  // rather than half empty.
+  RightmostAppend,
+}
+
+impl SplitPolicy {
+  // Given the sizes of an overfull node's entries, in order, returns
+  // how many entries stay in the left node. Midpoint keeps about half of
+  // the bytes on the left. An append keeps as many as fit in
+  // `RIGHTMOST_APPEND_SPLIT_FRACTION` of `entry_capacity`, the bytes of
+  // entries the left node can hold.
+  //
+  // The node must still make sure that each side gets what it needs.
+  pub fn split_idx(
+    self,
+    entry_sizes: &[usize],
+    entry_capacity: usize,
+    is_append: bool,
+  ) -> usize {
+    let target_size = match self {
+      SplitPolicy::RightmostAppend if is_append => {
+        (entry_capacity as f64 * RIGHTMOST_APPEND_SPLIT_FRACTION)
+          as usize
+      }
+      _ => entry_sizes.iter().sum::<usize>() / 2,
+    };
+
+    let mut left_size = 0;
+    entry_sizes
+      .iter()
+      .take_while(|&&entry_size| {
+        left_size += entry_size;
+        left_size <= target_size
+      })
+      .count()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SplitPolicy;
+  use btree::BTree;
+  use node::Node;
+  use std::sync::Arc;
+  use transaction::TransactionMode;
+
+  #[test]
+  fn midpoint_splits_the_bytes_in_half() {
+    for &is_append in &[false, true] {
+      let split_idx =
+        SplitPolicy::Midpoint.split_idx(&[10; 10], 200, is_append);
+      assert_eq!(split_idx, 5);
+    }
+    // Half of 80 bytes is 40, which the first two entries make.
+    let entry_sizes = [30, 10, 10, 10, 10, 10];
+    let split_idx =
+      SplitPolicy::Midpoint.split_idx(&entry_sizes, 200, false);
+    assert_eq!(split_idx, 2);
+  }
+
+  #[test]
+  fn rightmost_append_fills_the_left_node_only_on_appends() {
+    let policy = SplitPolicy::RightmostAppend;
+    // 90% of 200 bytes is 180.
+    assert_eq!(policy.split_idx(&[10; 21], 200, true), 18);
+    assert_eq!(policy.split_idx(&[10; 21], 200, false), 10);
+  }
+
+  fn num_leaves(btree: &BTree<String, String>) -> usize {
+    let mut identifier = *btree.root_identifier_lock().read();
+    loop {
+      let pin = btree.pin_node(identifier).unwrap();
+      let node = pin.node().read();
+      match &*node {
+        Node::InteriorNode(interior_node) => {
+          identifier = interior_node.child_identifier_by_idx(0);
+        }
+        Node::LeafNode(_) => break,
+      }
+    }
+
+    let mut num_leaves = 0;
+    let mut next_identifier = Some(identifier);
+    while let Some(identifier) = next_identifier {
+      let pin = btree.pin_node(identifier).unwrap();
+      let node = pin.node().read();
+      num_leaves += 1;
+      next_identifier = node.next_node_identifier();
+    }
+    num_leaves
+  }
+
+  fn leaves_after_inserts(
+    split_policy: SplitPolicy,
+    keys: &[String],
+  ) -> usize {
+    let mut btree = BTree::new(512).unwrap();
+    btree.set_split_policy(split_policy);
+    let btree = Arc::new(btree);
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for key in keys {
+      txn.insert(key, String::from("value")).unwrap();
+    }
+    txn.commit().unwrap();
+    btree.validate().unwrap();
+
+    num_leaves(&btree)
+  }
+
+  // Ascending keys leave Midpoint's nodes half empty. RightmostAppend
+  // fills them.
+  #[test]
+  fn rightmost_append_packs_ascending_keys() {
+    let keys: Vec<String> =
+      (0..3000).map(|idx| format!("key{:04}", idx)).collect();
+    let midpoint_leaves =
+      leaves_after_inserts(SplitPolicy::Midpoint, &keys);
+    let append_leaves =
+      leaves_after_inserts(SplitPolicy::RightmostAppend, &keys);
+    assert!(append_leaves * 4 < midpoint_leaves * 3);
+  }
+
+  // Descending keys are never appended, so both policies split the
+  // same way.
+  #[test]
+  fn rightmost_append_is_midpoint_for_other_inserts() {
+    let keys: Vec<String> =
+      (0..3000).rev().map(|idx| format!("key{:04}", idx)).collect();
+    assert_eq!(
+      leaves_after_inserts(SplitPolicy::RightmostAppend, &keys),
+      leaves_after_inserts(SplitPolicy::Midpoint, &keys),
+    );
+  }
+}
@@ -1,9 +1,222 @@
+use constants::MIN_ENTRIES_PER_NODE;
+use error::{self, Error};
+use key::{Key, Value};
+use node::ComparisonValue;
+use storage::{ByteReader, ByteWriter, NodeIdentifier};
+
 // This is used to search within the keys of a LeafNode or the splits of
 // an InteriorNode.
-pub(in node) fn search_sorted_strings_for_str(
-  strings: &[String],
-  target_value: &str,
+pub(in node) fn search_sorted_keys<K: Key>(
+  keys: &[K],
+  target_key: &K,
 ) -> Result<usize, usize> {
-  let search_fn = |value: &String| value.as_str().cmp(target_value);
-  strings.binary_search_by(search_fn)
+  keys.binary_search_by(|key| key.compare(target_key))
+}
+
+// The most bytes a serialized node takes up besides its entries and its
+// max value: the tag, identifier, LSN, max value tag, next node
+// identifier, max size, the lengths of the two vectors, and the length
+// of the prefix the keys share.
+const NODE_HEADER_SIZE: usize = 1 + 8 + 8 + 1 + 9 + 4 + 4 + 4 + 4;
+
+pub(in node) fn node_overhead<K: Key>(
+  max_value: ComparisonValue<&K>,
+) -> usize {
+  match max_value {
+    ComparisonValue::DefiniteValue(max_value) => {
+      NODE_HEADER_SIZE + max_value.encoded_size()
+    }
+    _ => NODE_HEADER_SIZE,
+  }
+}
+
+// No entry may take up more than this, so that an overfull node can
+// always be split into two that fit. See `BTree::max_entry_size`.
+pub(in node) fn max_entry_size(max_size: usize) -> usize {
+  max_size / MIN_ENTRIES_PER_NODE
+}
+
+// How many bytes of entries any node can hold, whatever its max value.
+pub fn entry_capacity(max_size: usize) -> usize {
+  max_size - NODE_HEADER_SIZE - max_entry_size(max_size)
+}
+
+// How many bytes of entries the left side of a split can hold. Its max
+// value will be one of `keys`, or a separator no longer than one.
+pub(in node) fn split_entry_capacity<K: Key>(
+  max_size: usize,
+  keys: &[K],
+) -> usize {
+  let max_key_size =
+    keys.iter().map(K::encoded_size).max().unwrap_or(0);
+  max_size.saturating_sub(NODE_HEADER_SIZE + max_key_size)
+}
+
+// A SplitPolicy goes by the sizes entries take up in the whole node.
+// But each side of a split may share a longer prefix than the whole
+// node did. Above all, a new key that shortened the node's prefix takes
+// up much more room than the keys it is split from will. So a split the
+// policy chose can leave a side overfull even when another split
+// wouldn't.
+//
+// Returns the split nearest to `split_idx` (within `min_idx..=max_idx`)
+// for which `fits` holds. If none does, returns `split_idx`.
+pub(in node) fn nearest_fitting_split_idx<F>(
+  split_idx: usize,
+  min_idx: usize,
+  max_idx: usize,
+  fits: F,
+) -> usize
+where
+  F: Fn(usize) -> bool,
+{
+  if fits(split_idx) {
+    return split_idx;
+  }
+
+  for distance in 1..=(max_idx - min_idx) {
+    let candidates = [
+      split_idx.checked_sub(distance).filter(|&idx| min_idx <= idx),
+      Some(split_idx + distance).filter(|&idx| idx <= max_idx),
+    ];
+    for &idx in candidates.iter().flatten() {
+      if fits(idx) {
+        return idx;
+      }
+    }
+  }
+
+  split_idx
+}
+
+// Helper to determine whether a node is deficient in size.
+pub(in node) fn is_deficient(size: usize, max_size: usize) -> bool {
+  // The largest deficient node should be able to merge with the
+  // smallest sufficient node and still fit in max_size.
+  size + (size + 1) <= max_size
+}
+
+// These are used when serializing either kind of node.
+pub(in node) fn write_comparison_value<K: Key>(
+  writer: &mut ByteWriter,
+  value: ComparisonValue<&K>,
+) {
+  match value {
+    ComparisonValue::NegativeInfinity => writer.write_u8(0),
+    ComparisonValue::DefiniteValue(value) => {
+      writer.write_u8(1);
+      value.serialize(writer);
+    }
+    ComparisonValue::Infinity => writer.write_u8(2),
+  }
+}
+
+pub(in node) fn read_comparison_value<K: Key>(
+  reader: &mut ByteReader,
+) -> error::Result<ComparisonValue<K>> {
+  match reader.read_u8()? {
+    0 => Ok(ComparisonValue::NegativeInfinity),
+    1 => Ok(ComparisonValue::DefiniteValue(K::deserialize(reader)?)),
+    2 => Ok(ComparisonValue::Infinity),
+    _ => Err(Error::CorruptPage(String::from(
+      "unknown comparison value tag",
+    ))),
+  }
+}
+
+// The keys of a node are written with the prefix they all share only
+// once, as part of the first key. (See `Key`.)
+pub(in node) fn shared_prefix_len<K: Key>(keys: &[K]) -> usize {
+  match keys.first() {
+    None => 0,
+    Some(first_key) => keys
+      .iter()
+      .map(|key| first_key.common_prefix_len(key))
+      .min()
+      .unwrap_or(0),
+  }
+}
+
+// How many bytes `write_keys` spends on the key at `idx`.
+pub(in node) fn written_key_size<K: Key>(
+  key: &K,
+  idx: usize,
+  prefix_len: usize,
+) -> usize {
+  if idx == 0 {
+    key.encoded_size()
+  } else {
+    key.encoded_suffix_size(prefix_len)
+  }
+}
+
+pub(in node) fn write_keys<K: Key>(
+  writer: &mut ByteWriter,
+  keys: &[K],
+) {
+  writer.write_u32(keys.len() as u32);
+  let first_key = match keys.first() {
+    None => return,
+    Some(first_key) => first_key,
+  };
+
+  let prefix_len = shared_prefix_len(keys);
+  writer.write_u32(prefix_len as u32);
+  first_key.serialize(writer);
+  for key in &keys[1..] {
+    key.serialize_suffix(prefix_len, writer);
+  }
+}
+
+pub(in node) fn read_keys<K: Key>(
+  reader: &mut ByteReader,
+) -> error::Result<Vec<K>> {
+  let len = reader.read_u32()? as usize;
+  if len == 0 {
+    return Ok(vec![]);
+  }
+
+  let prefix_len = reader.read_u32()? as usize;
+  let mut keys = Vec::with_capacity(len);
+  keys.push(K::deserialize(reader)?);
+  for _ in 1..len {
+    let key = K::deserialize_suffix(&keys[0], prefix_len, reader)?;
+    keys.push(key);
+  }
+
+  Ok(keys)
+}
+
+pub(in node) fn write_values<T: Value>(
+  writer: &mut ByteWriter,
+  values: &[T],
+) {
+  writer.write_u32(values.len() as u32);
+  for value in values {
+    value.serialize(writer);
+  }
+}
+
+pub(in node) fn read_values<T: Value>(
+  reader: &mut ByteReader,
+) -> error::Result<Vec<T>> {
+  let len = reader.read_u32()?;
+  (0..len).map(|_| T::deserialize(reader)).collect()
+}
+
+pub(in node) fn write_identifiers(
+  writer: &mut ByteWriter,
+  identifiers: &[NodeIdentifier],
+) {
+  writer.write_u32(identifiers.len() as u32);
+  for identifier in identifiers {
+    writer.write_u64(*identifier);
+  }
+}
+
+pub(in node) fn read_identifiers(
+  reader: &mut ByteReader,
+) -> error::Result<Vec<NodeIdentifier>> {
+  let len = reader.read_u32()?;
+  (0..len).map(|_| reader.read_u64()).collect()
 }
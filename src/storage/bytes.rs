@@ -0,0 +1,114 @@
+use error::{Error, Result};
+use std::str;
+
+// ByteWriter and ByteReader are how we lay out nodes and pages. Integers
+// are written little-endian. A string is written as its length (a u32)
+// followed by its UTF-8 bytes.
+#[derive(Default)]
+pub struct ByteWriter {
+  bytes: Vec<u8>,
+}
+
+impl ByteWriter {
+  pub fn new() -> ByteWriter {
+    ByteWriter::default()
+  }
+
+  pub fn write_u8(&mut self, value: u8) {
+    self.bytes.push(value);
+  }
+
+  pub fn write_u32(&mut self, value: u32) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn write_u64(&mut self, value: u64) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn write_optional_u64(&mut self, value: Option<u64>) {
+    match value {
+      None => self.write_u8(0),
+      Some(value) => {
+        self.write_u8(1);
+        self.write_u64(value);
+      }
+    }
+  }
+
+  pub fn write_bytes(&mut self, value: &[u8]) {
+    self.write_u32(value.len() as u32);
+    self.bytes.extend_from_slice(value);
+  }
+
+  pub fn write_str(&mut self, value: &str) {
+    self.write_bytes(value.as_bytes());
+  }
+
+  pub fn into_bytes(self) -> Vec<u8> {
+    self.bytes
+  }
+}
+
+// Reading past the end, or reading a string that isn't UTF-8, means the
+// bytes were corrupted.
+pub struct ByteReader<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+  pub fn new(bytes: &'a [u8]) -> ByteReader<'a> {
+    ByteReader { bytes, position: 0 }
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+    if self.bytes.len() - self.position < len {
+      return Err(Error::CorruptPage(String::from(
+        "ran off the end of the bytes",
+      )));
+    }
+
+    let taken = &self.bytes[self.position..(self.position + len)];
+    self.position += len;
+    Ok(taken)
+  }
+
+  pub fn read_u8(&mut self) -> Result<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  pub fn read_u32(&mut self) -> Result<u32> {
+    let mut value = [0; 4];
+    value.copy_from_slice(self.take(4)?);
+    Ok(u32::from_le_bytes(value))
+  }
+
+  pub fn read_u64(&mut self) -> Result<u64> {
+    let mut value = [0; 8];
+    value.copy_from_slice(self.take(8)?);
+    Ok(u64::from_le_bytes(value))
+  }
+
+  pub fn read_optional_u64(&mut self) -> Result<Option<u64>> {
+    match self.read_u8()? {
+      0 => Ok(None),
+      1 => Ok(Some(self.read_u64()?)),
+      _ => Err(Error::CorruptPage(String::from("unknown option tag"))),
+    }
+  }
+
+  pub fn read_bytes(&mut self) -> Result<&'a [u8]> {
+    let len = self.read_u32()? as usize;
+    self.take(len)
+  }
+
+  pub fn read_string(&mut self) -> Result<String> {
+    match str::from_utf8(self.read_bytes()?) {
+      Ok(value) => Ok(String::from(value)),
+      Err(_) => {
+        Err(Error::CorruptPage(String::from("string is not UTF-8")))
+      }
+    }
+  }
+}
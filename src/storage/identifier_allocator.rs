@@ -0,0 +1,89 @@
+use super::NodeIdentifier;
+use std::cmp;
+use std::collections::BTreeSet;
+
+// Hands out NodeIdentifiers for a NodeStore. A freed identifier is
+// reused before we count any higher, so a PageFileNodeStore reuses its
+// pages.
+//
+// Writing a node *claims* its identifier. Recovery may write a node
+// whose identifier we had forgotten handing out (or had since been told
+// was free); claiming it makes sure we never hand it out twice.
+pub struct IdentifierAllocator {
+  next_identifier: NodeIdentifier,
+  free_identifiers: BTreeSet<NodeIdentifier>,
+}
+
+impl IdentifierAllocator {
+  pub fn new(
+    next_identifier: NodeIdentifier,
+    free_identifiers: BTreeSet<NodeIdentifier>,
+  ) -> IdentifierAllocator {
+    IdentifierAllocator {
+      next_identifier,
+      free_identifiers,
+    }
+  }
+
+  pub fn allocate(&mut self) -> NodeIdentifier {
+    if let Some(identifier) =
+      self.free_identifiers.iter().next().cloned()
+    {
+      self.free_identifiers.remove(&identifier);
+      return identifier;
+    }
+
+    let identifier = self.next_identifier;
+    self.next_identifier += 1;
+    identifier
+  }
+
+  pub fn free(&mut self, identifier: NodeIdentifier) {
+    self.free_identifiers.insert(identifier);
+  }
+
+  // Returns whether the identifier had been free.
+  pub fn claim(&mut self, identifier: NodeIdentifier) -> bool {
+    self.next_identifier =
+      cmp::max(self.next_identifier, identifier + 1);
+    self.free_identifiers.remove(&identifier)
+  }
+
+  pub fn free_identifiers(&self) -> &BTreeSet<NodeIdentifier> {
+    &self.free_identifiers
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::IdentifierAllocator;
+  use std::collections::BTreeSet;
+
+  #[test]
+  fn freed_identifiers_are_reused_lowest_first() {
+    let mut allocator = IdentifierAllocator::new(1, BTreeSet::new());
+    let identifiers: Vec<_> =
+      (0..5).map(|_| allocator.allocate()).collect();
+    assert_eq!(identifiers, vec![1, 2, 3, 4, 5]);
+
+    allocator.free(4);
+    allocator.free(2);
+    assert_eq!(allocator.allocate(), 2);
+    assert_eq!(allocator.allocate(), 4);
+    // Only then do we count higher.
+    assert_eq!(allocator.allocate(), 6);
+  }
+
+  #[test]
+  fn claimed_identifiers_are_never_handed_out() {
+    let mut allocator =
+      IdentifierAllocator::new(3, vec![1].into_iter().collect());
+
+    // Claiming a free identifier takes it off the free list.
+    assert!(allocator.claim(1));
+    assert!(allocator.free_identifiers().is_empty());
+    // Claiming one past the end counts past it.
+    assert!(!allocator.claim(7));
+    assert_eq!(allocator.allocate(), 8);
+  }
+}
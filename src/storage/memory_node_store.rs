@@ -0,0 +1,79 @@
+use super::{IdentifierAllocator, NodeIdentifier, NodeStore};
+use error::Result;
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, HashMap};
+
+// A MemoryNodeStore is what `BTree::new` uses. Nothing written to it
+// survives the process.
+pub struct MemoryNodeStore {
+  nodes: Mutex<HashMap<NodeIdentifier, Vec<u8>>>,
+  identifier_allocator: Mutex<IdentifierAllocator>,
+  root_identifier: Mutex<Option<NodeIdentifier>>,
+}
+
+impl MemoryNodeStore {
+  pub fn new() -> MemoryNodeStore {
+    MemoryNodeStore {
+      nodes: Mutex::new(HashMap::new()),
+      identifier_allocator: Mutex::new(IdentifierAllocator::new(
+        1,
+        BTreeSet::new(),
+      )),
+      root_identifier: Mutex::new(None),
+    }
+  }
+}
+
+impl Default for MemoryNodeStore {
+  fn default() -> MemoryNodeStore {
+    MemoryNodeStore::new()
+  }
+}
+
+impl NodeStore for MemoryNodeStore {
+  fn read_node(
+    &self,
+    identifier: NodeIdentifier,
+  ) -> Result<Option<Vec<u8>>> {
+    Ok(self.nodes.lock().get(&identifier).cloned())
+  }
+
+  fn write_node(
+    &self,
+    identifier: NodeIdentifier,
+    bytes: &[u8],
+  ) -> Result<()> {
+    self.nodes.lock().insert(identifier, bytes.to_vec());
+    self.identifier_allocator.lock().claim(identifier);
+    Ok(())
+  }
+
+  fn allocate_identifier(&self) -> NodeIdentifier {
+    self.identifier_allocator.lock().allocate()
+  }
+
+  fn free_identifier(&self, identifier: NodeIdentifier) {
+    self.nodes.lock().remove(&identifier);
+    self.identifier_allocator.lock().free(identifier);
+  }
+
+  fn max_node_size(&self) -> Option<usize> {
+    None
+  }
+
+  fn read_root_identifier(&self) -> Result<Option<NodeIdentifier>> {
+    Ok(*self.root_identifier.lock())
+  }
+
+  fn write_root_identifier(
+    &self,
+    root_identifier: NodeIdentifier,
+  ) -> Result<()> {
+    *self.root_identifier.lock() = Some(root_identifier);
+    Ok(())
+  }
+
+  fn sync(&self) -> Result<()> {
+    Ok(())
+  }
+}
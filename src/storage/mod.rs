@@ -0,0 +1,14 @@
+mod bytes;
+mod identifier_allocator;
+mod memory_node_store;
+mod node_identifier;
+mod node_store;
+mod page_file_node_store;
+
+pub(self) use self::identifier_allocator::IdentifierAllocator;
+
+pub use self::bytes::{ByteReader, ByteWriter};
+pub use self::memory_node_store::MemoryNodeStore;
+pub use self::node_identifier::NodeIdentifier;
+pub use self::node_store::NodeStore;
+pub use self::page_file_node_store::PageFileNodeStore;
@@ -0,0 +1,4 @@
+// Every node is named by a NodeIdentifier. The NodeStore hands them out,
+// counting up from one. (A PageFileNodeStore keeps node N on page N;
+// page zero is its header.)
+pub type NodeIdentifier = u64;
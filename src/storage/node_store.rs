@@ -0,0 +1,48 @@
+use super::NodeIdentifier;
+use error::Result;
+
+// A NodeStore is where nodes live when they aren't in memory. The BTree
+// serializes a node before handing it over, so a NodeStore only ever
+// sees bytes, keyed by the node's identifier.
+//
+// The NodeStore also hands out the identifiers, and remembers the root
+// identifier. That is how a reopened BTree finds its way back into the
+// nodes.
+pub trait NodeStore: Send + Sync {
+  // Returns `None` if nothing was ever written for this identifier.
+  fn read_node(
+    &self,
+    identifier: NodeIdentifier,
+  ) -> Result<Option<Vec<u8>>>;
+
+  // Replaces whatever was previously written for this identifier. The
+  // identifier is in use from now on, even if it was freed.
+  fn write_node(
+    &self,
+    identifier: NodeIdentifier,
+    bytes: &[u8],
+  ) -> Result<()>;
+
+  // Never hands out an identifier that is in use.
+  fn allocate_identifier(&self) -> NodeIdentifier;
+
+  // The identifier may be handed out again. Nothing may refer to its
+  // node any more. (The log may, since recovery only redoes a change
+  // that is newer than the node it finds.)
+  fn free_identifier(&self, identifier: NodeIdentifier);
+
+  // Returns `None` if the store is brand new.
+  fn read_root_identifier(&self) -> Result<Option<NodeIdentifier>>;
+
+  fn write_root_identifier(
+    &self,
+    root_identifier: NodeIdentifier,
+  ) -> Result<()>;
+
+  // Makes every write so far durable.
+  fn sync(&self) -> Result<()>;
+
+  // The most bytes `write_node` accepts, if there is a limit. The BTree
+  // splits nodes before they get any bigger.
+  fn max_node_size(&self) -> Option<usize>;
+}
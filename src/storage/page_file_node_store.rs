@@ -0,0 +1,440 @@
+use super::{
+  ByteReader, ByteWriter, IdentifierAllocator, NodeIdentifier,
+  NodeStore,
+};
+use constants::{MIN_NODE_SIZE, PAGE_SIZE};
+use error::{Error, Result};
+use parking_lot::Mutex;
+use std::cmp;
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8] = b"nedbase";
+const FORMAT_VERSION: u32 = 4;
+
+const EMPTY_PAGE_TAG: u8 = 0;
+const NODE_PAGE_TAG: u8 = 1;
+const FREE_PAGE_TAG: u8 = 2;
+
+// A node page starts with its tag and the length of the node.
+const NODE_PAGE_OVERHEAD: usize = 1 + 4;
+
+// The header is the magic, the format version, the page size, the root
+// identifier (with a byte to say if there is one), and the first free
+// page. Every page must be able to hold it.
+const MIN_PAGE_SIZE: usize = MAGIC.len() + 4 + 4 + 1 + 8 + 8;
+
+// A PageFileNodeStore keeps every node in a single data file, which is
+// divided into fixed-size pages. Node N lives on page N. A node that
+// doesn't fit in a page can't be written.
+//
+// Page zero is the header. It records the page size the file was made
+// with, the root identifier, and the first free page. Each free page
+// records the next one. We write out the free pages (and then the
+// header) when we sync.
+//
+// A crash can leave the free pages out of date. At worst, a page we
+// thought was free has since been written with a node; we stop
+// following the free pages there (and lose track of the rest). Or a
+// page we thought was free is in use, but its node was never written.
+// Recovery writes it from the log, which claims it again.
+pub struct PageFileNodeStore {
+  page_file: Mutex<PageFile>,
+}
+
+struct PageFile {
+  file: File,
+  page_size: usize,
+  root_identifier: Option<NodeIdentifier>,
+  num_pages: u64,
+  identifier_allocator: IdentifierAllocator,
+  // The first free page, as far as the file knows.
+  first_free_page: NodeIdentifier,
+  free_pages_are_dirty: bool,
+}
+
+impl PageFileNodeStore {
+  // Creates the file if it doesn't exist yet, with pages of
+  // `PAGE_SIZE`.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<PageFileNodeStore> {
+    PageFileNodeStore::open_with_page_size(path, PAGE_SIZE)
+  }
+
+  // A file that already exists keeps the page size it was made with.
+  // Either way, we refuse a page size too small to hold a node of
+  // `MIN_NODE_SIZE` bytes before touching the file.
+  pub fn open_with_page_size<P: AsRef<Path>>(
+    path: P,
+    page_size: usize,
+  ) -> Result<PageFileNodeStore> {
+    let max_node_size = page_size.saturating_sub(NODE_PAGE_OVERHEAD);
+    if max_node_size < MIN_NODE_SIZE {
+      return Err(Error::NodeSizeTooSmall(max_node_size));
+    }
+
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(path)?;
+
+    let page_file = if file.metadata()?.len() == 0 {
+      PageFile::create(file, page_size)?
+    } else {
+      PageFile::load(file)?
+    };
+
+    Ok(PageFileNodeStore {
+      page_file: Mutex::new(page_file),
+    })
+  }
+}
+
+impl PageFile {
+  fn create(file: File, page_size: usize) -> Result<PageFile> {
+    let mut page_file = PageFile {
+      file,
+      page_size,
+      root_identifier: None,
+      num_pages: 1,
+      identifier_allocator: IdentifierAllocator::new(
+        1,
+        BTreeSet::new(),
+      ),
+      first_free_page: 0,
+      free_pages_are_dirty: false,
+    };
+    page_file.write_header()?;
+
+    Ok(page_file)
+  }
+
+  fn load(mut file: File) -> Result<PageFile> {
+    // The header says how big a page is, so we must read it before we
+    // know how big the header page is.
+    let mut header = vec![0; MAGIC.len() + 8];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    let page_size = {
+      let mut reader = ByteReader::new(&header[MAGIC.len()..]);
+      if &header[..MAGIC.len()] != MAGIC {
+        return Err(Error::CorruptPage(String::from(
+          "not a nedbase data file",
+        )));
+      }
+      if reader.read_u32()? != FORMAT_VERSION {
+        return Err(Error::CorruptPage(String::from(
+          "unknown data file format version",
+        )));
+      }
+      reader.read_u32()? as usize
+    };
+    if page_size < MIN_PAGE_SIZE {
+      return Err(Error::CorruptPage(String::from(
+        "data file page size is too small",
+      )));
+    }
+
+    let file_len = file.metadata()?.len();
+    if file_len % page_size as u64 != 0 {
+      return Err(Error::CorruptPage(String::from(
+        "data file ends partway through a page",
+      )));
+    }
+    let num_pages = file_len / page_size as u64;
+
+    let mut page_file = PageFile {
+      file,
+      page_size,
+      root_identifier: None,
+      num_pages,
+      identifier_allocator: IdentifierAllocator::new(
+        num_pages,
+        BTreeSet::new(),
+      ),
+      first_free_page: 0,
+      free_pages_are_dirty: false,
+    };
+
+    // Now read the rest of the header.
+    let header_page = page_file.read_page(0)?;
+    let mut reader = ByteReader::new(&header_page[MAGIC.len() + 8..]);
+    if reader.read_u8()? == 1 {
+      page_file.root_identifier = Some(reader.read_u64()?);
+    }
+    page_file.first_free_page = reader.read_u64()?;
+
+    // Follow the free pages for as long as they really are free.
+    let mut free_identifiers = BTreeSet::new();
+    let mut free_page = page_file.first_free_page;
+    while 0 < free_page
+      && free_page < num_pages
+      && !free_identifiers.contains(&free_page)
+    {
+      let page = page_file.read_page(free_page)?;
+      let mut reader = ByteReader::new(&page);
+      if reader.read_u8()? != FREE_PAGE_TAG {
+        break;
+      }
+      free_identifiers.insert(free_page);
+      free_page = reader.read_u64()?;
+    }
+    page_file.identifier_allocator =
+      IdentifierAllocator::new(num_pages, free_identifiers);
+
+    Ok(page_file)
+  }
+
+  fn read_page(&mut self, page_number: u64) -> Result<Vec<u8>> {
+    let mut page = vec![0; self.page_size];
+    self
+      .file
+      .seek(SeekFrom::Start(page_number * self.page_size as u64))?;
+    self.file.read_exact(&mut page)?;
+
+    Ok(page)
+  }
+
+  // Pads the bytes out to a whole page. Writing past the end of the
+  // file leaves empty pages in between.
+  fn write_page(
+    &mut self,
+    page_number: u64,
+    mut page: Vec<u8>,
+  ) -> Result<()> {
+    if page.len() > self.page_size {
+      return Err(Error::PageOverflow(page_number));
+    }
+    page.resize(self.page_size, 0);
+    self
+      .file
+      .seek(SeekFrom::Start(page_number * self.page_size as u64))?;
+    self.file.write_all(&page)?;
+    self.num_pages = cmp::max(self.num_pages, page_number + 1);
+
+    Ok(())
+  }
+
+  fn write_header(&mut self) -> Result<()> {
+    let mut writer = ByteWriter::new();
+    for byte in MAGIC {
+      writer.write_u8(*byte);
+    }
+    writer.write_u32(FORMAT_VERSION);
+    writer.write_u32(self.page_size as u32);
+    match self.root_identifier {
+      None => writer.write_u8(0),
+      Some(root_identifier) => {
+        writer.write_u8(1);
+        writer.write_u64(root_identifier);
+      }
+    }
+    writer.write_u64(self.first_free_page);
+
+    self.write_page(0, writer.into_bytes())
+  }
+
+  // Each free page records the next. Then the header records the first.
+  fn write_free_pages(&mut self) -> Result<()> {
+    let free_pages: Vec<NodeIdentifier> = self
+      .identifier_allocator
+      .free_identifiers()
+      .iter()
+      .cloned()
+      .collect();
+
+    for (idx, free_page) in free_pages.iter().enumerate() {
+      let mut writer = ByteWriter::new();
+      writer.write_u8(FREE_PAGE_TAG);
+      writer.write_u64(free_pages.get(idx + 1).cloned().unwrap_or(0));
+      self.write_page(*free_page, writer.into_bytes())?;
+    }
+
+    self.first_free_page = free_pages.first().cloned().unwrap_or(0);
+    self.write_header()
+  }
+}
+
+impl NodeStore for PageFileNodeStore {
+  fn read_node(
+    &self,
+    identifier: NodeIdentifier,
+  ) -> Result<Option<Vec<u8>>> {
+    let mut page_file = self.page_file.lock();
+    if identifier == 0 || page_file.num_pages <= identifier {
+      return Ok(None);
+    }
+
+    let page = page_file.read_page(identifier)?;
+    let mut reader = ByteReader::new(&page);
+    match reader.read_u8()? {
+      NODE_PAGE_TAG => Ok(Some(reader.read_bytes()?.to_vec())),
+      EMPTY_PAGE_TAG | FREE_PAGE_TAG => Ok(None),
+      _ => Err(Error::CorruptPage(String::from("unknown page tag"))),
+    }
+  }
+
+  fn write_node(
+    &self,
+    identifier: NodeIdentifier,
+    bytes: &[u8],
+  ) -> Result<()> {
+    let mut writer = ByteWriter::new();
+    writer.write_u8(NODE_PAGE_TAG);
+    writer.write_bytes(bytes);
+    let page = writer.into_bytes();
+
+    let mut page_file = self.page_file.lock();
+    if page.len() > page_file.page_size {
+      return Err(Error::PageOverflow(identifier));
+    }
+
+    page_file.write_page(identifier, page)?;
+    if page_file.identifier_allocator.claim(identifier) {
+      page_file.free_pages_are_dirty = true;
+    }
+    Ok(())
+  }
+
+  fn allocate_identifier(&self) -> NodeIdentifier {
+    let mut page_file = self.page_file.lock();
+    if !page_file.identifier_allocator.free_identifiers().is_empty() {
+      page_file.free_pages_are_dirty = true;
+    }
+    page_file.identifier_allocator.allocate()
+  }
+
+  fn free_identifier(&self, identifier: NodeIdentifier) {
+    let mut page_file = self.page_file.lock();
+    page_file.free_pages_are_dirty = true;
+    page_file.identifier_allocator.free(identifier);
+  }
+
+  fn max_node_size(&self) -> Option<usize> {
+    // A page too small for even the overhead can hold no node at all.
+    let page_size = self.page_file.lock().page_size;
+    Some(page_size.saturating_sub(NODE_PAGE_OVERHEAD))
+  }
+
+  fn read_root_identifier(&self) -> Result<Option<NodeIdentifier>> {
+    Ok(self.page_file.lock().root_identifier)
+  }
+
+  fn write_root_identifier(
+    &self,
+    root_identifier: NodeIdentifier,
+  ) -> Result<()> {
+    let mut page_file = self.page_file.lock();
+    page_file.root_identifier = Some(root_identifier);
+    page_file.write_header()
+  }
+
+  fn sync(&self) -> Result<()> {
+    let mut page_file = self.page_file.lock();
+    if page_file.free_pages_are_dirty {
+      page_file.write_free_pages()?;
+      page_file.free_pages_are_dirty = false;
+    }
+    page_file.file.sync_data()?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::PageFileNodeStore;
+  use std::fs;
+  use std::path::Path;
+  use storage::NodeStore;
+  use test_util::data_path;
+
+  const PAGE_SIZE: usize = 512;
+
+  // Writes `num_nodes` nodes, and frees the ones in `freed`.
+  fn open_with_free_pages(
+    path: &Path,
+    num_nodes: usize,
+    freed: &[u64],
+  ) -> PageFileNodeStore {
+    let node_store =
+      PageFileNodeStore::open_with_page_size(path, PAGE_SIZE).unwrap();
+    for _ in 0..num_nodes {
+      let identifier = node_store.allocate_identifier();
+      node_store.write_node(identifier, b"node").unwrap();
+    }
+    for identifier in freed {
+      node_store.free_identifier(*identifier);
+    }
+    node_store
+  }
+
+  #[test]
+  fn free_pages_survive_reopening() {
+    let path = data_path("free-pages-survive");
+
+    let node_store = open_with_free_pages(&path, 6, &[5, 2, 4]);
+    node_store.sync().unwrap();
+    drop(node_store);
+
+    let node_store =
+      PageFileNodeStore::open_with_page_size(&path, PAGE_SIZE).unwrap();
+    assert_eq!(node_store.read_node(2).unwrap(), None);
+    assert_eq!(
+      node_store.read_node(3).unwrap(),
+      Some(b"node".to_vec()),
+    );
+    assert_eq!(node_store.allocate_identifier(), 2);
+    assert_eq!(node_store.allocate_identifier(), 4);
+    assert_eq!(node_store.allocate_identifier(), 5);
+    assert_eq!(node_store.allocate_identifier(), 7);
+
+    drop(node_store);
+    let _ = fs::remove_file(&path);
+  }
+
+  #[test]
+  fn free_pages_are_only_written_by_sync() {
+    let path = data_path("free-pages-need-sync");
+
+    let node_store = open_with_free_pages(&path, 4, &[]);
+    node_store.sync().unwrap();
+    node_store.free_identifier(3);
+    drop(node_store);
+
+    // Having crashed before the sync, we never knew it was free.
+    let node_store =
+      PageFileNodeStore::open_with_page_size(&path, PAGE_SIZE).unwrap();
+    assert_eq!(node_store.allocate_identifier(), 5);
+
+    drop(node_store);
+    let _ = fs::remove_file(&path);
+  }
+
+  // If a free page was written with a node after the last sync, we stop
+  // following the free pages there, rather than hand it out twice.
+  #[test]
+  fn a_reused_free_page_ends_the_free_list() {
+    let path = data_path("reused-free-page");
+
+    let node_store = open_with_free_pages(&path, 6, &[2, 4]);
+    node_store.sync().unwrap();
+    // The free list starts at page 2, which now holds a node.
+    node_store.write_node(2, b"reused").unwrap();
+    drop(node_store);
+
+    let node_store =
+      PageFileNodeStore::open_with_page_size(&path, PAGE_SIZE).unwrap();
+    assert_eq!(
+      node_store.read_node(2).unwrap(),
+      Some(b"reused".to_vec()),
+    );
+    // We lost track of page 4, but never hand out page 2.
+    assert_eq!(node_store.allocate_identifier(), 7);
+
+    drop(node_store);
+    let _ = fs::remove_file(&path);
+  }
+}
@@ -0,0 +1,62 @@
+// Helpers shared by the tests of many modules.
+
+use btree::BTree;
+use node::Node;
+use std::fs;
+use std::path::{Path, PathBuf};
+use storage::NodeIdentifier;
+
+// Zero padded, so that keys sort in the order of their indexes.
+pub fn key(idx: usize) -> String {
+  format!("key{:08}", idx)
+}
+
+// A fresh path in the temp directory for a test's data file. Whatever
+// an earlier run of the test left there (and its log) is removed.
+pub fn data_path(name: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!(
+    "nedbase-{}-{}",
+    name,
+    std::process::id()
+  ));
+  remove_files(&path);
+  path
+}
+
+// Where `BTree::open` keeps the log for the data file at `path`.
+pub fn wal_path(path: &Path) -> PathBuf {
+  let mut wal_path = path.as_os_str().to_owned();
+  wal_path.push(".wal");
+  PathBuf::from(wal_path)
+}
+
+pub fn remove_files(path: &Path) {
+  let _ = fs::remove_file(path);
+  let _ = fs::remove_file(wal_path(path));
+}
+
+// Returns how many nodes are reachable from the root, and how many
+// levels there are.
+pub fn count_nodes(btree: &BTree<String, String>) -> (usize, usize) {
+  let root_identifier = *btree.root_identifier_lock().read();
+  let mut level: Vec<NodeIdentifier> = vec![root_identifier];
+  let mut num_nodes = 0;
+  let mut num_levels = 0;
+  while !level.is_empty() {
+    num_nodes += level.len();
+    num_levels += 1;
+    let mut next_level = vec![];
+    for identifier in level {
+      let pin = btree.pin_node(identifier).unwrap();
+      let node = pin.node().read();
+      if let Node::InteriorNode(interior_node) = &*node {
+        for idx in 0..=interior_node.num_split_keys() {
+          next_level.push(interior_node.child_identifier_by_idx(idx));
+        }
+      }
+    }
+    level = next_level;
+  }
+
+  (num_nodes, num_levels)
+}
@@ -0,0 +1,16 @@
+// How durable a Transaction is once `Transaction::commit` returns. This
+// only matters for a BTree with a WriteAheadLog.
+//
+// * Sync: the commit record has been flushed. Transactions committing
+//   at the same time share one flush (that's group commit).
+// * Async: the commit record will be flushed in the background within
+//   `ASYNC_COMMIT_INTERVAL`. A crash before then loses the Transaction
+//   (but never only part of it).
+// * None: the commit record is flushed whenever something else flushes
+//   the log. Meant for bulk jobs that can simply be rerun.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Commit {
+  Sync,
+  Async,
+  None,
+}
@@ -0,0 +1,13 @@
+mod commit;
+#[allow(clippy::module_inception)]
+mod transaction;
+mod transaction_id;
+mod transaction_mode;
+mod undo_entry;
+
+pub(crate) use self::undo_entry::{UndoEntry, UndoLog};
+
+pub use self::commit::Commit;
+pub use self::transaction::Transaction;
+pub use self::transaction_id::TransactionId;
+pub use self::transaction_mode::TransactionMode;
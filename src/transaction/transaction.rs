@@ -0,0 +1,659 @@
+use super::{
+  Commit, TransactionId, TransactionMode, UndoEntry, UndoLog,
+};
+use btree::{BTree, RangeIterator, ReverseRangeIterator};
+use error::{Error, Result};
+use key::{Key, Value};
+use locking::LockSet;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+use std::time::Duration;
+
+// A Transaction owns the LockSet that holds its locks. Locks are held
+// until the Transaction either commits or aborts (that's 2PL).
+//
+// Aborting rolls back every insert and delete the Transaction made.
+// Since the Transaction still holds the write locks on the keys it
+// changed, no one else can have seen (or built on) those changes.
+//
+// A Transaction that is dropped without committing is aborted. So is a
+// Transaction that runs into an error (say, because it was chosen as a
+// deadlock victim, or because it waited longer than its lock timeout
+// for a lock). After that, every operation fails with
+// `Error::TransactionAborted`.
+//
+// The `try_` operations are the exception. They never wait for a lock:
+// if one isn't free, they fail with `Error::LockTimeout`, and the
+// Transaction carries on as if they had never been tried.
+//
+// A Snapshot transaction reads the tree as it was when it began, and
+// holds no locks. It can't change anything.
+//
+// Range iterators are an exception: they borrow the LockSet, so when
+// one yields an error we can't roll back right away. We roll back the
+// next time the Transaction is used (or when it is dropped).
+pub struct Transaction<K: Key, V: Value> {
+  btree: Arc<BTree<K, V>>,
+  lock_set: LockSet<K, V>,
+  undo_log: Arc<UndoLog<K, V>>,
+  commit: Commit,
+  is_finished: bool,
+}
+
+impl<K: Key, V: Value> Transaction<K, V> {
+  pub fn new(
+    btree: &Arc<BTree<K, V>>,
+    tx_mode: TransactionMode,
+  ) -> Transaction<K, V> {
+    Transaction {
+      btree: Arc::clone(btree),
+      lock_set: LockSet::new(btree, tx_mode),
+      undo_log: Arc::new(UndoLog::new(vec![])),
+      commit: Commit::Sync,
+      is_finished: false,
+    }
+  }
+
+  // By default we wait as long as it takes to get a lock (or until we
+  // are chosen as a deadlock victim). With a lock timeout, an operation
+  // that waits too long for any one lock fails with
+  // `Error::LockTimeout`.
+  pub fn set_lock_timeout(&mut self, lock_timeout: Option<Duration>) {
+    self.lock_set.set_lock_timeout(lock_timeout);
+  }
+
+  // By default, `commit` waits until the Transaction is durable. See
+  // `Commit` for the alternatives.
+  pub fn set_commit(&mut self, commit: Commit) {
+    self.commit = commit;
+  }
+
+  pub fn contains_key(&mut self, key: &K) -> Result<bool> {
+    self.check_is_active()?;
+    let result = BTree::contains_key(&mut self.lock_set, key);
+    self.rollback_on_error(result)
+  }
+
+  pub fn get(&mut self, key: &K) -> Result<Option<V>> {
+    self.check_is_active()?;
+    let result = BTree::get(&mut self.lock_set, key);
+    self.rollback_on_error(result)
+  }
+
+  // The undo entry goes in as soon as the leaf changes. If the insert
+  // then fails partway (say, splitting), the rollback still undoes it.
+  pub fn insert(&mut self, key: &K, value: V) -> Result<Option<V>> {
+    self.check_is_active()?;
+    let btree = &self.btree;
+    let transaction_id = self.lock_set.transaction_id();
+    let undo_log = &self.undo_log;
+    let result = BTree::insert_reporting_change(
+      btree,
+      &mut self.lock_set,
+      key,
+      value,
+      |previous_value| {
+        push_undo_entry(
+          btree,
+          transaction_id,
+          undo_log,
+          UndoEntry::Insert {
+            key: key.clone(),
+            previous_value: previous_value.cloned(),
+          },
+        )
+      },
+    );
+    self.rollback_on_error(result)
+  }
+
+  pub fn delete(&mut self, key: &K) -> Result<Option<V>> {
+    self.check_is_active()?;
+    let btree = &self.btree;
+    let transaction_id = self.lock_set.transaction_id();
+    let undo_log = &self.undo_log;
+    let result = BTree::delete_reporting_change(
+      btree,
+      &mut self.lock_set,
+      key,
+      |deleted_value| {
+        push_undo_entry(
+          btree,
+          transaction_id,
+          undo_log,
+          UndoEntry::Delete {
+            key: key.clone(),
+            deleted_value: deleted_value.clone(),
+          },
+        )
+      },
+    );
+    self.rollback_on_error(result)
+  }
+
+  pub fn try_get(&mut self, key: &K) -> Result<Option<V>> {
+    self.check_is_active()?;
+    let result = self
+      .lock_set
+      .without_waiting(|lock_set| BTree::get(lock_set, key));
+    self.rollback_on_error_unless_busy(result, 0)
+  }
+
+  pub fn try_insert(&mut self, key: &K, value: V) -> Result<Option<V>> {
+    self.check_is_active()?;
+    let num_undo_entries = self.undo_log.lock().len();
+    let btree = &self.btree;
+    let transaction_id = self.lock_set.transaction_id();
+    let undo_log = &self.undo_log;
+    let result = self.lock_set.without_waiting(|lock_set| {
+      BTree::insert_reporting_change(
+        btree,
+        lock_set,
+        key,
+        value,
+        |previous_value| {
+          push_undo_entry(
+            btree,
+            transaction_id,
+            undo_log,
+            UndoEntry::Insert {
+              key: key.clone(),
+              previous_value: previous_value.cloned(),
+            },
+          )
+        },
+      )
+    });
+    self.rollback_on_error_unless_busy(result, num_undo_entries)
+  }
+
+  pub fn try_delete(&mut self, key: &K) -> Result<Option<V>> {
+    self.check_is_active()?;
+    let num_undo_entries = self.undo_log.lock().len();
+    let btree = &self.btree;
+    let transaction_id = self.lock_set.transaction_id();
+    let undo_log = &self.undo_log;
+    let result = self.lock_set.without_waiting(|lock_set| {
+      BTree::delete_reporting_change(
+        btree,
+        lock_set,
+        key,
+        |deleted_value| {
+          push_undo_entry(
+            btree,
+            transaction_id,
+            undo_log,
+            UndoEntry::Delete {
+              key: key.clone(),
+              deleted_value: deleted_value.clone(),
+            },
+          )
+        },
+      )
+    });
+    self.rollback_on_error_unless_busy(result, num_undo_entries)
+  }
+
+  pub fn range<'a, 'b, R>(
+    &'a mut self,
+    range: R,
+  ) -> Result<RangeIterator<'a, K, V>>
+  where
+    R: RangeBounds<&'b K>,
+  {
+    self.check_is_active()?;
+    BTree::range(&mut self.lock_set, range)
+  }
+
+  pub fn range_rev<'a, 'b, R>(
+    &'a mut self,
+    range: R,
+  ) -> Result<ReverseRangeIterator<'a, K, V>>
+  where
+    R: RangeBounds<&'b K>,
+  {
+    self.check_is_active()?;
+    BTree::range_rev(&mut self.lock_set, range)
+  }
+
+  pub fn first(&mut self) -> Result<Option<(K, V)>> {
+    self.check_is_active()?;
+    let result = BTree::first(&mut self.lock_set);
+    self.rollback_on_error(result)
+  }
+
+  pub fn last(&mut self) -> Result<Option<(K, V)>> {
+    self.check_is_active()?;
+    let result = BTree::last(&mut self.lock_set);
+    self.rollback_on_error(result)
+  }
+
+  pub fn floor(&mut self, key: &K) -> Result<Option<(K, V)>> {
+    self.check_is_active()?;
+    let result = BTree::floor(&mut self.lock_set, key);
+    self.rollback_on_error(result)
+  }
+
+  pub fn ceiling(&mut self, key: &K) -> Result<Option<(K, V)>> {
+    self.check_is_active()?;
+    let result = BTree::ceiling(&mut self.lock_set, key);
+    self.rollback_on_error(result)
+  }
+
+  // Committing keeps every change. The locks are released when the
+  // LockSet is dropped. A Transaction that already ran into an error
+  // can't commit.
+  //
+  // If the BTree has a WriteAheadLog, a Transaction that changed
+  // anything has committed once its commit record is flushed. Whether
+  // we wait for that depends on `set_commit`.
+  //
+  // Snapshot transactions that begin after this see the changes. We
+  // still hold our locks, so no one else can have read them yet. The
+  // commit is logged by then, so should the VersionStore fail us, the
+  // changes stand; we finish up, and then return its error.
+  //
+  // Leaves that our deletes left empty are only reclaimed now. (See
+  // `reclaim_emptied_leaves`.)
+  //
+  // Last, once our locks are released, we checkpoint if the log has
+  // grown enough. (See `checkpoint_if_log_is_large`.)
+  pub fn commit(mut self) -> Result<()> {
+    self.check_is_active()?;
+    let mut versioning_result = Ok(());
+    if !self.undo_log.lock().is_empty() {
+      let transaction_id = self.lock_set.transaction_id();
+      let result = self.btree.log_commit(transaction_id, self.commit);
+      self.rollback_on_error(result)?;
+      versioning_result =
+        self.btree.version_store().commit(transaction_id);
+    }
+    self.is_finished = true;
+
+    let deleted_keys: Vec<K> = self
+      .undo_log
+      .lock()
+      .iter()
+      .filter_map(|undo_entry| match undo_entry {
+        UndoEntry::Delete { key, .. } => Some(key.clone()),
+        UndoEntry::Insert { .. } => None,
+      })
+      .collect();
+    self.reclaim_emptied_leaves(&deleted_keys);
+
+    let btree = Arc::clone(&self.btree);
+    drop(self);
+    btree.checkpoint_if_log_is_large();
+
+    versioning_result
+  }
+
+  // Rolling back can fail only if undoing a change does (say, on an I/O
+  // error). We stop there, and don't log the end of the rollback, so
+  // that recovery undoes the rest when the BTree is next opened. Until
+  // then, Snapshot transactions keep seeing the values we saved.
+  //
+  // Undoing our inserts may leave leaves that we split empty. Those are
+  // reclaimed once the rollback is done, just as a commit reclaims the
+  // leaves its deletes emptied.
+  pub fn abort(mut self) -> Result<()> {
+    if self.is_finished {
+      return Ok(());
+    }
+
+    self.rollback()
+  }
+
+  fn check_is_active(&mut self) -> Result<()> {
+    if !self.is_finished {
+      if let Some(error) = self.lock_set.error() {
+        return self.rollback_after_error(error);
+      }
+    }
+
+    if self.is_finished {
+      return Err(Error::TransactionAborted);
+    }
+
+    Ok(())
+  }
+
+  fn rollback_on_error<T>(&mut self, result: Result<T>) -> Result<T> {
+    match result {
+      Ok(value) => Ok(value),
+      Err(error) => self.rollback_after_error(error),
+    }
+  }
+
+  // A `try_` operation that found a lock busy gave up before it changed
+  // anything, so there is nothing to roll back. (If it did change
+  // something, the undo log grew, and we roll back as usual.)
+  fn rollback_on_error_unless_busy<T>(
+    &mut self,
+    result: Result<T>,
+    num_undo_entries: usize,
+  ) -> Result<T> {
+    match result {
+      Err(Error::LockTimeout)
+        if self.undo_log.lock().len() == num_undo_entries =>
+      {
+        Err(Error::LockTimeout)
+      }
+      result => self.rollback_on_error(result),
+    }
+  }
+
+  // The error that made us roll back says the most, so that is the one
+  // we hand back. But if the rollback fails too, the caller must hear
+  // of both.
+  fn rollback_after_error<T>(&mut self, error: Error) -> Result<T> {
+    match self.rollback() {
+      Ok(()) => Err(error),
+      Err(rollback_error) => Err(Error::RollbackFailed {
+        error: Box::new(error),
+        rollback_error: Box::new(rollback_error),
+      }),
+    }
+  }
+
+  fn rollback(&mut self) -> Result<()> {
+    // A deadlock victim shouldn't be chosen again (or time out) while
+    // it undoes its changes, or it could never release its locks.
+    self.lock_set.begin_rollback();
+    self.is_finished = true;
+
+    // Undo the changes in the reverse order they were made. Note that
+    // the undo operations go through the LockSet, which still holds
+    // our write locks on those keys.
+    //
+    // An entry leaves the undo log only once it is undone. Until then,
+    // a Snapshot transaction that begins can still find the value we
+    // are about to restore.
+    let mut undone_insert_keys = vec![];
+    loop {
+      let undo_entry = match self.undo_log.lock().last() {
+        None => break,
+        Some(undo_entry) => undo_entry.clone(),
+      };
+      let btree = &self.btree;
+      let lock_set = &mut self.lock_set;
+      match undo_entry {
+        UndoEntry::Insert {
+          key,
+          previous_value: Some(previous_value),
+        } => {
+          BTree::insert(btree, lock_set, &key, previous_value)?;
+        }
+
+        UndoEntry::Insert {
+          key,
+          previous_value: None,
+        } => {
+          BTree::delete(btree, lock_set, &key)?;
+          undone_insert_keys.push(key);
+        }
+
+        UndoEntry::Delete { key, deleted_value } => {
+          BTree::insert(btree, lock_set, &key, deleted_value)?;
+        }
+      }
+      self.undo_log.lock().pop();
+    }
+
+    let transaction_id = self.lock_set.transaction_id();
+    self.btree.version_store().abort(transaction_id);
+    self.btree.log_end(transaction_id)?;
+
+    self.reclaim_emptied_leaves(&undone_insert_keys);
+    Ok(())
+  }
+
+  // Reclaims the leaves the keys were deleted from, if they are empty
+  // now. (See `reclaim_leaf_if_empty`.) We are done by then, so failing
+  // to reclaim one fails nothing; the leaf just stays empty.
+  fn reclaim_emptied_leaves(&mut self, keys: &[K]) {
+    for key in keys {
+      let btree = &self.btree;
+      if BTree::reclaim_leaf_if_empty(btree, &mut self.lock_set, key)
+        .is_err()
+      {
+        // Whatever went wrong will likely go wrong again.
+        break;
+      }
+    }
+  }
+}
+
+// Called as soon as the leaf changes, while we still hold its write
+// latch. The first change registers the undo log with the VersionStore,
+// so that a snapshot can find it. (Then the leaf saves a version, if a
+// snapshot is running; see `version_leaf_change`.)
+fn push_undo_entry<K: Key, V: Value>(
+  btree: &BTree<K, V>,
+  transaction_id: TransactionId,
+  undo_log: &Arc<UndoLog<K, V>>,
+  undo_entry: UndoEntry<K, V>,
+) {
+  if undo_log.lock().is_empty() {
+    btree
+      .version_store()
+      .register_undo_log(transaction_id, undo_log);
+  }
+  undo_log.lock().push(undo_entry);
+}
+
+// There is no one to tell if the rollback fails here. Whatever it
+// didn't undo is left for recovery; call `abort` yourself if you need to
+// know.
+impl<K: Key, V: Value> Drop for Transaction<K, V> {
+  fn drop(&mut self) {
+    if !self.is_finished {
+      let _ = self.rollback();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use btree::BTree;
+  use error::{Error, Result};
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::sync::Arc;
+  use std::time::Duration;
+  use storage::{MemoryNodeStore, NodeIdentifier, NodeStore};
+  use test_util::key;
+  use transaction::TransactionMode;
+
+  // Fails every read and write once it is told to.
+  struct FailingNodeStore {
+    node_store: MemoryNodeStore,
+    is_failing: Arc<AtomicBool>,
+  }
+
+  impl FailingNodeStore {
+    fn check(&self) -> Result<()> {
+      if self.is_failing.load(Ordering::SeqCst) {
+        Err(Error::Io(String::from("the disk is on fire")))
+      } else {
+        Ok(())
+      }
+    }
+  }
+
+  impl NodeStore for FailingNodeStore {
+    fn read_node(
+      &self,
+      identifier: NodeIdentifier,
+    ) -> Result<Option<Vec<u8>>> {
+      self.check()?;
+      self.node_store.read_node(identifier)
+    }
+
+    fn write_node(
+      &self,
+      identifier: NodeIdentifier,
+      bytes: &[u8],
+    ) -> Result<()> {
+      self.check()?;
+      self.node_store.write_node(identifier, bytes)
+    }
+
+    fn allocate_identifier(&self) -> NodeIdentifier {
+      self.node_store.allocate_identifier()
+    }
+
+    fn free_identifier(&self, identifier: NodeIdentifier) {
+      self.node_store.free_identifier(identifier)
+    }
+
+    fn read_root_identifier(&self) -> Result<Option<NodeIdentifier>> {
+      self.node_store.read_root_identifier()
+    }
+
+    fn write_root_identifier(
+      &self,
+      root_identifier: NodeIdentifier,
+    ) -> Result<()> {
+      self.node_store.write_root_identifier(root_identifier)
+    }
+
+    fn sync(&self) -> Result<()> {
+      self.check()?;
+      self.node_store.sync()
+    }
+
+    fn max_node_size(&self) -> Option<usize> {
+      self.node_store.max_node_size()
+    }
+  }
+
+  // Returns the BTree, and the switch that makes its NodeStore fail.
+  // The BufferPool is tiny, so most nodes must be read back from the
+  // NodeStore.
+  fn failing_btree() -> (Arc<BTree<String, String>>, Arc<AtomicBool>) {
+    let is_failing = Arc::new(AtomicBool::new(false));
+    let node_store = FailingNodeStore {
+      node_store: MemoryNodeStore::new(),
+      is_failing: Arc::clone(&is_failing),
+    };
+    let btree = Arc::new(
+      BTree::with_node_store(Box::new(node_store), None, 256, 4)
+        .unwrap(),
+    );
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in 0..500 {
+      txn.insert(&key(idx), format!("value {}", idx)).unwrap();
+    }
+    txn.commit().unwrap();
+
+    (btree, is_failing)
+  }
+
+  #[test]
+  fn abort_returns_the_rollback_error() {
+    let (btree, is_failing) = failing_btree();
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in (0..500).step_by(50) {
+      txn.delete(&key(idx)).unwrap();
+    }
+    is_failing.store(true, Ordering::SeqCst);
+    assert!(matches!(txn.abort(), Err(Error::Io(_))));
+    is_failing.store(false, Ordering::SeqCst);
+  }
+
+  #[test]
+  fn failed_rollback_is_reported_with_the_error() {
+    let (btree, is_failing) = failing_btree();
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in (0..500).step_by(50) {
+      txn.delete(&key(idx)).unwrap();
+    }
+    is_failing.store(true, Ordering::SeqCst);
+    match txn.get(&key(275)) {
+      Err(Error::RollbackFailed {
+        error,
+        rollback_error,
+      }) => {
+        assert!(matches!(*error, Error::Io(_)));
+        assert!(matches!(*rollback_error, Error::Io(_)));
+      }
+      result => panic!("expected RollbackFailed, got {:?}", result),
+    }
+    assert_eq!(txn.get(&key(275)), Err(Error::TransactionAborted));
+    is_failing.store(false, Ordering::SeqCst);
+  }
+
+  #[test]
+  fn rollback_undoes_every_change() {
+    let (btree, _) = failing_btree();
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    for idx in (0..500).step_by(7) {
+      txn.delete(&key(idx)).unwrap();
+    }
+    for idx in 500..600 {
+      txn.insert(&key(idx), String::from("new")).unwrap();
+    }
+    txn.insert(&key(3), String::from("changed")).unwrap();
+    txn.abort().unwrap();
+
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    let entries: Vec<(String, String)> =
+      txn.range(..).unwrap().map(|entry| entry.unwrap()).collect();
+    let expected: Vec<(String, String)> = (0..500)
+      .map(|idx| (key(idx), format!("value {}", idx)))
+      .collect();
+    assert_eq!(entries, expected);
+  }
+
+  #[test]
+  fn try_operations_fail_without_waiting() {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    let mut writer = btree.begin(TransactionMode::ReadWrite);
+    writer.insert(&key(1), String::from("one")).unwrap();
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    assert_eq!(txn.try_get(&key(1)), Err(Error::LockTimeout));
+    assert_eq!(
+      txn.try_insert(&key(1), String::from("uno")),
+      Err(Error::LockTimeout)
+    );
+    assert_eq!(txn.try_delete(&key(1)), Err(Error::LockTimeout));
+
+    // The Transaction carries on.
+    txn.insert(&key(2), String::from("two")).unwrap();
+    writer.commit().unwrap();
+    assert_eq!(txn.try_get(&key(1)), Ok(Some(String::from("one"))));
+    assert_eq!(
+      txn.try_delete(&key(1)),
+      Ok(Some(String::from("one")))
+    );
+    txn.commit().unwrap();
+
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(txn.get(&key(1)), Ok(None));
+    assert_eq!(txn.get(&key(2)), Ok(Some(String::from("two"))));
+  }
+
+  #[test]
+  fn lock_timeout_aborts_the_transaction() {
+    let btree = Arc::new(BTree::new(256).unwrap());
+    let mut writer = btree.begin(TransactionMode::ReadWrite);
+    writer.insert(&key(1), String::from("one")).unwrap();
+
+    let mut txn = btree.begin(TransactionMode::ReadWrite);
+    txn.insert(&key(2), String::from("two")).unwrap();
+    txn.set_lock_timeout(Some(Duration::from_millis(10)));
+    assert_eq!(txn.get(&key(1)), Err(Error::LockTimeout));
+    assert_eq!(txn.get(&key(2)), Err(Error::TransactionAborted));
+    drop(txn);
+
+    writer.commit().unwrap();
+    let mut txn = btree.begin(TransactionMode::ReadOnly);
+    assert_eq!(txn.get(&key(1)), Ok(Some(String::from("one"))));
+    assert_eq!(txn.get(&key(2)), Ok(None));
+  }
+}
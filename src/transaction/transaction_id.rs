@@ -0,0 +1,3 @@
+// Every LockSet is given a unique TransactionId by the LockManager. It
+// is how the LockManager knows who holds and who waits for each lock.
+pub type TransactionId = u64;
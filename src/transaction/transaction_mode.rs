@@ -0,0 +1,15 @@
+// TransactionMode determines whether you are allowed to acquire write
+// locks at all. If you *read* a key in ReadWrite mode, we'll have you
+// acquire a read lock all the same. Should another query in the
+// transaction want to write that key, the lock is upgraded.
+//
+// A Snapshot transaction can't write either. It reads the tree as it
+// was when the transaction began, from old versions of the keys where
+// need be (see `nedbase::mvcc`). So it takes no key locks, and never
+// holds up a writer for longer than it takes to read a leaf.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum TransactionMode {
+  ReadOnly,
+  ReadWrite,
+  Snapshot,
+}
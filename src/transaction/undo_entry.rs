@@ -0,0 +1,41 @@
+use key::{Key, Value};
+use parking_lot::Mutex;
+
+// Each change a Transaction makes is recorded with enough information to
+// reverse it. The undo is *logical*: we don't remember which leaf was
+// changed, because the key may have moved to another leaf (by a split)
+// by the time we abort. We simply redo the inverse operation through the
+// BTree.
+#[derive(Clone)]
+pub enum UndoEntry<K: Key, V: Value> {
+  // Undone by restoring the previous value, or by deleting the key if
+  // there was none.
+  Insert { key: K, previous_value: Option<V> },
+
+  // Undone by reinserting the deleted value.
+  Delete { key: K, deleted_value: V },
+}
+
+impl<K: Key, V: Value> UndoEntry<K, V> {
+  pub fn key(&self) -> &K {
+    match self {
+      UndoEntry::Insert { key, .. } => key,
+      UndoEntry::Delete { key, .. } => key,
+    }
+  }
+
+  // The value the key had before the change, if it had one.
+  pub fn previous_value(&self) -> Option<&V> {
+    match self {
+      UndoEntry::Insert { previous_value, .. } => {
+        previous_value.as_ref()
+      }
+      UndoEntry::Delete { deleted_value, .. } => Some(deleted_value),
+    }
+  }
+}
+
+// A Transaction shares its undo log with the VersionStore, so that a
+// Snapshot transaction that begins later can find the values the
+// Transaction replaced. (See `VersionStore`.)
+pub type UndoLog<K, V> = Mutex<Vec<UndoEntry<K, V>>>;
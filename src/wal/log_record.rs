@@ -0,0 +1,203 @@
+use error::{Error, Result};
+use key::{Key, Value};
+use node::SplitInfo;
+use storage::{ByteReader, ByteWriter, NodeIdentifier};
+use transaction::TransactionId;
+
+// These are the records in the WriteAheadLog.
+//
+// A change to a leaf's data is logged *physiologically*: we name the
+// leaf, and say what was done to it. That is enough to redo the change.
+// It also says what the key held before, which is enough to undo the
+// change *logically* (through the BTree), wherever the key lives by
+// then.
+//
+// A change to the structure of the tree is logged as the after-images of
+// every node it touched. These are redo-only: once a split is logged,
+// it is never undone, even if the transaction that caused it is.
+//
+// A change made while rolling back is marked `is_compensation`. It is
+// never itself undone.
+#[derive(Debug)]
+pub enum LogRecord<K: Key, V: Value> {
+  Insert {
+    transaction_id: TransactionId,
+    is_compensation: bool,
+    identifier: NodeIdentifier,
+    key: K,
+    value: V,
+    previous_value: Option<V>,
+  },
+
+  Delete {
+    transaction_id: TransactionId,
+    is_compensation: bool,
+    identifier: NodeIdentifier,
+    key: K,
+    deleted_value: V,
+  },
+
+  // One step of a split, or the removal of an empty leaf. A split is
+  // *started* by splitting a node, and *completed* when its parent
+  // learns of the new right sibbling (possibly splitting the parent,
+  // which starts another split).
+  //
+  // Splits are identified by their new right sibbling.
+  StructureChange {
+    images: Vec<Vec<u8>>,
+    started_split: Option<SplitInfo<K>>,
+    completed_split: Option<NodeIdentifier>,
+    root_identifier: Option<NodeIdentifier>,
+  },
+
+  Commit {
+    transaction_id: TransactionId,
+  },
+
+  // The transaction finished rolling back.
+  End {
+    transaction_id: TransactionId,
+  },
+}
+
+const INSERT_TAG: u8 = 0;
+const DELETE_TAG: u8 = 1;
+const STRUCTURE_CHANGE_TAG: u8 = 2;
+const COMMIT_TAG: u8 = 3;
+const END_TAG: u8 = 4;
+
+impl<K: Key, V: Value> LogRecord<K, V> {
+  pub fn serialize(&self, writer: &mut ByteWriter) {
+    match self {
+      LogRecord::Insert {
+        transaction_id,
+        is_compensation,
+        identifier,
+        key,
+        value,
+        previous_value,
+      } => {
+        writer.write_u8(INSERT_TAG);
+        writer.write_u64(*transaction_id);
+        writer.write_u8(*is_compensation as u8);
+        writer.write_u64(*identifier);
+        key.serialize(writer);
+        value.serialize(writer);
+        match previous_value {
+          None => writer.write_u8(0),
+          Some(previous_value) => {
+            writer.write_u8(1);
+            previous_value.serialize(writer);
+          }
+        }
+      }
+
+      LogRecord::Delete {
+        transaction_id,
+        is_compensation,
+        identifier,
+        key,
+        deleted_value,
+      } => {
+        writer.write_u8(DELETE_TAG);
+        writer.write_u64(*transaction_id);
+        writer.write_u8(*is_compensation as u8);
+        writer.write_u64(*identifier);
+        key.serialize(writer);
+        deleted_value.serialize(writer);
+      }
+
+      LogRecord::StructureChange {
+        images,
+        started_split,
+        completed_split,
+        root_identifier,
+      } => {
+        writer.write_u8(STRUCTURE_CHANGE_TAG);
+        writer.write_u32(images.len() as u32);
+        for image in images {
+          writer.write_bytes(image);
+        }
+        match started_split {
+          None => writer.write_u8(0),
+          Some(split_info) => {
+            writer.write_u8(1);
+            split_info.new_median.serialize(writer);
+            writer.write_u64(split_info.new_right_identifier);
+          }
+        }
+        writer.write_optional_u64(*completed_split);
+        writer.write_optional_u64(*root_identifier);
+      }
+
+      LogRecord::Commit { transaction_id } => {
+        writer.write_u8(COMMIT_TAG);
+        writer.write_u64(*transaction_id);
+      }
+
+      LogRecord::End { transaction_id } => {
+        writer.write_u8(END_TAG);
+        writer.write_u64(*transaction_id);
+      }
+    }
+  }
+
+  pub fn deserialize(
+    reader: &mut ByteReader,
+  ) -> Result<LogRecord<K, V>> {
+    match reader.read_u8()? {
+      INSERT_TAG => Ok(LogRecord::Insert {
+        transaction_id: reader.read_u64()?,
+        is_compensation: reader.read_u8()? != 0,
+        identifier: reader.read_u64()?,
+        key: K::deserialize(reader)?,
+        value: V::deserialize(reader)?,
+        previous_value: match reader.read_u8()? {
+          0 => None,
+          _ => Some(V::deserialize(reader)?),
+        },
+      }),
+
+      DELETE_TAG => Ok(LogRecord::Delete {
+        transaction_id: reader.read_u64()?,
+        is_compensation: reader.read_u8()? != 0,
+        identifier: reader.read_u64()?,
+        key: K::deserialize(reader)?,
+        deleted_value: V::deserialize(reader)?,
+      }),
+
+      STRUCTURE_CHANGE_TAG => {
+        let num_images = reader.read_u32()?;
+        let images = (0..num_images)
+          .map(|_| Ok(reader.read_bytes()?.to_vec()))
+          .collect::<Result<_>>()?;
+        let started_split = match reader.read_u8()? {
+          0 => None,
+          _ => Some(SplitInfo {
+            new_median: K::deserialize(reader)?,
+            new_right_identifier: reader.read_u64()?,
+          }),
+        };
+
+        Ok(LogRecord::StructureChange {
+          images,
+          started_split,
+          completed_split: reader.read_optional_u64()?,
+          root_identifier: reader.read_optional_u64()?,
+        })
+      }
+
+      COMMIT_TAG => Ok(LogRecord::Commit {
+        transaction_id: reader.read_u64()?,
+      }),
+
+      END_TAG => Ok(LogRecord::End {
+        transaction_id: reader.read_u64()?,
+      }),
+
+      _ => {
+        Err(Error::CorruptPage(String::from("unknown log record tag")))
+      }
+    }
+  }
+}
@@ -0,0 +1,7 @@
+// A log sequence number. Each LogRecord gets the next one, and they keep
+// increasing even across restarts, so that a node's LSN can be compared
+// with any record in the log.
+//
+// Zero is never used for a record. A node with LSN zero has never been
+// logged.
+pub type Lsn = u64;
@@ -0,0 +1,7 @@
+mod log_record;
+mod lsn;
+mod write_ahead_log;
+
+pub use self::log_record::LogRecord;
+pub use self::lsn::Lsn;
+pub use self::write_ahead_log::WriteAheadLog;
@@ -0,0 +1,551 @@
+use super::{LogRecord, Lsn};
+use error::{Error, Result};
+use key::{Key, Value};
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use std::cmp;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use storage::{ByteReader, ByteWriter};
+
+const MAGIC: &[u8] = b"nedblog";
+const FORMAT_VERSION: u32 = 4;
+const HEADER_LEN: usize = 7 + 4 + 8;
+
+// The WriteAheadLog is a single file. The header records the LSN of the
+// first record in the file. Each record is framed by its length and a
+// checksum; after that comes its LSN and the record itself.
+//
+// Appending a record only puts it in a buffer. The buffer is written
+// and synced by `flush_to`. The rule (the "WAL rule") is that a node
+// must not be written back until every record up to the node's LSN has
+// been flushed. The BufferPool sees to that.
+//
+// Flushing is a group commit. One thread at a time takes the whole
+// buffer and writes it out, without holding the buffer's lock. Anyone
+// else who needs a flush meanwhile waits for it to finish; if their
+// record came too late to be part of it, one of them flushes next, for
+// all of them at once.
+//
+// If we crash partway through writing a record, the last record's
+// checksum won't match. `open` throws away any such torn record. If a
+// write fails and we can't cut the torn record back off, nothing more
+// may be appended after it: every later append and flush fails.
+//
+// A checkpoint throws away the records that are no longer needed, from
+// the front of the log. See `truncate_before`.
+//
+// The log itself doesn't know the BTree's key and value types. The
+// records found at `open` are kept as bytes, and only read once
+// recovery asks for them.
+pub struct WriteAheadLog {
+  path: PathBuf,
+  log_buffer: Mutex<LogBuffer>,
+  // Notified whenever a flush finishes.
+  flush_finished: Condvar,
+  // Only the thread doing the flush writes to the file.
+  file: Mutex<File>,
+  // Set once the file is broken beyond repair.
+  failure: Mutex<Option<Error>>,
+}
+
+struct LogBuffer {
+  next_lsn: Lsn,
+  // Every record with a lower LSN is durable.
+  flushed_lsn: Lsn,
+  // The LSN of the first record in the file (or of the next one to be
+  // written there, if there are none).
+  first_lsn: Lsn,
+  // How many bytes the file takes up.
+  file_size: u64,
+  buffer: Vec<u8>,
+  // Set while someone writes to the file. Only they may.
+  is_flushing: bool,
+  // The background flusher flushes through this LSN.
+  requested_lsn: Lsn,
+  // What we found in the file when we opened it. Recovery takes these.
+  recovered_records: Vec<(Lsn, Vec<u8>)>,
+  // How many times the buffer was written out.
+  #[cfg(test)]
+  num_flushes: usize,
+}
+
+impl WriteAheadLog {
+  // Creates the file if it doesn't exist yet.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<WriteAheadLog> {
+    let path = path.as_ref().to_path_buf();
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(&path)?;
+
+    let mut contents = vec![];
+    file.read_to_end(&mut contents)?;
+
+    let mut log_buffer = LogBuffer {
+      next_lsn: 1,
+      flushed_lsn: 1,
+      first_lsn: 1,
+      file_size: HEADER_LEN as u64,
+      buffer: vec![],
+      is_flushing: false,
+      requested_lsn: 0,
+      recovered_records: vec![],
+      #[cfg(test)]
+      num_flushes: 0,
+    };
+
+    if contents.is_empty() {
+      write_header(&mut file, log_buffer.next_lsn)?;
+    } else {
+      log_buffer.load(&mut file, &contents)?;
+    }
+
+    Ok(WriteAheadLog {
+      path,
+      log_buffer: Mutex::new(log_buffer),
+      flush_finished: Condvar::new(),
+      file: Mutex::new(file),
+      failure: Mutex::new(None),
+    })
+  }
+
+  // Hands over the records that were in the log when it was opened.
+  pub fn take_recovered_records<K: Key, V: Value>(
+    &self,
+  ) -> Result<Vec<(Lsn, LogRecord<K, V>)>> {
+    let recovered_records =
+      std::mem::take(&mut self.log_buffer.lock().recovered_records);
+
+    recovered_records
+      .into_iter()
+      .map(|(lsn, bytes)| {
+        let record =
+          LogRecord::deserialize(&mut ByteReader::new(&bytes))?;
+        Ok((lsn, record))
+      })
+      .collect()
+  }
+
+  // Fails if the log is broken. Nothing was appended then.
+  pub fn append<K: Key, V: Value>(
+    &self,
+    record: &LogRecord<K, V>,
+  ) -> Result<Lsn> {
+    self.check_failure()?;
+    let mut log_buffer = self.log_buffer.lock();
+    let lsn = log_buffer.next_lsn;
+    log_buffer.next_lsn += 1;
+
+    let mut writer = ByteWriter::new();
+    writer.write_u64(lsn);
+    record.serialize(&mut writer);
+    let payload = writer.into_bytes();
+
+    let mut frame = ByteWriter::new();
+    frame.write_u32(payload.len() as u32);
+    frame.write_u32(checksum(&payload));
+    log_buffer.buffer.extend(frame.into_bytes());
+    log_buffer.buffer.extend(payload);
+
+    Ok(lsn)
+  }
+
+  // The LSN the next record will get.
+  pub fn next_lsn(&self) -> Lsn {
+    self.log_buffer.lock().next_lsn
+  }
+
+  #[cfg(test)]
+  pub fn flushed_lsn(&self) -> Lsn {
+    self.log_buffer.lock().flushed_lsn
+  }
+
+  #[cfg(test)]
+  pub fn num_flushes(&self) -> usize {
+    self.log_buffer.lock().num_flushes
+  }
+
+  // How many bytes the log takes up, counting records not yet flushed.
+  pub fn size(&self) -> u64 {
+    let log_buffer = self.log_buffer.lock();
+    log_buffer.file_size + log_buffer.buffer.len() as u64
+  }
+
+  // Makes every record up to and including `lsn` durable.
+  pub fn flush_to(&self, lsn: Lsn) -> Result<()> {
+    self.check_failure()?;
+    let mut log_buffer = self.log_buffer.lock();
+    loop {
+      if lsn < log_buffer.flushed_lsn {
+        return Ok(());
+      }
+      if !log_buffer.is_flushing {
+        break;
+      }
+      self.flush_finished.wait(&mut log_buffer);
+    }
+
+    // It's our turn. We flush everything, for everyone.
+    log_buffer.is_flushing = true;
+    let bytes = std::mem::take(&mut log_buffer.buffer);
+    let flushed_lsn = log_buffer.next_lsn;
+
+    let result =
+      MutexGuard::unlocked(&mut log_buffer, || self.write(&bytes));
+
+    log_buffer.is_flushing = false;
+    match result {
+      Ok(()) => {
+        log_buffer.flushed_lsn = flushed_lsn;
+        log_buffer.file_size += bytes.len() as u64;
+        #[cfg(test)]
+        {
+          log_buffer.num_flushes += 1;
+        }
+      }
+      // Put the records back; the next flush will try again.
+      Err(_) => {
+        let newer_bytes =
+          std::mem::replace(&mut log_buffer.buffer, bytes);
+        log_buffer.buffer.extend(newer_bytes);
+      }
+    }
+    self.flush_finished.notify_all();
+
+    result
+  }
+
+  pub fn flush(&self) -> Result<()> {
+    let next_lsn = self.log_buffer.lock().next_lsn;
+    self.flush_to(next_lsn - 1)
+  }
+
+  // Asks the background flusher to flush through `lsn`, without
+  // waiting for it.
+  pub fn request_flush(&self, lsn: Lsn) {
+    let mut log_buffer = self.log_buffer.lock();
+    log_buffer.requested_lsn = cmp::max(log_buffer.requested_lsn, lsn);
+  }
+
+  // Every `interval`, flushes whatever `request_flush` asked for. The
+  // thread stops once the log is dropped.
+  pub fn start_background_flushing(
+    self: &Arc<Self>,
+    interval: Duration,
+  ) {
+    let wal = Arc::downgrade(self);
+    thread::spawn(move || loop {
+      thread::sleep(interval);
+      let wal = match wal.upgrade() {
+        None => return,
+        Some(wal) => wal,
+      };
+
+      // An error will come up again at the next flush that someone
+      // waits for.
+      let requested_lsn = wal.log_buffer.lock().requested_lsn;
+      let _ = wal.flush_to(requested_lsn);
+    });
+  }
+
+  // Throws away every record before `lsn`. Their changes must all be in
+  // the NodeStore already, and no transaction that may still have to
+  // be rolled back (nor any split that isn't finished) can have made
+  // them.
+  //
+  // We copy the records we keep to a new file, which we then move into
+  // place. A crash leaves either the old file or the new one. Records
+  // can still be appended meanwhile; only flushes wait for us.
+  //
+  // LSNs keep counting up from where they were.
+  pub fn truncate_before(&self, lsn: Lsn) -> Result<()> {
+    self.check_failure()?;
+    let mut log_buffer = self.log_buffer.lock();
+    while log_buffer.is_flushing {
+      self.flush_finished.wait(&mut log_buffer);
+    }
+    if lsn <= log_buffer.first_lsn {
+      return Ok(());
+    }
+
+    // Records still in the buffer are kept, whatever their LSN.
+    let first_lsn = cmp::min(lsn, log_buffer.flushed_lsn);
+    log_buffer.is_flushing = true;
+    let result = MutexGuard::unlocked(&mut log_buffer, || {
+      self.rewrite_from(first_lsn)
+    });
+
+    log_buffer.is_flushing = false;
+    if let Ok(file_size) = result {
+      log_buffer.first_lsn = first_lsn;
+      log_buffer.file_size = file_size;
+    }
+    self.flush_finished.notify_all();
+
+    result.map(|_| ())
+  }
+
+  // Returns the size of the new file.
+  fn rewrite_from(&self, first_lsn: Lsn) -> Result<u64> {
+    let mut file = self.file.lock();
+    let mut contents = vec![];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut contents)?;
+
+    let mut position = HEADER_LEN;
+    while let Some((lsn, _, len)) = read_record(&contents[position..]) {
+      if first_lsn <= lsn {
+        break;
+      }
+      position += len;
+    }
+
+    let mut new_path = self.path.as_os_str().to_owned();
+    new_path.push(".new");
+    let mut new_file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(true)
+      .open(&new_path)?;
+    write_header(&mut new_file, first_lsn)?;
+    new_file.write_all(&contents[position..])?;
+    new_file.sync_data()?;
+    fs::rename(&new_path, &self.path)?;
+    *file = new_file;
+
+    // Until the move is durable, a crash could bring back the old file,
+    // without the records we append to the new one.
+    if let Err(error) = sync_parent_directory(&self.path) {
+      *self.failure.lock() = Some(error.clone());
+      return Err(error);
+    }
+
+    Ok((HEADER_LEN + contents.len() - position) as u64)
+  }
+
+  fn check_failure(&self) -> Result<()> {
+    match &*self.failure.lock() {
+      None => Ok(()),
+      Some(error) => Err(error.clone()),
+    }
+  }
+
+  // If the write fails partway, we cut the file back, so that a retry
+  // doesn't leave a torn record in the middle of the log.
+  fn write(&self, bytes: &[u8]) -> Result<()> {
+    let mut file = self.file.lock();
+    let len = file.seek(SeekFrom::End(0))?;
+
+    let result = file
+      .write_all(bytes)
+      .and_then(|()| file.sync_data())
+      .map_err(Error::from);
+    if let Err(error) = &result {
+      if file.set_len(len).is_err() {
+        *self.failure.lock() = Some(error.clone());
+      }
+    }
+
+    result
+  }
+}
+
+impl LogBuffer {
+  fn load(&mut self, file: &mut File, contents: &[u8]) -> Result<()> {
+    if contents.len() < HEADER_LEN || &contents[..MAGIC.len()] != MAGIC
+    {
+      return Err(Error::CorruptPage(String::from(
+        "not a nedbase log file",
+      )));
+    }
+
+    let mut reader =
+      ByteReader::new(&contents[MAGIC.len()..HEADER_LEN]);
+    if reader.read_u32()? != FORMAT_VERSION {
+      return Err(Error::CorruptPage(String::from(
+        "unknown log file format version",
+      )));
+    }
+    self.next_lsn = reader.read_u64()?;
+    self.first_lsn = self.next_lsn;
+
+    // Read records until we run out, or reach a torn one.
+    let mut position = HEADER_LEN;
+    while let Some((lsn, record, len)) =
+      read_record(&contents[position..])
+    {
+      self.recovered_records.push((lsn, record));
+      self.next_lsn = lsn + 1;
+      position += len;
+    }
+
+    // Anything after the last good record is garbage.
+    if position < contents.len() {
+      file.set_len(position as u64)?;
+      file.sync_data()?;
+    }
+    self.flushed_lsn = self.next_lsn;
+    self.file_size = position as u64;
+
+    Ok(())
+  }
+}
+
+fn write_header(file: &mut File, first_lsn: Lsn) -> Result<()> {
+  let mut writer = ByteWriter::new();
+  for byte in MAGIC {
+    writer.write_u8(*byte);
+  }
+  writer.write_u32(FORMAT_VERSION);
+  writer.write_u64(first_lsn);
+
+  file.seek(SeekFrom::Start(0))?;
+  file.write_all(&writer.into_bytes())?;
+  file.sync_data()?;
+
+  Ok(())
+}
+
+fn sync_parent_directory(path: &Path) -> Result<()> {
+  let directory = match path.parent() {
+    Some(directory) if directory != Path::new("") => directory,
+    _ => Path::new("."),
+  };
+  File::open(directory)?.sync_all()?;
+
+  Ok(())
+}
+
+// Returns None if the bytes don't start with a whole, intact record.
+// Otherwise hands back the record's LSN, the record itself (still as
+// bytes), and how many bytes it took up in all.
+fn read_record(bytes: &[u8]) -> Option<(Lsn, Vec<u8>, usize)> {
+  let mut reader = ByteReader::new(bytes);
+  let payload_len = reader.read_u32().ok()? as usize;
+  let expected_checksum = reader.read_u32().ok()?;
+  if bytes.len() < 8 + payload_len {
+    return None;
+  }
+
+  let payload = &bytes[8..(8 + payload_len)];
+  if checksum(payload) != expected_checksum {
+    return None;
+  }
+
+  let mut reader = ByteReader::new(payload);
+  let lsn = reader.read_u64().ok()?;
+  let record = payload[8..].to_vec();
+
+  Some((lsn, record, 8 + payload_len))
+}
+
+// FNV-1a. This only needs to catch a record that was torn by a crash.
+fn checksum(bytes: &[u8]) -> u32 {
+  bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+    (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::WriteAheadLog;
+  use std::fs;
+  use std::sync::Arc;
+  use std::thread;
+  use test_util::{data_path, wal_path};
+  use wal::{LogRecord, Lsn};
+
+  fn append_end(wal: &WriteAheadLog, transaction_id: u64) -> Lsn {
+    wal
+      .append(&LogRecord::<String, String>::End { transaction_id })
+      .unwrap()
+  }
+
+  fn recovered_lsns(wal: &WriteAheadLog) -> Vec<Lsn> {
+    wal
+      .take_recovered_records::<String, String>()
+      .unwrap()
+      .into_iter()
+      .map(|(lsn, _)| lsn)
+      .collect()
+  }
+
+  #[test]
+  fn truncate_before_keeps_later_and_unflushed_records() {
+    let path = wal_path(&data_path("truncate-before"));
+
+    let wal = WriteAheadLog::open(&path).unwrap();
+    let lsns: Vec<Lsn> =
+      (0..10).map(|idx| append_end(&wal, idx)).collect();
+    wal.flush().unwrap();
+    let size = wal.size();
+    // These were never flushed, so they stay, whatever we ask for.
+    let unflushed_lsn = append_end(&wal, 10);
+    wal.truncate_before(unflushed_lsn + 1).unwrap();
+    assert!(wal.size() < size);
+    append_end(&wal, 11);
+    wal.flush().unwrap();
+    drop(wal);
+
+    let wal = WriteAheadLog::open(&path).unwrap();
+    assert_eq!(
+      recovered_lsns(&wal),
+      vec![unflushed_lsn, unflushed_lsn + 1],
+    );
+    // LSNs go on counting up.
+    assert_eq!(wal.next_lsn(), unflushed_lsn + 2);
+    assert_eq!(lsns[9] + 1, unflushed_lsn);
+
+    wal.truncate_before(unflushed_lsn + 1).unwrap();
+    drop(wal);
+    let wal = WriteAheadLog::open(&path).unwrap();
+    assert_eq!(recovered_lsns(&wal), vec![unflushed_lsn + 1]);
+
+    drop(wal);
+    let _ = fs::remove_file(&path);
+  }
+
+  #[test]
+  fn waiting_flushes_are_done_together() {
+    let path = wal_path(&data_path("group-commit"));
+    let wal = Arc::new(WriteAheadLog::open(&path).unwrap());
+
+    // Hold up the first flush in the middle of writing.
+    let file = wal.file.lock();
+    let first_lsn = append_end(&wal, 0);
+    let first_flush = {
+      let wal = Arc::clone(&wal);
+      thread::spawn(move || wal.flush_to(first_lsn).unwrap())
+    };
+    while !wal.log_buffer.lock().is_flushing {
+      thread::yield_now();
+    }
+
+    // These come too late for the first flush, so they have to wait.
+    let waiting_flushes: Vec<_> = (1..5)
+      .map(|transaction_id| {
+        let lsn = append_end(&wal, transaction_id);
+        let wal = Arc::clone(&wal);
+        thread::spawn(move || wal.flush_to(lsn).unwrap())
+      })
+      .collect();
+
+    drop(file);
+    first_flush.join().unwrap();
+    for waiting_flush in waiting_flushes {
+      waiting_flush.join().unwrap();
+    }
+    assert_eq!(wal.flushed_lsn(), wal.next_lsn());
+    assert_eq!(wal.num_flushes(), 2);
+
+    drop(wal);
+    let _ = fs::remove_file(&path);
+  }
+}